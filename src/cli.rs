@@ -4,11 +4,29 @@
 //! APIs and consistency across all other modules. This is where the parent
 //! CLI can be found, as well as utilities for fetching common switches and
 //! values.
+use chrono::{DateTime, Utc};
 use clap::{App, AppSettings, Arg, ArgMatches};
+use regex::Regex;
 use rusoto_s3::*;
 
+use crate::client;
+use crate::cost::{self, Pricing};
+use crate::filter;
+use crate::guard;
 use crate::types::UtilResult;
 
+/// Storage classes which can be applied to a target.
+pub const STORAGE_CLASSES: [&str; 8] = [
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER",
+    "GLACIER_IR",
+    "DEEP_ARCHIVE",
+];
+
 /// Constructs a new CLI application using Clap.
 ///
 /// This will register all subcommand modules and embed all metadata. All
@@ -23,6 +41,7 @@ pub fn build<'a, 'b>() -> App<'a, 'b> {
         .subcommand(crate::concat::cmd())
         .subcommand(crate::rename::cmd())
         .subcommand(crate::report::cmd())
+        .subcommand(crate::swap::cmd())
         .settings(&[
             AppSettings::ArgRequiredElseHelp,
             AppSettings::DisableHelpSubcommand,
@@ -34,17 +53,36 @@ pub fn build<'a, 'b>() -> App<'a, 'b> {
 /// Executes a subcommand based on the parsed arguments from the CLI.
 ///
 /// This will pass a singleton `S3Client` to each submodule to avoid
-/// having to construct a client inside each module.
+/// having to construct a client inside each module. Once a subcommand
+/// has finished, a summary of all requests made to S3 is printed.
 pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
-    match args.subcommand() {
-        ("concat", Some(subargs)) => crate::concat::exec(s3, subargs).await,
-        ("rename", Some(subargs)) => crate::rename::exec(s3, subargs).await,
-        ("report", Some(subargs)) => crate::report::exec(s3, subargs).await,
+    // find the subcommand to execute
+    let (name, subargs) = match args.subcommand() {
+        (name, Some(subargs)) => (name, subargs),
         _ => {
             build().print_help().expect("Unable to log to TTY");
-            Ok(())
+            return Ok(());
         }
+    };
+
+    // parse the pricing up front, to avoid failing after the fact
+    let pricing = Pricing::from_args(subargs)?;
+
+    // delegate to the subcommand
+    let result = match name {
+        "concat" => crate::concat::exec(s3, subargs).await,
+        "rename" => crate::rename::exec(s3, subargs).await,
+        "report" => crate::report::exec(s3, subargs).await,
+        "swap" => crate::swap::exec(s3, subargs).await,
+        _ => unreachable!("subcommands should be exhaustive"),
+    };
+
+    // summarize requests, unless quiet
+    if !subargs.is_present("quiet") {
+        cost::print(&pricing);
     }
+
+    result
 }
 
 /// Fetches a bucket/prefix pair from the common argument set.
@@ -63,8 +101,54 @@ pub fn get_bucket_pair<'a>(args: &'a ArgMatches<'a>) -> (String, Option<String>)
     )
 }
 
+/// Fetches all bucket/prefix pairs to operate on, alongside their clients.
+///
+/// Bucket names containing glob syntax are expanded against all buckets
+/// visible to the provided credentials, with each bucket receiving its own
+/// client bound to the region it lives in. Destructive commands will refuse
+/// to expand a pattern unless `--yes-all-buckets` is also provided.
+pub async fn expand_buckets(
+    s3: &S3Client,
+    args: &ArgMatches<'_>,
+    destructive: bool,
+) -> UtilResult<Vec<(S3Client, String, Option<String>)>> {
+    let (pattern, prefix) = get_bucket_pair(args);
+
+    // plain bucket names need no expansion
+    if !filter::is_glob(&pattern) {
+        return Ok(vec![(s3.clone(), pattern, prefix)]);
+    }
+
+    // destructive commands must be explicit
+    if destructive && !args.is_present("yes_all_buckets") {
+        return Err(format!(
+            "Refusing to expand bucket pattern {} without --yes-all-buckets",
+            pattern
+        )
+        .into());
+    }
+
+    // compile the pattern to check against all bucket names
+    let matcher = Regex::new(&filter::compile(&pattern)?)?;
+    let mut buckets = Vec::new();
+
+    for bucket in client::list_buckets(s3).await? {
+        if matcher.is_match(&bucket) {
+            let (bucket_s3, _) = client::for_bucket(s3, &bucket).await;
+            buckets.push((bucket_s3, bucket, prefix.clone()));
+        }
+    }
+
+    // no buckets is almost certainly a mistake
+    if buckets.is_empty() {
+        return Err(format!("No buckets found matching pattern {}", pattern).into());
+    }
+
+    Ok(buckets)
+}
+
 /// Fetches the set of global arguments which should be attached on each command.
-pub fn global_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
+pub fn global_args<'a, 'b>() -> [Arg<'a, 'b>; 6] {
     [
         Arg::with_name("dry")
             .help("Only print out the calculated writes")
@@ -74,14 +158,271 @@ pub fn global_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
             .help("Only prints errors during execution")
             .short("q")
             .long("quiet"),
+        Arg::with_name("verbose")
+            .help("Prints additional detail during execution")
+            .short("v")
+            .long("verbose")
+            .conflicts_with("quiet"),
+        Arg::with_name("max_delete")
+            .help("The maximum number of objects which can be removed")
+            .long("max-delete")
+            .takes_value(true)
+            .default_value(guard::DEFAULT_MAX_DELETE)
+            .validator(validate_number),
+        Arg::with_name("request_pricing")
+            .help("Overrides the price per 1,000 requests (e.g. list=0.005,get=0.0004)")
+            .long("request-pricing")
+            .takes_value(true),
         Arg::with_name("bucket")
-            .help("An S3 bucket prefix to work within")
+            .help("An S3 bucket prefix (or bucket glob pattern) to work within")
             .index(1)
             .required(true),
     ]
 }
 
+/// Fetches the set of glob filter arguments shared by object walking commands.
+pub fn filter_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("include_glob")
+            .help("Only include keys matching a glob pattern")
+            .long("include-glob")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("exclude_glob")
+            .help("Exclude keys matching a glob pattern")
+            .long("exclude-glob")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    ]
+}
+
+/// Fetches the set of arguments shared by commands which remove objects.
+pub fn destructive_args<'a, 'b>() -> [Arg<'a, 'b>; 1] {
+    [Arg::with_name("yes_all_buckets")
+        .help("Allows a bucket pattern to expand to multiple buckets")
+        .long("yes-all-buckets")]
+}
+
 /// Determines if the dry-run switch was provided in this execution.
 pub fn is_dry_run(args: &ArgMatches<'_>) -> bool {
     args.is_present("dry")
 }
+
+/// Validates that an argument value is a duration (such as `12h` or `7d`).
+pub fn validate_duration(value: String) -> Result<(), String> {
+    humantime::parse_duration(&value)
+        .map(|_| ())
+        .map_err(|_| format!("Invalid duration: {}", value))
+}
+
+/// Parses a size with optional units (such as `10MB` or `2GiB`) into bytes.
+///
+/// Units are case insensitive, and can be decimal (`KB`, `MB`, etc.) or
+/// binary (`KiB`, `MiB`, etc.). A size without units is treated as bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+
+    let (number, unit) = value.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return None,
+    };
+
+    // whole numbers stay exact, so only fractions go through floating point
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(multiplier);
+    }
+
+    let size = (number.parse::<f64>().ok()? * multiplier as f64).round();
+    Some(size as u64).filter(|_| size < u64::MAX as f64)
+}
+
+/// Validates that an argument value is a size, as accepted by `parse_size`.
+pub fn validate_size(value: String) -> Result<(), String> {
+    parse_size(&value)
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+/// Parses a point in time, either as an RFC 3339 timestamp or a duration.
+///
+/// Durations (such as `24h` or `7d`) are resolved relative to the provided
+/// time, as that long before it.
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let duration = humantime::parse_duration(value).ok()?;
+    let duration = chrono::Duration::from_std(duration).ok()?;
+
+    now.checked_sub_signed(duration)
+}
+
+/// Validates that an argument value is a point in time, as accepted by `parse_time`.
+pub fn validate_time(value: String) -> Result<(), String> {
+    parse_time(&value, Utc::now())
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid timestamp or duration: {}", value))
+}
+
+/// Parses a non-empty byte string, either as escaped text or as hex.
+///
+/// Hex values are prefixed with `hex:` (such as `hex:0d0a`), and any other
+/// value is taken as text, with support for the `\n`, `\r`, `\t`, `\0` and
+/// `\\` escapes (so that they can be passed easily from a shell).
+pub fn parse_bytes(value: &str) -> Option<Vec<u8>> {
+    let bytes = match value.strip_prefix("hex:") {
+        Some(hex) => {
+            if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            (0..hex.len())
+                .step_by(2)
+                .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+                .collect::<Option<Vec<_>>>()?
+        }
+        None => {
+            let mut bytes = Vec::with_capacity(value.len());
+            let mut chars = value.chars();
+
+            while let Some(c) = chars.next() {
+                let c = match c {
+                    '\\' => match chars.next()? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        '0' => '\0',
+                        '\\' => '\\',
+                        _ => return None,
+                    },
+                    c => c,
+                };
+
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+
+            bytes
+        }
+    };
+
+    Some(bytes).filter(|bytes| !bytes.is_empty())
+}
+
+/// Validates that an argument value is a byte string, as accepted by `parse_bytes`.
+pub fn validate_bytes(value: String) -> Result<(), String> {
+    parse_bytes(&value)
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid byte string: {}", value))
+}
+
+/// Parses a `key=value` pair, with a non-empty key.
+///
+/// Only the first `=` separates the key from the value, so values can
+/// contain any further `=` characters.
+pub fn parse_pair(value: &str) -> Option<(&str, &str)> {
+    value.split_once('=').filter(|(key, _)| !key.is_empty())
+}
+
+/// Validates that an argument value is a pair, as accepted by `parse_pair`.
+pub fn validate_pair(value: String) -> Result<(), String> {
+    parse_pair(&value)
+        .map(|_| ())
+        .ok_or_else(|| format!("Value must be in the form key=value: {}", value))
+}
+
+/// Validates that an argument value is a non-negative integer.
+pub fn validate_number(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("Invalid number: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bytes, parse_pair, parse_size, parse_time};
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn parsing_bytes() {
+        assert_eq!(parse_bytes("\\n"), Some(b"\n".to_vec()));
+        assert_eq!(parse_bytes("--\\r\\n"), Some(b"--\r\n".to_vec()));
+        assert_eq!(parse_bytes("a\\\\b"), Some(b"a\\b".to_vec()));
+        assert_eq!(parse_bytes("hex:0D0a"), Some(b"\r\n".to_vec()));
+        assert_eq!(parse_bytes("\u{e9}"), Some("\u{e9}".as_bytes().to_vec()));
+
+        assert_eq!(parse_bytes(""), None);
+        assert_eq!(parse_bytes("hex:"), None);
+        assert_eq!(parse_bytes("hex:0"), None);
+        assert_eq!(parse_bytes("hex:zz"), None);
+        assert_eq!(parse_bytes("\\"), None);
+        assert_eq!(parse_bytes("\\q"), None);
+    }
+
+    #[test]
+    fn parsing_pairs() {
+        assert_eq!(parse_pair("a=b"), Some(("a", "b")));
+        assert_eq!(parse_pair("a=b=c"), Some(("a", "b=c")));
+        assert_eq!(parse_pair("a="), Some(("a", "")));
+
+        assert_eq!(parse_pair("a"), None);
+        assert_eq!(parse_pair("=b"), None);
+    }
+
+    #[test]
+    fn parsing_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("10MB"), Some(10_000_000));
+        assert_eq!(parse_size("10mb"), Some(10_000_000));
+        assert_eq!(parse_size("2GiB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5KiB"), Some(1536));
+        assert_eq!(parse_size("5 MiB"), Some(5 * 1024 * 1024));
+
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size("10XB"), None);
+        assert_eq!(parse_size("-1"), None);
+
+        assert_eq!(parse_size("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_size("18446744073709551616"), None);
+        assert_eq!(parse_size("20000000TB"), None);
+        assert_eq!(parse_size("20000000.5TB"), None);
+    }
+
+    #[test]
+    fn parsing_times() {
+        let now = DateTime::parse_from_rfc3339("2018-01-08T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let parse = |value| parse_time(value, now).map(|time| time.to_rfc3339());
+
+        assert_eq!(
+            parse("2018-01-01T10:00:00+02:00"),
+            Some("2018-01-01T08:00:00+00:00".to_string())
+        );
+        assert_eq!(parse("24h"), Some("2018-01-07T12:00:00+00:00".to_string()));
+        assert_eq!(parse("7d"), Some("2018-01-01T12:00:00+00:00".to_string()));
+
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("2018-01-01"), None);
+    }
+}
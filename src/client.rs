@@ -0,0 +1,269 @@
+//! Client construction and bucket region resolution for AWS S3.
+//!
+//! Buckets live in a single region, and requests sent to the wrong region
+//! fail with rather unhelpful errors. This module builds clients, and can
+//! locate the region of a bucket so that commands which work across more
+//! than one bucket are able to route each call to the right endpoint.
+use rusoto_cloudwatch::CloudWatchClient;
+use rusoto_core::{credential::ChainProvider, region::Region, Client, HttpClient, RusotoError};
+use rusoto_s3::*;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::cost::CountingDispatcher;
+use crate::retry::Retry;
+use crate::types::{UtilError, UtilResult};
+
+/// Maximum number of objects removed in a single batch.
+pub const MAX_REMOVAL_BATCH: usize = 1_000;
+
+/// Constructs a new `S3Client` bound to the provided `Region`.
+pub fn new(region: Region) -> UtilResult<S3Client> {
+    Ok(S3Client::new_with_client(core()?, region))
+}
+
+/// Constructs a new `CloudWatchClient` bound to the provided `Region`.
+pub fn cloudwatch(region: Region) -> UtilResult<CloudWatchClient> {
+    Ok(CloudWatchClient::new_with_client(core()?, region))
+}
+
+/// Resolves a client bound to the region the provided bucket lives in.
+///
+/// The region is returned alongside the client when it could be located.
+/// Custom endpoints (such as S3 compatible stores) are never re-routed,
+/// and neither are buckets where the location lookup fails; in both of
+/// these cases the provided client is handed back as-is.
+pub async fn for_bucket(s3: &S3Client, bucket: &str) -> (S3Client, Option<Region>) {
+    // never re-route custom endpoints
+    if let Region::Custom { .. } = Region::default() {
+        return (s3.clone(), None);
+    }
+
+    // attempt to locate the bucket, falling back to the default client
+    match locate(s3, bucket).await {
+        Ok(region) => match new(region.clone()) {
+            Ok(client) => (client, Some(region)),
+            Err(_) => (s3.clone(), None),
+        },
+        Err(_) => (s3.clone(), None),
+    }
+}
+
+/// Locates the `Region` of a bucket using `GetBucketLocation`.
+pub async fn locate(s3: &S3Client, bucket: &str) -> UtilResult<Region> {
+    // create the request to find the bucket location
+    let request = GetBucketLocationRequest {
+        bucket: bucket.to_string(),
+        ..GetBucketLocationRequest::default()
+    };
+
+    // execute the request and await the response
+    let response = s3.get_bucket_location(request).await?;
+
+    // legacy buckets use an empty (us-east-1) or EU (eu-west-1) constraint
+    match response.location_constraint.as_deref() {
+        None | Some("") => Ok(Region::UsEast1),
+        Some("EU") => Ok(Region::EuWest1),
+        Some(name) => Ok(Region::from_str(name)?),
+    }
+}
+
+/// Lists the names of all buckets visible to the current credentials.
+pub async fn list_buckets(s3: &S3Client) -> UtilResult<Vec<String>> {
+    let response = s3.list_buckets().await?;
+
+    Ok(response
+        .buckets
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|bucket| bucket.name)
+        .collect())
+}
+
+/// Constructs the shared request dispatcher and credential chain.
+fn core() -> UtilResult<Client> {
+    // create client options, counting all requests
+    let client = CountingDispatcher::new(HttpClient::new()?);
+
+    // create provided with timeout
+    let mut chain = ChainProvider::new();
+    chain.set_timeout(Duration::from_millis(500));
+
+    // create the new core client
+    Ok(Client::new_with(chain, client))
+}
+
+/// Describes the location of a bucket for use in logging and errors.
+pub fn describe(bucket: &str, region: &Option<Region>) -> String {
+    match region {
+        Some(region) => format!("{} ({})", bucket, region.name()),
+        None => format!("{} (unknown region)", bucket),
+    }
+}
+
+/// Fetches the details of an object in S3, if it exists.
+pub async fn head(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> UtilResult<Option<HeadObjectOutput>> {
+    let request = HeadObjectRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        request_payer: request_payer.map(str::to_string),
+        ..HeadObjectRequest::default()
+    };
+
+    // HEAD responses have no body, so a missing key may surface as a raw 404
+    match s3.head_object(request).await {
+        Ok(head) => Ok(Some(head)),
+        Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Removes objects from S3 in batches, logging rather than failing on error.
+///
+/// Each batch removes up to 1,000 objects in a single request. Any object
+/// which couldn't be removed is logged, and returned alongside the reason.
+pub async fn remove_objects(
+    s3: &S3Client,
+    bucket: &str,
+    keys: &[String],
+    retry: &Retry,
+    request_payer: Option<&str>,
+) -> HashMap<String, String> {
+    let objects = keys
+        .iter()
+        .map(|key| ObjectIdentifier {
+            key: key.to_string(),
+            ..ObjectIdentifier::default()
+        })
+        .collect::<Vec<_>>();
+
+    remove_versions(s3, bucket, &objects, retry, request_payer).await
+}
+
+/// Removes specific versions of objects from S3 in batches.
+///
+/// Identifiers without a version remove the current version of an object,
+/// exactly as `remove_objects` does. Any object with a version which couldn't
+/// be removed is logged, and returned alongside the reason.
+pub async fn remove_versions(
+    s3: &S3Client,
+    bucket: &str,
+    objects: &[ObjectIdentifier],
+    retry: &Retry,
+    request_payer: Option<&str>,
+) -> HashMap<String, String> {
+    let mut failed = HashMap::new();
+
+    for batch in objects.chunks(MAX_REMOVAL_BATCH) {
+        // print that we're removing
+        info!("Removing {} sources...", batch.len());
+
+        // create the batch removal request
+        let objects = batch.to_vec();
+
+        let delete = DeleteObjectsRequest {
+            bucket: bucket.to_string(),
+            delete: Delete {
+                objects,
+                quiet: Some(true),
+            },
+            request_payer: request_payer.map(str::to_string),
+            ..DeleteObjectsRequest::default()
+        };
+
+        // attempt to remove the batch, failing all keys on error
+        let output = match retry
+            .run("batch removal", || s3.delete_objects(delete.clone()))
+            .await
+        {
+            Ok(output) => output,
+            Err(err) => {
+                let err = UtilError::from(err);
+                error!("Unable to remove {} sources: {}", batch.len(), err);
+                failed.extend(
+                    batch
+                        .iter()
+                        .map(|object| (object.key.clone(), err.to_string())),
+                );
+                continue;
+            }
+        };
+
+        // quiet mode only reports the keys which failed
+        for err in output.errors.unwrap_or_default() {
+            let key = err.key.unwrap_or_default();
+            let reason = err.message.or(err.code).unwrap_or_default();
+
+            error!("Unable to remove {}: {}", key, reason);
+            failed.insert(key, reason);
+        }
+    }
+
+    failed
+}
+
+/// Formats the source of a copy request, for an object within a bucket.
+///
+/// S3 requires the key within a copy source to be URL encoded, otherwise
+/// keys with spaces, `+` or unicode will either fail or copy the wrong
+/// object. Slashes are left as they are, as they separate the key path.
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, encode(key, b"/"))
+}
+
+/// URL encodes a value, leaving only unreserved and any safe characters.
+pub fn encode(value: &str, safe: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte if safe.contains(&byte) => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::copy_source;
+
+    #[test]
+    fn encoding_copy_sources() {
+        assert_eq!(copy_source("bucket", "logs/a.log"), "bucket/logs/a.log");
+        assert_eq!(
+            copy_source("bucket", "logs/my file.log"),
+            "bucket/logs/my%20file.log"
+        );
+        assert_eq!(
+            copy_source("bucket", "logs/a+b.log"),
+            "bucket/logs/a%2Bb.log"
+        );
+        assert_eq!(
+            copy_source("bucket", "logs/100%/#1.log"),
+            "bucket/logs/100%25/%231.log"
+        );
+        assert_eq!(
+            copy_source("bucket", "logs/caf\u{e9}/\u{65e5}.log"),
+            "bucket/logs/caf%C3%A9/%E6%97%A5.log"
+        );
+
+        // keys starting with the bucket name are still keys
+        assert_eq!(
+            copy_source("bucket", "bucket-logs/a.log"),
+            "bucket/bucket-logs/a.log"
+        );
+    }
+}
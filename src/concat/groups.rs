@@ -0,0 +1,140 @@
+//! Grouping of sources by the common prefixes beneath a prefix.
+//!
+//! Rather than mapping sources to targets with a pattern, every common
+//! prefix one level below the provided prefix (such as each `dt=<date>/`
+//! folder) forms a group, and everything beneath it is concatenated into
+//! a single target. Targets are named from a template, where `{prefix}` is
+//! replaced by the common prefix without its trailing delimiter.
+use regex::Regex;
+use rusoto_s3::*;
+
+use crate::types::UtilResult;
+
+/// Token replaced by the common prefix of a group within a target template.
+const PREFIX_TOKEN: &str = "{prefix}";
+
+/// Lists all common prefixes one level below a prefix, using a delimiter.
+///
+/// Prefixes have any trailing delimiter trimmed on the command line, so
+/// it's added back here to list the level below the prefix itself.
+pub async fn list(
+    s3: &S3Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    delimiter: &str,
+) -> UtilResult<Vec<String>> {
+    let prefix = parent(prefix, delimiter);
+
+    let mut prefixes = Vec::new();
+    let mut continuation_token = None;
+
+    loop {
+        // create the request for the next page of prefixes
+        let request = ListObjectsV2Request {
+            bucket: bucket.to_string(),
+            prefix: prefix.clone(),
+            delimiter: Some(delimiter.to_string()),
+            continuation_token,
+            ..ListObjectsV2Request::default()
+        };
+
+        let listed = s3.list_objects_v2(request).await?;
+
+        for common in listed.common_prefixes.unwrap_or_default() {
+            prefixes.extend(common.prefix);
+        }
+
+        // stop once there are no more pages
+        if listed.is_truncated != Some(true) {
+            break;
+        }
+
+        continuation_token = listed.next_continuation_token;
+    }
+
+    Ok(prefixes)
+}
+
+/// Determines the prefix to list groups under, ending with the delimiter.
+fn parent(prefix: Option<&str>, delimiter: &str) -> Option<String> {
+    let prefix = prefix.filter(|prefix| !prefix.is_empty())?;
+
+    if prefix.ends_with(delimiter) {
+        return Some(prefix.to_string());
+    }
+
+    Some(format!("{}{}", prefix, delimiter))
+}
+
+/// Creates a pattern matching every source within a group.
+///
+/// The common prefix (without its delimiter) is captured as `prefix`, so
+/// it can be referenced by the template produced by `template`.
+pub fn pattern(group: &str, delimiter: &str) -> UtilResult<Regex> {
+    let prefix = group.strip_suffix(delimiter).unwrap_or(group);
+
+    let pattern = format!(
+        "^(?s)(?P<prefix>{}){}.*$",
+        regex::escape(prefix),
+        regex::escape(delimiter)
+    );
+
+    Ok(Regex::new(&pattern)?)
+}
+
+/// Converts a target template into a replacement for the group pattern.
+pub fn template(template: &str) -> String {
+    template.replace(PREFIX_TOKEN, "${prefix}")
+}
+
+/// Validates a target template, which must reference the group prefix.
+///
+/// Without the prefix every group would be concatenated into the same
+/// target, which is almost certainly a mistake.
+pub fn validate(template: String) -> Result<(), String> {
+    if template.contains(PREFIX_TOKEN) {
+        return Ok(());
+    }
+    Err(format!("Target template must contain {}", PREFIX_TOKEN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parent, pattern, template, validate};
+
+    #[test]
+    fn listing_below_prefixes() {
+        assert_eq!(parent(Some("logs"), "/").as_deref(), Some("logs/"));
+        assert_eq!(parent(Some("logs/"), "/").as_deref(), Some("logs/"));
+        assert_eq!(parent(Some(""), "/"), None);
+        assert_eq!(parent(None, "/"), None);
+    }
+
+    #[test]
+    fn rendering_group_targets() {
+        let pattern = pattern("logs/dt=2018-01-01/", "/").unwrap();
+        let template = template("{prefix}/merged.bin");
+
+        let render = |key: &str| {
+            pattern.captures(key).map(|captures| {
+                let mut rendered = String::new();
+                captures.expand(&template, &mut rendered);
+                rendered
+            })
+        };
+
+        assert_eq!(
+            render("logs/dt=2018-01-01/part-0.bin").as_deref(),
+            Some("logs/dt=2018-01-01/merged.bin")
+        );
+        assert_eq!(
+            render("logs/dt=2018-01-01/nested/part-1.bin").as_deref(),
+            Some("logs/dt=2018-01-01/merged.bin")
+        );
+        assert_eq!(render("logs/dt=2018-01-02/part-0.bin"), None);
+        assert_eq!(render("logs/dt=2018-01-01").as_deref(), None);
+
+        assert!(validate("{prefix}.bin".to_string()).is_ok());
+        assert!(validate("merged.bin".to_string()).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Manifests recording the outcome of every target within a run.
+//!
+//! Each target is recorded as soon as it has been planned, alongside all of
+//! its sources, and is then updated as it progresses through the run. This
+//! means a manifest can be written at any point (including after a failure)
+//! and will still list every target which was attempted, and how it ended.
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::fs;
+
+use super::stages::Planned;
+use crate::types::UtilResult;
+
+/// Record of all targets concatenated within a run.
+#[derive(Default)]
+pub struct Manifest {
+    acl: Option<String>,
+    entries: Vec<Entry>,
+    indices: HashMap<String, usize>,
+    source_bucket: String,
+    target_bucket: String,
+}
+
+/// Record of a single target within a manifest.
+struct Entry {
+    source_bucket: String,
+    target_bucket: String,
+    key: String,
+    status: Status,
+    e_tag: Option<String>,
+    error: Option<String>,
+    cleaned_up: bool,
+    sources: Vec<Value>,
+}
+
+/// Status of a target within a manifest.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Status {
+    Planned,
+    Completed,
+    Skipped,
+    Failed,
+}
+
+/// Main implementation.
+impl Manifest {
+    /// Sets the canned ACL applied to every target, to include in the manifest.
+    pub fn acl(mut self, acl: Option<&str>) -> Self {
+        self.acl = acl.map(str::to_string);
+        self
+    }
+
+    /// Starts recording the targets concatenated between a pair of buckets.
+    ///
+    /// Targets are only unique within a pair of buckets, so all updates are
+    /// applied to the targets recorded since the last call to this method.
+    pub fn begin(&mut self, source_bucket: &str, target_bucket: &str) {
+        self.indices.clear();
+        self.source_bucket = source_bucket.to_string();
+        self.target_bucket = target_bucket.to_string();
+    }
+
+    /// Records a planned target, alongside the sources within each part.
+    pub fn plan(&mut self, planned: &Planned) {
+        let sources = planned
+            .parts
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, pieces)| {
+                pieces
+                    .iter()
+                    .filter(|piece| !piece.is_inline())
+                    .map(move |piece| {
+                        json!({
+                            "key": piece.key,
+                            "size": piece.length(),
+                            "part": idx + 1,
+                        })
+                    })
+            })
+            .collect();
+
+        self.indices
+            .insert(planned.target.to_string(), self.entries.len());
+
+        self.entries.push(Entry {
+            source_bucket: self.source_bucket.clone(),
+            target_bucket: self.target_bucket.clone(),
+            key: planned.target.to_string(),
+            status: Status::Planned,
+            e_tag: None,
+            error: None,
+            cleaned_up: false,
+            sources,
+        });
+    }
+
+    /// Marks a target as skipped, with the reason it was skipped.
+    pub fn skip(&mut self, target: &str, reason: &str) {
+        if let Some(entry) = self.entry(target) {
+            entry.status = Status::Skipped;
+            entry.error = Some(reason.to_string());
+        }
+    }
+
+    /// Marks a target as completed, with the ETag of the completed object.
+    ///
+    /// Anything not recorded as a target (such as an intermediate) is ignored.
+    pub fn complete(&mut self, target: &str, e_tag: Option<String>) {
+        if let Some(entry) = self.entry(target) {
+            entry.status = Status::Completed;
+            entry.e_tag = e_tag;
+        }
+    }
+
+    /// Marks a target as failed, with the error which caused the failure.
+    pub fn fail(&mut self, target: &str, error: &str) {
+        if let Some(entry) = self.entry(target) {
+            entry.status = Status::Failed;
+            entry.error = Some(error.to_string());
+        }
+    }
+
+    /// Marks every target still pending as failed by the same error.
+    pub fn fail_pending(&mut self, error: &str) {
+        for entry in &mut self.entries {
+            if entry.status == Status::Planned {
+                entry.status = Status::Failed;
+                entry.error = Some(error.to_string());
+            }
+        }
+    }
+
+    /// Marks the sources of a target as cleaned up.
+    pub fn clean(&mut self, target: &str) {
+        if let Some(entry) = self.entry(target) {
+            entry.cleaned_up = true;
+        }
+    }
+
+    /// Renders this manifest as a JSON document.
+    pub fn render(&self) -> String {
+        let targets = self
+            .entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "source_bucket": entry.source_bucket,
+                    "bucket": entry.target_bucket,
+                    "key": entry.key,
+                    "status": entry.status.name(),
+                    "e_tag": entry.e_tag,
+                    "error": entry.error,
+                    "cleaned_up": entry.cleaned_up,
+                    "sources": entry.sources,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "acl": self.acl, "targets": targets }).to_string()
+    }
+
+    /// Writes this manifest out to a file, replacing any existing file.
+    pub fn write(&self, path: &str) -> UtilResult<()> {
+        fs::write(path, self.render())?;
+        Ok(())
+    }
+
+    /// Retrieves the entry for a target recorded since the last `begin`.
+    fn entry(&mut self, target: &str) -> Option<&mut Entry> {
+        let idx = *self.indices.get(target)?;
+        self.entries.get_mut(idx)
+    }
+}
+
+/// Main implementation.
+impl Status {
+    /// Retrieves the name of this status, as written in a manifest.
+    fn name(self) -> &'static str {
+        match self {
+            Status::Planned => "planned",
+            Status::Completed => "completed",
+            Status::Skipped => "skipped",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use crate::concat::pack::Piece;
+    use crate::concat::stages::Planned;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn recording_target_outcomes() {
+        let planned = |target: &str| Planned {
+            target: target.to_string(),
+            parts: vec![
+                vec![Piece::whole("a".to_string(), 10)],
+                vec![Piece::whole("b".to_string(), 5)],
+            ],
+            intermediate: false,
+        };
+
+        let mut manifest = Manifest::default().acl(Some("bucket-owner-full-control"));
+
+        manifest.begin("source", "target");
+        manifest.plan(&planned("done"));
+        manifest.plan(&planned("failed"));
+        manifest.plan(&planned("existing"));
+
+        manifest.skip("existing", "Target already exists");
+        manifest.complete("done", Some("\"etag-2\"".to_string()));
+        manifest.complete("done.concat-1-1", None);
+        manifest.clean("done");
+        manifest.fail_pending("Access Denied");
+
+        let rendered: Value = serde_json::from_str(&manifest.render()).unwrap();
+
+        assert_eq!(
+            rendered["targets"][0],
+            json!({
+                "source_bucket": "source",
+                "bucket": "target",
+                "key": "done",
+                "status": "completed",
+                "e_tag": "\"etag-2\"",
+                "error": null,
+                "cleaned_up": true,
+                "sources": [
+                    { "key": "a", "size": 10, "part": 1 },
+                    { "key": "b", "size": 5, "part": 2 },
+                ],
+            })
+        );
+
+        assert_eq!(rendered["targets"][1]["status"], "failed");
+        assert_eq!(rendered["targets"][1]["error"], "Access Denied");
+        assert_eq!(rendered["targets"][2]["status"], "skipped");
+        assert_eq!(rendered["targets"][2]["error"], "Target already exists");
+        assert_eq!(rendered["targets"].as_array().unwrap().len(), 3);
+        assert_eq!(rendered["acl"], "bucket-owner-full-control");
+    }
+}
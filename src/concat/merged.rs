@@ -0,0 +1,93 @@
+//! Records of the sources merged into each target, for incremental runs.
+//!
+//! Each completed target has a sidecar object alongside it (at the key of
+//! the target, suffixed with `.manifest`) listing every source which has
+//! been merged into it, one key per line. Incremental runs read this record
+//! to skip any source already merged, and extend it as new sources are
+//! appended onto the target.
+use futures::StreamExt;
+use rusoto_core::RusotoError;
+use rusoto_s3::*;
+
+use crate::types::UtilResult;
+
+/// Generates the key of the record kept alongside a target.
+pub fn sidecar(target: &str) -> String {
+    format!("{}.manifest", target)
+}
+
+/// Reads the sources merged into a target, in the order they were merged.
+///
+/// Targets without a record (such as new targets) have nothing merged.
+pub async fn read(s3: &S3Client, bucket: &str, target: &str) -> UtilResult<Vec<String>> {
+    let request = GetObjectRequest {
+        bucket: bucket.to_string(),
+        key: sidecar(target),
+        ..GetObjectRequest::default()
+    };
+
+    let object = match s3.get_object(request).await {
+        Ok(object) => object,
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(Vec::new()),
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => {
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut contents = Vec::new();
+
+    if let Some(mut body) = object.body {
+        while let Some(chunk) = body.next().await {
+            contents.extend_from_slice(&chunk?);
+        }
+    }
+
+    Ok(parse(&String::from_utf8_lossy(&contents)))
+}
+
+/// Writes the sources merged into a target, replacing any existing record.
+pub async fn write(s3: &S3Client, bucket: &str, target: &str, merged: &[String]) -> UtilResult<()> {
+    let contents = render(merged);
+    let request = PutObjectRequest {
+        bucket: bucket.to_string(),
+        key: sidecar(target),
+        content_type: Some("text/plain".to_string()),
+        content_length: Some(contents.len() as i64),
+        body: Some(contents.into_bytes().into()),
+        ..PutObjectRequest::default()
+    };
+
+    s3.put_object(request).await?;
+    Ok(())
+}
+
+/// Parses a record into the list of merged sources.
+fn parse(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Renders a list of merged sources into a record.
+fn render(merged: &[String]) -> String {
+    merged.iter().map(|key| format!("{}\n", key)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, render, sidecar};
+
+    #[test]
+    fn recording_merged_sources() {
+        let merged = vec!["logs/a.log".to_string(), "logs/b.log".to_string()];
+        let rendered = render(&merged);
+
+        assert_eq!(rendered, "logs/a.log\nlogs/b.log\n");
+        assert_eq!(parse(&rendered), merged);
+        assert_eq!(parse(""), Vec::<String>::new());
+        assert_eq!(sidecar("merged.log"), "merged.log.manifest");
+    }
+}
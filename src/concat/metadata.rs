@@ -0,0 +1,91 @@
+//! Metadata copied from the sources of a target onto the target itself.
+//!
+//! Multipart uploads don't carry over any metadata from the sources of their
+//! parts, so it has to be set when each upload is created. The metadata of
+//! a target is taken from its first source, but the content type of every
+//! source is checked so that any disagreement can be flagged to the user.
+use rusoto_s3::*;
+
+use std::collections::HashMap;
+
+use crate::retry::Retry;
+use crate::types::UtilResult;
+
+/// Metadata of a source, to be applied to a target.
+#[derive(Clone, Default)]
+pub struct Metadata {
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+}
+
+/// Main implementation.
+impl Metadata {
+    /// Fetches the metadata to apply to a target from its sources.
+    ///
+    /// Each `HEAD` goes through the retry policy, so it also waits on any
+    /// rate limit shared with the rest of the run.
+    pub async fn fetch(
+        s3: &S3Client,
+        bucket: &str,
+        target: &str,
+        sources: &[&str],
+        retry: &Retry,
+    ) -> UtilResult<Metadata> {
+        let mut fetched: Option<Metadata> = None;
+
+        for source in sources {
+            // fetch the metadata of the source object
+            let request = HeadObjectRequest {
+                bucket: bucket.to_string(),
+                key: source.to_string(),
+                ..HeadObjectRequest::default()
+            };
+
+            let head = retry
+                .run("metadata fetch", || s3.head_object(request.clone()))
+                .await?;
+
+            // the first source provides all metadata
+            let first = match fetched {
+                Some(ref first) => first,
+                None => {
+                    info!("Copying metadata from {} -> {}", source, target);
+                    fetched = Some(Metadata {
+                        content_type: head.content_type,
+                        content_encoding: head.content_encoding,
+                        metadata: head.metadata,
+                    });
+                    continue;
+                }
+            };
+
+            // flag the first disagreement, then stop checking
+            if head.content_type != first.content_type {
+                warn!(
+                    "Sources of {} disagree on content type ({} has {}), using {}",
+                    target,
+                    source,
+                    head.content_type.as_deref().unwrap_or("none"),
+                    first.content_type.as_deref().unwrap_or("none")
+                );
+                break;
+            }
+        }
+
+        Ok(fetched.unwrap_or_default())
+    }
+
+    /// Applies this metadata to a request used to create an upload.
+    ///
+    /// A content type already set on the request takes priority, as this
+    /// will have been provided explicitly.
+    pub fn apply(&self, creation: &mut CreateMultipartUploadRequest) {
+        if creation.content_type.is_none() {
+            creation.content_type = self.content_type.clone();
+        }
+
+        creation.content_encoding = self.content_encoding.clone();
+        creation.metadata = self.metadata.clone();
+    }
+}
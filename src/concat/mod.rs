@@ -1,290 +1,2099 @@
 //! Concatenate Amazon S3 files remotely using flexible patterns.
+use chrono::{DateTime, Utc};
 use clap::{App, Arg, ArgMatches, SubCommand};
-use regex::Regex;
+use futures::stream::{self, StreamExt};
+use regex::{Regex, RegexSet};
+use rusoto_core::{HttpDispatchError, RusotoError};
 use rusoto_s3::*;
 
 use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::cli;
-use crate::types::UtilResult;
+use crate::cli::{self, STORAGE_CLASSES};
+use crate::client;
+use crate::copy::MAX_COPY_SIZE;
+use crate::dates::{parse_modified, DateRange, DateSource, DatedTemplate};
+use crate::filter::{self, Filter};
+use crate::guard::DeleteGuard;
+use crate::limits::Limits;
+use crate::listing::{self, Listed};
+use crate::retry::Retry;
+use crate::tags;
+use crate::types::{UtilError, UtilResult};
 use crate::walker::ObjectWalker;
 
+use self::manifest::Manifest;
+use self::metadata::Metadata;
+use self::order::Order;
+use self::pack::Piece;
+use self::render::Renderer;
+use self::stages::Planned;
+use self::state::{Part, State};
+use self::summary::Summary;
+use self::uploads::{Settled, Uploads};
+
+pub mod groups;
+pub mod manifest;
+pub mod merged;
+pub mod metadata;
+pub mod order;
+pub mod pack;
+pub mod render;
+pub mod resume;
+pub mod stages;
+pub mod state;
+pub mod summary;
+pub mod uploads;
+
+/// Canned ACLs which can be applied to a target.
+const CANNED_ACLS: [&str; 7] = [
+    "private",
+    "public-read",
+    "public-read-write",
+    "authenticated-read",
+    "aws-exec-read",
+    "bucket-owner-read",
+    "bucket-owner-full-control",
+];
+
+/// Minimum size of any part other than the final part of an upload.
+const MIN_PART_SIZE: i64 = 5 * 1024 * 1024;
+
+/// Sources grouped against a target pending resolution.
+struct Group {
+    pending: Arc<str>,
+    range: DateRange,
+    sources: Vec<Source>,
+}
+
+/// Options controlling how sources are selected and planned into targets.
+struct Planning {
+    limits: Limits,
+    exclude: RegexSet,
+    max_sources: Option<usize>,
+    separator: Option<Vec<u8>>,
+    pack: bool,
+    in_place: bool,
+}
+
+/// Main implementation.
+impl Planning {
+    /// Determines whether sources are packed into parts, rather than copied.
+    ///
+    /// Separators can only be added by packing, so targets with any source
+    /// needing a separator are packed, even if packing isn't enabled.
+    fn is_packed(&self, sources: &[Source], unterminated: &HashSet<String>) -> bool {
+        self.pack
+            || sources
+                .iter()
+                .any(|source| unterminated.contains(&source.key))
+    }
+}
+
+/// Source object to be copied into a target.
+struct Source {
+    key: String,
+    size: i64,
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Sources of each target, in the order they're to be concatenated.
+type Ordered = Vec<(String, Vec<Source>)>;
+
+/// Everything needed to finish targets once their uploads have settled.
+///
+/// This is carried across every stage of a run, and collects the outcome
+/// of each target as it's finished.
+struct Finishing<'a> {
+    dry: bool,
+    source: (&'a S3Client, &'a str),
+    target: (&'a S3Client, &'a str),
+    retry: &'a Retry,
+    intermediates: &'a HashSet<String>,
+    expected: &'a HashMap<String, (i64, usize)>,
+    manifest: &'a mut Manifest,
+    records: &'a mut HashMap<String, Vec<String>>,
+    completed: HashMap<String, String>,
+    failed: Vec<(String, String)>,
+    broken: HashMap<String, String>,
+    mismatched: usize,
+    keep_going: bool,
+    verify_e_tags: bool,
+    incremental: bool,
+    cleanup: bool,
+    delete_on_mismatch: bool,
+}
+
+/// Main implementation.
+impl Finishing<'_> {
+    /// Records a target which couldn't be finished, alongside the error.
+    ///
+    /// Intermediates aren't targets of their own, so a failed intermediate
+    /// is held against the target relying on it instead, which then fails
+    /// once its stage is reached. Unless keeping going, this fails the run.
+    fn fail(&mut self, key: &str, err: String) -> UtilResult<()> {
+        self.manifest.fail(key, &err);
+
+        if !self.intermediates.contains(key) {
+            if self.keep_going {
+                self.failed.push((key.to_string(), err));
+            }
+            return Ok(());
+        }
+
+        if !self.keep_going {
+            return Err(format!("Unable to complete intermediate {}: {}", key, err).into());
+        }
+
+        self.broken.insert(key.to_string(), err);
+
+        Ok(())
+    }
+}
+
 /// Generates an appropriate `SubCommand` for this module.
 pub fn cmd<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("concat")
         .about("Concatenate Amazon S3 files remotely using flexible pattern")
         .args(&cli::global_args())
+        .args(&cli::filter_args())
+        .args(&cli::destructive_args())
         .args(&[
+            Arg::with_name("acl")
+                .help("The canned ACL to create targets with")
+                .long("acl")
+                .takes_value(true)
+                .possible_values(&CANNED_ACLS),
+            Arg::with_name("append")
+                .help("Appends onto existing targets, rather than skipping them")
+                .long("append")
+                .conflicts_with_all(&["fail_on_existing", "overwrite", "target_bucket"]),
             Arg::with_name("cleanup")
                 .help("Removes source files after concatenation")
                 .short("c")
                 .long("cleanup"),
+            Arg::with_name("content_type")
+                .help("The content type to create targets with")
+                .long("content-type")
+                .takes_value(true),
+            Arg::with_name("copy_metadata")
+                .help("Copies metadata from the first source of each target")
+                .long("copy-metadata"),
+            Arg::with_name("concurrency")
+                .help("The number of part copies to run concurrently")
+                .long("concurrency")
+                .takes_value(true)
+                .default_value("1")
+                .validator(cli::validate_number),
+            Arg::with_name("date_source")
+                .help("The timestamp used to expand date directives in the target")
+                .long("date-source")
+                .takes_value(true)
+                .possible_values(&["now", "newest", "oldest"]),
+            Arg::with_name("delete_on_mismatch")
+                .help("Removes targets which fail verification after completion")
+                .long("delete-on-mismatch"),
+            Arg::with_name("exclude")
+                .help("A pattern of matching sources to exclude from concatenation")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+            Arg::with_name("fail_on_existing")
+                .help("Fails the run if any target already exists")
+                .long("fail-on-existing")
+                .conflicts_with("overwrite"),
+            Arg::with_name("from_file")
+                .help("A file (or - for stdin) listing the sources to concatenate")
+                .long("from-file")
+                .takes_value(true),
+            Arg::with_name("group_by_prefix")
+                .help("Concatenates each common prefix below the prefix, split by a delimiter")
+                .long("group-by-prefix")
+                .takes_value(true)
+                .requires("target_template")
+                .conflicts_with_all(&["from_file", "source", "target"]),
+            Arg::with_name("incremental")
+                .help("Appends only the sources not merged into each target by earlier runs")
+                .long("incremental")
+                .conflicts_with_all(&[
+                    "fail_on_existing",
+                    "max_sources",
+                    "overwrite",
+                    "target_bucket",
+                ]),
+            Arg::with_name("keep_going")
+                .help("Fails only the targets which hit an error, rather than the run")
+                .long("keep-going"),
+            Arg::with_name("manifest")
+                .help("A path to write a JSON manifest of all targets to")
+                .long("manifest")
+                .takes_value(true),
+            Arg::with_name("max_open_uploads")
+                .help("The maximum number of uploads to keep open at once")
+                .long("max-open-uploads")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("max_size")
+                .help("The maximum size of sources to concatenate (e.g. 2GiB)")
+                .long("max-size")
+                .takes_value(true)
+                .validator(cli::validate_size),
+            Arg::with_name("max_sources")
+                .help("The maximum number of sources concatenated into each target")
+                .long("max-sources")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("min_size")
+                .help("The minimum size of sources to concatenate (e.g. 10MB)")
+                .long("min-size")
+                .takes_value(true)
+                .validator(cli::validate_size),
+            Arg::with_name("newer_than")
+                .help("Only concatenate sources modified after a time (or duration ago)")
+                .long("newer-than")
+                .takes_value(true)
+                .validator(cli::validate_time),
+            Arg::with_name("older_than")
+                .help("Only concatenate sources modified before a time (or duration ago)")
+                .long("older-than")
+                .takes_value(true)
+                .validator(cli::validate_time),
+            Arg::with_name("no_verify")
+                .help("Skips checking target ETags against the ETags of their parts")
+                .long("no-verify"),
+            Arg::with_name("overwrite")
+                .help("Replaces any targets which already exist")
+                .long("overwrite"),
+            Arg::with_name("pack_small")
+                .help("Packs sources smaller than 5MiB together into shared parts")
+                .long("pack-small"),
+            Arg::with_name("purge_age")
+                .help("The minimum age of pending uploads removed by --purge-pending")
+                .long("purge-age")
+                .takes_value(true)
+                .default_value("24h")
+                .validator(cli::validate_duration),
+            Arg::with_name("purge_pending")
+                .help("Aborts stale pending uploads before concatenating")
+                .long("purge-pending"),
+            Arg::with_name("rate_limit")
+                .help("The maximum number of requests to send per second")
+                .long("rate-limit")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("resume")
+                .help("Resumes pending uploads left behind by an interrupted run")
+                .long("resume")
+                .requires("state_dir"),
+            Arg::with_name("retries")
+                .help("The number of times to retry requests failing transiently")
+                .long("retries")
+                .takes_value(true)
+                .default_value("3")
+                .validator(cli::validate_number),
+            Arg::with_name("retry_base_delay")
+                .help("The base delay between retries, in milliseconds")
+                .long("retry-base-delay")
+                .takes_value(true)
+                .default_value("100")
+                .validator(cli::validate_number),
+            Arg::with_name("separator")
+                .help("A separator to follow each source with (e.g. \\n or hex:0a)")
+                .long("separator")
+                .takes_value(true)
+                .validator(cli::validate_bytes),
+            Arg::with_name("sse")
+                .help("The server side encryption to create targets with")
+                .long("sse")
+                .takes_value(true)
+                .possible_values(&["AES256", "aws:kms"]),
+            Arg::with_name("sse_kms_key_id")
+                .help("The KMS key to encrypt targets with, when using aws:kms")
+                .long("sse-kms-key-id")
+                .takes_value(true)
+                .requires("sse"),
+            Arg::with_name("storage_class")
+                .help("The storage class to create targets with")
+                .long("storage-class")
+                .takes_value(true)
+                .possible_values(&STORAGE_CLASSES),
+            Arg::with_name("tag")
+                .help("A tag to apply to every target created, as key=value")
+                .long("tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .max_values(tags::MAX_TAGS)
+                .validator(tags::validate),
+            Arg::with_name("target_bucket")
+                .help("A bucket to concatenate files into, rather than the source bucket")
+                .long("target-bucket")
+                .takes_value(true),
+            Arg::with_name("target_template")
+                .help("A target template for each group, containing {prefix}")
+                .long("target-template")
+                .takes_value(true)
+                .requires("group_by_prefix")
+                .validator(groups::validate),
+            Arg::with_name("sort")
+                .help("The order to concatenate the sources of each target in")
+                .long("sort")
+                .takes_value(true)
+                .default_value("lexical")
+                .possible_values(&["lexical", "natural", "modified"]),
+            Arg::with_name("sort_key")
+                .help("A pattern with a single group to capture a value to sort by")
+                .long("sort-key")
+                .takes_value(true)
+                .conflicts_with("sort"),
+            Arg::with_name("state_dir")
+                .help("A directory to spill part state to, rather than memory")
+                .long("state-dir")
+                .takes_value(true),
             Arg::with_name("source")
                 .help("A source pattern to use to locate files")
                 .index(2)
-                .required(true),
+                .required_unless_one(&["from_file", "group_by_prefix"]),
             Arg::with_name("target")
                 .help("A target pattern to use to concatenate files into")
                 .index(3)
-                .required(true),
+                .required_unless_one(&["from_file", "group_by_prefix"]),
         ])
 }
 
-/// Executes this subcommand and returns a `UtilResult` to indicate success.
-pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
-    // parse all global arguments
-    let dryrun = cli::is_dry_run(args);
-    let (bucket, prefix) = cli::get_bucket_pair(args);
-
-    // unwrap and compile the source regex (unwrap should be safe)
-    let source = Regex::new(&args.value_of("source").unwrap())?;
-    let target = args.value_of("target").unwrap();
-
-    // sources and target -> upload mappings
-    let mut sources: HashMap<String, HashSet<String>> = HashMap::new();
-    let mut targets: HashMap<String, String> = HashMap::new();
-
-    // walker strings to pass through
-    let walker_bucket = bucket.clone();
-    let walker_prefix = prefix.clone();
-
-    // construct uploads - this is separate to allow easy handling of errors
-    let walker = ObjectWalker::new(&s3, walker_bucket, walker_prefix);
-    let result = construct_uploads(
-        dryrun,
-        &s3,
-        source,
-        &mut sources,
-        &mut targets,
-        walker,
-        (&bucket, &target),
-    );
-    let result = result.await;
+/// Executes this subcommand and returns a `UtilResult` to indicate success.
+pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
+    // deletions are limited across all buckets
+    let mut guard = DeleteGuard::from_args(args)?;
+
+    // outcome of every target across all buckets
+    let mut manifest = Manifest::default().acl(args.value_of("acl"));
+    let mut result = Ok(());
+
+    // listings are read up front, as stdin can only be read once
+    let listed = match args.value_of("from_file") {
+        Some(path) => Some(listing::parse(&listing::read(path)?, default_target(args))?),
+        None => None,
+    };
+
+    // buckets matched by a pattern which fail are reported once all are done
+    let is_pattern = filter::is_glob(&cli::get_bucket_pair(args).0);
+    let mut failed = Vec::new();
+
+    // concatenate within each bucket in turn
+    for (s3, bucket, prefix) in cli::expand_buckets(&s3, args, true).await? {
+        let listed = listed.as_deref();
+        let name = bucket.clone();
+        let concatenated =
+            concat(&s3, args, &mut guard, &mut manifest, listed, bucket, prefix).await;
+
+        let err = match concatenated {
+            Ok(()) => continue,
+            Err(err) => err,
+        };
+
+        // anything left unfinished failed with its bucket
+        manifest.fail_pending(&err.to_string());
+
+        if !is_pattern {
+            result = Err(err);
+            break;
+        }
+
+        error!("Unable to concatenate within bucket {}: {}", name, err);
+        failed.push(name);
+    }
+
+    // the manifest is written regardless, so failures can be retried
+    if let Some(path) = args.value_of("manifest") {
+        if let Err(err) = manifest.write(path) {
+            // the error which failed the run is never hidden by this one
+            if result.is_ok() {
+                return Err(err);
+            }
+            error!("Unable to write manifest to {}: {}", path, err);
+        }
+    }
+
+    // passthrough
+    result?;
+
+    // failed buckets fail the run, once every other bucket is done
+    if !failed.is_empty() {
+        return Err(format!(
+            "Unable to concatenate within {} buckets: {}",
+            failed.len(),
+            failed.join(", ")
+        )
+        .into());
+    }
+
+    // show planned deletions
+    guard.summarize();
+
+    Ok(())
+}
+
+/// Concatenates all matching files within a bucket/prefix pair.
+async fn concat(
+    s3: &S3Client,
+    args: &ArgMatches<'_>,
+    guard: &mut DeleteGuard,
+    manifest: &mut Manifest,
+    listed: Option<&[Listed]>,
+    bucket: String,
+    prefix: Option<String>,
+) -> UtilResult<()> {
+    // parse all global arguments
+    let dryrun = cli::is_dry_run(args);
+
+    // part state for all uploads
+    let mut state = match args.value_of("state_dir") {
+        Some(dir) => State::disk(dir)?,
+        None => State::memory(),
+    };
+
+    // targets can be written into another bucket (possibly in another region)
+    let (target_s3, target_bucket) = match args.value_of("target_bucket") {
+        Some(target_bucket) => (
+            client::for_bucket(s3, target_bucket).await.0,
+            target_bucket.to_string(),
+        ),
+        None => (s3.clone(), bucket.clone()),
+    };
+
+    // settings applied to every upload created
+    let creation = creation_from_args(args)?;
+
+    // uploads from earlier runs can only belong to targets beneath this
+    let target_prefix = target_prefix(args, listed.is_some())?;
+
+    // stale uploads from earlier runs are removed before planning
+    if args.is_present("purge_pending") {
+        // validated by clap, so should be safe to unwrap
+        let age = humantime::parse_duration(args.value_of("purge_age").unwrap()).unwrap();
+
+        purge_pending(
+            &target_s3,
+            &target_bucket,
+            target_prefix.as_deref(),
+            age,
+            dryrun,
+        )
+        .await?;
+    }
+
+    // plan every target before anything is copied
+    let planning = planning_from_args(args, bucket == target_bucket)?;
+    let mut summary = Summary::default();
+    let mut ordered = match listed {
+        Some(listed) => list_sources(s3, &bucket, listed).await?,
+        None => {
+            let filter = Filter::from_args(args)?;
+            let order = Order::from_args(args)?;
+
+            match args.value_of("group_by_prefix") {
+                Some(delimiter) => {
+                    // validated by clap, so should be safe to unwrap
+                    let template = groups::template(args.value_of("target_template").unwrap());
+                    let target = DatedTemplate::parse(&template, date_source(args))?;
+
+                    let mut ordered = Vec::new();
+
+                    // each common prefix is walked as its own group
+                    for group in groups::list(s3, &bucket, prefix.as_deref(), delimiter).await? {
+                        let source = groups::pattern(&group, delimiter)?;
+                        let walker = ObjectWalker::new(s3, bucket.clone(), Some(group))
+                            .filter(filter.clone());
+
+                        let walked =
+                            walk_sources(source, walker, &target, &order, &planning, &mut summary)
+                                .await?;
+
+                        ordered.extend(walked);
+                    }
+
+                    ordered
+                }
+                None => {
+                    // unwrap and compile the source regex (unwrap should be safe)
+                    let source = Regex::new(&args.value_of("source").unwrap())?;
+                    let target =
+                        DatedTemplate::parse(args.value_of("target").unwrap(), date_source(args))?;
+
+                    let walker = ObjectWalker::new(s3, bucket.clone(), prefix).filter(filter);
+                    walk_sources(source, walker, &target, &order, &planning, &mut summary).await?
+                }
+            }
+        }
+    };
+
+    // sources merged by earlier runs are skipped when incremental
+    let incremental = args.is_present("incremental");
+    let mut records = HashMap::new();
+
+    if incremental {
+        for (target, target_sources) in &mut ordered {
+            let record = merged::read(&target_s3, &target_bucket, target).await?;
+            let sidecar = merged::sidecar(target);
+
+            let previous = record.iter().collect::<HashSet<_>>();
+            let count = target_sources.len();
+
+            // the record itself can match the source pattern
+            target_sources
+                .retain(|source| !previous.contains(&source.key) && source.key != sidecar);
+
+            if target_sources.len() < count {
+                info!(
+                    "Skipping {} sources already merged into {}",
+                    count - target_sources.len(),
+                    target
+                );
+            }
+
+            records.insert(target.to_string(), record);
+        }
+
+        // targets with nothing new are left as they are
+        ordered.retain(|(_, target_sources)| !target_sources.is_empty());
+    }
+
+    // copy parts with the requested concurrency (validated by clap)
+    let concurrency = args.value_of("concurrency").unwrap().parse().unwrap();
+
+    // transient failures are retried rather than failing the run
+    let retry = Retry::from_args(args);
+
+    // sources already ending with a separator don't need another
+    let unterminated = match planning.separator {
+        Some(ref separator) => {
+            find_unterminated(s3, &bucket, &ordered, separator, &retry, concurrency).await?
+        }
+        None => HashSet::new(),
+    };
+
+    let (mut sources, planned) = plan_targets(ordered, &planning, &unterminated, &mut summary)?;
+
+    // record every planned target, before any are skipped
+    manifest.begin(&bucket, &target_bucket);
+
+    for planned in &planned {
+        manifest.plan(planned);
+    }
+
+    // existing targets are only replaced (or appended onto) when explicit
+    let append = args.is_present("append") || incremental;
+    let overwrite = args.is_present("overwrite");
+    let fail_on_existing = args.is_present("fail_on_existing");
+    let mut accepted = Vec::with_capacity(planned.len());
+
+    for mut planned in planned {
+        let existing = client::head(&target_s3, &target_bucket, &planned.target, None).await?;
+        let existing = match existing {
+            Some(existing) => existing,
+            None => {
+                accepted.push(planned);
+                continue;
+            }
+        };
+
+        if append {
+            let size = existing.content_length.unwrap_or_default();
+            info!(
+                "Appending onto {} ({} bytes) as the first part",
+                planned.target, size
+            );
+
+            // targets already packed around separators can pack the target too
+            let pack = planning.pack || planned.parts.iter().flatten().any(Piece::is_inline);
+
+            append_onto(&mut planned, size, existing.e_tag, pack)?;
+            accepted.push(planned);
+            continue;
+        }
+
+        if overwrite {
+            info!("Overwriting existing target {}", planned.target);
+            accepted.push(planned);
+            continue;
+        }
+
+        if fail_on_existing {
+            return Err(format!("Target {} already exists", planned.target).into());
+        }
+
+        warn!("Skipping {} as it already exists", planned.target);
+        manifest.skip(&planned.target, "Target already exists");
+
+        // skipped sources are never removed by cleanup
+        sources -= count_sources(&planned);
+    }
+
+    // targets which failed without failing the run, alongside the error
+    let keep_going = args.is_present("keep_going");
+    let mut failed: Vec<(String, String)> = Vec::new();
+
+    // targets which can never be completed fail before any upload is created
+    let mut planned = Vec::with_capacity(accepted.len());
+
+    for accepted in accepted {
+        let err = match stages::validate(&accepted) {
+            Ok(()) => {
+                planned.push(accepted);
+                continue;
+            }
+            Err(err) if keep_going => err,
+            Err(err) => return Err(err),
+        };
+
+        error!("{}", err);
+        manifest.fail(&accepted.target, &err.to_string());
+
+        sources -= count_sources(&accepted);
+        failed.push((accepted.target, err.to_string()));
+    }
+
+    // metadata of each target, copied from its sources (not needed in a dry run)
+    let mut metadata = HashMap::new();
+
+    if args.is_present("copy_metadata") && !dryrun {
+        let bucket = &bucket;
+        let retry = &retry;
+
+        let mut fetches = stream::iter(&planned)
+            .map(|planned| async move {
+                let mut keys = planned
+                    .parts
+                    .iter()
+                    .flatten()
+                    .filter(|piece| !piece.is_inline())
+                    .map(|piece| piece.key.as_str())
+                    .collect::<Vec<_>>();
+
+                // ranged sources can span parts
+                keys.dedup();
+
+                let fetched = Metadata::fetch(s3, bucket, &planned.target, &keys, retry).await?;
+                Ok::<_, UtilError>((planned.target.to_string(), fetched))
+            })
+            .buffer_unordered(concurrency);
+
+        while let Some(fetched) = fetches.next().await {
+            let (target, fetched) = fetched?;
+            metadata.insert(target, fetched);
+        }
+    }
+
+    // totals across all targets, to summarize a dry run
+    summary.sources = sources;
+    for planned in &planned {
+        let size = planned.parts.iter().flatten().map(Piece::length).sum();
+        summary.target(&planned.target, size);
+    }
+
+    // split any targets over the part limit into stages
+    let stages = stages::stage(planned)?;
+
+    // cleanup removes every source, so check before copying anything
+    if args.is_present("cleanup") {
+        guard.check(sources)?;
+    }
+
+    // intermediates are always removed once the run is done with them
+    let intermediates = stages
+        .iter()
+        .flatten()
+        .filter(|planned| planned.intermediate)
+        .map(|planned| planned.target.to_string())
+        .collect::<HashSet<_>>();
+
+    // expected size and part count of every upload, to verify against
+    let expected = stages
+        .iter()
+        .flatten()
+        .map(|planned| {
+            let size = planned.parts.iter().flatten().map(Piece::length).sum();
+            (planned.target.to_string(), (size, planned.parts.len()))
+        })
+        .collect::<HashMap<String, (i64, usize)>>();
+
+    // pending uploads from an interrupted run, by target
+    let pending = if args.is_present("resume") {
+        resume::pending(&target_s3, &target_bucket, target_prefix.as_deref()).await?
+    } else {
+        HashMap::new()
+    };
+
+    // number of uploads which can be open at once (validated by clap)
+    let max_open_uploads = args
+        .value_of("max_open_uploads")
+        .map(|max| max.parse::<usize>().unwrap().max(1));
+
+    // everything needed to finish targets as they settle
+    let mut finishing = Finishing {
+        dry: dryrun,
+        source: (s3, bucket.as_str()),
+        target: (&target_s3, target_bucket.as_str()),
+        retry: &retry,
+        intermediates: &intermediates,
+        expected: &expected,
+        manifest,
+        records: &mut records,
+        completed: HashMap::new(),
+        failed,
+        broken: HashMap::new(),
+        mismatched: 0,
+        keep_going,
+        verify_e_tags: !args.is_present("no_verify"),
+        incremental,
+        cleanup: args.is_present("cleanup"),
+        delete_on_mismatch: args.is_present("delete_on_mismatch"),
+    };
+
+    let count = stages.len();
+
+    // each stage relies on all stages before it being complete
+    for (idx, stage) in stages.into_iter().enumerate() {
+        // log out the stage when there's more than one
+        if count > 1 {
+            info!("Starting stage {} of {}...", idx + 1, count);
+        }
+
+        // later stages only ever copy from intermediates
+        let (origin_s3, origin_bucket) = if idx == 0 {
+            (s3, &bucket)
+        } else {
+            (&target_s3, &target_bucket)
+        };
+
+        // targets relying on a failed intermediate can't be concatenated
+        let stage = skip_broken(&mut finishing, stage);
+
+        // target -> upload mappings for every upload still open
+        let mut targets: HashMap<String, String> = HashMap::new();
+        let mut uploads =
+            Uploads::new(dryrun, &target_s3, &target_bucket, &mut state, &mut targets)
+                .origin(origin_s3, origin_bucket)
+                .creation(creation.clone())
+                .metadata(&metadata)
+                .concurrency(concurrency)
+                .retry(retry.clone())
+                .keep_going(keep_going);
+
+        // send all parts, finishing each target as soon as it settles
+        let result = send_stage(
+            &mut uploads,
+            &mut finishing,
+            stage,
+            &pending,
+            max_open_uploads,
+        )
+        .await;
+
+        // in-flight copies must finish before anything is completed or aborted
+        let flushed = uploads.flush().await;
+
+        // finish everything left once the whole stage has been sent
+        let result = match result.and(flushed) {
+            Ok(()) => {
+                let settled = uploads.settled();
+                finish_targets(&mut finishing, uploads.state(), settled).await
+            }
+            Err(err) => Err(err),
+        };
+
+        // release the state borrowed by the uploads
+        drop(uploads);
+
+        // dry doesn't post-process
+        if dryrun {
+            continue;
+        }
+
+        // handle errors
+        if result.is_err() {
+            // abort every upload still open
+            for (key, upload_id) in &targets {
+                abort_request(
+                    &target_s3,
+                    key.to_string(),
+                    target_bucket.to_string(),
+                    upload_id.to_string(),
+                )
+                .await;
+                state.forget(upload_id);
+            }
+
+            // intermediates can't be used by anything else, so remove them
+            for (key, upload_id) in &finishing.completed {
+                if intermediates.contains(key) {
+                    remove_object(&target_s3, &target_bucket, key).await;
+                }
+                state.forget(upload_id);
+            }
+
+            // passthrough
+            return result;
+        }
+    }
+
+    // outcome of every target across the run
+    let Finishing {
+        completed,
+        mut failed,
+        mismatched,
+        ..
+    } = finishing;
+
+    // dry doesn't post-process
+    if dryrun {
+        summary.print(&bucket);
+
+        if args.is_present("cleanup") {
+            let batches =
+                (sources as usize + client::MAX_REMOVAL_BATCH - 1) / client::MAX_REMOVAL_BATCH;
+            info!(
+                "Cleanup would remove {} sources in {} batches",
+                sources, batches
+            );
+        }
+        return Ok(());
+    }
+
+    // release all state, and any intermediates
+    for (key, upload_id) in &completed {
+        if intermediates.contains(key) {
+            remove_object(&target_s3, &target_bucket, key).await;
+        }
+        state.forget(upload_id);
+    }
+
+    // list every failed target together, so none are lost in the logs
+    if !failed.is_empty() {
+        failed.sort();
+        error!("Failed to concatenate {} targets:", failed.len());
+
+        for (key, err) in &failed {
+            error!("  {}: {}", key, err);
+        }
+    }
+
+    // targets failing verification fail the run
+    if mismatched > 0 {
+        return Err(format!("Unable to verify {} concatenated targets", mismatched).into());
+    }
+
+    // as do any other targets which failed
+    if !failed.is_empty() {
+        return Err(format!("Unable to concatenate {} targets", failed.len()).into());
+    }
+
+    Ok(())
+}
+
+/// Constructs the request used as a template to create each upload.
+///
+/// All settings are logged out, so they can be verified in a dry run.
+fn creation_from_args(args: &ArgMatches<'_>) -> UtilResult<CreateMultipartUploadRequest> {
+    // tags are validated by clap, so should be safe to unwrap
+    let tagged = args
+        .values_of("tag")
+        .into_iter()
+        .flatten()
+        .map(|tag| tags::parse(tag).unwrap())
+        .collect::<Vec<_>>();
+
+    // keys must be unique within an object
+    let mut keys = HashSet::new();
+
+    if let Some((key, _)) = tagged.iter().find(|(key, _)| !keys.insert(*key)) {
+        return Err(format!("Tag {} can only be provided once", key).into());
+    }
+
+    let creation = CreateMultipartUploadRequest {
+        acl: args.value_of("acl").map(str::to_string),
+        content_type: args.value_of("content_type").map(str::to_string),
+        server_side_encryption: args.value_of("sse").map(str::to_string),
+        ssekms_key_id: args.value_of("sse_kms_key_id").map(str::to_string),
+        storage_class: args.value_of("storage_class").map(str::to_string),
+        tagging: Some(tags::encode(&tagged)).filter(|_| !tagged.is_empty()),
+        ..CreateMultipartUploadRequest::default()
+    };
+
+    // KMS keys can only be used with KMS encryption
+    if creation.ssekms_key_id.is_some()
+        && creation.server_side_encryption.as_deref() != Some("aws:kms")
+    {
+        return Err("A KMS key can only be provided when using aws:kms encryption".into());
+    }
+
+    if let Some(ref content_type) = creation.content_type {
+        info!("Creating targets with content type {}", content_type);
+    }
+
+    if let Some(ref acl) = creation.acl {
+        info!("Creating targets with ACL {}", acl);
+    }
+
+    if let Some(ref storage_class) = creation.storage_class {
+        info!("Creating targets with storage class {}", storage_class);
+    }
+
+    if !tagged.is_empty() {
+        let described = tagged
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+
+        info!("Creating targets with tags {}", described.join(", "));
+    }
+
+    if let Some(ref encryption) = creation.server_side_encryption {
+        match creation.ssekms_key_id {
+            Some(ref key_id) => info!(
+                "Creating targets with {} encryption ({})",
+                encryption, key_id
+            ),
+            None => info!("Creating targets with {} encryption", encryption),
+        }
+    }
+
+    Ok(creation)
+}
+
+/// Determines the target used for listed sources without their own target.
+///
+/// When sources are listed, a single pattern on the command line is taken
+/// as the target, as there's no need for a source pattern.
+fn default_target<'a>(args: &'a ArgMatches<'_>) -> Option<&'a str> {
+    args.value_of("target").or_else(|| args.value_of("source"))
+}
+
+/// Determines the literal prefix shared by all targets, to purge beneath.
+///
+/// Listed sources can each name their own target, so there's no prefix
+/// shared between them and the entire bucket is covered instead.
+fn target_prefix(args: &ArgMatches<'_>, listed: bool) -> UtilResult<Option<String>> {
+    let template = match args.value_of("group_by_prefix") {
+        // validated by clap, so should be safe to unwrap
+        Some(_) => groups::template(args.value_of("target_template").unwrap()),
+        None if listed => return Ok(None),
+        None => args.value_of("target").unwrap().to_string(),
+    };
+
+    let prefix = DatedTemplate::parse(&template, date_source(args))?
+        .prefix()
+        .to_string();
+
+    Ok(Some(prefix).filter(|prefix| !prefix.is_empty()))
+}
+
+/// Determines the source of bare date directives in the target, if any.
+fn date_source(args: &ArgMatches<'_>) -> Option<DateSource> {
+    match args.value_of("date_source") {
+        Some("now") => Some(DateSource::Now),
+        Some("newest") => Some(DateSource::Newest),
+        Some("oldest") => Some(DateSource::Oldest),
+        _ => None,
+    }
+}
+
+/// Constructs the options used to plan all targets.
+fn planning_from_args(args: &ArgMatches<'_>, in_place: bool) -> UtilResult<Planning> {
+    let planning = Planning {
+        limits: Limits::from_args(args, "sources")?,
+        exclude: RegexSet::new(args.values_of("exclude").into_iter().flatten())?,
+        max_sources: args
+            .value_of("max_sources")
+            .map(|max| max.parse::<usize>().unwrap().max(1)),
+        // validated by clap, so should be safe to unwrap
+        separator: args
+            .value_of("separator")
+            .map(|separator| cli::parse_bytes(separator).unwrap()),
+        pack: args.is_present("pack_small"),
+        in_place,
+    };
+
+    Ok(planning)
+}
+
+/// Sends all planned parts of a stage into the uploads for their targets.
+///
+/// Each target is finished as soon as it settles, rather than once the whole
+/// stage has been sent, and no target is started while the maximum number of
+/// uploads are open. Any failure is passed to the uploads, which decide
+/// whether it fails the whole stage or only the target it happened in.
+async fn send_stage(
+    uploads: &mut Uploads<'_>,
+    finishing: &mut Finishing<'_>,
+    stage: Vec<Planned>,
+    pending: &HashMap<String, String>,
+    max_open_uploads: Option<usize>,
+) -> UtilResult<()> {
+    for planned in stage {
+        // wait for targets to settle while too many uploads are open
+        while max_open_uploads.map_or(false, |max| uploads.open() >= max) {
+            if !uploads.wait().await? {
+                break;
+            }
+
+            let settled = uploads.settled();
+            finish_targets(finishing, uploads.state(), settled).await?;
+        }
+
+        let target = planned.target.to_string();
+
+        if let Err(err) = send_target(uploads, planned, pending, finishing.dry).await {
+            uploads.fail(&target, err)?;
+        }
+
+        uploads.sent(&target);
+
+        // finish anything which has settled in the meantime
+        let settled = uploads.settled();
+        finish_targets(finishing, uploads.state(), settled).await?;
+    }
+    Ok(())
+}
+
+/// Sends all planned parts of a single target into its upload.
+///
+/// Dry runs also log the totals of each target, once all parts are logged.
+async fn send_target(
+    uploads: &mut Uploads<'_>,
+    planned: Planned,
+    pending: &HashMap<String, String>,
+    dry: bool,
+) -> UtilResult<()> {
+    uploads
+        .prepare(&planned, pending.get(&planned.target))
+        .await?;
+
+    let size = planned
+        .parts
+        .iter()
+        .flatten()
+        .map(Piece::length)
+        .sum::<i64>();
+    let count = planned.parts.len();
+
+    for pieces in planned.parts {
+        uploads.concat(pieces, &planned.target).await?;
+    }
+
+    if dry {
+        info!(
+            "Planned {} with {} parts ({} bytes)",
+            planned.target, count, size
+        );
+    }
+    Ok(())
+}
+
+/// Finishes the uploads of settled targets.
+///
+/// Failed targets are aborted, and all others are completed and verified
+/// before their sources are recorded (when incremental) and removed (when
+/// cleaning up). An error is only returned when the run can't continue, in
+/// which case any settled upload not yet completed has been aborted.
+async fn finish_targets(
+    finishing: &mut Finishing<'_>,
+    state: &mut State,
+    settled: Settled,
+) -> UtilResult<()> {
+    // dry doesn't post-process
+    if finishing.dry {
+        return Ok(());
+    }
+
+    let (target_s3, target_bucket) = finishing.target;
+    let intermediates = finishing.intermediates;
+    let mut result = Ok(());
+
+    // abort only the uploads of targets which failed
+    for (key, upload_id, err) in settled.failures {
+        if let Some(upload_id) = upload_id {
+            abort_request(
+                target_s3,
+                key.to_string(),
+                target_bucket.to_string(),
+                upload_id.to_string(),
+            )
+            .await;
+            state.forget(&upload_id);
+        }
+
+        result = result.and(finishing.fail(&key, err.to_string()));
+    }
+
+    // every upload settled, to verify once complete
+    let targets = settled.uploads;
+    let finished = targets.keys().cloned().collect::<Vec<_>>();
+
+    // complete the uploads, or abort all of them
+    match result {
+        Ok(()) => complete_stage(finishing, state, targets).await?,
+        Err(err) => {
+            for (key, upload_id) in &targets {
+                abort_request(
+                    target_s3,
+                    key.to_string(),
+                    target_bucket.to_string(),
+                    upload_id.to_string(),
+                )
+                .await;
+                state.forget(upload_id);
+            }
+            return Err(err);
+        }
+    }
+
+    // verify every upload completed
+    let mut verified = Ok(());
+
+    for key in &finished {
+        // skip anything which failed to complete (already recorded)
+        if !finishing.completed.contains_key(key) {
+            continue;
+        }
+
+        // composite ETags are predicted from every part
+        let e_tag = if finishing.verify_e_tags {
+            state
+                .parts(&finishing.completed[key])
+                .ok()
+                .and_then(composite_e_tag)
+        } else {
+            None
+        };
+
+        let (size, parts) = finishing.expected[key];
+        let verification = verify(
+            finishing.target,
+            finishing.retry,
+            key,
+            size,
+            parts,
+            e_tag.as_deref(),
+        )
+        .await;
+
+        // targets which can't be read may be fine, so are never removed
+        let err = match verification {
+            Ok(None) => continue,
+            Ok(Some(mismatch)) => UtilError::from(mismatch),
+            Err(err) => {
+                error!("Unable to verify {}: {}", key, err);
+                finishing.manifest.fail(key, &err.to_string());
+                verified = Err(err);
+                continue;
+            }
+        };
+
+        error!("{}", err);
+
+        // nothing can rely on the target, including cleanup
+        if let Some(upload_id) = finishing.completed.remove(key) {
+            state.forget(&upload_id);
+        }
+
+        // intermediates are never left behind, as nothing can use them
+        let is_intermediate = intermediates.contains(key);
+
+        if is_intermediate || finishing.delete_on_mismatch {
+            remove_object(target_s3, target_bucket, key).await;
+        }
+
+        if !is_intermediate {
+            finishing.mismatched += 1;
+        }
+
+        verified = verified.and(finishing.fail(key, err.to_string()));
+    }
+
+    verified?;
+
+    // record all merged sources, so later runs can skip them
+    if finishing.incremental {
+        let recorded = cleanup_sources(state, &finishing.completed, intermediates, &finished)?;
+
+        for (key, merged_sources) in recorded {
+            let mut record = finishing.records.remove(key).unwrap_or_default();
+            record.extend(merged_sources);
+
+            merged::write(target_s3, target_bucket, key, &record).await?;
+        }
+    }
+
+    // only cleanup when explicit
+    if !finishing.cleanup {
+        return Ok(());
+    }
+
+    // only the sources of completed (and verified) targets are removed
+    let cleaned = cleanup_sources(state, &finishing.completed, intermediates, &finished)?;
+
+    // ranged sources can span parts, so only remove once
+    let mut removed = HashSet::new();
+    let mut removals = Vec::new();
+
+    for (_, concatenated) in &cleaned {
+        for source in concatenated {
+            if removed.insert(source) {
+                removals.push(source.to_string());
+            }
+        }
+    }
+
+    // remove everything in batches, noting anything left behind
+    let (s3, bucket) = finishing.source;
+    let failed = client::remove_objects(s3, bucket, &removals, finishing.retry, None).await;
+
+    for (key, sources) in cleaned {
+        if !sources.iter().any(|source| failed.contains_key(source)) {
+            finishing.manifest.clean(key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Completes the uploads of settled targets.
+///
+/// Any upload which can't be completed is aborted, and recorded as failed
+/// alongside the error (which only fails the run for an intermediate, when
+/// not keeping going). Each completed upload is added to the completed
+/// mappings, and the outcome of each upload is recorded in the manifest.
+async fn complete_stage(
+    finishing: &mut Finishing<'_>,
+    state: &mut State,
+    targets: HashMap<String, String>,
+) -> UtilResult<()> {
+    let (s3, bucket) = finishing.target;
+    let mut result = Ok(());
+
+    // attempt to complete all requests
+    for (key, upload_id) in targets {
+        // log out to be user friendly...
+        info!("Completing {}...", upload_id);
+
+        // retrieve all parts recorded against the upload
+        let mut parts = match state.parts(&upload_id) {
+            Ok(parts) => parts,
+            Err(err) => {
+                // if we can't read the parts, tell the user to help out
+                error!("Unable to read pending parts for {}: {}", upload_id, err);
+
+                // gotta abort
+                abort_request(
+                    s3,
+                    key.to_string(),
+                    bucket.to_string(),
+                    upload_id.to_string(),
+                )
+                .await;
+
+                // move on
+                state.forget(&upload_id);
+
+                let err = format!("Unable to read pending parts: {}", err);
+                result = result.and(finishing.fail(&key, err));
+                continue;
+            }
+        };
+
+        // parts may have been recorded out of order
+        parts.sort_by_key(|part| part.number);
+
+        // buffer up all completed parts
+        let completed_parts = parts
+            .into_iter()
+            .map(|part| CompletedPart {
+                e_tag: part.e_tag,
+                part_number: Some(part.number),
+            })
+            .collect();
+
+        // create our multipart completion body
+        let multipart = CompletedMultipartUpload {
+            parts: Some(completed_parts),
+        };
+
+        // create our multipart completion request
+        let complete = CompleteMultipartUploadRequest {
+            key: key.to_string(),
+            bucket: bucket.to_string(),
+            upload_id: upload_id.to_string(),
+            multipart_upload: Some(multipart),
+            ..CompleteMultipartUploadRequest::default()
+        };
+
+        // attempt to complete each request, abort on fail (can't short circut)
+        let completion = finishing
+            .retry
+            .run("upload completion", || {
+                s3.complete_multipart_upload(complete.clone())
+            })
+            .await;
+
+        let output = match completion {
+            Ok(output) => output,
+            Err(err) => {
+                let err = UtilError::from(err);
+                error!("Unable to complete {}: {}", key, err);
+
+                // remove the upload sources
+                state.forget(&upload_id);
+
+                // abort now!
+                abort_request(
+                    s3,
+                    key.to_string(),
+                    bucket.to_string(),
+                    upload_id.to_string(),
+                )
+                .await;
+
+                result = result.and(finishing.fail(&key, err.to_string()));
+                continue;
+            }
+        };
+
+        finishing.manifest.complete(&key, output.e_tag);
+        finishing.completed.insert(key, upload_id);
+    }
+
+    result
+}
+
+/// Fails every target relying on a failed intermediate, keeping the rest.
+///
+/// Intermediates relying on a failed intermediate (when there are several
+/// stages) fail in the same way, so their own targets are failed later on.
+fn skip_broken(finishing: &mut Finishing<'_>, stage: Vec<Planned>) -> Vec<Planned> {
+    if finishing.broken.is_empty() {
+        return stage;
+    }
+
+    let mut remaining = Vec::with_capacity(stage.len());
+
+    for planned in stage {
+        let broken = planned.parts.iter().flatten().find_map(|piece| {
+            let err = finishing.broken.get(&piece.key)?;
+            Some((&piece.key, err))
+        });
+
+        let err = match broken {
+            Some((key, err)) => format!("Unable to complete intermediate {}: {}", key, err),
+            None => {
+                remaining.push(planned);
+                continue;
+            }
+        };
+
+        error!("Unable to concatenate {}: {}", planned.target, err);
+        finishing.manifest.fail(&planned.target, &err);
+
+        if planned.intermediate {
+            finishing.broken.insert(planned.target, err);
+        } else {
+            finishing.failed.push((planned.target, err));
+        }
+    }
+
+    remaining
+}
+
+/// Verifies a completed target against the size and part count expected.
+///
+/// The part count is read from the multipart suffix of the ETag, and will
+/// only be checked when the ETag has one (S3 compatible stores may not).
+/// If an ETag is expected, the ETag of the target must match it exactly.
+///
+/// Any mismatch is returned as a message, so it can be told apart from a
+/// failure to fetch the target (which says nothing about its contents).
+async fn verify(
+    target: (&S3Client, &str),
+    retry: &Retry,
+    key: &str,
+    size: i64,
+    parts: usize,
+    e_tag: Option<&str>,
+) -> UtilResult<Option<String>> {
+    let (s3, bucket) = target;
+
+    // fetch the completed target
+    let request = HeadObjectRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        ..HeadObjectRequest::default()
+    };
+
+    let head = retry
+        .run("verify", || s3.head_object(request.clone()))
+        .await?;
+
+    // compare the size against the sum of all sources
+    let length = head.content_length.unwrap_or_default();
+    if length != size {
+        return Ok(Some(format!(
+            "Target {} has a size of {} bytes, but expected {} bytes",
+            key, length, size
+        )));
+    }
+
+    // multipart ETags are suffixed with the number of parts
+    let count = head
+        .e_tag
+        .as_deref()
+        .map(|e_tag| e_tag.trim_matches('"'))
+        .and_then(|e_tag| e_tag.rsplit_once('-'))
+        .and_then(|(_, count)| count.parse::<usize>().ok());
+
+    if let Some(count) = count.filter(|count| *count != parts) {
+        return Ok(Some(format!(
+            "Target {} has {} parts, but expected {} parts",
+            key, count, parts
+        )));
+    }
+
+    // compare the ETag against the one predicted from all parts
+    let actual = head.e_tag.as_deref().unwrap_or_default().trim_matches('"');
+
+    Ok(match e_tag {
+        Some(e_tag) if e_tag != actual => Some(format!(
+            "Target {} has an ETag of {}, but expected {}",
+            key, actual, e_tag
+        )),
+        _ => None,
+    })
+}
+
+/// Predicts the ETag of a completed upload from the ETags of its parts.
+///
+/// Multipart ETags are the MD5 of the MD5 of every part, suffixed with the
+/// number of parts. If any part lacks an MD5 based ETag, nothing can be
+/// predicted and `None` is returned.
+fn composite_e_tag(mut parts: Vec<Part>) -> Option<String> {
+    let mut digests = Vec::with_capacity(parts.len() * 16);
+
+    // parts may have been recorded out of order
+    parts.sort_by_key(|part| part.number);
+
+    for part in &parts {
+        let e_tag = part.e_tag.as_deref()?.trim_matches('"');
+
+        if e_tag.len() != 32 || !e_tag.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        for idx in (0..e_tag.len()).step_by(2) {
+            digests.push(u8::from_str_radix(&e_tag[idx..idx + 2], 16).ok()?);
+        }
+    }
+
+    Some(format!("{:x}-{}", md5::compute(&digests), parts.len()))
+}
+
+/// Resolves the sources of finished targets, to be removed by cleanup.
+///
+/// Targets are only ever present in the completed mappings once their upload
+/// has been completed and verified, so the sources of any target which failed
+/// along the way are never included (and are left in place for a retry).
+fn cleanup_sources<'a>(
+    state: &State,
+    completed: &HashMap<String, String>,
+    intermediates: &HashSet<String>,
+    finished: &'a [String],
+) -> UtilResult<Vec<(&'a str, Vec<String>)>> {
+    let mut cleaned = Vec::new();
+
+    // iterate all final targets
+    for key in finished {
+        if intermediates.contains(key) {
+            continue;
+        }
+
+        let upload_id = match completed.get(key) {
+            Some(upload_id) => upload_id,
+            None => continue,
+        };
+
+        let mut sources = resolve_sources(state, completed, intermediates, upload_id)?;
 
-    // dry doesn't post-process
-    if dryrun {
-        return Ok(());
+        // appended targets are a source of themselves
+        sources.retain(|source| source != key);
+
+        cleaned.push((key.as_str(), sources));
     }
 
-    // handle errors
-    if result.is_err() {
-        // try to abort all requests
-        for (key, upload_id) in targets {
-            abort_request(
-                &s3,
-                key.to_string(),
-                bucket.to_string(),
-                upload_id.to_string(),
-            )
-            .await;
-        }
+    // keep removals in a stable order
+    cleaned.sort();
+
+    Ok(cleaned)
+}
 
-        // passthrough
-        return result;
+/// Resolves the original sources of a completed upload.
+///
+/// Sources which are intermediates are resolved through the upload used to
+/// create them, so the sources of a staged target can be removed.
+fn resolve_sources(
+    state: &State,
+    completed: &HashMap<String, String>,
+    intermediates: &HashSet<String>,
+    upload_id: &str,
+) -> UtilResult<Vec<String>> {
+    let mut sources = Vec::new();
+    let mut direct = state
+        .parts(upload_id)?
+        .into_iter()
+        .flat_map(|part| part.sources)
+        .collect::<Vec<_>>();
+
+    // intermediates are sliced across consecutive parts
+    direct.dedup();
+
+    for source in direct {
+        match completed.get(&source) {
+            Some(nested) if intermediates.contains(&source) => {
+                sources.extend(resolve_sources(state, completed, intermediates, nested)?);
+            }
+            _ => sources.push(source),
+        }
     }
 
-    // attempt to complete all requests
-    for (key, upload_id) in targets {
-        // log out to be user friendly...
-        info!("Completing {}...", upload_id);
+    Ok(sources)
+}
 
-        // create a request to list parts buffer
-        let parts = ListPartsRequest {
-            key: key.to_string(),
-            bucket: bucket.to_string(),
-            upload_id: upload_id.to_string(),
-            ..ListPartsRequest::default()
-        };
+/// Groups all sources by target, based on walking the S3 tree.
+///
+/// Sources are first grouped by target and sorted into the requested order,
+/// and nothing is planned until the walk has finished. This allows any date
+/// tokens to be resolved once each group is complete, before the targets are
+/// planned. Sources which are excluded or outside of any size limits are
+/// skipped entirely, as if they had never matched (but are still counted
+/// into the summary).
+async fn walk_sources(
+    pattern: Regex,
+    mut walker: ObjectWalker<'_>,
+    template: &DatedTemplate,
+    order: &Order,
+    planning: &Planning,
+    summary: &mut Summary,
+) -> UtilResult<Ordered> {
+    // create a renderer to map sources to targets
+    let mut renderer = Renderer::new(&pattern, template.template());
 
-        // carry out the request for the parts list
-        let parts_result = s3.list_parts(parts).await;
+    // groups of sources for targets pending resolution
+    let mut groups: Vec<Group> = Vec::new();
+    let mut indices: HashMap<Arc<str>, usize> = HashMap::new();
 
-        // attempt to list the pending parts
-        if let Err(err) = parts_result {
-            // if we can't list the parts, tell the user to help out
-            error!("Unable to list pending parts for {}: {}", upload_id, err);
+    // iterate all objects in the remo
+    while let Some(object) = walker.next().await? {
+        // unwrap the source key
+        let key = object.key.unwrap();
 
-            // gotta abort
-            abort_request(
-                &s3,
-                key.to_string(),
-                bucket.to_string(),
-                upload_id.to_string(),
-            )
-            .await;
+        // render the target, skipping non-matching files
+        let full_target = match renderer.render(&key) {
+            Some(full_target) => full_target,
+            None => continue,
+        };
 
-            // move on
+        // skip matching sources which have been excluded
+        if planning.exclude.is_match(&key) {
+            debug!("Skipping {} as it matches an exclusion", key);
+            summary.excluded += 1;
             continue;
         }
 
-        // buffer up all completed parts
-        let completed = parts_result
-            .unwrap()
-            .parts
-            .unwrap()
-            .into_iter()
-            .map(|part| CompletedPart {
-                e_tag: part.e_tag,
-                part_number: part.part_number,
-            })
-            .collect();
+        // skip sources outside of the size limits
+        let size = object.size.unwrap();
+        if !planning.limits.is_within_size(size) {
+            debug!("Skipping {} ({} bytes) outside of size limits", key, size);
+            summary.outside_size += 1;
+            continue;
+        }
 
-        // create our multipart completion body
-        let multipart = CompletedMultipartUpload {
-            parts: Some(completed),
-        };
+        // skip sources outside of the modification window
+        let modified = parse_modified(object.last_modified.as_deref());
+        if !planning.limits.is_within_window(modified) {
+            debug!(
+                "Skipping {} (modified {}) outside of modification window",
+                key,
+                object.last_modified.as_deref().unwrap_or("never")
+            );
+            summary.outside_window += 1;
+            continue;
+        }
 
-        // create our multipart completion request
-        let complete = CompleteMultipartUploadRequest {
-            key: key.to_string(),
-            bucket: bucket.to_string(),
-            upload_id: upload_id.to_string(),
-            multipart_upload: Some(multipart),
-            ..CompleteMultipartUploadRequest::default()
+        // targets dated by their sources can't place a source without a date
+        let date = match object.last_modified.as_deref() {
+            _ if !template.needs_dates() => None,
+            Some(date) if modified.is_some() => Some(date),
+            _ => {
+                warn!("Skipping {} as it has no valid modification date", key);
+                continue;
+            }
         };
 
-        // attempt to complete each request, abort on fail (can't short circut)
-        if s3.complete_multipart_upload(complete).await.is_err() {
-            // remove the upload sources
-            sources.remove(&key);
+        // locate the group for the pending target
+        let idx = *indices.entry(full_target.clone()).or_insert_with(|| {
+            groups.push(Group {
+                pending: full_target,
+                range: DateRange::default(),
+                sources: Vec::new(),
+            });
+            groups.len() - 1
+        });
 
-            // abort now!
-            abort_request(
-                &s3,
-                key.to_string(),
-                bucket.to_string(),
-                upload_id.to_string(),
+        // include the source in the group
+        let group = &mut groups[idx];
+
+        if let Some(date) = date {
+            group.range.include(date)?;
+        }
+
+        group.sources.push(Source {
+            key,
+            size,
+            modified,
+        });
+    }
+
+    // resolve all targets, checking for conflicts
+    let mut resolved = Vec::with_capacity(groups.len());
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (idx, group) in groups.iter().enumerate() {
+        // resolve the final target name for the group
+        let target = template.resolve(&group.pending, &group.range);
+
+        // two groups can't be concatenated into the same target
+        if let Some(other) = seen.insert(target.clone(), idx) {
+            return Err(format!(
+                "Sources {} and {} resolve to the same target: {}",
+                groups[other].sources[0].key, group.sources[0].key, target
             )
-            .await;
+            .into());
         }
+
+        resolved.push(target);
     }
 
-    // only cleanup when explicit
-    if !args.is_present("cleanup") {
-        return result;
+    // order the sources of each target
+    let mut ordered = Vec::with_capacity(resolved.len());
+
+    for (mut group, target) in groups.into_iter().zip(resolved) {
+        // listing order isn't guaranteed, so sort to keep parts stable
+        order.sort(&mut group.sources)?;
+        ordered.push((target, group.sources));
     }
 
-    // iterate all upload sources
-    for keys in sources.values() {
-        // iterate all concat'ed
-        for key in keys {
-            // print that we're removing
-            info!("Removing {}...", key);
+    Ok(ordered)
+}
+
+/// Looks up all listed sources, keeping the order they were listed in.
+///
+/// Every listed source is looked up before anything is planned, so that a
+/// missing source fails the plan rather than the upload it belongs to. As
+/// the sources were chosen explicitly, no filters, exclusions or limits are
+/// applied.
+async fn list_sources(s3: &S3Client, bucket: &str, listed: &[Listed]) -> UtilResult<Ordered> {
+    let mut ordered = Vec::with_capacity(listed.len());
+
+    for listed in listed {
+        let mut target_sources = Vec::with_capacity(listed.keys.len());
 
-            // create the removal request
-            let delete = DeleteObjectRequest {
-                key: key.to_string(),
-                bucket: bucket.to_string(),
-                ..DeleteObjectRequest::default()
+        for key in &listed.keys {
+            let head = match client::head(s3, bucket, key, None).await? {
+                Some(head) => head,
+                None => return Err(format!("Listed source {} does not exist", key).into()),
             };
 
-            // attemp to remove the objects from S3
-            if s3.delete_object(delete).await.is_err() {
-                error!("Unable to remove {}", key);
-            }
+            // HEAD responses carry dates in the RFC 2822 format
+            let modified = head
+                .last_modified
+                .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+                .map(|date| date.with_timezone(&Utc));
+
+            target_sources.push(Source {
+                key: key.to_string(),
+                size: head.content_length.unwrap_or_default(),
+                modified,
+            });
         }
+
+        ordered.push((listed.target.to_string(), target_sources));
     }
 
-    Ok(())
+    Ok(ordered)
 }
 
-/// Constructs all upload requests based on walking the S3 tree.
+/// Locates all sources which don't already end with a separator.
 ///
-/// This will populate the provided mappings, as they're using in the main
-/// function for error handling (this allows us to use ? in this function).
-async fn construct_uploads(
-    dry: bool,
+/// Only the tail of each source is fetched, using a ranged GET. Empty
+/// sources have nothing to separate, so they're never included. Tails are
+/// fetched concurrently, with the same retries and rate limit as copies.
+async fn find_unterminated(
     s3: &S3Client,
-    pattern: Regex,
-    sources: &mut HashMap<String, HashSet<String>>,
-    targets: &mut HashMap<String, String>,
-    mut walker: ObjectWalker<'_>,
-    mapping: (&str, &str),
-) -> UtilResult<()> {
-    // unpack the mapping tuple
-    let (bucket, target) = mapping;
+    bucket: &str,
+    ordered: &[(String, Vec<Source>)],
+    separator: &[u8],
+    retry: &Retry,
+    concurrency: usize,
+) -> UtilResult<HashSet<String>> {
+    let mut unterminated = HashSet::new();
 
-    // iterate all objects in the remo
-    while let Some(object) = walker.next().await? {
-        // unwrap the source key
-        let key = object.key.unwrap();
+    let sources = ordered
+        .iter()
+        .flat_map(|(_, sources)| sources)
+        .filter(|source| source.size > 0);
 
-        // skip non-matching files
-        if !pattern.is_match(&key) {
-            continue;
-        }
+    let mut tails = stream::iter(sources)
+        .map(|source| async move {
+            let tail = fetch_tail(s3, bucket, source, separator.len(), retry).await?;
+            Ok::<_, UtilError>((source, tail))
+        })
+        .buffer_unordered(concurrency);
+
+    while let Some(fetched) = tails.next().await {
+        let (source, tail) = fetched?;
 
-        // AWS doesn't let us concat < 5MB
-        if object.size.unwrap() < 5_000_000 {
-            return Err(format!("Unable to concat files below 5MB: {}", key).into());
+        if !tail.ends_with(separator) {
+            debug!("Adding a separator after {}", source.key);
+            unterminated.insert(source.key.to_string());
         }
+    }
+
+    Ok(unterminated)
+}
+
+/// Fetches up to the final `length` bytes of a source.
+async fn fetch_tail(
+    s3: &S3Client,
+    bucket: &str,
+    source: &Source,
+    length: usize,
+    retry: &Retry,
+) -> UtilResult<Vec<u8>> {
+    let start = (source.size - length as i64).max(0);
+    let request = GetObjectRequest {
+        bucket: bucket.to_string(),
+        key: source.key.to_string(),
+        range: Some(format!("bytes={}-{}", start, source.size - 1)),
+        ..GetObjectRequest::default()
+    };
+
+    let tail = retry
+        .run("separator check", || {
+            let request = request.clone();
+
+            async move {
+                let object = s3.get_object(request).await?;
+                let mut tail = Vec::with_capacity(length);
+
+                if let Some(mut body) = object.body {
+                    while let Some(chunk) = body.next().await {
+                        // a broken body is retried just like a failed request
+                        let chunk = chunk.map_err(|err| {
+                            RusotoError::HttpDispatch(HttpDispatchError::new(err.to_string()))
+                        })?;
+                        tail.extend_from_slice(&chunk);
+                    }
+                }
+
+                Ok::<_, RusotoError<GetObjectError>>(tail)
+            }
+        })
+        .await?;
 
-        // format the source path, as well as the target
-        let part_source = format!("{}/{}", bucket, key);
-        let full_target = pattern
-            .replace_all(&key, target.to_string().as_str())
-            .to_string();
+    Ok(tail)
+}
+
+/// Plans the parts of each target from its ordered sources.
+///
+/// Part sizes are checked against each position (or small sources are packed
+/// together, when packing is enabled). As nothing is copied during planning,
+/// errors here don't leave anything behind. Any unterminated source has a
+/// separator planned after it, packed alongside a neighbouring source.
+///
+/// If a maximum number of sources is provided, any target with more sources
+/// is split into numbered targets (`target.1`, `target.2`, etc.) after being
+/// ordered, so the split points follow the order of the sources.
+///
+/// The number of sources to be concatenated is returned alongside the plan.
+fn plan_targets(
+    ordered: Ordered,
+    planning: &Planning,
+    unterminated: &HashSet<String>,
+    summary: &mut Summary,
+) -> UtilResult<(u64, Vec<Planned>)> {
+    // number of sources concatenated
+    let mut sources = 0;
+
+    // split any targets with more sources than the maximum
+    let mut targets = Vec::with_capacity(ordered.len());
 
+    for (target, mut target_sources) in ordered {
         // don't concat into self
-        if full_target == key {
+        if planning.in_place {
+            let count = target_sources.len();
+            target_sources.retain(|source| source.key != target);
+            summary.self_targets += (count - target_sources.len()) as u64;
+        }
+
+        let max = match planning.max_sources {
+            Some(max) if target_sources.len() > max => max,
+            _ => {
+                targets.push((target, target_sources));
+                continue;
+            }
+        };
+
+        info!(
+            "Splitting {} into {} targets of up to {} sources",
+            target,
+            (target_sources.len() + max - 1) / max,
+            max
+        );
+
+        let mut remaining = target_sources;
+        let mut count = 0;
+
+        // split the sources into numbered targets, in order
+        while !remaining.is_empty() {
+            let rest = remaining.split_off(max.min(remaining.len()));
+            let chunk = mem::replace(&mut remaining, rest);
+
+            count += 1;
+            let split = format!("{}.{}", target, count);
+
+            info!("Starting {} at {}", split, chunk[0].key);
+            targets.push((split, chunk));
+        }
+    }
+
+    // numbered targets can collide with other targets
+    let mut seen = HashSet::new();
+
+    for (target, _) in &targets {
+        if !seen.insert(target) {
+            return Err(format!("Target {} is produced more than once", target).into());
+        }
+    }
+
+    // validate the sources of each target before copying anything
+    for (_, target_sources) in &targets {
+        // packed parts are always large enough
+        if planning.is_packed(target_sources, unterminated) {
             continue;
         }
 
-        // log out exactly what we're concatenating right now
-        info!("Concatenating {} -> {}", key, full_target);
+        // AWS doesn't let us concat < 5MiB, except in the final part
+        if let Some((_, leading)) = target_sources.split_last() {
+            if let Some(small) = leading.iter().find(|s| s.size < MIN_PART_SIZE) {
+                return Err(format!(
+                    "Unable to concat files below 5MiB (except as the final part): {}",
+                    small.key
+                )
+                .into());
+            }
+        }
+    }
+
+    // plan the parts of all sources within their resolved targets
+    let mut planned = Vec::with_capacity(targets.len());
 
-        // skip
-        if dry {
+    for (target, target_sources) in targets {
+        sources += target_sources.len() as u64;
+
+        // decide before the sources are consumed into pieces
+        let packed = planning.is_packed(&target_sources, unterminated);
+
+        // plan the pieces of the target, with any separators
+        let mut pieces = Vec::with_capacity(target_sources.len());
+
+        for source in target_sources {
+            let terminate = unterminated.contains(&source.key);
+
+            pieces.push(Piece::whole(source.key, source.size));
+
+            if let (true, Some(separator)) = (terminate, &planning.separator) {
+                pieces.push(Piece::inline(separator.clone()));
+            }
+        }
+
+        // plan the parts of the target
+        let parts = if packed {
+            pack::plan(pieces, MIN_PART_SIZE)
+        } else {
+            pieces.into_iter().map(|piece| vec![piece]).collect()
+        };
+
+        planned.push(Planned {
+            target,
+            parts: split_parts(parts),
+            intermediate: false,
+        });
+    }
+
+    // happy
+    Ok((sources, planned))
+}
+
+/// Counts the distinct sources concatenated into a planned target.
+fn count_sources(planned: &Planned) -> u64 {
+    let sources = planned
+        .parts
+        .iter()
+        .flatten()
+        .filter(|piece| !piece.is_inline())
+        .map(|piece| piece.key.as_str())
+        .collect::<HashSet<_>>();
+
+    sources.len() as u64
+}
+
+/// Splits any part of a single piece over the copy limit into ranged parts.
+fn split_parts(parts: Vec<Vec<Piece>>) -> Vec<Vec<Piece>> {
+    let mut split = Vec::with_capacity(parts.len());
+
+    for pieces in parts {
+        if pieces.len() > 1 || pieces[0].length() <= MAX_COPY_SIZE {
+            split.push(pieces);
             continue;
         }
 
-        // ensure we have an upload identifier
-        if !targets.contains_key(&full_target) {
-            // initialize the upload request as needed
-            let creation = CreateMultipartUploadRequest {
-                bucket: bucket.to_string(),
-                key: full_target.to_string(),
-                ..CreateMultipartUploadRequest::default()
-            };
+        let ranges = pieces[0].split(MAX_COPY_SIZE);
+        info!(
+            "Splitting {} into {} parts",
+            pieces[0].describe(),
+            ranges.len()
+        );
+        split.extend(ranges.into_iter().map(|piece| vec![piece]));
+    }
+
+    split
+}
+
+/// Prepends an existing target onto its own plan, so it can be appended onto.
+///
+/// The target is copied as the leading parts of the upload (split into
+/// ranges when over the copy limit), pinned to its ETag so that a target
+/// overwritten in the meantime fails the upload rather than being lost. As
+/// these parts come first, a target below 5MiB can only be appended onto by
+/// planning it as the leading piece of a packed part.
+fn append_onto(
+    planned: &mut Planned,
+    size: i64,
+    e_tag: Option<String>,
+    pack: bool,
+) -> UtilResult<()> {
+    let existing = Piece::whole(planned.target.to_string(), size).pinned(e_tag);
+
+    // small targets can only lead when packed alongside what follows
+    if size < MIN_PART_SIZE && !planned.parts.is_empty() {
+        if !pack {
+            return Err(format!(
+                "Unable to append onto {}, as it's below 5MiB (see --pack-small)",
+                planned.target
+            )
+            .into());
+        }
+
+        let mut pieces = vec![existing];
+        pieces.extend(planned.parts.drain(..).flatten());
+
+        planned.parts = split_parts(pack::plan(pieces, MIN_PART_SIZE));
+        return Ok(());
+    }
+
+    let mut parts = split_parts(vec![vec![existing]]);
+
+    parts.append(&mut planned.parts);
+    planned.parts = parts;
+
+    Ok(())
+}
+
+/// Aborts all pending uploads under a prefix which are older than an age.
+///
+/// Uploads are only listed during a dry run, so they can be reviewed before
+/// anything is aborted. Failing to abort an upload is logged, rather than
+/// failing the run, as the upload will simply be left as it was.
+async fn purge_pending(
+    s3: &S3Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    age: Duration,
+    dry: bool,
+) -> UtilResult<()> {
+    let age = chrono::Duration::from_std(age).map_err(|_| "Purge age is out of range")?;
+    let cutoff = Utc::now() - age;
 
-            // init the request against AWS, and retrieve the identifier
-            let created = s3.create_multipart_upload(creation).await?;
-            let upload = created.upload_id.expect("upload id should exist");
+    for upload in resume::uploads(s3, bucket, prefix).await? {
+        let initiated = match parse_modified(upload.initiated.as_deref()) {
+            Some(initiated) if initiated < cutoff => initiated,
+            _ => continue,
+        };
 
-            // insert the upload identifier against the target
-            targets.insert(full_target.clone(), upload.clone());
-            sources.insert(upload, HashSet::new());
+        let (key, upload_id) = match (upload.key, upload.upload_id) {
+            (Some(key), Some(upload_id)) => (key, upload_id),
+            _ => continue,
         };
 
-        // retrieve the upload identifier for the target
-        let upload_id = targets
-            .get(&full_target)
-            .expect("upload identifier should always be mapped");
+        // print that it's being aborted
+        info!(
+            "Aborting stale upload {} for {} (initiated {})",
+            upload_id,
+            key,
+            initiated.to_rfc3339()
+        );
 
-        // retrieve the sources list for the upload_id
-        let sources = sources.get_mut(&*upload_id).unwrap();
+        // skip
+        if dry {
+            continue;
+        }
 
-        // create the copy request for the existing key
-        let copy_request = UploadPartCopyRequest {
+        // create the main abort request
+        let abort = AbortMultipartUploadRequest {
+            key: key.to_string(),
             bucket: bucket.to_string(),
-            copy_source: part_source,
-            part_number: (sources.len() + 1) as i64,
-            key: full_target,
             upload_id: upload_id.to_string(),
-            ..UploadPartCopyRequest::default()
+            ..AbortMultipartUploadRequest::default()
         };
 
-        // carry out the request for the part copy
-        s3.upload_part_copy(copy_request).await?;
-
-        // push the source for removal
-        sources.insert(key);
+        // attempt to abort the upload, leaving it on failure
+        if s3.abort_multipart_upload(abort).await.is_err() {
+            error!("Unable to abort {}", upload_id);
+        }
     }
 
-    // happy
     Ok(())
 }
 
+/// Removes an object from S3, logging rather than failing on error.
+async fn remove_object(s3: &S3Client, bucket: &str, key: &str) {
+    // print that we're removing
+    info!("Removing {}...", key);
+
+    // create the removal request
+    let delete = DeleteObjectRequest {
+        key: key.to_string(),
+        bucket: bucket.to_string(),
+        ..DeleteObjectRequest::default()
+    };
+
+    // attemp to remove the objects from S3
+    if s3.delete_object(delete).await.is_err() {
+        error!("Unable to remove {}", key);
+    }
+}
+
 /// Aborts a multipart request in S3 by upload_id.
 ///
 /// This can be used to abort a failed upload request, due to either the inability
@@ -306,3 +2115,250 @@ async fn abort_request(s3: &S3Client, key: String, bucket: String, upload_id: St
         error!("Unable to abort: {}", upload_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use rusoto_core::Region;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+    use rusoto_s3::S3Client;
+
+    use super::manifest::Manifest;
+    use super::pack::Piece;
+    use super::stages::Planned;
+    use super::state::{Part, State};
+    use super::{
+        append_onto, cleanup_sources, complete_stage, composite_e_tag, Finishing, MIN_PART_SIZE,
+    };
+    use crate::copy::MAX_COPY_SIZE;
+    use crate::retry::Retry;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn appending_onto_existing_targets() {
+        let planned = || Planned {
+            target: "target".to_string(),
+            parts: vec![
+                vec![Piece::whole("a".to_string(), MIN_PART_SIZE)],
+                vec![Piece::whole("b".to_string(), 10)],
+            ],
+            intermediate: false,
+        };
+
+        let e_tag = || Some("\"abc\"".to_string());
+
+        // large targets are copied as leading parts
+        let mut large = planned();
+        append_onto(&mut large, MAX_COPY_SIZE + 1, e_tag(), false).unwrap();
+
+        assert_eq!(large.parts.len(), 4);
+        assert_eq!(large.parts[0][0].key, "target");
+        assert_eq!(large.parts[0][0].start, 0);
+        assert_eq!(large.parts[0][0].e_tag, e_tag());
+        assert_eq!(large.parts[1][0].end, MAX_COPY_SIZE + 1);
+        assert_eq!(large.parts[1][0].e_tag, e_tag());
+        assert_eq!(large.parts[2][0].key, "a");
+        assert_eq!(large.parts[2][0].e_tag, None);
+
+        // small targets have to be packed into the first part
+        let mut small = planned();
+        assert!(append_onto(&mut small, 10, e_tag(), false).is_err());
+
+        append_onto(&mut small, 10, e_tag(), true).unwrap();
+
+        assert_eq!(small.parts.len(), 2);
+        assert_eq!(
+            small.parts[0],
+            vec![
+                Piece::whole("target".to_string(), 10).pinned(e_tag()),
+                Piece::whole("a".to_string(), MIN_PART_SIZE),
+            ]
+        );
+
+        // only enough of a large first part is packed alongside them
+        let mut topped = Planned {
+            target: "target".to_string(),
+            parts: vec![vec![Piece::whole("a".to_string(), MAX_COPY_SIZE)]],
+            intermediate: false,
+        };
+
+        append_onto(&mut topped, 10, e_tag(), true).unwrap();
+
+        assert_eq!(topped.parts.len(), 2);
+        assert_eq!(topped.parts[0].len(), 2);
+        assert_eq!(
+            topped.parts[0].iter().map(Piece::length).sum::<i64>(),
+            MIN_PART_SIZE
+        );
+        assert_eq!(topped.parts[1][0].end, MAX_COPY_SIZE);
+    }
+
+    #[test]
+    fn predicting_composite_e_tags() {
+        let part = |number: i64, e_tag: Option<&str>| Part {
+            number,
+            e_tag: e_tag.map(str::to_string),
+            sources: vec![],
+        };
+
+        // parts can be recorded in any order
+        let parts = vec![
+            part(2, Some("\"7d793037a0760186574b0282f2f435e7\"")),
+            part(1, Some("\"5d41402abc4b2a76b9719d911017c592\"")),
+        ];
+
+        assert_eq!(
+            composite_e_tag(parts),
+            Some("065947336a2f2a95ba8899f3675c3be6-2".to_string())
+        );
+
+        // ETags which aren't an MD5 can't be predicted from
+        assert_eq!(composite_e_tag(vec![part(1, None)]), None);
+        assert_eq!(composite_e_tag(vec![part(1, Some("\"abc\""))]), None);
+    }
+
+    #[test]
+    fn cleaning_up_only_completed_targets() {
+        let mut state = State::memory();
+
+        // direct target, failed target, and a staged target with an intermediate
+        let uploads = [
+            ("upload-a", "a", vec!["a-1", "a-2"]),
+            ("upload-b", "b", vec!["b-1"]),
+            ("upload-c1", "c.concat-1-1", vec!["c-1", "c-2"]),
+            ("upload-c", "c", vec!["c.concat-1-1", "c.concat-1-1"]),
+            ("upload-d", "d", vec!["d", "d-1"]),
+        ];
+
+        for (upload_id, target, sources) in &uploads {
+            state.track(upload_id, target).unwrap();
+
+            for source in sources {
+                let part = Part {
+                    number: state.next_part(upload_id),
+                    e_tag: None,
+                    sources: vec![source.to_string()],
+                };
+                state.record(upload_id, part).unwrap();
+            }
+        }
+
+        // the completion of b failed, so it was never marked as completed
+        let completed = vec![
+            ("a".to_string(), "upload-a".to_string()),
+            ("c.concat-1-1".to_string(), "upload-c1".to_string()),
+            ("c".to_string(), "upload-c".to_string()),
+            ("d".to_string(), "upload-d".to_string()),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        let intermediates = vec!["c.concat-1-1".to_string()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let finished = vec![
+            "a".to_string(),
+            "b".to_string(),
+            "c.concat-1-1".to_string(),
+            "c".to_string(),
+            "d".to_string(),
+        ];
+
+        let cleaned = cleanup_sources(&state, &completed, &intermediates, &finished).unwrap();
+
+        assert_eq!(
+            cleaned,
+            vec![
+                ("a", vec!["a-1".to_string(), "a-2".to_string()]),
+                ("c", vec!["c-1".to_string(), "c-2".to_string()]),
+                ("d", vec!["d-1".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn aborting_failed_completions() {
+        // every request is refused, including the completion
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::with_status(403),
+            MockCredentialsProvider,
+            Region::UsEast1,
+        );
+
+        let mut state = State::memory();
+        let mut manifest = Manifest::default();
+        let mut records = HashMap::new();
+        let intermediates = vec!["b.concat-1-1".to_string()]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let expected = HashMap::new();
+        let retry = Retry::default();
+
+        for (upload_id, target) in &[("upload-a", "a"), ("upload-b1", "b.concat-1-1")] {
+            let part = Part {
+                number: 1,
+                e_tag: Some("\"abc\"".to_string()),
+                sources: vec![format!("{}-1", target)],
+            };
+
+            state.track(upload_id, target).unwrap();
+            state.record(upload_id, part).unwrap();
+        }
+
+        let mut finishing = Finishing {
+            dry: false,
+            source: (&s3, "bucket"),
+            target: (&s3, "bucket"),
+            retry: &retry,
+            intermediates: &intermediates,
+            expected: &expected,
+            manifest: &mut manifest,
+            records: &mut records,
+            completed: HashMap::new(),
+            failed: Vec::new(),
+            broken: HashMap::new(),
+            mismatched: 0,
+            keep_going: true,
+            verify_e_tags: false,
+            incremental: false,
+            cleanup: true,
+            delete_on_mismatch: false,
+        };
+
+        let targets = vec![
+            ("a".to_string(), "upload-a".to_string()),
+            ("b.concat-1-1".to_string(), "upload-b1".to_string()),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        // failures are kept to their targets when keeping going
+        block_on(complete_stage(&mut finishing, &mut state, targets)).unwrap();
+
+        assert!(finishing.completed.is_empty());
+        assert_eq!(finishing.failed.len(), 1);
+        assert_eq!(finishing.failed[0].0, "a");
+        assert!(finishing.broken.contains_key("b.concat-1-1"));
+
+        // both uploads were forgotten, so nothing is left to clean up
+        assert!(state.parts("upload-a").unwrap().is_empty());
+        assert!(state.parts("upload-b1").unwrap().is_empty());
+
+        let finished = vec!["a".to_string(), "b.concat-1-1".to_string()];
+        let cleaned =
+            cleanup_sources(&state, &finishing.completed, &intermediates, &finished).unwrap();
+
+        assert!(cleaned.is_empty());
+
+        // otherwise a failed intermediate fails the run
+        state.track("upload-b1", "b.concat-1-1").unwrap();
+        finishing.keep_going = false;
+
+        let targets = vec![("b.concat-1-1".to_string(), "upload-b1".to_string())]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert!(block_on(complete_stage(&mut finishing, &mut state, targets)).is_err());
+    }
+}
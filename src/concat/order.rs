@@ -0,0 +1,263 @@
+//! Ordering of the sources concatenated into each target.
+//!
+//! Sources are sorted before any part numbers are assigned, so the layout
+//! of each target is stable regardless of listing order.
+use clap::ArgMatches;
+use regex::Regex;
+
+use std::cmp::Ordering;
+
+use super::Source;
+use crate::types::UtilResult;
+
+/// Orderings available to sort the sources of a target.
+pub enum Order {
+    Lexical,
+    Natural,
+    Modified,
+    Captured(Regex),
+}
+
+/// Value captured from a key to sort by, preferring numbers.
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Captured {
+    Number(u64),
+    Text(String),
+}
+
+/// Main implementation.
+impl Order {
+    /// Constructs an `Order` from the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> UtilResult<Order> {
+        // a sort key takes the place of any named ordering
+        if let Some(pattern) = args.value_of("sort_key") {
+            let pattern = Regex::new(pattern)?;
+
+            // the first group is the implicit match
+            if pattern.captures_len() != 2 {
+                return Err("Sort key pattern must contain exactly one capture group".into());
+            }
+
+            return Ok(Order::Captured(pattern));
+        }
+
+        match args.value_of("sort") {
+            None | Some("lexical") => Ok(Order::Lexical),
+            Some("natural") => Ok(Order::Natural),
+            Some("modified") => Ok(Order::Modified),
+            Some(other) => Err(format!("Unknown sort order: {}", other).into()),
+        }
+    }
+
+    /// Sorts a set of sources in place, using this ordering.
+    ///
+    /// Sorting by a captured value will fail if any source key doesn't match
+    /// the sort key pattern, as there's no sensible position for it.
+    pub fn sort(&self, sources: &mut Vec<Source>) -> UtilResult<()> {
+        match self {
+            Order::Lexical => sources.sort_by(|left, right| left.key.cmp(&right.key)),
+            Order::Natural => sources.sort_by(|left, right| natural(&left.key, &right.key)),
+            Order::Modified => sources.sort_by(|left, right| {
+                left.modified
+                    .cmp(&right.modified)
+                    .then_with(|| left.key.cmp(&right.key))
+            }),
+            Order::Captured(pattern) => {
+                // capture every value up front, as the sort itself can't fail
+                let mut captured = sources
+                    .drain(..)
+                    .map(|source| Ok((capture(pattern, &source.key)?, source)))
+                    .collect::<UtilResult<Vec<_>>>()?;
+
+                captured.sort_by(|(left_value, left), (right_value, right)| {
+                    left_value
+                        .cmp(right_value)
+                        .then_with(|| left.key.cmp(&right.key))
+                });
+
+                sources.extend(captured.into_iter().map(|(_, source)| source));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Captures the value to sort a key by, using a sort key pattern.
+///
+/// Values made up entirely of digits are compared numerically, and are
+/// sorted before any other values (which are compared as strings).
+fn capture(pattern: &Regex, key: &str) -> UtilResult<Captured> {
+    let value = pattern
+        .captures(key)
+        .and_then(|captures| captures.get(1))
+        .ok_or_else(|| format!("Unable to find sort key in source: {}", key))?
+        .as_str();
+
+    Ok(match value.parse() {
+        Ok(number) => Captured::Number(number),
+        Err(_) => Captured::Text(value.to_string()),
+    })
+}
+
+/// Compares two keys, treating runs of digits as numbers.
+///
+/// Numbers are compared by value, so `chunk-2` sorts before `chunk-10`.
+/// Keys which only differ by zero padding (`chunk-7` and `chunk-007`) are
+/// ordered lexicographically, to keep the ordering total.
+pub fn natural(left: &str, right: &str) -> Ordering {
+    let lefts = segments(left);
+    let rights = segments(right);
+
+    for (lhs, rhs) in lefts.iter().zip(&rights) {
+        let digits = (is_digits(lhs), is_digits(rhs));
+
+        let ordering = if let (true, true) = digits {
+            let lhs = lhs.trim_start_matches('0');
+            let rhs = rhs.trim_start_matches('0');
+
+            lhs.len().cmp(&rhs.len()).then_with(|| lhs.cmp(rhs))
+        } else {
+            lhs.cmp(rhs)
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    lefts.len().cmp(&rights.len()).then_with(|| left.cmp(right))
+}
+
+/// Splits a key into alternating runs of digits and non-digits.
+fn segments(key: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut digits = None;
+    let mut start = 0;
+
+    for (idx, c) in key.char_indices() {
+        let is_digit = c.is_ascii_digit();
+
+        if digits == Some(!is_digit) {
+            segments.push(&key[start..idx]);
+            start = idx;
+        }
+
+        digits = Some(is_digit);
+    }
+
+    if start < key.len() {
+        segments.push(&key[start..]);
+    }
+
+    segments
+}
+
+/// Determines whether a segment is entirely digits.
+fn is_digits(segment: &str) -> bool {
+    segment.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{natural, Order, Source};
+    use crate::dates::parse_modified;
+    use regex::Regex;
+
+    #[test]
+    fn ordering_keys_naturally() {
+        let mut keys = vec![
+            "chunk-10",
+            "chunk-2",
+            "chunk-1",
+            "chunk-007",
+            "chunk-7",
+            "chunk-2a",
+            "chunk-2-b",
+            "chunk-a",
+            "chunk",
+        ];
+
+        keys.sort_by(|left, right| natural(left, right));
+
+        assert_eq!(
+            keys,
+            vec![
+                "chunk",
+                "chunk-1",
+                "chunk-2",
+                "chunk-2-b",
+                "chunk-2a",
+                "chunk-007",
+                "chunk-7",
+                "chunk-10",
+                "chunk-a",
+            ]
+        );
+    }
+
+    #[test]
+    fn ordering_sources_by_modification() {
+        let source = |key: &str, date: Option<&str>| Source {
+            key: key.to_string(),
+            size: 0,
+            modified: parse_modified(date),
+        };
+
+        let mut sources = vec![
+            source("c", Some("2018-01-02T10:00:00.000Z")),
+            source("d", Some("2018-01-01T10:00:00.000Z")),
+            source("a", Some("2018-01-02T10:00:00.000Z")),
+            source("f", None),
+            source("e", Some("invalid")),
+        ];
+
+        Order::Modified.sort(&mut sources).unwrap();
+
+        let keys = sources
+            .iter()
+            .map(|source| source.key.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(keys, vec!["e", "f", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn ordering_sources_by_captured_values() {
+        let source = |key: &str| Source {
+            key: key.to_string(),
+            size: 0,
+            modified: None,
+        };
+
+        let order = Order::Captured(Regex::new(r"seq-(\w+)\.bin$").unwrap());
+
+        let mut sources = vec![
+            source("events/shard-1/seq-000042.bin"),
+            source("events/shard-3/seq-7.bin"),
+            source("events/shard-2/seq-final.bin"),
+            source("events/shard-2/seq-000041.bin"),
+        ];
+
+        order.sort(&mut sources).unwrap();
+
+        let keys = sources
+            .iter()
+            .map(|source| source.key.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            keys,
+            vec![
+                "events/shard-3/seq-7.bin",
+                "events/shard-2/seq-000041.bin",
+                "events/shard-1/seq-000042.bin",
+                "events/shard-2/seq-final.bin",
+            ]
+        );
+
+        let mut sources = vec![source("events/shard-1/other.bin")];
+
+        assert!(order.sort(&mut sources).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! Planning of upload parts, packing small sources into shared parts.
+//!
+//! S3 requires every part other than the last to be at least 5MiB, and a
+//! part copied server side can only have a single source. Runs of small
+//! sources are therefore planned as a single packed part (to be downloaded
+//! and uploaded together), while larger sources are still copied server
+//! side. When a small run is followed by a large source, only enough of the
+//! large source to fill the part is packed, and the rest of it is copied as
+//! a ranged part.
+//!
+//! Pieces can also carry inline bytes (such as a separator) rather than
+//! referring to a source. These are always packed alongside a neighbouring
+//! source, so they never end up as a tiny part of their own.
+use std::mem;
+
+/// Piece of a source object (or inline bytes) to be included in a part.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Piece {
+    pub key: String,
+    pub start: i64,
+    pub end: i64,
+    pub size: i64,
+    pub data: Option<Vec<u8>>,
+    pub e_tag: Option<String>,
+}
+
+/// Main implementation.
+impl Piece {
+    /// Constructs a new `Piece` covering an entire object.
+    pub fn whole(key: String, size: i64) -> Piece {
+        Piece {
+            key,
+            start: 0,
+            end: size,
+            size,
+            data: None,
+            e_tag: None,
+        }
+    }
+
+    /// Pins this piece to an ETag, so it fails if the source has changed.
+    pub fn pinned(mut self, e_tag: Option<String>) -> Piece {
+        self.e_tag = e_tag;
+        self
+    }
+
+    /// Constructs a new `Piece` of inline bytes, rather than a source.
+    pub fn inline(data: Vec<u8>) -> Piece {
+        let size = data.len() as i64;
+
+        Piece {
+            key: String::new(),
+            start: 0,
+            end: size,
+            size,
+            data: Some(data),
+            e_tag: None,
+        }
+    }
+
+    /// Determines whether this piece is made of inline bytes.
+    pub fn is_inline(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Determines whether this piece covers the entire object.
+    pub fn is_whole(&self) -> bool {
+        self.start == 0 && self.end == self.size
+    }
+
+    /// Retrieves the number of bytes covered by this piece.
+    pub fn length(&self) -> i64 {
+        self.end - self.start
+    }
+
+    /// Splits this piece into consecutive pieces of at most `max` bytes.
+    ///
+    /// Pieces are all roughly equal in size, rather than leaving a small
+    /// final piece, so that every piece can be used as a part anywhere.
+    pub fn split(&self, max: i64) -> Vec<Piece> {
+        let count = ((self.length() + max - 1) / max).max(1);
+        let length = (self.length() + count - 1) / count;
+
+        (0..count)
+            .map(|idx| {
+                self.slice(
+                    self.start + idx * length,
+                    self.end.min(self.start + (idx + 1) * length),
+                )
+            })
+            .collect()
+    }
+
+    /// Creates a piece covering a range of the same source as this piece.
+    pub fn slice(&self, start: i64, end: i64) -> Piece {
+        Piece {
+            key: self.key.clone(),
+            start,
+            end,
+            size: self.size,
+            data: None,
+            e_tag: self.e_tag.clone(),
+        }
+    }
+
+    /// Formats the byte range of this piece for use in a request.
+    pub fn range(&self) -> String {
+        format!("bytes={}-{}", self.start, self.end - 1)
+    }
+
+    /// Describes this piece for use in logging.
+    pub fn describe(&self) -> String {
+        if self.is_inline() {
+            format!("inline ({} bytes)", self.length())
+        } else if self.is_whole() {
+            self.key.to_string()
+        } else {
+            format!("{} ({})", self.key, self.range())
+        }
+    }
+}
+
+/// Plans the parts of a target from its ordered sources.
+///
+/// Each part is a list of pieces; a part with a single piece can be copied
+/// server side, whereas parts with several pieces (or inline bytes) have to
+/// be packed.
+pub fn plan(sources: Vec<Piece>, min: i64) -> Vec<Vec<Piece>> {
+    let mut parts = Vec::new();
+    let mut packed = Vec::new();
+    let mut buffered = 0;
+
+    let count = sources.len();
+
+    for (idx, piece) in sources.into_iter().enumerate() {
+        let size = piece.length();
+
+        // large sources with nothing pending are copied as-is
+        if size >= min && packed.is_empty() {
+            parts.push(vec![piece]);
+            continue;
+        }
+
+        // large sources can top up a pending part from their start
+        if size >= min && !piece.is_inline() {
+            let needed = min - buffered;
+            let remaining = size - needed;
+
+            // only if the remainder is still a valid part by itself
+            if remaining >= min || (idx + 1 == count && remaining > 0) {
+                packed.push(piece.slice(piece.start, piece.start + needed));
+                parts.push(mem::take(&mut packed));
+                parts.push(vec![piece.slice(piece.start + needed, piece.end)]);
+                buffered = 0;
+                continue;
+            }
+        }
+
+        // everything else is packed in full
+        buffered += size;
+        packed.push(piece);
+
+        // flush the pending part once it's large enough
+        if buffered >= min {
+            parts.push(mem::take(&mut packed));
+            buffered = 0;
+        }
+    }
+
+    // the final part can be any size
+    if !packed.is_empty() {
+        parts.push(packed);
+    }
+
+    coalesce(&mut parts, min);
+    parts
+}
+
+/// Moves a final part of only inline bytes into the part before it.
+///
+/// If the part before is copied server side, only enough of its tail to
+/// form a valid part is packed with the inline bytes (when the rest of it
+/// would still be a valid part by itself).
+fn coalesce(parts: &mut Vec<Vec<Piece>>, min: i64) {
+    let inline = parts
+        .last()
+        .filter(|part| part.iter().all(Piece::is_inline))
+        .is_some();
+
+    if !inline || parts.len() < 2 {
+        return;
+    }
+
+    let trailing = parts.pop().unwrap();
+    let mut previous = parts.pop().unwrap();
+
+    // packed parts can take the inline bytes as-is
+    if previous.len() > 1 || previous[0].is_inline() || previous[0].length() < min * 2 {
+        previous.extend(trailing);
+        parts.push(previous);
+        return;
+    }
+
+    // otherwise split off the tail of the copied piece
+    let piece = &previous[0];
+    let cut = piece.end - min;
+
+    let mut tail = vec![piece.slice(cut, piece.end)];
+    tail.extend(trailing);
+
+    parts.push(vec![piece.slice(piece.start, cut)]);
+    parts.push(tail);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, Piece};
+
+    fn sources(sizes: &[i64]) -> Vec<Piece> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(idx, size)| Piece::whole(format!("{}", idx), *size))
+            .collect()
+    }
+
+    fn shape(parts: &[Vec<Piece>]) -> Vec<Vec<(String, i64, i64)>> {
+        parts
+            .iter()
+            .map(|part| {
+                part.iter()
+                    .map(|piece| (piece.key.clone(), piece.start, piece.end))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn piece(key: &str, start: i64, end: i64) -> (String, i64, i64) {
+        (key.to_string(), start, end)
+    }
+
+    #[test]
+    fn planning_without_small_sources() {
+        let parts = plan(sources(&[10, 12, 3]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![
+                vec![piece("0", 0, 10)],
+                vec![piece("1", 0, 12)],
+                vec![piece("2", 0, 3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn planning_packed_small_sources() {
+        let parts = plan(sources(&[4, 4, 4, 2, 1]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![
+                vec![piece("0", 0, 4), piece("1", 0, 4), piece("2", 0, 4)],
+                vec![piece("3", 0, 2), piece("4", 0, 1)],
+            ]
+        );
+    }
+
+    #[test]
+    fn planning_ranged_large_sources() {
+        // the remainder of the large source is still a valid part
+        let parts = plan(sources(&[4, 30]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![
+                vec![piece("0", 0, 4), piece("1", 0, 6)],
+                vec![piece("1", 6, 30)],
+            ]
+        );
+
+        // the remainder would be too small, so the source is packed whole
+        let parts = plan(sources(&[4, 12, 10]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![
+                vec![piece("0", 0, 4), piece("1", 0, 12)],
+                vec![piece("2", 0, 10)],
+            ]
+        );
+    }
+
+    #[test]
+    fn planning_every_part_above_the_minimum() {
+        let sizes = [1, 15, 3, 3, 11, 25, 9, 9, 14, 2, 10, 1];
+        let parts = plan(sources(&sizes), 10);
+
+        for (idx, part) in parts.iter().enumerate() {
+            let size: i64 = part.iter().map(|piece| piece.end - piece.start).sum();
+            assert!(size >= 10 || idx + 1 == parts.len());
+        }
+
+        let total: i64 = parts
+            .iter()
+            .flatten()
+            .map(|piece| piece.end - piece.start)
+            .sum();
+
+        assert_eq!(total, sizes.iter().sum::<i64>());
+    }
+
+    #[test]
+    fn planning_inline_pieces() {
+        let with_separators = |sizes: &[i64]| {
+            sources(sizes)
+                .into_iter()
+                .flat_map(|piece| vec![piece, Piece::inline(b"\n".to_vec())])
+                .collect::<Vec<_>>()
+        };
+
+        // separators are packed with the start of the next source
+        let parts = plan(with_separators(&[20, 30]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![
+                vec![piece("0", 0, 20)],
+                vec![piece("", 0, 1), piece("1", 0, 9)],
+                vec![piece("1", 9, 20)],
+                vec![piece("1", 20, 30), piece("", 0, 1)],
+            ]
+        );
+        assert!(parts[1][0].is_inline());
+        assert!(parts[3][1].is_inline());
+
+        // or with the whole of a trailing source too small to split
+        let parts = plan(with_separators(&[15]), 10);
+
+        assert_eq!(
+            shape(&parts),
+            vec![vec![piece("0", 0, 15), piece("", 0, 1)]]
+        );
+    }
+
+    #[test]
+    fn splitting_large_pieces() {
+        let ranges = |pieces: Vec<Piece>| {
+            pieces
+                .iter()
+                .map(|piece| (piece.start, piece.end))
+                .collect::<Vec<_>>()
+        };
+
+        let whole = Piece::whole("a".to_string(), 25);
+        assert_eq!(ranges(whole.split(10)), vec![(0, 9), (9, 18), (18, 25)]);
+        assert!(Piece::whole("a".to_string(), 10).split(10)[0].is_whole());
+
+        let ranged = Piece::whole("a".to_string(), 25).slice(5, 25);
+        assert_eq!(ranges(ranged.split(15)), vec![(5, 15), (15, 25)]);
+    }
+}
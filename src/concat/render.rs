@@ -0,0 +1,215 @@
+//! Rendering of concatenation targets from matched source keys.
+use regex::Regex;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Renderer to expand a target template against matching source keys.
+///
+/// A rendered target is determined only by the text between each match in
+/// a key, and the capture groups referenced by the template. Sources tend
+/// to arrive in runs mapping to the same target, so the inputs of the last
+/// render are retained and the previous target is reused while they match.
+pub struct Renderer<'a> {
+    pattern: &'a Regex,
+    template: &'a str,
+    groups: Vec<usize>,
+    last_key: String,
+    last_inputs: Vec<Option<Range<usize>>>,
+    next_inputs: Vec<Option<Range<usize>>>,
+    rendered: Option<Arc<str>>,
+}
+
+/// Main implementation.
+impl<'a> Renderer<'a> {
+    /// Constructs a new `Renderer` from a source pattern and target template.
+    pub fn new(pattern: &'a Regex, template: &'a str) -> Renderer<'a> {
+        Renderer {
+            pattern,
+            template,
+            groups: referenced_groups(pattern, template),
+            last_key: String::new(),
+            last_inputs: Vec::new(),
+            next_inputs: Vec::new(),
+            rendered: None,
+        }
+    }
+
+    /// Renders the target of a key, or `None` if the key doesn't match.
+    pub fn render(&mut self, key: &str) -> Option<Arc<str>> {
+        let mut offset = 0;
+
+        // gather the inputs of this key
+        self.next_inputs.clear();
+        for captures in self.pattern.captures_iter(key) {
+            let whole = captures.get(0).expect("group 0 should always exist");
+
+            // the gap before the match
+            self.next_inputs.push(Some(offset..whole.start()));
+
+            // any groups the template makes use of
+            for group in &self.groups {
+                self.next_inputs
+                    .push(captures.get(*group).map(|group| group.range()));
+            }
+
+            offset = whole.end();
+        }
+
+        // no inputs, no match
+        if self.next_inputs.is_empty() {
+            return None;
+        }
+
+        // the gap after the last match
+        self.next_inputs.push(Some(offset..key.len()));
+
+        // reuse the previous render if nothing changed
+        if let Some(ref rendered) = self.rendered {
+            if self.is_unchanged(key) {
+                return Some(rendered.clone());
+            }
+        }
+
+        // render the target and store the inputs for next time
+        let rendered: Arc<str> = Arc::from(self.pattern.replace_all(key, self.template).as_ref());
+
+        self.last_key.clear();
+        self.last_key.push_str(key);
+        self.rendered = Some(rendered.clone());
+
+        std::mem::swap(&mut self.last_inputs, &mut self.next_inputs);
+
+        Some(rendered)
+    }
+
+    /// Determines if the inputs of a key match those of the last render.
+    fn is_unchanged(&self, key: &str) -> bool {
+        if self.last_inputs.len() != self.next_inputs.len() {
+            return false;
+        }
+
+        self.last_inputs
+            .iter()
+            .zip(&self.next_inputs)
+            .all(|pair| match pair {
+                (Some(last), Some(next)) => self.last_key[last.clone()] == key[next.clone()],
+                (None, None) => true,
+                _ => false,
+            })
+    }
+}
+
+/// Locates all capture group indices referenced by a replacement template.
+///
+/// This follows the same rules as the `regex` crate: `$$` is an escape,
+/// `${name}` is a braced reference, and `$name` consumes the longest run
+/// of word characters. Names which are not numbers map to named groups.
+fn referenced_groups(pattern: &Regex, template: &str) -> Vec<usize> {
+    let mut groups = Vec::new();
+    let mut rest = template;
+
+    while let Some(idx) = rest.find('$') {
+        rest = &rest[idx + 1..];
+
+        // parse out the name of the reference
+        let name = if rest.starts_with('$') {
+            rest = &rest[1..];
+            continue;
+        } else if rest.starts_with('{') {
+            match rest.find('}') {
+                Some(end) => {
+                    let name = &rest[1..end];
+                    rest = &rest[end + 1..];
+                    name
+                }
+                None => continue,
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            name
+        };
+
+        // resolve the name to a group index
+        let group = match name.parse::<usize>() {
+            Ok(group) => Some(group),
+            Err(_) => pattern
+                .capture_names()
+                .position(|group| group == Some(name)),
+        };
+
+        if let Some(group) = group {
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Renderer;
+    use regex::Regex;
+    use std::sync::Arc;
+
+    #[test]
+    fn rendering_matches_regex_replacement() {
+        let cases = [
+            (r"(\d{4})/(\d{2})/(\d{2})/.*\.gz", "$1-$2-$3.gz"),
+            (r"archives/.*\.gz", "archive.gz"),
+            (r"(?P<year>\d{4})/", "${year}_"),
+            (r"logs/(.*)", "$0/$$1"),
+            (r"(a)|(b)", "[$1$2]"),
+        ];
+
+        let keys = [
+            "2018/01/01/a.gz",
+            "2018/01/01/b.gz",
+            "2018/01/02/a.gz",
+            "archives/a.gz",
+            "archives/b.gz",
+            "logs/a",
+            "logs/b",
+            "prefix/2018/key",
+            "abab",
+            "unmatched",
+        ];
+
+        for (pattern, template) in &cases {
+            let pattern = Regex::new(pattern).unwrap();
+            let mut renderer = Renderer::new(&pattern, template);
+
+            for key in &keys {
+                let expected = if pattern.is_match(key) {
+                    Some(pattern.replace_all(key, *template).to_string())
+                } else {
+                    None
+                };
+
+                let rendered = renderer.render(key).map(|target| target.to_string());
+
+                assert_eq!(rendered, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rendering_reuses_unchanged_targets() {
+        let pattern = Regex::new(r"(\d{4})/(\d{2})/.*\.gz").unwrap();
+        let mut renderer = Renderer::new(&pattern, "$1-$2.gz");
+
+        let first = renderer.render("2018/01/a.gz").unwrap();
+        let second = renderer.render("2018/01/b.gz").unwrap();
+        let third = renderer.render("2018/02/a.gz").unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert_eq!(&*third, "2018-02.gz");
+    }
+}
@@ -0,0 +1,241 @@
+//! Resumption of concatenations which were interrupted part way through.
+//!
+//! Uploads left pending by an interrupted run are located by their target,
+//! and are only resumed if the target would be concatenated from exactly
+//! the same plan (the sources of every part) as when the upload was made.
+//! The parts already within a resumed upload are then skipped, so nothing
+//! is copied into the upload twice. Pending uploads which are never going
+//! to be resumed can also be listed, so they can be aborted instead.
+use rusoto_s3::*;
+use serde_json::json;
+
+use std::collections::HashMap;
+
+use super::stages::Planned;
+use super::state::Part;
+use crate::retry::Retry;
+use crate::types::UtilResult;
+
+/// Locates all pending uploads within a bucket, by their target.
+///
+/// Only uploads beneath the prefix (if any) are listed, as targets can't
+/// be anywhere else. If there are several pending uploads for the same
+/// target, the one most recently created is used, as that's most likely
+/// from the latest run.
+pub async fn pending(
+    s3: &S3Client,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> UtilResult<HashMap<String, String>> {
+    let mut pending = HashMap::new();
+    let mut initiated = HashMap::new();
+
+    for upload in uploads(s3, bucket, prefix).await? {
+        let (key, upload_id) = match (upload.key, upload.upload_id) {
+            (Some(key), Some(upload_id)) => (key, upload_id),
+            _ => continue,
+        };
+
+        // RFC 3339 dates from S3 will sort as strings
+        let created = upload.initiated.unwrap_or_default();
+
+        match initiated.get(&key) {
+            Some(latest) if *latest > created => continue,
+            _ => (),
+        }
+
+        initiated.insert(key.clone(), created);
+        pending.insert(key, upload_id);
+    }
+
+    Ok(pending)
+}
+
+/// Lists all pending uploads within a bucket, optionally under a prefix.
+pub async fn uploads(
+    s3: &S3Client,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> UtilResult<Vec<MultipartUpload>> {
+    let mut uploads = Vec::new();
+
+    let mut key_marker = None;
+    let mut upload_id_marker = None;
+
+    loop {
+        // create the request for the next page of uploads
+        let request = ListMultipartUploadsRequest {
+            bucket: bucket.to_string(),
+            prefix: prefix.map(str::to_string),
+            key_marker,
+            upload_id_marker,
+            ..ListMultipartUploadsRequest::default()
+        };
+
+        let listed = s3.list_multipart_uploads(request).await?;
+
+        uploads.extend(listed.uploads.unwrap_or_default());
+
+        // stop once there are no more pages
+        if listed.is_truncated != Some(true) {
+            break;
+        }
+
+        key_marker = listed.next_key_marker;
+        upload_id_marker = listed.next_upload_id_marker;
+    }
+
+    Ok(uploads)
+}
+
+/// Serializes the plan of a target, to be compared against later runs.
+///
+/// Inline pieces are included with their bytes (as hex), so changing a
+/// separator changes the plan, even though the offsets stay the same.
+pub fn plan(planned: &Planned) -> String {
+    let parts = planned
+        .parts
+        .iter()
+        .enumerate()
+        .map(|(idx, pieces)| {
+            let sources = pieces
+                .iter()
+                .map(|piece| {
+                    let mut source = json!({
+                        "key": piece.key,
+                        "start": piece.start,
+                        "end": piece.end,
+                    });
+
+                    // inline bytes (such as separators) are part of the plan too
+                    if let Some(ref data) = piece.data {
+                        let hex = data
+                            .iter()
+                            .map(|b| format!("{:02x}", b))
+                            .collect::<String>();
+                        source["data"] = json!(hex);
+                    }
+
+                    source
+                })
+                .collect::<Vec<_>>();
+
+            json!({
+                "number": idx + 1,
+                "sources": sources,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "target": planned.target,
+        "parts": parts,
+    })
+    .to_string()
+}
+
+/// Lists all parts already copied into an upload for a planned target.
+///
+/// Parts which don't match the size planned for them are left out, so they
+/// will be copied again (replacing whatever is in the upload already).
+pub async fn copied(
+    s3: &S3Client,
+    bucket: &str,
+    planned: &Planned,
+    upload_id: &str,
+    retry: &Retry,
+) -> UtilResult<Vec<Part>> {
+    let mut copied = Vec::new();
+    let mut part_number_marker = None;
+
+    loop {
+        // create the request for the next page of parts
+        let request = ListPartsRequest {
+            bucket: bucket.to_string(),
+            key: planned.target.to_string(),
+            upload_id: upload_id.to_string(),
+            part_number_marker,
+            ..ListPartsRequest::default()
+        };
+
+        let listed = retry
+            .run("part listing", || s3.list_parts(request.clone()))
+            .await?;
+
+        for part in listed.parts.unwrap_or_default() {
+            let number = part.part_number.unwrap_or_default();
+            if number < 1 {
+                continue;
+            }
+
+            // look up the pieces planned for this part
+            let pieces = match planned.parts.get(number as usize - 1) {
+                Some(pieces) => pieces,
+                None => continue,
+            };
+
+            let size = pieces.iter().map(|piece| piece.length()).sum::<i64>();
+            if part.size != Some(size) {
+                continue;
+            }
+
+            let mut sources = pieces
+                .iter()
+                .filter(|piece| !piece.is_inline())
+                .map(|piece| piece.key.to_string())
+                .collect::<Vec<_>>();
+            sources.dedup();
+
+            copied.push(Part {
+                number,
+                e_tag: part.e_tag,
+                sources,
+            });
+        }
+
+        // stop once there are no more pages
+        if listed.is_truncated != Some(true) {
+            break;
+        }
+
+        part_number_marker = listed.next_part_number_marker;
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::plan;
+    use crate::concat::pack::Piece;
+    use crate::concat::stages::Planned;
+
+    #[test]
+    fn serializing_plans() {
+        let mut planned = Planned {
+            target: "target".to_string(),
+            parts: vec![vec![Piece::whole("a".to_string(), 10)]],
+            intermediate: false,
+        };
+
+        let serialized = plan(&planned);
+
+        assert_eq!(
+            serialized,
+            r#"{"parts":[{"number":1,"sources":[{"end":10,"key":"a","start":0}]}],"target":"target"}"#
+        );
+
+        planned.parts[0][0].end = 5;
+
+        assert_ne!(plan(&planned), serialized);
+
+        planned.parts[0].push(Piece::inline(b"\n".to_vec()));
+        let separated = plan(&planned);
+
+        assert!(separated.contains(r#""data":"0a""#));
+
+        planned.parts[0][1] = Piece::inline(b"\r".to_vec());
+
+        assert_ne!(plan(&planned), separated);
+    }
+}
@@ -0,0 +1,197 @@
+//! Staging of targets which need more parts than a single upload allows.
+//!
+//! S3 limits each multipart upload to 10,000 parts, so any target planned
+//! with more parts than this is concatenated in stages. The parts are first
+//! split across intermediate objects of up to 10,000 parts each, and these
+//! intermediates are then concatenated into the target in a later stage. As
+//! a single part copy is limited to 5GiB, intermediates are copied in ranged
+//! slices, and staging repeats until every target fits in a single upload.
+use std::mem;
+
+use super::pack::Piece;
+use crate::copy::MAX_COPY_SIZE;
+use crate::types::UtilResult;
+
+/// Maximum number of parts in a single multipart upload.
+pub const MAX_PARTS: usize = 10_000;
+
+/// Maximum size of any object created in S3.
+pub const MAX_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024 * 1024;
+
+/// Target to be concatenated from a list of planned parts.
+pub struct Planned {
+    pub target: String,
+    pub parts: Vec<Vec<Piece>>,
+    pub intermediate: bool,
+}
+
+/// Splits planned targets into stages, in the order they must be run.
+///
+/// Targets which fit in a single upload are placed into the first stage.
+/// Any other target has its parts split across intermediates in the first
+/// stage, and is itself concatenated from those intermediates in a later
+/// stage. Every upload within a stage must be completed before the next
+/// stage can begin, as they form the sources of the following stage.
+pub fn stage(targets: Vec<Planned>) -> UtilResult<Vec<Vec<Planned>>> {
+    let mut stages = Vec::new();
+    let mut pending = targets;
+
+    while !pending.is_empty() {
+        let mut current = Vec::new();
+        let mut deferred = Vec::new();
+
+        for planned in pending {
+            // staging can't help a target which is too large to exist
+            validate(&planned)?;
+
+            // targets which fit can be concatenated directly
+            if planned.parts.len() <= MAX_PARTS {
+                current.push(planned);
+                continue;
+            }
+
+            let mut parts = planned.parts;
+            let mut slices = Vec::new();
+            let mut count = 0;
+
+            // split the parts across as many intermediates as needed
+            while !parts.is_empty() {
+                let remaining = parts.split_off(MAX_PARTS.min(parts.len()));
+                let chunk = mem::replace(&mut parts, remaining);
+
+                count += 1;
+                let key = intermediate(&planned.target, stages.len(), count);
+                let size = chunk.iter().flatten().map(Piece::length).sum();
+
+                current.push(Planned {
+                    target: key.clone(),
+                    parts: chunk,
+                    intermediate: true,
+                });
+
+                // each intermediate is sliced into copyable parts
+                for piece in Piece::whole(key, size).split(MAX_COPY_SIZE) {
+                    slices.push(vec![piece]);
+                }
+            }
+
+            // concatenate the intermediates in the next stage
+            deferred.push(Planned {
+                target: planned.target,
+                parts: slices,
+                intermediate: planned.intermediate,
+            });
+        }
+
+        stages.push(current);
+        pending = deferred;
+    }
+
+    Ok(stages)
+}
+
+/// Validates that a planned target can be concatenated at all.
+///
+/// Targets over the part limit can be staged, but nothing can be done for
+/// a target larger than S3 allows, so these are rejected during planning
+/// rather than failing once their parts have been copied.
+pub fn validate(planned: &Planned) -> UtilResult<()> {
+    let size = planned
+        .parts
+        .iter()
+        .flatten()
+        .map(Piece::length)
+        .sum::<i64>();
+
+    if size <= MAX_OBJECT_SIZE {
+        return Ok(());
+    }
+
+    Err(format!(
+        "Unable to concat {}, as its {} parts ({} bytes) would exceed the 5TiB object limit",
+        planned.target,
+        planned.parts.len(),
+        size
+    )
+    .into())
+}
+
+/// Generates the key of an intermediate object for a target.
+fn intermediate(target: &str, stage: usize, count: usize) -> String {
+    format!("{}.concat-{}-{}", target, stage + 1, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stage, validate, Planned, MAX_PARTS};
+    use crate::concat::pack::Piece;
+
+    fn planned(target: &str, count: usize, size: i64) -> Planned {
+        Planned {
+            target: target.to_string(),
+            parts: (0..count)
+                .map(|idx| vec![Piece::whole(format!("{}", idx), size)])
+                .collect(),
+            intermediate: false,
+        }
+    }
+
+    #[test]
+    fn staging_targets_within_the_part_limit() {
+        let stages = stage(vec![planned("a", 10, 1), planned("b", MAX_PARTS, 1)]).unwrap();
+
+        assert_eq!(stages.len(), 1);
+        assert_eq!(stages[0].len(), 2);
+    }
+
+    #[test]
+    fn staging_targets_over_the_part_limit() {
+        let stages = stage(vec![planned("a", 10, 1), planned("b", 25_000, 1)]).unwrap();
+
+        assert_eq!(stages.len(), 2);
+
+        // the small target and all intermediates come first
+        let first = stages[0]
+            .iter()
+            .map(|planned| (planned.target.as_str(), planned.parts.len()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            first,
+            vec![
+                ("a", 10),
+                ("b.concat-1-1", MAX_PARTS),
+                ("b.concat-1-2", MAX_PARTS),
+                ("b.concat-1-3", 5_000),
+            ]
+        );
+
+        // then the target is concatenated from the intermediates
+        let last = &stages[1][0];
+        let sources = last
+            .parts
+            .iter()
+            .flatten()
+            .map(|piece| (piece.key.as_str(), piece.length()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(last.target, "b");
+        assert_eq!(
+            sources,
+            vec![
+                ("b.concat-1-1", 10_000),
+                ("b.concat-1-2", 10_000),
+                ("b.concat-1-3", 5_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejecting_oversized_targets() {
+        assert!(stage(vec![planned("a", MAX_PARTS + 1, 1 << 30)]).is_err());
+
+        // even when the target would fit in a single upload
+        assert!(validate(&planned("a", 2_000, 3 << 30)).is_err());
+        assert!(validate(&planned("a", MAX_PARTS, 1 << 20)).is_ok());
+    }
+}
@@ -0,0 +1,302 @@
+//! Bookkeeping of copied parts for in-progress concatenations.
+//!
+//! By default all state is held in memory, but very large jobs can choose
+//! to spill it to disk instead. In this case each upload is tracked by a
+//! small file of appended part records, which keeps memory usage flat no
+//! matter how many sources are being concatenated. Fields within each
+//! record are escaped, as source keys can contain tabs and newlines.
+//!
+//! State on disk also holds the plan of each target (the sources of every
+//! part), which allows an interrupted run to be resumed later on.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::records;
+use crate::types::UtilResult;
+
+/// Record of a single part copied into a multipart upload.
+///
+/// Parts usually have a single source, but packed parts can have several.
+#[derive(Clone)]
+pub struct Part {
+    pub number: i64,
+    pub e_tag: Option<String>,
+    pub sources: Vec<String>,
+}
+
+/// State container to track the parts of each pending upload.
+pub struct State {
+    counts: HashMap<String, i64>,
+    targets: HashMap<String, String>,
+    storage: Storage,
+}
+
+/// Storage backends available to `State`.
+enum Storage {
+    Disk(PathBuf),
+    Memory(HashMap<String, Vec<Part>>),
+}
+
+/// Main implementation.
+impl State {
+    /// Constructs a new `State` held entirely in memory.
+    pub fn memory() -> State {
+        State {
+            counts: HashMap::new(),
+            targets: HashMap::new(),
+            storage: Storage::Memory(HashMap::new()),
+        }
+    }
+
+    /// Constructs a new `State` which spills parts to the provided directory.
+    pub fn disk(dir: &str) -> UtilResult<State> {
+        fs::create_dir_all(dir)?;
+        Ok(State {
+            counts: HashMap::new(),
+            targets: HashMap::new(),
+            storage: Storage::Disk(PathBuf::from(dir)),
+        })
+    }
+
+    /// Begins tracking a newly created upload for the provided target.
+    pub fn track(&mut self, upload_id: &str, target: &str) -> UtilResult<()> {
+        self.counts.insert(upload_id.to_string(), 0);
+        self.targets
+            .insert(upload_id.to_string(), target.to_string());
+
+        // parts files on disk are only created once a part is recorded
+        if let Storage::Memory(ref mut parts) = self.storage {
+            parts.insert(upload_id.to_string(), Vec::new());
+        }
+
+        Ok(())
+    }
+
+    /// Reserves the part number to use for the next part of an upload.
+    pub fn next_part(&mut self, upload_id: &str) -> i64 {
+        let count = self.counts.entry(upload_id.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// Records a part which has been copied into an upload.
+    ///
+    /// Parts may be recorded in any order, regardless of their numbers.
+    pub fn record(&mut self, upload_id: &str, part: Part) -> UtilResult<()> {
+        match self.storage {
+            Storage::Memory(ref mut parts) => {
+                parts
+                    .entry(upload_id.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(part);
+            }
+            Storage::Disk(ref dir) => {
+                let mut file = append(parts_path(dir, upload_id))?;
+                let e_tag = records::escape(&part.e_tag.unwrap_or_default());
+
+                // one record per source, all sharing the part number
+                for source in part.sources {
+                    let source = records::escape(&source);
+                    writeln!(file, "{}\t{}\t{}", part.number, e_tag, source)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves all parts recorded against an upload, in recorded order.
+    pub fn parts(&self, upload_id: &str) -> UtilResult<Vec<Part>> {
+        let dir = match self.storage {
+            Storage::Disk(ref dir) => dir,
+            Storage::Memory(ref parts) => {
+                return Ok(parts.get(upload_id).cloned().unwrap_or_default());
+            }
+        };
+
+        // open the parts file, which may not exist yet
+        let file = match File::open(parts_path(dir, upload_id)) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut parts: Vec<Part> = Vec::new();
+
+        // parse each line back into a part record
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let mut splitn = line.splitn(3, '\t');
+
+            let number = splitn.next().and_then(|num| num.parse().ok());
+            let e_tag = splitn.next();
+            let source = splitn.next();
+
+            let (number, e_tag, source) = match (number, e_tag, source) {
+                (Some(number), Some(e_tag), Some(source)) => {
+                    (number, records::unescape(e_tag), records::unescape(source))
+                }
+                _ => return Err(format!("Invalid part record for {}: {}", upload_id, line).into()),
+            };
+
+            // consecutive records of the same part are packed sources
+            match parts.last_mut() {
+                Some(last) if last.number == number => {
+                    last.sources.push(source);
+                    continue;
+                }
+                _ => (),
+            }
+
+            parts.push(Part {
+                number,
+                e_tag: Some(e_tag).filter(|e_tag| !e_tag.is_empty()),
+                sources: vec![source],
+            });
+        }
+
+        Ok(parts)
+    }
+
+    /// Saves the plan of a target, so that its upload can be resumed.
+    ///
+    /// Plans are only saved when the state is on disk, as there's no way
+    /// to resume a run from state held in memory.
+    pub fn save_plan(&mut self, target: &str, plan: &str) -> UtilResult<()> {
+        if let Storage::Disk(ref dir) = self.storage {
+            fs::write(plan_path(dir, target), plan)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves the plan saved for a target, if there is one.
+    pub fn plan(&self, target: &str) -> UtilResult<Option<String>> {
+        let dir = match self.storage {
+            Storage::Disk(ref dir) => dir,
+            Storage::Memory(_) => return Ok(None),
+        };
+
+        match fs::read_to_string(plan_path(dir, target)) {
+            Ok(plan) => Ok(Some(plan)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Stops tracking an upload, discarding all of its recorded parts.
+    pub fn forget(&mut self, upload_id: &str) {
+        self.counts.remove(upload_id);
+
+        let target = self.targets.remove(upload_id);
+
+        match self.storage {
+            Storage::Memory(ref mut parts) => {
+                parts.remove(upload_id);
+            }
+            Storage::Disk(ref dir) => {
+                let _ = fs::remove_file(parts_path(dir, upload_id));
+
+                // the plan is no longer needed to resume anything
+                if let Some(target) = target {
+                    let _ = fs::remove_file(plan_path(dir, &target));
+                }
+            }
+        }
+    }
+}
+
+/// Opens a file for appending, creating it as necessary.
+fn append(path: PathBuf) -> UtilResult<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Constructs the path of the parts file for an upload.
+fn parts_path(dir: &Path, upload_id: &str) -> PathBuf {
+    dir.join(format!("{}.parts", upload_id))
+}
+
+/// Constructs the path of the plan file for a target.
+///
+/// Targets can be long and contain any characters, so they're hashed (using
+/// FNV-1a, which is stable across builds) to form a valid file name. Plans
+/// contain their target, so a collision can never resume the wrong upload.
+fn plan_path(dir: &Path, target: &str) -> PathBuf {
+    let hash = target
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    dir.join(format!("{:016x}.plan", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Part, State};
+    use std::fs;
+
+    fn exercise(mut state: State) {
+        state.track("upload", "target").unwrap();
+
+        assert_eq!(state.next_part("upload"), 1);
+        assert_eq!(state.next_part("upload"), 2);
+
+        for number in &[2, 1] {
+            state
+                .record(
+                    "upload",
+                    Part {
+                        number: *number,
+                        e_tag: Some("\"etag\"".into()),
+                        sources: vec!["my\tkey\n".into(), format!("packed{}", number)],
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(state.next_part("upload"), 3);
+
+        let parts = state.parts("upload").unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].number, 2);
+        assert_eq!(parts[1].number, 1);
+        assert_eq!(parts[0].e_tag, Some("\"etag\"".into()));
+        assert_eq!(parts[0].sources, vec!["my\tkey\n", "packed2"]);
+        assert_eq!(parts[1].sources, vec!["my\tkey\n", "packed1"]);
+
+        state.forget("upload");
+
+        assert!(state.parts("upload").unwrap().is_empty());
+        assert_eq!(state.next_part("upload"), 1);
+    }
+
+    #[test]
+    fn tracking_parts_in_memory() {
+        exercise(State::memory());
+    }
+
+    #[test]
+    fn tracking_parts_on_disk() {
+        // unique to this process, so concurrent test runs don't collide
+        let dir = std::env::temp_dir().join(format!("s3-utils-state-{}", std::process::id()));
+        let path = dir.to_string_lossy().into_owned();
+
+        exercise(State::disk(&path).unwrap());
+
+        let mut state = State::disk(&path).unwrap();
+
+        state.save_plan("my/target", "{}").unwrap();
+        state.track("upload", "my/target").unwrap();
+
+        assert_eq!(state.plan("my/target").unwrap(), Some("{}".into()));
+
+        state.forget("upload");
+
+        assert_eq!(state.plan("my/target").unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
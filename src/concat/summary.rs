@@ -0,0 +1,92 @@
+//! Summaries of everything planned within a dry run.
+//!
+//! Dry runs over a large prefix will log a line for every part, which is
+//! hard to total up by hand. All counters are tracked while planning, and
+//! are printed once planning is complete in the same `key=value` format as
+//! the `report` command, so they can be easily consumed by scripts.
+use crate::report::util;
+
+/// Counters tracked while planning the targets within a bucket.
+#[derive(Default)]
+pub struct Summary {
+    pub sources: u64,
+    pub excluded: u64,
+    pub outside_size: u64,
+    pub outside_window: u64,
+    pub self_targets: u64,
+    targets: u64,
+    bytes: i64,
+    smallest: Option<(String, i64)>,
+    largest: Option<(String, i64)>,
+}
+
+/// Main implementation.
+impl Summary {
+    /// Tracks a planned target, alongside its size.
+    pub fn target(&mut self, target: &str, bytes: i64) {
+        self.targets += 1;
+        self.bytes += bytes;
+
+        if self
+            .smallest
+            .as_ref()
+            .filter(|(_, min)| *min <= bytes)
+            .is_none()
+        {
+            self.smallest = Some((target.to_string(), bytes));
+        }
+
+        if self
+            .largest
+            .as_ref()
+            .filter(|(_, max)| *max >= bytes)
+            .is_none()
+        {
+            self.largest = Some((target.to_string(), bytes));
+        }
+    }
+
+    /// Prints this summary under the `concat` header.
+    pub fn print(&self, bucket: &str) {
+        util::log_head("concat");
+        util::log_pair("bucket_name", bucket);
+        util::log_pair("total_targets", self.targets);
+        util::log_pair("total_sources", self.sources);
+        util::log_pair("total_bytes", self.bytes);
+
+        if let Some((ref name, bytes)) = self.smallest {
+            util::log_pair("smallest_target_name", name);
+            util::log_pair("smallest_target_bytes", bytes);
+        }
+
+        if let Some((ref name, bytes)) = self.largest {
+            util::log_pair("largest_target_name", name);
+            util::log_pair("largest_target_bytes", bytes);
+        }
+
+        util::log_pair("skipped_self_targets", self.self_targets);
+        util::log_pair("skipped_outside_size", self.outside_size);
+        util::log_pair("skipped_outside_window", self.outside_window);
+        util::log_pair("skipped_excluded", self.excluded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn tracking_target_sizes() {
+        let mut summary = Summary::default();
+
+        summary.target("b", 20);
+        summary.target("a", 10);
+        summary.target("c", 30);
+        summary.target("d", 10);
+
+        assert_eq!(summary.targets, 4);
+        assert_eq!(summary.bytes, 70);
+        assert_eq!(summary.smallest, Some(("a".to_string(), 10)));
+        assert_eq!(summary.largest, Some(("c".to_string(), 30)));
+    }
+}
@@ -0,0 +1,592 @@
+//! Parts sent into pending multipart uploads, with bounded concurrency.
+//!
+//! Part numbers are always assigned in the order parts are provided, so
+//! the layout of each target is deterministic, but the parts themselves
+//! may complete in any order. Completed parts are recorded into the part
+//! state as they arrive. When an upload is resumed, any parts copied into it
+//! by an earlier run are skipped (but still reserve their part numbers).
+//!
+//! Parts are usually copied from sources in the same bucket as the target,
+//! but an origin can be provided to copy parts from another bucket instead.
+//!
+//! By default the first failure fails every upload. When keeping going, a
+//! failure is instead recorded against its target, and any further parts of
+//! that target are skipped while all other targets continue.
+//!
+//! Once every part of a target has been sent and copied, the target settles
+//! and its upload can be taken to complete it while other targets continue.
+use futures::stream::{FuturesUnordered, StreamExt};
+use rusoto_s3::*;
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+
+use super::metadata::Metadata;
+use super::pack::Piece;
+use super::resume;
+use super::stages::Planned;
+use super::state::{Part, State};
+use crate::client;
+use crate::retry::Retry;
+use crate::types::{UtilError, UtilResult};
+
+/// Result of an in-flight part, alongside the target and part it was sending.
+type Copied = (String, String, Part, UtilResult<Option<String>>);
+
+/// Location of a part, as the bucket, key, upload and part number.
+type Target<'a> = (&'a str, &'a str, &'a str, i64);
+
+/// Location of the sources of a part, as the client and bucket.
+type Origin<'a> = (&'a S3Client, &'a str);
+
+/// Uploads structure to copy sources into targets as upload parts.
+pub struct Uploads<'a> {
+    dry: bool,
+    s3: &'a S3Client,
+    bucket: &'a str,
+    origin: Origin<'a>,
+    creation: CreateMultipartUploadRequest,
+    creations: HashMap<String, CreateMultipartUploadRequest>,
+    metadata: Option<&'a HashMap<String, Metadata>>,
+    state: &'a mut State,
+    targets: &'a mut HashMap<String, String>,
+    concurrency: usize,
+    retry: Retry,
+    keep_going: bool,
+    failures: HashMap<String, UtilError>,
+    copied: HashMap<String, HashSet<i64>>,
+    sent: HashSet<String>,
+    inflight: HashMap<String, usize>,
+    pending: FuturesUnordered<Pin<Box<dyn Future<Output = Copied> + 'a>>>,
+}
+
+/// Targets which have settled, with nothing left to send or copy.
+#[derive(Default)]
+pub struct Settled {
+    /// Uploads of targets with every part copied, by target.
+    pub uploads: HashMap<String, String>,
+    /// Failed targets, with their upload (if one was created).
+    pub failures: Vec<(String, Option<String>, UtilError)>,
+}
+
+/// Main implementation.
+impl<'a> Uploads<'a> {
+    /// Constructs a new `Uploads` to copy parts within a bucket.
+    ///
+    /// The provided mappings of target to upload identifier are populated
+    /// as uploads are created, to allow the caller to abort on failure.
+    pub fn new(
+        dry: bool,
+        s3: &'a S3Client,
+        bucket: &'a str,
+        state: &'a mut State,
+        targets: &'a mut HashMap<String, String>,
+    ) -> Uploads<'a> {
+        Uploads {
+            dry,
+            s3,
+            bucket,
+            origin: (s3, bucket),
+            creation: CreateMultipartUploadRequest::default(),
+            creations: HashMap::new(),
+            metadata: None,
+            state,
+            targets,
+            concurrency: 1,
+            retry: Retry::default(),
+            keep_going: false,
+            failures: HashMap::new(),
+            copied: HashMap::new(),
+            sent: HashSet::new(),
+            inflight: HashMap::new(),
+            pending: FuturesUnordered::new(),
+        }
+    }
+
+    /// Sets the client and bucket to copy the sources of parts from.
+    pub fn origin(mut self, s3: &'a S3Client, bucket: &'a str) -> Self {
+        self.origin = (s3, bucket);
+        self
+    }
+
+    /// Sets the request used as a template to create each upload.
+    ///
+    /// Intermediates are only ever copied from, so they're created without
+    /// a storage class (as not all classes can be copied from directly).
+    pub fn creation(mut self, creation: CreateMultipartUploadRequest) -> Self {
+        self.creation = creation;
+        self
+    }
+
+    /// Sets the metadata to apply to each target, as copied from its sources.
+    pub fn metadata(mut self, metadata: &'a HashMap<String, Metadata>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the number of part copies which can be in flight at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets the policy used to retry requests which fail transiently.
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets whether failures are kept to their target, rather than failing all.
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Records a failure against a target.
+    ///
+    /// Unless keeping going, the failure is passed straight back.
+    pub fn fail(&mut self, target: &str, err: UtilError) -> UtilResult<()> {
+        if !self.keep_going {
+            return Err(err);
+        }
+
+        error!("Unable to concatenate {}: {}", target, err);
+        self.failures.entry(target.to_string()).or_insert(err);
+
+        Ok(())
+    }
+
+    /// Marks every part of a target as sent, so it can settle.
+    pub fn sent(&mut self, target: &str) {
+        self.sent.insert(target.to_string());
+    }
+
+    /// Retrieves the number of uploads currently open.
+    pub fn open(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// Retrieves the part state recorded into by all uploads.
+    pub fn state(&mut self) -> &mut State {
+        self.state
+    }
+
+    /// Takes every target which has settled since last called.
+    ///
+    /// A target settles once all of its parts are sent and none are still
+    /// in flight, so nothing will touch its upload after it's been taken.
+    pub fn settled(&mut self) -> Settled {
+        let inflight = &self.inflight;
+        let ready = self
+            .sent
+            .iter()
+            .filter(|target| !inflight.contains_key(*target))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut settled = Settled::default();
+
+        for target in ready {
+            self.sent.remove(&target);
+
+            let upload_id = self.targets.remove(&target);
+
+            match (self.failures.remove(&target), upload_id) {
+                (Some(err), upload_id) => settled.failures.push((target, upload_id, err)),
+                (None, Some(upload_id)) => {
+                    settled.uploads.insert(target, upload_id);
+                }
+                (None, None) => (),
+            }
+        }
+
+        settled
+    }
+
+    /// Prepares the upload for a planned target, before sending any parts.
+    ///
+    /// The plan of the target is saved to the part state, so that a later
+    /// run can resume the upload. If a pending upload is provided for the
+    /// target, and it was created from the same plan, it will be resumed.
+    pub async fn prepare(&mut self, planned: &Planned, pending: Option<&String>) -> UtilResult<()> {
+        let plan = resume::plan(planned);
+
+        // the request to create the upload with, if needed
+        let mut creation = self.creation.clone();
+
+        if planned.intermediate {
+            creation.storage_class = None;
+        } else if let Some(metadata) = self.metadata.and_then(|m| m.get(&planned.target)) {
+            metadata.apply(&mut creation);
+        }
+
+        self.creations.insert(planned.target.to_string(), creation);
+
+        if let Some(upload_id) = pending {
+            // only resume if the parts would be exactly the same
+            if self.state.plan(&planned.target)?.as_ref() == Some(&plan) {
+                self.resume(planned, upload_id).await?;
+            } else {
+                info!(
+                    "Unable to resume {}, as the plan for {} has changed",
+                    upload_id, planned.target
+                );
+
+                // the upload can never be resumed, so it's not left behind
+                if !self.dry {
+                    super::abort_request(
+                        self.s3,
+                        planned.target.to_string(),
+                        self.bucket.to_string(),
+                        upload_id.to_string(),
+                    )
+                    .await;
+                    self.state.forget(upload_id);
+                }
+            }
+        }
+
+        // dry runs never create uploads to resume
+        if !self.dry {
+            self.state.save_plan(&planned.target, &plan)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resumes a pending upload for a target, skipping any copied parts.
+    async fn resume(&mut self, planned: &Planned, upload_id: &str) -> UtilResult<()> {
+        let parts = resume::copied(self.s3, self.bucket, planned, upload_id, &self.retry).await?;
+
+        info!(
+            "Resuming {} for {} ({} of {} parts already copied)",
+            upload_id,
+            planned.target,
+            parts.len(),
+            planned.parts.len()
+        );
+
+        // dry runs number parts against the target, as no upload exists
+        let key = if self.dry {
+            planned.target.to_string()
+        } else {
+            upload_id.to_string()
+        };
+
+        // mark each part as copied, to skip it when sending
+        self.copied
+            .insert(key, parts.iter().map(|part| part.number).collect());
+
+        // dry runs don't keep any upload state
+        if self.dry {
+            return Ok(());
+        }
+
+        // replace anything recorded for the upload by the earlier run
+        self.state.forget(upload_id);
+        self.state.track(upload_id, &planned.target)?;
+
+        for part in parts {
+            self.state.record(upload_id, part)?;
+        }
+
+        self.targets
+            .insert(planned.target.to_string(), upload_id.to_string());
+
+        Ok(())
+    }
+
+    /// Retrieves the upload identifier for a target, creating it if needed.
+    async fn upload_id(&mut self, full_target: &str) -> UtilResult<String> {
+        if let Some(upload_id) = self.targets.get(full_target) {
+            return Ok(upload_id.to_string());
+        }
+
+        // initialize the upload request as needed
+        let creation = CreateMultipartUploadRequest {
+            bucket: self.bucket.to_string(),
+            key: full_target.to_string(),
+            ..self.creations.remove(full_target).unwrap_or_default()
+        };
+
+        // init the request against AWS, and retrieve the identifier
+        let created = self
+            .retry
+            .run("upload creation", || {
+                self.s3.create_multipart_upload(creation.clone())
+            })
+            .await?;
+        let upload = created.upload_id.expect("upload id should exist");
+
+        // insert the upload identifier against the target
+        self.state.track(&upload, full_target)?;
+        self.targets
+            .insert(full_target.to_string(), upload.to_string());
+
+        Ok(upload)
+    }
+
+    /// Concatenates a planned part onto the upload for a target.
+    ///
+    /// The upload for the target is created on the first call for a target,
+    /// and reused across all parts being concatenated into that target. A
+    /// part with a single piece is copied server side, whereas a part with
+    /// several pieces (or inline bytes) is downloaded and uploaded as a
+    /// single packed part.
+    pub async fn concat(&mut self, pieces: Vec<Piece>, full_target: &str) -> UtilResult<()> {
+        // nothing more is sent for a target which has failed
+        if self.failures.contains_key(full_target) {
+            return Ok(());
+        }
+
+        // dry runs number parts against the target, as no upload exists
+        let upload_id = if self.dry {
+            full_target.to_string()
+        } else {
+            self.upload_id(full_target).await?
+        };
+
+        // reserve the next part number for the upload_id
+        let part_number = self.state.next_part(&upload_id);
+
+        // parts copied by an earlier run are left as they are
+        if let Some(copied) = self.copied.get(&upload_id) {
+            if copied.contains(&part_number) {
+                info!(
+                    "Skipping part {} of {} (already copied)",
+                    part_number, full_target
+                );
+                return Ok(());
+            }
+        }
+
+        // log out exactly what we're concatenating right now
+        let size = pieces.iter().map(Piece::length).sum::<i64>();
+
+        // inline bytes can only be uploaded
+        let server_side = pieces.len() == 1 && !pieces[0].is_inline();
+
+        if server_side {
+            info!(
+                "Concatenating {} -> {} (part {}, {} bytes)",
+                pieces[0].describe(),
+                full_target,
+                part_number,
+                size
+            );
+        } else {
+            let packed = pieces.iter().map(Piece::describe).collect::<Vec<_>>();
+            info!(
+                "Packing {} -> {} (part {}, {} bytes)",
+                packed.join(", "),
+                full_target,
+                part_number,
+                size
+            );
+        }
+
+        // skip
+        if self.dry {
+            return Ok(());
+        }
+
+        // the part to record once the copy is complete
+        let mut sources = pieces
+            .iter()
+            .filter(|piece| !piece.is_inline())
+            .map(|piece| piece.key.to_string())
+            .collect::<Vec<_>>();
+        sources.dedup();
+
+        let part = Part {
+            number: part_number,
+            e_tag: None,
+            sources,
+        };
+
+        // wait for a free slot before sending the part
+        self.drain(self.concurrency - 1).await?;
+
+        // carry out the request for the part in the background
+        let s3 = self.s3;
+        let bucket = self.bucket;
+        let origin = self.origin;
+        let retry = self.retry.clone();
+        let key = full_target.to_string();
+
+        *self.inflight.entry(key.clone()).or_insert(0) += 1;
+
+        self.pending.push(Box::pin(async move {
+            let target = (bucket, key.as_str(), upload_id.as_str(), part_number);
+            let e_tag = if server_side {
+                copy_part(s3, target, origin, &pieces[0], &retry).await
+            } else {
+                pack_part(s3, target, origin, &pieces).await
+            };
+
+            // name both buckets, as permissions are often the problem
+            let e_tag = e_tag.map_err(|err| {
+                if origin.1 == bucket {
+                    return err;
+                }
+                UtilError::from(format!(
+                    "Unable to copy from {} into {}: {}",
+                    origin.1, bucket, err
+                ))
+            });
+
+            (key, upload_id, part, e_tag)
+        }));
+
+        // happy
+        Ok(())
+    }
+
+    /// Waits for all in-flight part copies to complete.
+    pub async fn flush(&mut self) -> UtilResult<()> {
+        self.drain(0).await
+    }
+
+    /// Waits for the next in-flight part copy to complete, if there is one.
+    pub async fn wait(&mut self) -> UtilResult<bool> {
+        if self.pending.is_empty() {
+            return Ok(false);
+        }
+
+        self.drain(self.pending.len() - 1).await?;
+
+        Ok(true)
+    }
+
+    /// Waits for in-flight part copies until at most `limit` remain.
+    ///
+    /// If any copy fails, all other in-flight copies are still awaited (so
+    /// nothing is left running against an upload about to be aborted) and
+    /// the first failure is returned. When keeping going, failures are only
+    /// recorded against their targets instead.
+    async fn drain(&mut self, mut limit: usize) -> UtilResult<()> {
+        let mut failure: Option<UtilError> = None;
+
+        while self.pending.len() > limit {
+            let (key, upload_id, mut part, copied) = match self.pending.next().await {
+                Some(completed) => completed,
+                None => break,
+            };
+
+            // the target can settle once nothing else is in flight
+            if let Some(count) = self.inflight.get_mut(&key) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.inflight.remove(&key);
+                }
+            }
+
+            // record the part for completion and removal
+            let recorded = copied.and_then(|e_tag| {
+                part.e_tag = e_tag;
+                self.state.record(&upload_id, part)
+            });
+
+            // keep only the first failure, and wait for everything else
+            if let Err(err) = recorded {
+                if self.keep_going {
+                    self.fail(&key, err)?;
+                    continue;
+                }
+
+                if failure.is_none() {
+                    failure = Some(err);
+                }
+                limit = 0;
+            }
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Copies a single piece of a source into a part, server side.
+async fn copy_part(
+    s3: &S3Client,
+    target: Target<'_>,
+    origin: Origin<'_>,
+    piece: &Piece,
+    retry: &Retry,
+) -> UtilResult<Option<String>> {
+    let (bucket, key, upload_id, part_number) = target;
+
+    // create the copy request for the existing key
+    let copy_request = UploadPartCopyRequest {
+        bucket: bucket.to_string(),
+        copy_source: client::copy_source(origin.1, &piece.key),
+        copy_source_range: Some(piece.range()).filter(|_| !piece.is_whole()),
+        copy_source_if_match: piece.e_tag.clone(),
+        part_number,
+        key: key.to_string(),
+        upload_id: upload_id.to_string(),
+        ..UploadPartCopyRequest::default()
+    };
+
+    // carry out the request for the part copy
+    let copied = retry
+        .run("part copy", || s3.upload_part_copy(copy_request.clone()))
+        .await?;
+
+    Ok(copied.copy_part_result.and_then(|result| result.e_tag))
+}
+
+/// Downloads several pieces and uploads them together as a single part.
+async fn pack_part(
+    s3: &S3Client,
+    target: Target<'_>,
+    origin: Origin<'_>,
+    pieces: &[Piece],
+) -> UtilResult<Option<String>> {
+    let (bucket, key, upload_id, part_number) = target;
+    let (origin_s3, origin_bucket) = origin;
+    let mut buffer = Vec::new();
+
+    // download each piece into the buffer
+    for piece in pieces {
+        if let Some(ref data) = piece.data {
+            buffer.extend_from_slice(data);
+            continue;
+        }
+
+        let request = GetObjectRequest {
+            bucket: origin_bucket.to_string(),
+            key: piece.key.to_string(),
+            range: Some(piece.range()).filter(|_| !piece.is_whole()),
+            if_match: piece.e_tag.clone(),
+            ..GetObjectRequest::default()
+        };
+
+        let object = origin_s3.get_object(request).await?;
+
+        if let Some(mut body) = object.body {
+            while let Some(chunk) = body.next().await {
+                buffer.extend_from_slice(&chunk?);
+            }
+        }
+    }
+
+    // upload the buffer as the part
+    let upload_request = UploadPartRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        content_length: Some(buffer.len() as i64),
+        body: Some(buffer.into()),
+        part_number,
+        upload_id: upload_id.to_string(),
+        ..UploadPartRequest::default()
+    };
+
+    let uploaded = s3.upload_part(upload_request).await?;
+
+    Ok(uploaded.e_tag)
+}
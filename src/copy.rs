@@ -0,0 +1,367 @@
+//! Server side copies of single objects, shared by commands moving them.
+//!
+//! A copy is a new object, so anything `CopyObject` doesn't carry across
+//! (such as an ACL) has to be read from the source and applied separately.
+//! S3 also limits a single copy to 5GiB, so anything larger is copied in
+//! ranged parts of a multipart upload instead, which carries the metadata and
+//! tags of the source across explicitly (as an upload wouldn't keep them).
+use rusoto_s3::*;
+
+use crate::retry::Retry;
+use crate::tags;
+use crate::types::UtilResult;
+
+/// Largest object which can be copied by a single request.
+pub const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+/// Storage classes which must be restored before they can be copied.
+const ARCHIVED_CLASSES: [&str; 2] = ["GLACIER", "DEEP_ARCHIVE"];
+
+/// Copies an object of a known size, as described by a copy request.
+///
+/// The source bucket and key are only read when the object is too large to
+/// copy in a single request. Returns the server side encryption of the copy.
+pub async fn copy(
+    s3: &S3Client,
+    request: &CopyObjectRequest,
+    source: (&str, &str),
+    size: i64,
+    retry: &Retry,
+) -> UtilResult<Option<String>> {
+    if size <= MAX_COPY_SIZE {
+        let copied = retry
+            .run("copy", || s3.copy_object(request.clone()))
+            .await?;
+        return Ok(copied.server_side_encryption);
+    }
+
+    // create the upload, carrying across everything a copy would
+    let creation = upload_request(s3, request, source, retry).await?;
+    let created = retry
+        .run("create upload", || {
+            s3.create_multipart_upload(creation.clone())
+        })
+        .await?;
+    let upload_id = created.upload_id.ok_or("Missing upload identifier")?;
+
+    let copied = copy_parts(s3, request, &upload_id, size, retry).await;
+
+    // attempt to abort a failed upload, leaving it on failure
+    if copied.is_err() {
+        let abort = AbortMultipartUploadRequest {
+            key: request.key.clone(),
+            bucket: request.bucket.clone(),
+            upload_id,
+            request_payer: request.request_payer.clone(),
+            ..AbortMultipartUploadRequest::default()
+        };
+
+        if s3.abort_multipart_upload(abort).await.is_err() {
+            error!("Unable to abort upload of {}", request.key);
+        }
+    }
+
+    copied
+}
+
+/// Constructs the request creating an upload to copy an object in parts.
+///
+/// Metadata and tags are read from the source, unless the copy request
+/// replaces them (in which case they're taken from the copy request).
+async fn upload_request(
+    s3: &S3Client,
+    request: &CopyObjectRequest,
+    source: (&str, &str),
+    retry: &Retry,
+) -> UtilResult<CreateMultipartUploadRequest> {
+    let (bucket, key) = source;
+    let payer = request.request_payer.as_deref();
+    let replaces = |directive: &Option<String>| directive.as_deref() == Some("REPLACE");
+
+    let head_request = HeadObjectRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        request_payer: request.request_payer.clone(),
+        ..HeadObjectRequest::default()
+    };
+
+    let head = retry
+        .run("head", || s3.head_object(head_request.clone()))
+        .await?;
+
+    let creation = CreateMultipartUploadRequest {
+        bucket: request.bucket.clone(),
+        key: request.key.clone(),
+        storage_class: request.storage_class.clone(),
+        server_side_encryption: request.server_side_encryption.clone(),
+        ssekms_key_id: request.ssekms_key_id.clone(),
+        object_lock_mode: request.object_lock_mode.clone(),
+        object_lock_retain_until_date: request.object_lock_retain_until_date.clone(),
+        object_lock_legal_hold_status: request.object_lock_legal_hold_status.clone(),
+        request_payer: request.request_payer.clone(),
+        ..CreateMultipartUploadRequest::default()
+    };
+
+    let creation = if replaces(&request.metadata_directive) {
+        CreateMultipartUploadRequest {
+            content_type: request.content_type.clone(),
+            metadata: request.metadata.clone(),
+            ..creation
+        }
+    } else {
+        CreateMultipartUploadRequest {
+            cache_control: head.cache_control,
+            content_disposition: head.content_disposition,
+            content_encoding: head.content_encoding,
+            content_language: head.content_language,
+            content_type: head.content_type,
+            expires: head.expires,
+            metadata: head.metadata,
+            ..creation
+        }
+    };
+
+    let tagging = if replaces(&request.tagging_directive) {
+        request.tagging.clone()
+    } else {
+        let tagged = read_tags(s3, bucket, key, payer).await?;
+        let pairs = tagged
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        Some(tags::encode(&pairs)).filter(|_| !pairs.is_empty())
+    };
+
+    Ok(CreateMultipartUploadRequest {
+        tagging,
+        ..creation
+    })
+}
+
+/// Copies an object into an upload in ranged parts, completing the upload.
+async fn copy_parts(
+    s3: &S3Client,
+    request: &CopyObjectRequest,
+    upload_id: &str,
+    size: i64,
+    retry: &Retry,
+) -> UtilResult<Option<String>> {
+    let mut parts = Vec::new();
+
+    // copy each range of the source as a part
+    for (range, part_number) in part_ranges(size).into_iter().zip(1..) {
+        let copy = UploadPartCopyRequest {
+            bucket: request.bucket.clone(),
+            key: request.key.clone(),
+            upload_id: upload_id.to_string(),
+            part_number,
+            copy_source: request.copy_source.clone(),
+            copy_source_range: Some(range),
+            request_payer: request.request_payer.clone(),
+            ..UploadPartCopyRequest::default()
+        };
+
+        let copied = retry
+            .run("part copy", || s3.upload_part_copy(copy.clone()))
+            .await?;
+
+        parts.push(CompletedPart {
+            e_tag: copied.copy_part_result.and_then(|result| result.e_tag),
+            part_number: Some(part_number),
+        });
+    }
+
+    // complete the upload with every part
+    let completion = CompleteMultipartUploadRequest {
+        bucket: request.bucket.clone(),
+        key: request.key.clone(),
+        upload_id: upload_id.to_string(),
+        multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+        request_payer: request.request_payer.clone(),
+        ..CompleteMultipartUploadRequest::default()
+    };
+
+    let completed = retry
+        .run("complete upload", || {
+            s3.complete_multipart_upload(completion.clone())
+        })
+        .await?;
+
+    Ok(completed.server_side_encryption)
+}
+
+/// Splits an object into the byte ranges copied as each part.
+fn part_ranges(size: i64) -> Vec<String> {
+    (0..size)
+        .step_by(MAX_COPY_SIZE as usize)
+        .map(|start| format!("bytes={}-{}", start, (start + MAX_COPY_SIZE).min(size) - 1))
+        .collect()
+}
+
+/// Reads the ACL of an object, to apply onto a copy.
+pub async fn read_acl(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> UtilResult<AccessControlPolicy> {
+    let acl = s3
+        .get_object_acl(GetObjectAclRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            request_payer: request_payer.map(str::to_string),
+            ..GetObjectAclRequest::default()
+        })
+        .await?;
+
+    Ok(AccessControlPolicy {
+        grants: acl.grants,
+        owner: acl.owner,
+    })
+}
+
+/// Applies an ACL read by `read_acl` onto an object.
+pub async fn write_acl(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    acl: AccessControlPolicy,
+    request_payer: Option<&str>,
+) -> UtilResult<()> {
+    s3.put_object_acl(acl_request(bucket, key, acl, request_payer))
+        .await?;
+
+    Ok(())
+}
+
+/// Constructs a request applying an ACL onto an object.
+pub fn acl_request(
+    bucket: &str,
+    key: &str,
+    acl: AccessControlPolicy,
+    request_payer: Option<&str>,
+) -> PutObjectAclRequest {
+    PutObjectAclRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        access_control_policy: Some(acl),
+        request_payer: request_payer.map(str::to_string),
+        ..PutObjectAclRequest::default()
+    }
+}
+
+/// Reads the tags of an object, sorted so they can be compared.
+pub async fn read_tags(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> UtilResult<Vec<(String, String)>> {
+    let mut tags = s3
+        .get_object_tagging(tags_request(bucket, key, request_payer))
+        .await?
+        .tag_set
+        .into_iter()
+        .map(|tag| (tag.key, tag.value))
+        .collect::<Vec<_>>();
+
+    tags.sort();
+
+    Ok(tags)
+}
+
+/// Constructs a request reading the tags of an object.
+pub fn tags_request(
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> GetObjectTaggingRequest {
+    GetObjectTaggingRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        request_payer: request_payer.map(str::to_string),
+        ..GetObjectTaggingRequest::default()
+    }
+}
+
+/// Determines the storage class of an object, as listed.
+///
+/// Listings from some stores leave out the storage class, so it's read from
+/// the object instead (where a missing class means `STANDARD`). Archived
+/// objects are always checked, and fail unless they've been restored.
+pub async fn storage_class(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    listed: Option<String>,
+    request_payer: Option<&str>,
+) -> UtilResult<Option<String>> {
+    let is_archived = |class: &Option<String>| {
+        class
+            .as_deref()
+            .filter(|class| ARCHIVED_CLASSES.contains(class))
+            .is_some()
+    };
+
+    if listed.is_some() && !is_archived(&listed) {
+        return Ok(listed);
+    }
+
+    let request = HeadObjectRequest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        request_payer: request_payer.map(str::to_string),
+        ..HeadObjectRequest::default()
+    };
+
+    let head = s3.head_object(request).await?;
+    let class = head.storage_class.or(listed);
+
+    if is_archived(&class) && !is_restored(head.restore.as_deref()) {
+        return Err(format!(
+            "Object is in {} and must be restored before it can be copied",
+            class.unwrap_or_default()
+        )
+        .into());
+    }
+
+    Ok(class)
+}
+
+/// Determines whether a restore header shows a completed restoration.
+fn is_restored(restore: Option<&str>) -> bool {
+    restore
+        .filter(|restore| restore.contains("ongoing-request=\"false\""))
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_restored, part_ranges, MAX_COPY_SIZE};
+
+    #[test]
+    fn detecting_restored_objects() {
+        assert!(is_restored(Some(
+            "ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\""
+        )));
+
+        assert!(!is_restored(Some("ongoing-request=\"true\"")));
+        assert!(!is_restored(None));
+    }
+
+    #[test]
+    fn splitting_large_copies() {
+        let size = MAX_COPY_SIZE * 2 + 1;
+
+        assert_eq!(
+            part_ranges(size),
+            vec![
+                format!("bytes=0-{}", MAX_COPY_SIZE - 1),
+                format!("bytes={}-{}", MAX_COPY_SIZE, MAX_COPY_SIZE * 2 - 1),
+                format!("bytes={}-{}", MAX_COPY_SIZE * 2, MAX_COPY_SIZE * 2),
+            ]
+        );
+
+        assert_eq!(part_ranges(MAX_COPY_SIZE).len(), 1);
+    }
+}
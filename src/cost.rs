@@ -0,0 +1,307 @@
+//! Request accounting and cost estimation for S3 API calls.
+//!
+//! Every request made to S3 is counted by type as it's dispatched, which
+//! allows a summary of the calls made during a run to be printed at the
+//! end, alongside an estimated cost based on standard request pricing.
+use clap::ArgMatches;
+use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture, HttpClient};
+use rusoto_core::signature::{Params, SignedRequest};
+use serde_json::{Map, Value};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::types::UtilResult;
+
+/// Request operation types, grouped the same way as S3 pricing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    List,
+    Get,
+    Put,
+    Delete,
+    Head,
+}
+
+/// All operation types, in printing order.
+const OPERATIONS: [Operation; 5] = [
+    Operation::List,
+    Operation::Get,
+    Operation::Put,
+    Operation::Delete,
+    Operation::Head,
+];
+
+/// Global request counters, indexed by operation type.
+static COUNTERS: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Main implementation.
+impl Operation {
+    /// Retrieves the label used to name this operation type.
+    fn label(self) -> &'static str {
+        match self {
+            Operation::List => "list",
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::Delete => "delete",
+            Operation::Head => "head",
+        }
+    }
+}
+
+/// Pricing structure containing the cost of each 1,000 requests.
+pub struct Pricing {
+    rates: [f64; 5],
+}
+
+/// Main implementation.
+impl Pricing {
+    /// Constructs `Pricing` from any overrides in the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> UtilResult<Pricing> {
+        match args.value_of("request_pricing") {
+            Some(overrides) => Pricing::parse(overrides),
+            None => Ok(Pricing::default()),
+        }
+    }
+
+    /// Parses pricing overrides from a set of `type=price` pairs.
+    ///
+    /// Any operation types which are not overridden will use the default
+    /// (standard tier) pricing.
+    pub fn parse(overrides: &str) -> UtilResult<Pricing> {
+        let mut pricing = Pricing::default();
+
+        for pair in overrides.split(',').filter(|pair| !pair.is_empty()) {
+            let mut splitn = pair.splitn(2, '=');
+
+            let label = splitn.next().unwrap().trim();
+            let price = splitn
+                .next()
+                .and_then(|price| price.trim().parse::<f64>().ok())
+                .ok_or_else(|| format!("Invalid request pricing: {}", pair))?;
+
+            let idx = OPERATIONS
+                .iter()
+                .position(|op| op.label() == label)
+                .ok_or_else(|| format!("Unknown request type: {}", label))?;
+
+            pricing.rates[idx] = price;
+        }
+
+        Ok(pricing)
+    }
+
+    /// Calculates the estimated cost of a number of operations.
+    fn cost(&self, op: Operation, count: u64) -> f64 {
+        self.rates[op as usize] * count as f64 / 1000.0
+    }
+}
+
+/// Default pricing, based on the S3 Standard tier in us-east-1.
+impl Default for Pricing {
+    fn default() -> Pricing {
+        Pricing {
+            rates: [0.005, 0.0004, 0.005, 0.0, 0.0004],
+        }
+    }
+}
+
+/// Snapshot of the request counts and estimated cost at a point in time.
+pub struct Snapshot {
+    counts: [u64; 5],
+    estimated_cost: f64,
+}
+
+/// Main implementation.
+impl Snapshot {
+    /// Retrieves the labelled count of each operation type, in printing order.
+    pub fn counts(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        OPERATIONS.iter().map(move |op| {
+            (
+                format!("{}_requests", op.label()),
+                self.counts[*op as usize],
+            )
+        })
+    }
+
+    /// Retrieves the estimated cost of all requests counted.
+    pub fn estimated_cost(&self) -> f64 {
+        self.estimated_cost
+    }
+
+    /// Converts this snapshot into a JSON object of counts and cost.
+    pub fn to_json(&self) -> Value {
+        let mut object: Map<String, Value> = self
+            .counts()
+            .map(|(label, count)| (label, count.into()))
+            .collect();
+
+        object.insert("estimated_cost".to_string(), self.estimated_cost.into());
+        Value::Object(object)
+    }
+}
+
+/// Request dispatcher to count requests before they're sent to S3.
+pub struct CountingDispatcher {
+    inner: HttpClient,
+}
+
+/// Main implementation.
+impl CountingDispatcher {
+    /// Constructs a new `CountingDispatcher` wrapping an `HttpClient`.
+    pub fn new(inner: HttpClient) -> CountingDispatcher {
+        CountingDispatcher { inner }
+    }
+}
+
+/// Dispatch implementation.
+impl DispatchSignedRequest for CountingDispatcher {
+    /// Counts an S3 request, before passing it through to be dispatched.
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        if request.service == "s3" {
+            let op = classify(&request.method, &request.path, &request.params);
+            COUNTERS[op as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.dispatch(request, timeout)
+    }
+}
+
+/// Retrieves the number of requests made of an operation type.
+pub fn count(op: Operation) -> u64 {
+    COUNTERS[op as usize].load(Ordering::Relaxed)
+}
+
+/// Takes a snapshot of the requests made so far, priced by `pricing`.
+pub fn snapshot(pricing: &Pricing) -> Snapshot {
+    let mut counts = [0; 5];
+    let mut estimated_cost = 0.0;
+
+    for op in &OPERATIONS {
+        counts[*op as usize] = count(*op);
+        estimated_cost += pricing.cost(*op, counts[*op as usize]);
+    }
+
+    Snapshot {
+        counts,
+        estimated_cost,
+    }
+}
+
+/// Prints the request counts and estimated cost under a `requests` header.
+///
+/// This is written to stderr, so it never mixes with any output of the
+/// command itself (such as records or reports) written to stdout.
+pub fn print(pricing: &Pricing) {
+    let snapshot = snapshot(pricing);
+
+    eprintln!("\n[requests]");
+
+    for (label, count) in snapshot.counts() {
+        eprintln!("{}={}", label, count);
+    }
+
+    eprintln!("estimated_cost=${:.6}", snapshot.estimated_cost());
+}
+
+/// Classifies a request into the operation type used for pricing.
+fn classify(method: &str, path: &str, params: &Params) -> Operation {
+    match method {
+        "HEAD" => Operation::Head,
+        "DELETE" => Operation::Delete,
+        "POST" if params.contains_key("delete") => Operation::Delete,
+        "PUT" | "POST" => Operation::Put,
+        _ => {
+            // list calls are all made against buckets or uploads
+            let listing = ["list-type", "uploads", "versions", "uploadId"]
+                .iter()
+                .any(|param| params.contains_key(*param));
+
+            // bucket paths (without any keys) are listings without params
+            let bucket_path = path.trim_matches('/').find('/').is_none();
+
+            if listing || (bucket_path && params.is_empty()) {
+                Operation::List
+            } else {
+                Operation::Get
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, Operation, Pricing, Snapshot};
+    use rusoto_core::signature::Params;
+    use serde_json::json;
+
+    fn params(keys: &[&str]) -> Params {
+        keys.iter().map(|key| (key.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn classifying_requests() {
+        let cases = [
+            ("GET", "/bucket", params(&["list-type"]), Operation::List),
+            ("GET", "/bucket/key", params(&["uploadId"]), Operation::List),
+            ("GET", "/", params(&[]), Operation::List),
+            ("GET", "/bucket/key", params(&[]), Operation::Get),
+            ("GET", "/bucket", params(&["location"]), Operation::Get),
+            ("HEAD", "/bucket/key", params(&[]), Operation::Head),
+            (
+                "PUT",
+                "/bucket/key",
+                params(&["partNumber"]),
+                Operation::Put,
+            ),
+            ("POST", "/bucket/key", params(&["uploads"]), Operation::Put),
+            ("POST", "/bucket", params(&["delete"]), Operation::Delete),
+            ("DELETE", "/bucket/key", params(&[]), Operation::Delete),
+        ];
+
+        for (method, path, params, expected) in &cases {
+            assert_eq!(classify(method, path, params), *expected);
+        }
+    }
+
+    #[test]
+    fn parsing_pricing_overrides() {
+        let pricing = Pricing::parse("list=0.01,delete=0.5").unwrap();
+
+        assert_eq!(pricing.cost(Operation::List, 1000), 0.01);
+        assert_eq!(pricing.cost(Operation::Delete, 2000), 1.0);
+        assert_eq!(pricing.cost(Operation::Get, 10000), 0.004);
+
+        assert!(Pricing::parse("list").is_err());
+        assert!(Pricing::parse("fake=0.1").is_err());
+    }
+
+    #[test]
+    fn converting_snapshots_to_json() {
+        let snapshot = Snapshot {
+            counts: [1, 2, 3, 4, 5],
+            estimated_cost: 0.5,
+        };
+
+        assert_eq!(
+            snapshot.to_json(),
+            json!({
+                "list_requests": 1,
+                "get_requests": 2,
+                "put_requests": 3,
+                "delete_requests": 4,
+                "head_requests": 5,
+                "estimated_cost": 0.5,
+            })
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Date tokens within targets, resolved from their sources.
+//!
+//! Targets can reference the modification dates of their sources using
+//! `${maxdate:<format>}` and `${mindate:<format>}`, with `strftime` style
+//! formatting. These depend on every source of a target, so they can only
+//! be resolved once all sources have been grouped by the rest of the target.
+//! Until then each token is held in the template as an opaque placeholder.
+//!
+//! When a date source is provided, bare `strftime` directives (such as `%Y`)
+//! in a target are also treated as date tokens, formatting the time of the
+//! run or the newest or oldest modification date of the sources.
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+
+use crate::types::UtilResult;
+
+/// Target template with all date tokens swapped out for placeholders.
+pub struct DatedTemplate {
+    template: String,
+    tokens: Vec<Token>,
+    now: DateTime<Utc>,
+}
+
+/// Timestamps which can be used to format a date token.
+#[derive(Clone, Copy)]
+pub enum DateSource {
+    Now,
+    Newest,
+    Oldest,
+}
+
+/// Date token parsed from a target template.
+struct Token {
+    source: DateSource,
+    format: String,
+}
+
+/// Range of modification dates covered by the sources of a target.
+#[derive(Default)]
+pub struct DateRange {
+    earliest: Option<DateTime<Utc>>,
+    latest: Option<DateTime<Utc>>,
+}
+
+/// Main implementation.
+impl DatedTemplate {
+    /// Parses a target template, extracting any date tokens.
+    ///
+    /// Bare `strftime` directives are only extracted when a source is given
+    /// for them, otherwise they're left in the template as literal text.
+    pub fn parse(template: &str, bare: Option<DateSource>) -> UtilResult<DatedTemplate> {
+        let mut stripped = String::with_capacity(template.len());
+        let mut tokens = Vec::new();
+        let mut rest = template;
+
+        while let Some(idx) = rest.find(|c| c == '$' || (c == '%' && bare.is_some())) {
+            stripped.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+
+            // bare directives are formatted from the provided source
+            if let (Some(source), true) = (bare, rest.starts_with('%')) {
+                // escaped percent signs are left as literals
+                if rest.starts_with("%%") {
+                    stripped.push('%');
+                    rest = &rest[2..];
+                    continue;
+                }
+
+                let len = directive_len(rest)
+                    .ok_or_else(|| format!("Unfinished date directive in target: {}", template))?;
+
+                let format = &rest[..len];
+                validate(format)?;
+
+                stripped.push_str(&placeholder(tokens.len()));
+                tokens.push(Token {
+                    source,
+                    format: format.to_string(),
+                });
+
+                rest = &rest[len..];
+                continue;
+            }
+
+            // escapes are left for the regex replacement
+            if rest.starts_with("$$") {
+                stripped.push_str("$$");
+                rest = &rest[2..];
+                continue;
+            }
+
+            // anything other than a date token is a group reference
+            let source = if rest.starts_with("${maxdate:") {
+                DateSource::Newest
+            } else if rest.starts_with("${mindate:") {
+                DateSource::Oldest
+            } else {
+                stripped.push('$');
+                rest = &rest[1..];
+                continue;
+            };
+
+            // locate the end of the date format
+            let end = rest
+                .find('}')
+                .ok_or_else(|| format!("Unclosed date token in target: {}", template))?;
+            let format = &rest[10..end];
+
+            validate(format)?;
+
+            stripped.push_str(&placeholder(tokens.len()));
+            tokens.push(Token {
+                source,
+                format: format.to_string(),
+            });
+
+            rest = &rest[end + 1..];
+        }
+
+        stripped.push_str(rest);
+
+        Ok(DatedTemplate {
+            template: stripped,
+            tokens,
+            now: Utc::now(),
+        })
+    }
+
+    /// Retrieves the template to render, containing any placeholders.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Retrieves the literal prefix shared by every target rendered.
+    ///
+    /// This is everything before the first group reference or date token,
+    /// as anything after that can only be known once a source is rendered.
+    pub fn prefix(&self) -> &str {
+        let end = self
+            .template
+            .find(|c| c == '$' || c == '\0')
+            .unwrap_or_else(|| self.template.len());
+
+        &self.template[..end]
+    }
+
+    /// Determines whether this template contains any date tokens.
+    pub fn is_dated(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Determines whether any date token is formatted from source dates.
+    pub fn needs_dates(&self) -> bool {
+        self.tokens
+            .iter()
+            .any(|token| !matches!(token.source, DateSource::Now))
+    }
+
+    /// Resolves all placeholders in a rendered target using a date range.
+    pub fn resolve(&self, rendered: &str, range: &DateRange) -> String {
+        let mut resolved = rendered.to_string();
+
+        for (idx, token) in self.tokens.iter().enumerate() {
+            let date = match token.source {
+                DateSource::Now => Some(self.now),
+                DateSource::Newest => range.latest,
+                DateSource::Oldest => range.earliest,
+            };
+
+            let formatted = date
+                .map(|date| date.format(&token.format).to_string())
+                .unwrap_or_default();
+
+            resolved = resolved.replace(&placeholder(idx), &formatted);
+        }
+
+        resolved
+    }
+}
+
+/// Main implementation.
+impl DateRange {
+    /// Extends this range to include an RFC 3339 modification date.
+    pub fn include(&mut self, date: &str) -> UtilResult<()> {
+        let date = DateTime::parse_from_rfc3339(date)?.with_timezone(&Utc);
+
+        if self.earliest.map_or(true, |earliest| date < earliest) {
+            self.earliest = Some(date);
+        }
+
+        if self.latest.map_or(true, |latest| date > latest) {
+            self.latest = Some(date);
+        }
+
+        Ok(())
+    }
+}
+
+/// Validates a date format up front, as formatting would otherwise panic.
+fn validate(format: &str) -> UtilResult<()> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("Invalid date format in target: {}", format).into());
+    }
+    Ok(())
+}
+
+/// Determines the length of the bare directive at the start of a template.
+///
+/// Directives are a `%`, followed by an optional padding flag (`-`, `_` or
+/// `0`), an optional `.`, `:` or `#` prefix and width, and a specifier.
+fn directive_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1).peekable();
+
+    if let Some((_, '-')) | Some((_, '_')) | Some((_, '0')) = chars.peek() {
+        chars.next();
+    }
+
+    if let Some((_, '.')) | Some((_, ':')) | Some((_, '#')) = chars.peek() {
+        chars.next();
+    }
+
+    while let Some((_, c)) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        chars.next();
+    }
+
+    chars.next().map(|(idx, c)| idx + c.len_utf8())
+}
+
+/// Generates the placeholder used for a date token in a template.
+///
+/// NUL characters will pass through regex replacement untouched, and are
+/// never realistically going to appear within an object key.
+fn placeholder(idx: usize) -> String {
+    format!("\0{}\0", idx)
+}
+
+/// Parses the modification date of an object, if it has a valid one.
+///
+/// An invalid date is treated the same as a missing one rather than failing
+/// the run, so such objects are sorted first by concatenation (in the order
+/// of their keys), and never match a modification window.
+pub fn parse_modified(last_modified: Option<&str>) -> Option<DateTime<Utc>> {
+    last_modified
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateRange, DateSource, DatedTemplate};
+    use regex::Regex;
+
+    #[test]
+    fn resolving_date_tokens() {
+        let template =
+            DatedTemplate::parse("merged/$1/${mindate:%Y%m%d}-${maxdate:%Y-%m-%d}.log", None)
+                .unwrap();
+
+        assert!(template.is_dated());
+        assert!(template.needs_dates());
+
+        let now = DatedTemplate::parse("merged/%Y/$1.log", Some(DateSource::Now)).unwrap();
+
+        assert!(now.is_dated());
+        assert!(!now.needs_dates());
+
+        let pattern = Regex::new(r"logs/(\w+)/.*").unwrap();
+        let rendered = pattern.replace_all("logs/app/a.log", template.template());
+
+        let mut range = DateRange::default();
+        range.include("2018-01-03T10:00:00.000Z").unwrap();
+        range.include("2018-01-01T10:00:00.000Z").unwrap();
+        range.include("2018-01-02T10:00:00.000Z").unwrap();
+
+        assert_eq!(
+            template.resolve(&rendered, &range),
+            "merged/app/20180101-2018-01-03.log"
+        );
+    }
+
+    #[test]
+    fn leaving_other_references() {
+        let template = DatedTemplate::parse("$1-${name}-$${maxdate:%Y}.gz", None).unwrap();
+
+        assert!(!template.is_dated());
+        assert_eq!(template.template(), "$1-${name}-$${maxdate:%Y}.gz");
+    }
+
+    #[test]
+    fn finding_literal_prefixes() {
+        let prefix = |template: &str, bare: Option<DateSource>| {
+            DatedTemplate::parse(template, bare)
+                .unwrap()
+                .prefix()
+                .to_string()
+        };
+
+        assert_eq!(prefix("merged/$1.log", None), "merged/");
+        assert_eq!(prefix("merged/${maxdate:%Y}/$1.log", None), "merged/");
+        assert_eq!(prefix("merged/%Y/$1.log", None), "merged/%Y/");
+        assert_eq!(prefix("merged/%Y/$1.log", Some(DateSource::Now)), "merged/");
+        assert_eq!(prefix("merged.log", None), "merged.log");
+        assert_eq!(prefix("$1.log", None), "");
+    }
+
+    #[test]
+    fn rejecting_invalid_date_tokens() {
+        assert!(DatedTemplate::parse("${maxdate:%Y", None).is_err());
+        assert!(DatedTemplate::parse("${maxdate:%Q}", None).is_err());
+        assert!(DatedTemplate::parse("%Q", Some(DateSource::Now)).is_err());
+        assert!(DatedTemplate::parse("merged/%", Some(DateSource::Now)).is_err());
+    }
+
+    #[test]
+    fn resolving_bare_date_tokens() {
+        let pattern = Regex::new(r"logs/(\w+)/.*").unwrap();
+        let rendered = |template: &DatedTemplate| {
+            pattern
+                .replace_all("logs/app/a.log", template.template())
+                .to_string()
+        };
+
+        let mut range = DateRange::default();
+        range.include("2018-01-03T10:00:00.000Z").unwrap();
+        range.include("2018-01-01T10:00:00.000Z").unwrap();
+
+        let template = "merged/%Y/%m/%-d/$1-100%%.log";
+
+        let newest = DatedTemplate::parse(template, Some(DateSource::Newest)).unwrap();
+        let oldest = DatedTemplate::parse(template, Some(DateSource::Oldest)).unwrap();
+
+        assert_eq!(
+            newest.resolve(&rendered(&newest), &range),
+            "merged/2018/01/3/app-100%.log"
+        );
+        assert_eq!(
+            oldest.resolve(&rendered(&oldest), &range),
+            "merged/2018/01/1/app-100%.log"
+        );
+
+        // without a date source, directives are left alone
+        let literal = DatedTemplate::parse(template, None).unwrap();
+
+        assert!(!literal.is_dated());
+        assert_eq!(literal.template(), template);
+    }
+}
@@ -0,0 +1,309 @@
+//! Glob based filtering of object keys, shared across all commands.
+//!
+//! Globs are compiled down into regular expressions, using the following
+//! semantics against `/` separated keys (the same as `globset` when using
+//! a literal separator):
+//!
+//! * `*` matches any sequence of characters, except for `/`.
+//! * `?` matches any single character, except for `/`.
+//! * `**` matches any sequence of characters, including `/`, but only
+//!   when it makes up an entire path component (`**/`, `/**/` or `/**`).
+//!   Elsewhere it is treated the same as a single `*`.
+//! * `[abc]` and `[!abc]` match (or don't match) a set of characters.
+//! * `{a,b}` matches either of the provided alternatives.
+//! * `\` escapes the character following it.
+//!
+//! Globs are always matched against the entire object key. Wildcards can
+//! also be compiled into capture groups, numbered in the order they appear
+//! within the glob (starting at 1), so that they can be referenced.
+use clap::ArgMatches;
+use regex::RegexSet;
+
+use crate::types::UtilResult;
+
+/// Filter structure to include or exclude object keys using globs.
+///
+/// A key is accepted when it matches at least one include glob (if any
+/// were provided), and does not match any of the exclude globs.
+#[derive(Clone, Default)]
+pub struct Filter {
+    include: Option<RegexSet>,
+    exclude: Option<RegexSet>,
+}
+
+/// Main implementation.
+impl Filter {
+    /// Constructs a new `Filter` from include and exclude globs.
+    pub fn new(include: &[&str], exclude: &[&str]) -> UtilResult<Filter> {
+        Ok(Filter {
+            include: compile_set(include)?,
+            exclude: compile_set(exclude)?,
+        })
+    }
+
+    /// Constructs a new `Filter` from the shared filter arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> UtilResult<Filter> {
+        let include = args
+            .values_of("include_glob")
+            .map(|values| values.collect())
+            .unwrap_or_else(Vec::new);
+
+        let exclude = args
+            .values_of("exclude_glob")
+            .map(|values| values.collect())
+            .unwrap_or_else(Vec::new);
+
+        Filter::new(&include, &exclude)
+    }
+
+    /// Determines if a key is accepted by this filter.
+    pub fn is_match(&self, key: &str) -> bool {
+        if let Some(ref include) = self.include {
+            if !include.is_match(key) {
+                return false;
+            }
+        }
+
+        if let Some(ref exclude) = self.exclude {
+            if exclude.is_match(key) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Determines whether a value contains any glob syntax.
+pub fn is_glob(value: &str) -> bool {
+    value.contains(|c| matches!(c, '*' | '?' | '[' | '{'))
+}
+
+/// Compiles a glob into an anchored regular expression.
+pub fn compile(glob: &str) -> UtilResult<String> {
+    translate(glob, false)
+}
+
+/// Compiles a glob into an anchored regular expression, capturing wildcards.
+pub fn compile_captures(glob: &str) -> UtilResult<String> {
+    translate(glob, true)
+}
+
+/// Translates a glob into an anchored regular expression.
+fn translate(glob: &str, capture: bool) -> UtilResult<String> {
+    let chars = glob.chars().collect::<Vec<_>>();
+    let mut regex = String::from("^");
+    let mut braces = 0;
+    let mut idx = 0;
+
+    // wildcards are only wrapped in a group when capturing
+    let wildcard = |regex: &mut String, pattern: &str| {
+        if capture {
+            regex.push('(');
+            regex.push_str(pattern);
+            regex.push(')');
+        } else {
+            regex.push_str(pattern);
+        }
+    };
+
+    while idx < chars.len() {
+        match chars[idx] {
+            // double stars may span components
+            '*' if chars.get(idx + 1) == Some(&'*') => {
+                let component_start = idx == 0 || chars[idx - 1] == '/';
+                let component_end = idx + 2 == chars.len() || chars[idx + 2] == '/';
+
+                if component_start && idx + 2 == chars.len() {
+                    wildcard(&mut regex, ".*");
+                    idx += 2;
+                } else if component_start && component_end {
+                    wildcard(&mut regex, "(?:.*/)?");
+                    idx += 3;
+                } else {
+                    wildcard(&mut regex, "[^/]*");
+                    idx += 2;
+                }
+                continue;
+            }
+
+            // single wildcards never cross components
+            '*' => wildcard(&mut regex, "[^/]*"),
+            '?' => wildcard(&mut regex, "[^/]"),
+
+            // character classes
+            '[' => {
+                let end = chars[idx + 1..]
+                    .iter()
+                    .skip(1)
+                    .position(|c| *c == ']')
+                    .map(|pos| idx + pos + 2)
+                    .ok_or_else(|| format!("Unclosed character class in glob: {}", glob))?;
+
+                let mut class = &chars[idx + 1..end];
+                let mut pattern = String::from("[");
+
+                if class.first() == Some(&'!') {
+                    pattern.push('^');
+                    class = &class[1..];
+                }
+                for c in class {
+                    match c {
+                        '\\' | '[' | ']' | '^' | '&' | '~' => {
+                            pattern.push('\\');
+                            pattern.push(*c);
+                        }
+                        _ => pattern.push(*c),
+                    }
+                }
+                pattern.push(']');
+
+                wildcard(&mut regex, &pattern);
+                idx = end;
+            }
+
+            // alternation groups
+            '{' => {
+                braces += 1;
+                regex.push_str(if capture { "(" } else { "(?:" });
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                regex.push(')');
+            }
+            ',' if braces > 0 => regex.push('|'),
+
+            // escaped literals
+            '\\' => {
+                idx += 1;
+                match chars.get(idx) {
+                    Some(c) => regex.push_str(&regex::escape(&c.to_string())),
+                    None => return Err(format!("Dangling escape in glob: {}", glob).into()),
+                }
+            }
+
+            // everything else is literal
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        idx += 1;
+    }
+
+    if braces > 0 {
+        return Err(format!("Unclosed alternation in glob: {}", glob).into());
+    }
+
+    regex.push('$');
+    Ok(regex)
+}
+
+/// Compiles a set of globs into a `RegexSet`, if any are provided.
+fn compile_set(globs: &[&str]) -> UtilResult<Option<RegexSet>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+
+    let patterns = globs
+        .iter()
+        .map(|glob| compile(glob))
+        .collect::<UtilResult<Vec<_>>>()?;
+
+    Ok(Some(RegexSet::new(patterns)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile_captures, is_glob, Filter};
+    use regex::Regex;
+
+    fn matches(glob: &str, key: &str) -> bool {
+        Filter::new(&[glob], &[]).unwrap().is_match(key)
+    }
+
+    #[test]
+    fn matching_single_stars() {
+        assert!(matches("*.gz", "file.gz"));
+        assert!(matches("logs/*.gz", "logs/file.gz"));
+
+        assert!(!matches("*.gz", "logs/file.gz"));
+        assert!(!matches("logs/*", "logs/nested/file.gz"));
+        assert!(!matches("logs/*.gz", "logs/file.gz.tmp"));
+    }
+
+    #[test]
+    fn matching_double_stars() {
+        assert!(matches("**/*.parquet", "file.parquet"));
+        assert!(matches("**/*.parquet", "a/b/c/file.parquet"));
+        assert!(matches("**/2023/**/*.parquet", "data/2023/file.parquet"));
+        assert!(matches(
+            "**/2023/**/*.parquet",
+            "data/2023/01/02/file.parquet"
+        ));
+        assert!(matches("logs/**", "logs/a/b/c"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+
+        assert!(!matches(
+            "**/2023/**/*.parquet",
+            "data/2024/01/file.parquet"
+        ));
+        assert!(!matches("logs/**", "other/logs/a"));
+        assert!(!matches("a**b/c", "a/x/b/c"));
+    }
+
+    #[test]
+    fn matching_other_syntax() {
+        assert!(matches("file.?z", "file.gz"));
+        assert!(matches("file.[gx]z", "file.xz"));
+        assert!(matches("file.[!x]z", "file.gz"));
+        assert!(matches("*.{gz,bz2}", "file.bz2"));
+        assert!(matches("\\*.gz", "*.gz"));
+        assert!(matches("a+b(c).gz", "a+b(c).gz"));
+
+        assert!(!matches("file.?z", "file/z"));
+        assert!(!matches("file.[!x]z", "file.xz"));
+        assert!(!matches("*.{gz,bz2}", "file.zip"));
+        assert!(!matches("\\*.gz", "file.gz"));
+    }
+
+    #[test]
+    fn combining_includes_and_excludes() {
+        let filter = Filter::new(&["**/*.gz", "**/*.log"], &["tmp/**"]).unwrap();
+
+        assert!(filter.is_match("logs/file.gz"));
+        assert!(filter.is_match("logs/file.log"));
+
+        assert!(!filter.is_match("logs/file.txt"));
+        assert!(!filter.is_match("tmp/file.gz"));
+    }
+
+    #[test]
+    fn detecting_glob_syntax() {
+        assert!(is_glob("logs-*-prod"));
+        assert!(is_glob("logs-{a,b}"));
+
+        assert!(!is_glob("my.bucket.name"));
+    }
+
+    #[test]
+    fn capturing_wildcards() {
+        let regex = Regex::new(&compile_captures("logs/**/*-[0-9].{gz,bz2}").unwrap()).unwrap();
+        let captures = regex.captures("logs/a/b/app-1.gz").unwrap();
+
+        assert_eq!(&captures[1], "a/b/");
+        assert_eq!(&captures[2], "app");
+        assert_eq!(&captures[3], "1");
+        assert_eq!(&captures[4], "gz");
+
+        let regex = Regex::new(&compile_captures("file.?z").unwrap()).unwrap();
+
+        assert_eq!(&regex.captures("file.xz").unwrap()[1], "x");
+        assert_eq!(regex.captures_len(), 2);
+    }
+
+    #[test]
+    fn rejecting_invalid_globs() {
+        assert!(Filter::new(&["file.[gz"], &[]).is_err());
+        assert!(Filter::new(&["file.{gz"], &[]).is_err());
+        assert!(Filter::new(&["file\\"], &[]).is_err());
+    }
+}
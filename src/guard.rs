@@ -0,0 +1,97 @@
+//! Safety guard to limit the number of objects removed in a single run.
+//!
+//! Destructive commands must check each batch of deletions against the
+//! guard before executing it. Once the total requested deletions exceeds
+//! the configured limit, the guard refuses the batch and the run aborts.
+//! During a dry run the guard only tallies deletions, so the plan can show
+//! how many objects would be removed against the limit.
+use clap::ArgMatches;
+
+use crate::cli;
+use crate::types::UtilResult;
+
+/// Default number of objects which can be removed in a single run.
+pub const DEFAULT_MAX_DELETE: &str = "10000";
+
+/// Guard structure tracking deletions against a configured limit.
+pub struct DeleteGuard {
+    dry: bool,
+    limit: u64,
+    requested: u64,
+}
+
+/// Main implementation.
+impl DeleteGuard {
+    /// Constructs a new `DeleteGuard` with a limit and dry run flag.
+    pub fn new(limit: u64, dry: bool) -> DeleteGuard {
+        DeleteGuard {
+            dry,
+            limit,
+            requested: 0,
+        }
+    }
+
+    /// Constructs a new `DeleteGuard` from the global arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> UtilResult<DeleteGuard> {
+        let limit = args
+            .value_of("max_delete")
+            .unwrap_or(DEFAULT_MAX_DELETE)
+            .parse::<u64>()
+            .map_err(|_| "Invalid deletion limit provided")?;
+
+        Ok(DeleteGuard::new(limit, cli::is_dry_run(args)))
+    }
+
+    /// Checks a batch of deletions against the limit before it executes.
+    pub fn check(&mut self, count: u64) -> UtilResult<()> {
+        self.requested += count;
+
+        if self.dry || self.requested <= self.limit {
+            return Ok(());
+        }
+
+        Err(format!(
+            "Refusing to delete {} objects, as this exceeds the limit of {} (use --max-delete to raise it)",
+            self.requested, self.limit
+        )
+        .into())
+    }
+
+    /// Logs the number of deletions planned during a dry run.
+    pub fn summarize(&self) {
+        if !self.dry {
+            return;
+        }
+
+        info!(
+            "Planned deletions: {} (limit {})",
+            self.requested, self.limit
+        );
+
+        if self.requested > self.limit {
+            info!("Planned deletions exceed the limit; the run would abort before deleting");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeleteGuard;
+
+    #[test]
+    fn enforcing_deletion_limits() {
+        let mut guard = DeleteGuard::new(10, false);
+
+        assert!(guard.check(5).is_ok());
+        assert!(guard.check(5).is_ok());
+        assert!(guard.check(1).is_err());
+    }
+
+    #[test]
+    fn tallying_dry_run_deletions() {
+        let mut guard = DeleteGuard::new(10, true);
+
+        assert!(guard.check(25).is_ok());
+        assert_eq!(guard.requested, 25);
+    }
+}
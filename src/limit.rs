@@ -0,0 +1,109 @@
+//! Rate limiting of requests sent to S3, shared across all in-flight tasks.
+//!
+//! Requests are limited using a token bucket, which refills at the rate of
+//! requests allowed per second. The bucket holds up to a second of tokens,
+//! so short bursts are allowed after a quiet period, but the rate over time
+//! never exceeds the limit. Every attempt of a request takes a token, which
+//! includes any retries, so backoff can only ever slow requests down further.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limiter shared across all requests, to cap the rate they're sent at.
+#[derive(Clone)]
+pub struct Limiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+/// Token bucket used to track the requests which can be sent.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Main implementation.
+impl Limiter {
+    /// Constructs a new `Limiter` allowing a number of requests per second.
+    pub fn new(rate: u32) -> Limiter {
+        Limiter {
+            bucket: Arc::new(Mutex::new(Bucket::new(rate, Instant::now()))),
+        }
+    }
+
+    /// Waits until a request can be sent, taking a token from the bucket.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .expect("limiter lock should not be poisoned")
+                .take(Instant::now());
+
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+}
+
+/// Main implementation.
+impl Bucket {
+    /// Constructs a new, full `Bucket` refilling at a rate per second.
+    fn new(rate: u32, now: Instant) -> Bucket {
+        let rate = f64::from(rate.max(1));
+
+        Bucket {
+            rate,
+            tokens: rate,
+            refilled: now,
+        }
+    }
+
+    /// Attempts to take a token, returning the time to wait if there is none.
+    fn take(&mut self, now: Instant) -> Option<Duration> {
+        // refill for the time since the last attempt, up to a second's worth
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bucket;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn limiting_requests_per_second() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(2, start);
+
+        // a full bucket allows a burst of a second's worth
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), None);
+        assert_eq!(bucket.take(start), Some(Duration::from_millis(500)));
+
+        // tokens refill at the rate over time
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.take(later), Some(Duration::from_millis(250)));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(later), None);
+        assert!(bucket.take(later).is_some());
+
+        // but never past a second's worth
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(later), None);
+        assert_eq!(bucket.take(later), None);
+        assert!(bucket.take(later).is_some());
+    }
+}
@@ -0,0 +1,143 @@
+//! Limits on the size and age of the files selected by a command.
+//!
+//! Files outside of the limits are left exactly where they are, as if they
+//! never matched (although they're still counted within a summary). Any
+//! relative times are resolved once, so the window is fixed for the run.
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+
+use std::convert::TryFrom;
+
+use crate::cli;
+use crate::types::UtilResult;
+
+/// Limits on the size and modification time of selected files.
+#[derive(Default)]
+pub struct Limits {
+    min_size: Option<i64>,
+    max_size: Option<i64>,
+    newer_than: Option<DateTime<Utc>>,
+    older_than: Option<DateTime<Utc>>,
+}
+
+/// Main implementation.
+impl Limits {
+    /// Constructs `Limits` from the provided arguments.
+    ///
+    /// The resolved window is logged using the provided noun (such as
+    /// `sources`), as relative times are hard to verify.
+    pub fn from_args(args: &ArgMatches<'_>, noun: &str) -> UtilResult<Limits> {
+        // sizes are validated by clap, and anything beyond an i64 is unlimited
+        let size = |name: &str| {
+            args.value_of(name).map(|size| {
+                let size = cli::parse_size(size).unwrap();
+                i64::try_from(size).unwrap_or(i64::MAX)
+            })
+        };
+
+        // times are resolved once, so the window is fixed for the whole run
+        let now = Utc::now();
+        let time = |name: &str| {
+            args.value_of(name)
+                .map(|time| cli::parse_time(time, now).unwrap())
+        };
+
+        let limits = Limits {
+            min_size: size("min_size"),
+            max_size: size("max_size"),
+            newer_than: time("newer_than"),
+            older_than: time("older_than"),
+        };
+
+        if let (Some(min), Some(max)) = (limits.min_size, limits.max_size) {
+            if min > max {
+                return Err("Minimum size must not exceed the maximum size".into());
+            }
+        }
+
+        match (limits.newer_than, limits.older_than) {
+            (Some(newer), Some(older)) if newer >= older => {
+                return Err(
+                    "Modification window is empty, as --newer-than is after --older-than".into(),
+                );
+            }
+            (Some(newer), Some(older)) => info!(
+                "Selecting {} modified after {} and before {}",
+                noun,
+                newer.to_rfc3339(),
+                older.to_rfc3339()
+            ),
+            (Some(newer), None) => {
+                info!("Selecting {} modified after {}", noun, newer.to_rfc3339())
+            }
+            (None, Some(older)) => {
+                info!("Selecting {} modified before {}", noun, older.to_rfc3339())
+            }
+            (None, None) => (),
+        }
+
+        Ok(limits)
+    }
+
+    /// Determines whether a file size is within the size limits.
+    pub fn is_within_size(&self, size: i64) -> bool {
+        let too_small = self.min_size.filter(|min| size < *min).is_some();
+        let too_large = self.max_size.filter(|max| size > *max).is_some();
+
+        !too_small && !too_large
+    }
+
+    /// Determines whether a modification date is within the window.
+    ///
+    /// Files without a modification date can't be placed in a window, so
+    /// they're only accepted when no window has been provided.
+    pub fn is_within_window(&self, modified: Option<DateTime<Utc>>) -> bool {
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return true;
+        }
+
+        let modified = match modified {
+            Some(modified) => modified,
+            None => return false,
+        };
+
+        let too_old = self.newer_than.filter(|newer| modified <= *newer).is_some();
+        let too_new = self.older_than.filter(|older| modified >= *older).is_some();
+
+        !too_old && !too_new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Limits;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn limiting_by_size_and_age() {
+        let time = |value| {
+            DateTime::parse_from_rfc3339(value)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        let limits = Limits {
+            min_size: Some(10),
+            max_size: Some(20),
+            newer_than: Some(time("2018-01-01T00:00:00Z")),
+            older_than: Some(time("2018-02-01T00:00:00Z")),
+        };
+
+        assert!(limits.is_within_size(10));
+        assert!(limits.is_within_size(20));
+        assert!(!limits.is_within_size(9));
+        assert!(!limits.is_within_size(21));
+
+        assert!(limits.is_within_window(Some(time("2018-01-15T00:00:00Z"))));
+        assert!(!limits.is_within_window(Some(time("2018-01-01T00:00:00Z"))));
+        assert!(!limits.is_within_window(Some(time("2018-03-01T00:00:00Z"))));
+        assert!(!limits.is_within_window(None));
+
+        assert!(Limits::default().is_within_window(None));
+    }
+}
@@ -0,0 +1,92 @@
+//! Explicit listings of sources, read from a file rather than walked.
+//!
+//! Listings are read the same way by every command, although only those
+//! for concatenation are parsed here. Each line of a listing names a single
+//! source key, optionally followed by a tab and the target it should be
+//! concatenated into. Lines without their own target use the target provided
+//! on the command line. Targets appear in the order they're first named, and
+//! their sources keep the order of the listing, as this is taken as the
+//! order of the parts.
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::types::UtilResult;
+
+/// Sources listed against a target, in the order they were listed.
+pub struct Listed {
+    pub target: String,
+    pub keys: Vec<String>,
+}
+
+/// Reads the contents of a listing, from a path or `-` for stdin.
+pub fn read(path: &str) -> UtilResult<String> {
+    if path != "-" {
+        return Ok(fs::read_to_string(path)?);
+    }
+
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Parses a listing into the sources of each target.
+///
+/// Blank lines are ignored, but any line without a target fails the whole
+/// listing if no default target has been provided.
+pub fn parse(contents: &str, default: Option<&str>) -> UtilResult<Vec<Listed>> {
+    let mut listed: Vec<Listed> = Vec::new();
+    let mut indices = HashMap::new();
+
+    for line in contents.lines() {
+        // allow listings written with Windows line endings
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // an explicit target follows the first tab
+        let (key, target) = match line.find('\t') {
+            Some(idx) => (&line[..idx], Some(line[idx + 1..].trim())),
+            None => (line, None),
+        };
+
+        let target = match target.filter(|target| !target.is_empty()).or(default) {
+            Some(target) => target,
+            None => return Err(format!("No target provided for source {}", key).into()),
+        };
+
+        // locate the sources for the target
+        let idx = *indices.entry(target.to_string()).or_insert_with(|| {
+            listed.push(Listed {
+                target: target.to_string(),
+                keys: Vec::new(),
+            });
+            listed.len() - 1
+        });
+
+        listed[idx].keys.push(key.to_string());
+    }
+
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parsing_source_listings() {
+        let contents = "chunk-2\nchunk-1\tother.log\r\n\nchunk-10\nchunk-3\tother.log\nchunk-4\t\n";
+        let listed = parse(contents, Some("merged.log")).unwrap();
+
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].target, "merged.log");
+        assert_eq!(listed[0].keys, vec!["chunk-2", "chunk-10", "chunk-4"]);
+        assert_eq!(listed[1].target, "other.log");
+        assert_eq!(listed[1].keys, vec!["chunk-1", "chunk-3"]);
+
+        assert!(parse("chunk-1\tmerged.log\n", None).is_ok());
+        assert!(parse("chunk-1\tmerged.log\nchunk-2\n", None).is_err());
+    }
+}
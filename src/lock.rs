@@ -0,0 +1,155 @@
+//! Preservation of Object Lock retention and legal holds across a copy.
+//!
+//! A copy is a new object, so it carries none of the Object Lock settings of
+//! its source unless they're set explicitly on the copy. Settings can only
+//! be set within a bucket with Object Lock enabled, and a retention period
+//! which has already passed can't be set at all (as it no longer applies).
+use chrono::{DateTime, Utc};
+use rusoto_core::RusotoError;
+use rusoto_s3::*;
+
+use crate::types::UtilResult;
+
+/// Object Lock settings of a single object.
+#[derive(Default)]
+pub struct Lock {
+    mode: Option<String>,
+    retain_until: Option<String>,
+    legal_hold: Option<String>,
+}
+
+/// Main implementation.
+impl Lock {
+    /// Reads the Object Lock settings of an object.
+    ///
+    /// Retention which has already expired is dropped, as it can't be set.
+    pub async fn read(
+        s3: &S3Client,
+        bucket: &str,
+        key: &str,
+        request_payer: Option<&str>,
+    ) -> UtilResult<Lock> {
+        let request = HeadObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            request_payer: request_payer.map(str::to_string),
+            ..HeadObjectRequest::default()
+        };
+
+        let head = s3.head_object(request).await?;
+
+        Ok(Lock::new(
+            head.object_lock_mode,
+            head.object_lock_retain_until_date,
+            head.object_lock_legal_hold_status,
+            Utc::now(),
+        ))
+    }
+
+    /// Constructs a new `Lock`, dropping retention expired by a time.
+    fn new(
+        mode: Option<String>,
+        retain_until: Option<String>,
+        legal_hold: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Lock {
+        let is_active = retain_until
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .filter(|date| *date > now)
+            .is_some();
+
+        let (mode, retain_until) = match (mode, retain_until) {
+            (Some(mode), Some(retain_until)) if is_active => (Some(mode), Some(retain_until)),
+            _ => (None, None),
+        };
+
+        // only an active hold needs carrying over
+        let legal_hold = legal_hold.filter(|status| status == "ON");
+
+        Lock {
+            mode,
+            retain_until,
+            legal_hold,
+        }
+    }
+
+    /// Determines whether there are any settings to preserve.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.legal_hold.is_none()
+    }
+
+    /// Describes the settings of this lock, for logging.
+    pub fn describe(&self) -> String {
+        let mut described = Vec::new();
+
+        if let (Some(mode), Some(retain_until)) = (&self.mode, &self.retain_until) {
+            described.push(format!("{} until {}", mode, retain_until));
+        }
+
+        if self.legal_hold.is_some() {
+            described.push("legal hold".to_string());
+        }
+
+        described.join(", ")
+    }
+
+    /// Applies these settings onto a copy request.
+    pub fn apply(&self, request: CopyObjectRequest) -> CopyObjectRequest {
+        CopyObjectRequest {
+            object_lock_mode: self.mode.clone(),
+            object_lock_retain_until_date: self.retain_until.clone(),
+            object_lock_legal_hold_status: self.legal_hold.clone(),
+            ..request
+        }
+    }
+}
+
+/// Determines whether a bucket has Object Lock enabled.
+pub async fn is_enabled(s3: &S3Client, bucket: &str) -> UtilResult<bool> {
+    let request = GetObjectLockConfigurationRequest {
+        bucket: bucket.to_string(),
+        ..GetObjectLockConfigurationRequest::default()
+    };
+
+    // buckets without a configuration respond with a raw 404
+    let output = match s3.get_object_lock_configuration(request).await {
+        Ok(output) => output,
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => {
+            return Ok(false)
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(output
+        .object_lock_configuration
+        .and_then(|config| config.object_lock_enabled)
+        .filter(|enabled| enabled == "Enabled")
+        .is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lock;
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn dropping_expired_retention() {
+        let now = DateTime::parse_from_rfc3339("2023-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let compliance = || Some("COMPLIANCE".to_string());
+
+        let lock = Lock::new(compliance(), Some("2024-01-01T00:00:00Z".into()), None, now);
+        assert!(!lock.is_empty());
+        assert_eq!(lock.describe(), "COMPLIANCE until 2024-01-01T00:00:00Z");
+
+        let lock = Lock::new(compliance(), Some("2023-01-01T00:00:00Z".into()), None, now);
+        assert!(lock.is_empty());
+
+        let lock = Lock::new(None, None, Some("ON".into()), now);
+        assert_eq!(lock.describe(), "legal hold");
+
+        assert!(Lock::new(None, None, Some("OFF".into()), now).is_empty());
+    }
+}
@@ -8,6 +8,7 @@ use logger::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
 /// Basic logger instance to allow quiet-aware logging.
 struct BasicLogger {
     quiet: bool,
+    stderr: bool,
 }
 
 // Basic logging implementation.
@@ -20,7 +21,7 @@ impl Log for BasicLogger {
     /// Logs out a `Record` when logging is enabled.
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            if record.metadata().level() == Level::Error {
+            if record.metadata().level() == Level::Error || self.stderr {
                 eprintln!("{}", record.args());
             } else if !self.quiet {
                 println!("{}", record.args());
@@ -35,9 +36,38 @@ impl Log for BasicLogger {
 /// Initializes the logger based on the provided arguments.
 ///
 /// If the `-q` flag was provided, this short circuits to cull all logging.
+/// If the `-v` flag was provided, additional debug logging is enabled.
+/// If the `--progress` flag was provided, only warnings and errors are
+/// logged, as progress is shown on a status line instead.
+/// If records (or a report) are written as output, or raw records are
+/// streamed to stdout, all logging is moved to stderr.
 pub fn init(args: &ArgMatches) -> Result<(), SetLoggerError> {
     let logger = Box::new(BasicLogger {
-        quiet: args.is_present("quiet"),
+        quiet: is_present(args, "quiet"),
+        stderr: args
+            .subcommand()
+            .1
+            .filter(|subargs| subargs.value_of("output").is_some() || subargs.is_present("raw"))
+            .is_some(),
     });
-    log::set_boxed_logger(logger).map(|_| log::set_max_level(LevelFilter::Info))
+
+    let level = if is_present(args, "verbose") {
+        LevelFilter::Debug
+    } else if is_present(args, "progress") {
+        LevelFilter::Warn
+    } else {
+        LevelFilter::Info
+    };
+
+    log::set_boxed_logger(logger).map(|_| log::set_max_level(level))
+}
+
+/// Determines if a switch was provided, either globally or to a subcommand.
+fn is_present(args: &ArgMatches, name: &str) -> bool {
+    args.is_present(name)
+        || args
+            .subcommand()
+            .1
+            .filter(|subargs| subargs.is_present(name))
+            .is_some()
 }
@@ -8,19 +8,30 @@
 #[macro_use]
 extern crate log as logger;
 
-use rusoto_core::{credential::ChainProvider, region::Region, HttpClient};
-use rusoto_s3::*;
-
-use std::time::Duration;
+use rusoto_core::region::Region;
 
 mod cli;
+mod client;
+mod copy;
+mod cost;
+mod dates;
+mod filter;
+mod guard;
+mod limit;
+mod limits;
+mod listing;
+mod lock;
 mod log;
+mod records;
+mod retry;
+mod tags;
 mod types;
 mod walker;
 
 mod concat;
 mod rename;
 mod report;
+mod swap;
 
 #[tokio::main]
 async fn main() -> types::UtilResult<()> {
@@ -30,16 +41,8 @@ async fn main() -> types::UtilResult<()> {
     // initialize logging
     log::init(&args)?;
 
-    // create client options
-    let client = HttpClient::new()?;
-    let region = Region::default();
-
-    // create provided with timeout
-    let mut chain = ChainProvider::new();
-    chain.set_timeout(Duration::from_millis(500));
-
     // create the new S3 client
-    let s3 = S3Client::new_with(client, chain, region);
+    let s3 = client::new(Region::default())?;
 
     // delegate to the cli mod
     cli::exec(s3, &args).await
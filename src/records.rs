@@ -0,0 +1,67 @@
+//! Escaping of fields written into tab separated record files.
+//!
+//! Keys in S3 can contain any character, including the tabs and newlines
+//! used to separate fields and records. Fields are therefore escaped with
+//! a backslash before they're written, and unescaped once read back.
+
+/// Escapes a field, so it can't be confused with a separator.
+pub fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Unescapes a field written by `escape`.
+///
+/// Unknown escapes are kept as they are, rather than being rejected.
+pub fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, unescape};
+
+    #[test]
+    fn escaping_record_fields() {
+        let field = "logs/a\tb\nc\r\\d.log";
+
+        assert_eq!(escape(field), "logs/a\\tb\\nc\\r\\\\d.log");
+        assert_eq!(unescape(&escape(field)), field);
+
+        assert_eq!(escape("logs/a.log"), "logs/a.log");
+        assert_eq!(unescape("logs\\x\\"), "logs\\x\\");
+    }
+}
@@ -0,0 +1,130 @@
+//! Case conversion of rename targets, using spans within the template.
+//!
+//! Replacements in the regex crate can't change the case of a capture, so
+//! templates support `\L` and `\U` to start a lowercase or uppercase span,
+//! and `\E` to end it. Spans apply to the substituted text (such as `\L$2`),
+//! and run to the end of the template when left open. Case mapping follows
+//! the Unicode rules, so characters can change length when converted.
+use regex::{Captures, Regex};
+
+/// Target template split into spans of the same case conversion.
+pub struct CaseTemplate {
+    spans: Vec<(Case, String)>,
+}
+
+/// Case conversion applied to a span of a template.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Case {
+    Keep,
+    Lower,
+    Upper,
+}
+
+/// Main implementation.
+impl CaseTemplate {
+    /// Parses a target template, splitting out any case spans.
+    pub fn parse(template: &str) -> CaseTemplate {
+        let mut spans = Vec::new();
+        let mut case = Case::Keep;
+        let mut current = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            // anything other than a marker is left as written
+            let next = match (c, chars.peek()) {
+                ('\\', Some('L')) => Case::Lower,
+                ('\\', Some('U')) => Case::Upper,
+                ('\\', Some('E')) => Case::Keep,
+                _ => {
+                    current.push(c);
+                    continue;
+                }
+            };
+
+            chars.next();
+
+            if !current.is_empty() {
+                spans.push((case, current.split_off(0)));
+            }
+
+            case = next;
+        }
+
+        if !current.is_empty() {
+            spans.push((case, current));
+        }
+
+        CaseTemplate { spans }
+    }
+
+    /// Renders the target of a key, replacing every match of a pattern.
+    ///
+    /// The entire target can also be converted, after rendering.
+    pub fn render(&self, pattern: &Regex, key: &str, case: Case) -> String {
+        let rendered = pattern.replace_all(key, |captures: &Captures<'_>| {
+            let mut rendered = String::new();
+
+            for (case, span) in &self.spans {
+                let mut expanded = String::new();
+                captures.expand(span, &mut expanded);
+                rendered.push_str(&convert(&expanded, *case));
+            }
+
+            rendered
+        });
+
+        convert(&rendered, case)
+    }
+}
+
+/// Converts a value into a case.
+fn convert(value: &str, case: Case) -> String {
+    match case {
+        Case::Keep => value.to_string(),
+        Case::Lower => value.to_lowercase(),
+        Case::Upper => value.to_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Case, CaseTemplate};
+    use regex::Regex;
+
+    #[test]
+    fn converting_template_spans() {
+        let pattern = Regex::new(r"(.*)/([A-Z]+)\.CSV").unwrap();
+        let render =
+            |template, key| CaseTemplate::parse(template).render(&pattern, key, Case::Keep);
+
+        assert_eq!(render(r"$1/\L$2.csv", "Data/REPORT.CSV"), "Data/report.csv");
+        assert_eq!(
+            render(r"\L$1\E/$2.csv", "Data/REPORT.CSV"),
+            "data/REPORT.csv"
+        );
+        assert_eq!(
+            render(r"\U$1\E/\L$2\E.csv", "Data/REPORT.CSV"),
+            "DATA/report.csv"
+        );
+        assert_eq!(render(r"$1\/$2", "Data/REPORT.CSV"), r"Data\/REPORT");
+
+        // unicode mappings can change the length of the value
+        let pattern = Regex::new(r"(.*)").unwrap();
+        let template = CaseTemplate::parse(r"\U$1");
+
+        assert_eq!(template.render(&pattern, "straße", Case::Keep), "STRASSE");
+        assert_eq!(
+            CaseTemplate::parse("$1").render(&pattern, "ÀÉÎ", Case::Lower),
+            "àéî"
+        );
+
+        // transforms render the key as-is, so convert all of it
+        let pattern = Regex::new(r"\.CSV$").unwrap();
+        let template = CaseTemplate::parse("$0");
+
+        assert_eq!(
+            template.render(&pattern, "Data/REPORT.CSV", Case::Lower),
+            "data/report.csv"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Checkpointing of rename progress, to allow resuming an interrupted rename.
+//!
+//! Keys are walked in order, so the last key which was fully processed is
+//! enough to resume a walk from. The checkpoint is a small append-only file
+//! of tab separated records; the first names the bucket and patterns of the
+//! rename (so it can't be resumed by a different rename), and every other
+//! record names a key which everything up to has been processed. A key is
+//! only recorded once every key before it has been removed (or skipped, or
+//! has failed), and fields are escaped so keys can contain tabs or newlines.
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::time::{Duration, Instant};
+
+use crate::records::{escape, unescape};
+use crate::types::UtilResult;
+
+/// Minimum interval between records appended to a checkpoint.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Checkpoint structure tracking the progress of a rename.
+pub struct Checkpoint {
+    path: String,
+    rename: String,
+    started: bool,
+    after: Option<String>,
+    recorded: Option<String>,
+    saved: Instant,
+}
+
+/// Main implementation.
+impl Checkpoint {
+    /// Loads a `Checkpoint` from a file, which may not exist yet.
+    ///
+    /// A checkpoint left behind by a rename of a different bucket, or with
+    /// different patterns, is refused rather than resumed.
+    pub fn load(path: &str, bucket: &str, source: &str, target: &str) -> UtilResult<Checkpoint> {
+        let rename = format!(
+            "rename\t{}\t{}\t{}",
+            escape(bucket),
+            escape(source),
+            escape(target)
+        );
+
+        let (started, after) = match fs::read_to_string(path) {
+            Ok(contents) => match parse(&contents, &rename) {
+                Ok(after) => (true, after),
+                Err(err) => return Err(format!("Invalid checkpoint in {}: {}", path, err).into()),
+            },
+            Err(ref err) if err.kind() == ErrorKind::NotFound => (false, None),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Checkpoint {
+            path: path.to_string(),
+            started,
+            recorded: after.clone(),
+            rename,
+            after,
+            saved: Instant::now(),
+        })
+    }
+
+    /// Retrieves the key to resume the rename after, if any.
+    pub fn after(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+
+    /// Advances the checkpoint to a key, recording it periodically.
+    pub fn advance(&mut self, key: &str) -> UtilResult<()> {
+        self.after = Some(key.to_string());
+
+        if self.saved.elapsed() >= INTERVAL {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the latest key, unless it has already been recorded.
+    pub fn save(&mut self) -> UtilResult<()> {
+        let after = match self.after {
+            Some(ref after) if self.recorded.as_ref() != Some(after) => after.clone(),
+            _ => return Ok(()),
+        };
+
+        if !self.started {
+            self.append(&self.rename)?;
+            self.started = true;
+        }
+
+        self.append(&format!("after\t{}", escape(&after)))?;
+        self.recorded = Some(after);
+        self.saved = Instant::now();
+
+        Ok(())
+    }
+
+    /// Removes the checkpoint, as there is nothing left to resume.
+    pub fn clear(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+
+    /// Appends a record to the checkpoint file.
+    fn append(&self, record: &str) -> UtilResult<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", record)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Parses the key to resume after from the records of a checkpoint.
+///
+/// A record cut short by a run dying mid-write is ignored, as the record
+/// before it is still accurate.
+fn parse(contents: &str, rename: &str) -> UtilResult<Option<String>> {
+    let complete = contents.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+    let mut lines = contents[..complete].lines();
+    let mut after = None;
+
+    match lines.next() {
+        Some(line) if line == rename => (),
+        Some(line) if line.starts_with("rename\t") => {
+            let fields = line.split('\t').skip(1).map(unescape).collect::<Vec<_>>();
+            let field = |idx: usize| fields.get(idx).map(String::as_str).unwrap_or_default();
+            return Err(format!(
+                "belongs to a rename of {} (from {} to {}), refusing to resume",
+                field(0),
+                field(1),
+                field(2)
+            )
+            .into());
+        }
+        _ => return Err("missing the details of the rename".into()),
+    }
+
+    for line in lines {
+        match line.split_once('\t') {
+            Some(("after", key)) if !key.is_empty() => after = Some(unescape(key)),
+            _ => return Err(format!("invalid record: {}", line).into()),
+        }
+    }
+
+    Ok(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parsing_checkpoints() {
+        let rename = "rename\tbucket\tlogs/(.*)\tarchive/$1";
+        let contents = format!("{}\nafter\tlogs/a.log\nafter\tlogs/b.log\n", rename);
+
+        assert_eq!(
+            parse(&contents, rename).unwrap(),
+            Some("logs/b.log".to_string())
+        );
+        assert_eq!(
+            parse(&format!("{}after\tlogs/c", contents), rename).unwrap(),
+            Some("logs/b.log".to_string())
+        );
+        assert_eq!(parse(&format!("{}\n", rename), rename).unwrap(), None);
+
+        assert_eq!(
+            parse(&format!("{}after\tlogs/c\\nd\n", contents), rename).unwrap(),
+            Some("logs/c\nd".to_string())
+        );
+
+        assert!(parse(&contents, "rename\tbucket\tlogs/(.*)\told/$1").is_err());
+        assert!(parse(&format!("{}bogus\n", contents), rename).is_err());
+        assert!(parse("after\tlogs/a.log\n", rename).is_err());
+    }
+}
@@ -0,0 +1,26 @@
+//! Claims on the targets of every file renamed within a run.
+//!
+//! Buckets may share a target bucket, so targets are claimed by their full
+//! location and tracked across every bucket within a run. The first source
+//! to claim a target keeps it, so a file is never renamed over the target
+//! of another file.
+use std::collections::HashMap;
+
+/// Claims structure tracking the source of every claimed target.
+#[derive(Default)]
+pub struct Claims {
+    targets: HashMap<String, String>,
+}
+
+/// Main implementation.
+impl Claims {
+    /// Retrieves the source which has already claimed a target, if any.
+    pub fn claimed(&self, target: &str) -> Option<&String> {
+        self.targets.get(target)
+    }
+
+    /// Claims a target for a source, so no other source renames into it.
+    pub fn claim(&mut self, target: String, source: String) {
+        self.targets.insert(target, source);
+    }
+}
@@ -0,0 +1,125 @@
+//! Failures of individual files, reported once a rename has finished.
+//!
+//! With `--keep-going`, a failing file is left in place while the rest of
+//! the files are renamed. Each failure is kept alongside the operation which
+//! failed, so they can all be reported together at the end of the run (and
+//! written out, so that just those files can be renamed again).
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+
+use crate::types::UtilResult;
+
+/// Failures of files within a rename, in the order they occurred.
+#[derive(Default)]
+pub struct Failures {
+    bucket: String,
+    failures: Vec<Failure>,
+}
+
+/// The failure of an operation on a single file.
+struct Failure {
+    bucket: String,
+    key: String,
+    operation: &'static str,
+    message: String,
+}
+
+/// Main implementation.
+impl Failures {
+    /// Constructs a new `Failures` for the files within a bucket.
+    pub fn new(bucket: &str) -> Failures {
+        Failures {
+            bucket: bucket.to_string(),
+            failures: Vec::new(),
+        }
+    }
+
+    /// Records the failure of an operation on a file.
+    pub fn record<E: Display>(&mut self, key: &str, operation: &'static str, err: E) {
+        self.failures.push(Failure {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            operation,
+            message: err.to_string(),
+        });
+    }
+
+    /// Records each source which couldn't be removed, alongside the reason.
+    pub fn refuse(&mut self, refused: HashMap<String, String>) {
+        for (key, reason) in refused {
+            self.record(&key, "remove", reason);
+        }
+    }
+
+    /// Moves all failures from another set of failures (of any bucket).
+    pub fn extend(&mut self, mut other: Failures) {
+        self.failures.append(&mut other.failures);
+    }
+
+    /// Determines whether any failures have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Retrieves the number of failures recorded.
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// Logs every failure, alongside the operation which failed.
+    pub fn print(&self) {
+        for failure in &self.failures {
+            error!(
+                "Failed to {} s3://{}/{}: {}",
+                failure.operation, failure.bucket, failure.key, failure.message
+            );
+        }
+    }
+
+    /// Writes the keys of all failed files to a path, one per line.
+    pub fn write(&self, path: &str) -> UtilResult<()> {
+        fs::write(path, self.render())?;
+        Ok(())
+    }
+
+    /// Renders the keys of all failed files, one per line.
+    ///
+    /// The same key can fail within several buckets, but each key is only
+    /// written once.
+    fn render(&self) -> String {
+        let mut rendered = String::new();
+        let mut keys = HashSet::new();
+
+        for failure in &self.failures {
+            if keys.insert(&failure.key) {
+                rendered.push_str(&failure.key);
+                rendered.push('\n');
+            }
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Failures;
+
+    #[test]
+    fn rendering_failed_keys() {
+        let mut failures = Failures::new("one");
+
+        failures.record("logs/a.log", "copy", "Access Denied");
+        failures.record("logs/b.log", "remove", "Internal Error");
+
+        let mut later = Failures::new("two");
+        later.record("logs/a.log", "remove", "Internal Error");
+
+        failures.extend(later);
+
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures.failures[2].bucket, "two");
+        assert_eq!(failures.render(), "logs/a.log\nlogs/b.log\n");
+    }
+}
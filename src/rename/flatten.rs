@@ -0,0 +1,54 @@
+//! Flattening of keys into a single prefix, using only their basename.
+//!
+//! Flattening drops every directory component of a key, so files from
+//! different directories can easily end up with the same target. Targets
+//! can be made unique with a numeric suffix, placed before any extension so
+//! that the type of the file is still recognisable.
+
+/// Renders the flattened target of a key within a prefix.
+///
+/// A separator is added between the prefix and the basename, unless the
+/// prefix is empty or already ends with one.
+pub fn target(prefix: &str, key: &str) -> String {
+    let basename = key.rsplit('/').next().unwrap_or(key);
+
+    if prefix.is_empty() || prefix.ends_with('/') {
+        format!("{}{}", prefix, basename)
+    } else {
+        format!("{}/{}", prefix, basename)
+    }
+}
+
+/// Appends a numeric suffix to a target, before any extension.
+///
+/// Leading dots (such as in `.env`) don't start an extension.
+pub fn suffix(target: &str, suffix: u64) -> String {
+    let start = target.rfind('/').map(|idx| idx + 1).unwrap_or(0);
+
+    match target[start..].rfind('.').filter(|idx| *idx > 0) {
+        Some(idx) => {
+            let (stem, extension) = target.split_at(start + idx);
+            format!("{}-{}{}", stem, suffix, extension)
+        }
+        None => format!("{}-{}", target, suffix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{suffix, target};
+
+    #[test]
+    fn flattening_keys_into_prefixes() {
+        assert_eq!(target("flat/", "a/b/c/file.parquet"), "flat/file.parquet");
+        assert_eq!(target("flat", "a/b/c/file.parquet"), "flat/file.parquet");
+        assert_eq!(target("", "a/b/c/file.parquet"), "file.parquet");
+        assert_eq!(target("flat/", "file.parquet"), "flat/file.parquet");
+
+        assert_eq!(suffix("flat/file.parquet", 1), "flat/file-1.parquet");
+        assert_eq!(suffix("flat/file.tar.gz", 2), "flat/file.tar-2.gz");
+        assert_eq!(suffix("flat/file", 3), "flat/file-3");
+        assert_eq!(suffix("flat/.env", 4), "flat/.env-4");
+        assert_eq!(suffix("fl.at/file", 5), "fl.at/file-5");
+    }
+}
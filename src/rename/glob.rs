@@ -0,0 +1,120 @@
+//! Glob source patterns, as an alternative to regular expressions.
+//!
+//! A glob source uses the same syntax as the shared `--include-glob` filter,
+//! and each wildcard within it can be referenced in the target by number
+//! (such as `{1}` for the first wildcard, and `{0}` for the entire key).
+//! Everything else in a glob target is literal, aside from date tokens.
+use regex::Regex;
+
+use crate::filter;
+use crate::types::UtilResult;
+
+/// Compiles a glob source pattern, capturing each wildcard.
+pub fn source(glob: &str) -> UtilResult<Regex> {
+    Ok(Regex::new(&filter::compile_captures(glob)?)?)
+}
+
+/// Translates a glob target into a regex replacement for its source.
+///
+/// Wildcard references become capture references, and dollar signs are
+/// escaped (unless they begin a date token). References to wildcards which
+/// don't exist within the source fail, rather than rendering as empty.
+pub fn target(target: &str, source: &Regex, glob: &str) -> UtilResult<String> {
+    let wildcards = source.captures_len() - 1;
+    let mut translated = String::with_capacity(target.len());
+    let mut rest = target;
+
+    while let Some(idx) = rest.find(|c| c == '{' || c == '$') {
+        translated.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        // dollar signs are literal, except for date tokens
+        if rest.starts_with("${") {
+            translated.push_str("${");
+            rest = &rest[2..];
+            continue;
+        }
+
+        if rest.starts_with('$') {
+            translated.push_str("$$");
+            rest = &rest[1..];
+            continue;
+        }
+
+        // braces without a number (such as `{seq}`) are left as written
+        let digits = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .filter(|len| *len > 0 && rest[1 + len..].starts_with('}'));
+
+        let len = match digits {
+            Some(len) => len,
+            None => {
+                translated.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let wildcard = rest[1..=len]
+            .parse::<usize>()
+            .ok()
+            .filter(|wildcard| *wildcard <= wildcards)
+            .ok_or_else(|| {
+                format!(
+                    "Target references wildcard {}, but glob {} only has {} wildcards",
+                    &rest[..len + 2],
+                    glob,
+                    wildcards
+                )
+            })?;
+
+        translated.push_str(&format!("${{{}}}", wildcard));
+        rest = &rest[len + 2..];
+    }
+
+    translated.push_str(rest);
+
+    Ok(translated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{source, target};
+
+    #[test]
+    fn translating_glob_targets() {
+        let glob = "logs/**/*.log";
+        let pattern = source(glob).unwrap();
+
+        assert_eq!(
+            target("archive/{1}{2}.txt", &pattern, glob).unwrap(),
+            "archive/${1}${2}.txt"
+        );
+        assert_eq!(
+            target("{0}-{seq:04}-$5", &pattern, glob).unwrap(),
+            "${0}-{seq:04}-$$5"
+        );
+        assert_eq!(
+            target("${maxdate:%Y}/{2}", &pattern, glob).unwrap(),
+            "${maxdate:%Y}/${2}"
+        );
+
+        assert!(target("archive/{3}.txt", &pattern, glob).is_err());
+    }
+
+    #[test]
+    fn renaming_with_globs() {
+        let glob = "data/[0-9]*.csv";
+        let pattern = source(glob).unwrap();
+        let replacement = target("csv/{2}-{1}.csv", &pattern, glob).unwrap();
+
+        assert_eq!(
+            pattern.replace_all("data/2023-report.csv", replacement.as_str()),
+            "csv/023-report-2.csv"
+        );
+
+        assert!(!pattern.is_match("data/report.csv"));
+        assert!(!pattern.is_match("data/nested/2023.csv"));
+        assert!(!pattern.is_match("data/2023.csv.tmp"));
+    }
+}
@@ -0,0 +1,70 @@
+//! Validation of rendered target keys, before anything is copied.
+//!
+//! A bad target pattern can easily render keys which S3 will reject, but
+//! only once a copy has been attempted (which may be well into a run). Keys
+//! which can never be written are caught up front, and keys which can be
+//! written but are rarely intended (such as a leading slash) are flagged.
+
+/// Maximum length of an object key, in bytes of UTF-8.
+pub const MAX_KEY_LEN: usize = 1024;
+
+/// Determines why a target key can't be written, if it can't be.
+pub fn invalid(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return Some("key is empty".to_string());
+    }
+
+    if key.len() > MAX_KEY_LEN {
+        return Some(format!(
+            "key is {} bytes, over the limit of {}",
+            key.len(),
+            MAX_KEY_LEN
+        ));
+    }
+
+    if key.chars().all(|c| c == '/') {
+        return Some("key contains only slashes".to_string());
+    }
+
+    if key.chars().any(char::is_control) {
+        return Some("key contains control characters".to_string());
+    }
+
+    None
+}
+
+/// Determines why a valid target key is likely unintended, if it is.
+///
+/// Tools presenting keys as paths will show these as empty directories.
+pub fn suspicious(key: &str) -> Option<&'static str> {
+    if key.starts_with('/') {
+        return Some("key starts with a slash");
+    }
+
+    if key.contains("//") {
+        return Some("key contains an empty path segment");
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{invalid, suspicious, MAX_KEY_LEN};
+
+    #[test]
+    fn validating_target_keys() {
+        assert!(invalid("archive/a.log").is_none());
+        assert!(invalid(&"a".repeat(MAX_KEY_LEN)).is_none());
+
+        assert!(invalid("").is_some());
+        assert!(invalid("///").is_some());
+        assert!(invalid("archive/a\n.log").is_some());
+        assert!(invalid(&"a".repeat(MAX_KEY_LEN + 1)).is_some());
+        assert!(invalid(&"é".repeat(MAX_KEY_LEN / 2 + 1)).is_some());
+
+        assert!(suspicious("archive/a.log").is_none());
+        assert!(suspicious("/archive/a.log").is_some());
+        assert!(suspicious("archive//a.log").is_some());
+    }
+}
@@ -0,0 +1,172 @@
+//! Manifests of every file renamed, to allow undoing a rename.
+//!
+//! A manifest is an append-only file with a tab separated record of the old
+//! and new location (bucket and key) of each file renamed, followed by the
+//! storage class the file was renamed into. Each record is written as soon
+//! as the rename is confirmed, so a manifest is complete up to the point a
+//! run dies.
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use crate::listing;
+use crate::types::UtilResult;
+
+/// Manifest structure recording everything renamed within a run.
+#[derive(Default)]
+pub struct Manifest {
+    file: Option<File>,
+}
+
+/// Main implementation.
+impl Manifest {
+    /// Constructs a new `Manifest` held only in memory.
+    pub fn memory() -> Manifest {
+        Manifest::default()
+    }
+
+    /// Creates a `Manifest` written to a file, replacing any existing file.
+    pub fn create(path: &str) -> UtilResult<Manifest> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        Ok(Manifest { file: Some(file) })
+    }
+
+    /// Records a confirmed rename, flushing it to the file (if there is one).
+    ///
+    /// Both the old and new location are provided as a bucket/key pair.
+    pub fn record(&mut self, old: (&str, &str), new: (&str, &str), class: &str) -> UtilResult<()> {
+        if let Some(ref mut file) = self.file {
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}\t{}",
+                old.0, old.1, new.0, new.1, class
+            )?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// A single rename recorded within a manifest.
+#[derive(Debug, PartialEq)]
+pub struct Rename {
+    pub source_bucket: String,
+    pub old: String,
+    pub target_bucket: String,
+    pub new: String,
+}
+
+/// Reads every rename in a manifest, in order.
+pub fn read(path: &str) -> UtilResult<Vec<Rename>> {
+    parse(&listing::read(path)?)
+}
+
+/// Splits renames into runs sharing the same source and target bucket.
+///
+/// Buckets are renamed one at a time, so each run covers a single bucket
+/// and runs can be undone in reverse to undo the newest renames first.
+pub fn group(renames: &[Rename]) -> Vec<&[Rename]> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+
+    for idx in 1..=renames.len() {
+        let is_end = idx == renames.len()
+            || renames[idx].source_bucket != renames[start].source_bucket
+            || renames[idx].target_bucket != renames[start].target_bucket;
+
+        if is_end {
+            groups.push(&renames[start..idx]);
+            start = idx;
+        }
+    }
+
+    groups
+}
+
+/// Parses every rename in a manifest, in order.
+///
+/// The storage class is only informational, so it's ignored (and may be
+/// left out entirely, such as in manifests written by hand).
+fn parse(contents: &str) -> UtilResult<Vec<Rename>> {
+    let mut renames = Vec::new();
+
+    for line in contents.lines() {
+        // allow manifests written with Windows line endings
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = line.split('\t').collect::<Vec<_>>();
+
+        if !(4..=5).contains(&fields.len()) || fields[..4].iter().any(|field| field.is_empty()) {
+            return Err(format!("Invalid rename record in manifest: {}", line).into());
+        }
+
+        renames.push(Rename {
+            source_bucket: fields[0].to_string(),
+            old: fields[1].to_string(),
+            target_bucket: fields[2].to_string(),
+            new: fields[3].to_string(),
+        });
+    }
+
+    Ok(renames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group, parse, Rename};
+
+    #[test]
+    fn parsing_rename_manifests() {
+        let renames = parse(concat!(
+            "logs\tlogs/a.log\tlogs\tarchive/a.log\tGLACIER_IR\r\n\n",
+            "logs\tlogs/b.log\tarchive\tb.log\n"
+        ))
+        .unwrap();
+
+        let rename = |source_bucket: &str, old: &str, target_bucket: &str, new: &str| Rename {
+            source_bucket: source_bucket.to_string(),
+            old: old.to_string(),
+            target_bucket: target_bucket.to_string(),
+            new: new.to_string(),
+        };
+
+        assert_eq!(
+            renames,
+            vec![
+                rename("logs", "logs/a.log", "logs", "archive/a.log"),
+                rename("logs", "logs/b.log", "archive", "b.log"),
+            ]
+        );
+
+        assert!(parse("logs/a.log\tarchive/a.log\n").is_err());
+        assert!(parse("logs\t\tlogs\tarchive/a.log\n").is_err());
+        assert!(parse("logs\tlogs/a.log\tlogs\tarchive/a.log\tSTANDARD\textra\n").is_err());
+    }
+
+    #[test]
+    fn grouping_renames_by_bucket() {
+        let renames = parse(concat!(
+            "one\ta\tone\tb\n",
+            "one\tc\tone\td\n",
+            "two\ta\ttwo\tb\n",
+            "one\te\tone\tf\n",
+        ))
+        .unwrap();
+
+        let groups = group(&renames);
+
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[1][0].source_bucket, "two");
+        assert_eq!(groups[2][0].old, "e");
+
+        assert!(group(&[]).is_empty());
+    }
+}
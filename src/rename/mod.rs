@@ -1,101 +1,1625 @@
 //! Dynamic (and remote) file renaming using flexible patterns.
 use clap::{App, Arg, ArgMatches, SubCommand};
-use regex::Regex;
+use regex::{Regex, RegexSet};
 use rusoto_s3::*;
 
-use crate::cli;
-use crate::types::UtilResult;
+use std::collections::HashMap;
+
+use crate::cli::{self, STORAGE_CLASSES};
+use crate::client;
+use crate::copy::{read_acl, read_tags, storage_class, write_acl};
+use crate::cost::{self, Pricing};
+use crate::dates::{parse_modified, DateRange, DateSource, DatedTemplate};
+use crate::filter::{self, Filter};
+use crate::guard::DeleteGuard;
+use crate::limits::Limits;
+use crate::listing;
+use crate::lock::{self, Lock};
+use crate::retry::Retry;
+use crate::tags;
+use crate::types::{UtilError, UtilResult};
 use crate::walker::ObjectWalker;
 
+use self::case::{Case, CaseTemplate};
+use self::checkpoint::Checkpoint;
+use self::claims::Claims;
+use self::failures::Failures;
+use self::manifest::{Manifest, Rename};
+use self::output::Output;
+use self::progress::Progress;
+use self::sanitize::Sanitizer;
+use self::sequence::Sequence;
+use self::sources::{Listed, Sort, Sources};
+use self::summary::Summary;
+
+pub mod case;
+pub mod checkpoint;
+pub mod claims;
+pub mod failures;
+pub mod flatten;
+pub mod glob;
+pub mod keys;
+pub mod manifest;
+pub mod output;
+pub mod progress;
+pub mod sanitize;
+pub mod sequence;
+pub mod sources;
+pub mod summary;
+pub mod versions;
+
+/// Everything tracked across the renames within every bucket of a run.
+struct Ledger {
+    guard: DeleteGuard,
+    manifest: Manifest,
+    claims: Claims,
+    limit: Option<u64>,
+    renamed: u64,
+}
+
+/// Main implementation.
+impl Ledger {
+    /// Retrieves the number of renames left within the limit, if there is one.
+    fn remaining(&self) -> Option<u64> {
+        self.limit.map(|limit| limit.saturating_sub(self.renamed))
+    }
+}
+
 /// Generates an appropriate `SubCommand` for this module.
 pub fn cmd<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("rename")
         .about("Renaming of files in S3 remotely")
         .args(&cli::global_args())
+        .args(&cli::filter_args())
+        .args(&cli::destructive_args())
         .args(&[
             Arg::with_name("source")
                 .help("A source pattern to use to locate files")
                 .index(2)
-                .required(true),
+                .required_unless_one(&["flatten", "from_file", "sanitize", "transform", "undo"]),
             Arg::with_name("target")
                 .help("A target pattern to use to rename files into")
                 .index(3)
-                .required(true),
+                .required_unless_one(&["flatten", "from_file", "sanitize", "transform", "undo"]),
+            Arg::with_name("checkpoint")
+                .help("A file to checkpoint progress into, resuming from it when it exists")
+                .long("checkpoint")
+                .takes_value(true)
+                .conflicts_with_all(&["from_file", "sort", "undo"]),
+            Arg::with_name("content_type")
+                .help("A content type to set on renamed files (replaces metadata)")
+                .long("content-type")
+                .takes_value(true),
+            Arg::with_name("date_directives")
+                .help(
+                    "Formats bare strftime directives in the target (e.g. %Y) by modification date",
+                )
+                .long("date-directives"),
+            Arg::with_name("dedupe_suffix")
+                .help("Adds a numeric suffix to flattened targets which collide")
+                .long("dedupe-suffix")
+                .requires("flatten"),
+            Arg::with_name("exclude")
+                .help("A pattern of matching files to exclude from renaming")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+            Arg::with_name("fail_on_existing")
+                .help("Fails the run when any target already exists")
+                .long("fail-on-existing")
+                .conflicts_with("overwrite"),
+            Arg::with_name("failures_out")
+                .help("A file to write the keys of any files which failed to rename")
+                .long("failures-out")
+                .takes_value(true),
+            Arg::with_name("flatten")
+                .help("Renames the basename of each file into a prefix, in place of a target")
+                .long("flatten")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "target",
+                    "transform",
+                    "lowercase_target",
+                    "uppercase_target",
+                ]),
+            Arg::with_name("from_file")
+                .help("A file (or - for stdin) listing the keys (and any targets) to rename")
+                .long("from-file")
+                .takes_value(true),
+            Arg::with_name("glob")
+                .help("Treats the source as a glob, referencing wildcards as {1}, {2}, etc.")
+                .long("glob"),
+            Arg::with_name("keep_going")
+                .help("Skips files which fail to copy, rather than failing the run")
+                .long("keep-going"),
+            Arg::with_name("limit")
+                .help("Stops once this many files have been renamed")
+                .long("limit")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("lowercase_target")
+                .help("Converts every target to lowercase once rendered")
+                .long("lowercase-target")
+                .conflicts_with("uppercase_target"),
+            Arg::with_name("manifest")
+                .help("A file to record the old and new key of every renamed file")
+                .long("manifest")
+                .takes_value(true),
+            Arg::with_name("max_size")
+                .help("The maximum size of files to rename (e.g. 2GiB)")
+                .long("max-size")
+                .takes_value(true)
+                .validator(cli::validate_size),
+            Arg::with_name("metadata")
+                .help("Metadata to set on renamed files as key=value (replaces metadata)")
+                .long("metadata")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(cli::validate_pair),
+            Arg::with_name("metadata_directive")
+                .help("Whether metadata is copied from sources or replaced")
+                .long("metadata-directive")
+                .takes_value(true)
+                .possible_values(&["copy", "replace"]),
+            Arg::with_name("min_size")
+                .help("The minimum size of files to rename (e.g. 1MB)")
+                .long("min-size")
+                .takes_value(true)
+                .validator(cli::validate_size),
+            Arg::with_name("newer_than")
+                .help("Only rename files modified after a time (or duration ago)")
+                .long("newer-than")
+                .takes_value(true)
+                .validator(cli::validate_time),
+            Arg::with_name("no_delete")
+                .help("Copies files into their targets, keeping the originals")
+                .long("no-delete")
+                .alias("copy-only"),
+            Arg::with_name("older_than")
+                .help("Only rename files modified before a time (or duration ago)")
+                .long("older-than")
+                .takes_value(true)
+                .validator(cli::validate_time),
+            Arg::with_name("output")
+                .help("Writes a record of each file processed to stdout, logging to stderr")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["jsonl"]),
+            Arg::with_name("overwrite")
+                .help("Replaces any targets which already exist")
+                .long("overwrite"),
+            Arg::with_name("precount")
+                .help("Counts all keys before renaming, to show progress against a total")
+                .long("precount")
+                .requires("progress"),
+            Arg::with_name("preserve_acl")
+                .help("Copies the ACL of each file onto its renamed file")
+                .long("preserve-acl"),
+            Arg::with_name("preserve_object_lock")
+                .help("Carries the Object Lock retention and legal hold of each file across")
+                .long("preserve-object-lock"),
+            Arg::with_name("progress")
+                .help("Shows a status line in place of a line for each file")
+                .long("progress"),
+            Arg::with_name("requester_pays")
+                .help("Accepts the cost of requests made to requester pays buckets")
+                .long("requester-pays"),
+            Arg::with_name("retries")
+                .help("The number of times to retry requests failing transiently")
+                .long("retries")
+                .takes_value(true)
+                .default_value("3")
+                .validator(cli::validate_number),
+            Arg::with_name("retry_base_delay")
+                .help("The base delay between retries, in milliseconds")
+                .long("retry-base-delay")
+                .takes_value(true)
+                .default_value("100")
+                .validator(cli::validate_number),
+            Arg::with_name("sanitize")
+                .help("Replaces characters in targets which are unsafe for other tools")
+                .long("sanitize"),
+            Arg::with_name("sanitize_chars")
+                .help("The characters to replace when sanitizing, in place of the defaults")
+                .long("sanitize-chars")
+                .takes_value(true)
+                .requires("sanitize"),
+            Arg::with_name("sanitize_replacement")
+                .help("The replacement for each sanitized character")
+                .long("sanitize-replacement")
+                .takes_value(true)
+                .requires("sanitize"),
+            Arg::with_name("sort")
+                .help("Sorts all matching files before renaming, to number them in order")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(&["key", "last_modified"]),
+            Arg::with_name("sse")
+                .help("The server side encryption to write renamed files with")
+                .long("sse")
+                .takes_value(true)
+                .possible_values(&["AES256", "aws:kms"]),
+            Arg::with_name("sse_kms_key_id")
+                .help("The KMS key to encrypt renamed files with, when using aws:kms")
+                .long("sse-kms-key-id")
+                .takes_value(true)
+                .requires("sse"),
+            Arg::with_name("storage_class")
+                .help("A storage class to set on renamed files, rather than their own")
+                .long("storage-class")
+                .takes_value(true)
+                .possible_values(&STORAGE_CLASSES),
+            Arg::with_name("strict")
+                .help("Fails the run on invalid targets, or several files renamed to one target")
+                .long("strict"),
+            Arg::with_name("tag")
+                .help("A tag to set on renamed files as key=value (replaces tags)")
+                .long("tag")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .max_values(tags::MAX_TAGS)
+                .validator(tags::validate),
+            Arg::with_name("tagging_directive")
+                .help("Whether tags are copied from sources or replaced")
+                .long("tagging-directive")
+                .takes_value(true)
+                .possible_values(&["copy", "replace"]),
+            Arg::with_name("target_bucket")
+                .help("A bucket to move renamed files into")
+                .long("target-bucket")
+                .takes_value(true),
+            Arg::with_name("transform")
+                .help("Converts the case of each entire key, in place of a target")
+                .long("transform")
+                .takes_value(true)
+                .possible_values(&["lowercase", "uppercase"])
+                .conflicts_with_all(&["target", "lowercase_target", "uppercase_target"]),
+            Arg::with_name("undo")
+                .help("Undoes the renames recorded in a manifest, newest first")
+                .long("undo")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "from_file",
+                    "manifest",
+                    "source",
+                    "target",
+                    "target_bucket",
+                ]),
+            Arg::with_name("uppercase_target")
+                .help("Converts every target to uppercase once rendered")
+                .long("uppercase-target"),
+            Arg::with_name("versions")
+                .help("Renames every version of each file, removing all source versions")
+                .long("versions"),
         ])
 }
 
 /// Executes this subcommand and returns a `UtilResult` to indicate success.
 pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
-    // parse all global arguments
-    let dryrun = cli::is_dry_run(args);
-    let (bucket, prefix) = cli::get_bucket_pair(args);
+    // deletions are limited across all buckets
+    let mut guard = DeleteGuard::from_args(args)?;
 
-    // unwrap and compile the source regex (unwrap should be safe)
-    let source = Regex::new(&args.value_of("source").unwrap())?;
-    let target = args.value_of("target").unwrap();
+    // undoing replays a manifest, rather than matching anything
+    if let Some(path) = args.value_of("undo") {
+        let renames = manifest::read(path)?;
+        let buckets = cli::expand_buckets(&s3, args, true).await?;
 
-    let walker_bucket = bucket.clone();
-    let mut walker = ObjectWalker::new(&s3, walker_bucket, prefix);
+        // renames are only undone within the buckets they were made in
+        let mut groups = Vec::new();
 
-    // walk across all remote objects
-    while let Some(object) = walker.next().await? {
-        // unwrap the source key
-        let key = object.key.unwrap();
+        for group in manifest::group(&renames) {
+            let bucket = &group[0].source_bucket;
 
-        // skip non-matching files
-        if !source.is_match(&key) {
-            continue;
+            if !buckets.iter().any(|(_, matched, _)| matched == bucket) {
+                warn!(
+                    "Skipping {} renames from unmatched bucket {}",
+                    group.len(),
+                    bucket
+                );
+                continue;
+            }
+
+            groups.push(group);
         }
 
-        // format the target path
-        let full_target = source
-            .replace_all(&key, target.to_string().as_str())
-            .to_string();
+        // every restore removes a new key, so they're all checked up front
+        guard.check(groups.iter().map(|group| group.len() as u64).sum())?;
 
-        // don't concat into self
-        if full_target == key {
-            continue;
+        // newest renames are undone first
+        for group in groups.into_iter().rev() {
+            undo(&s3, args, group).await?;
         }
 
-        // log out exactly what we're renaming right now
-        info!("Renaming {} -> {}", key, full_target);
+        guard.summarize();
+
+        return Ok(());
+    }
+
+    // renames across all buckets are recorded to a single manifest, and targets
+    // are claimed across all buckets, as they may share a target bucket
+    let manifest = match args.value_of("manifest") {
+        Some(path) if !cli::is_dry_run(args) => Manifest::create(path)?,
+        _ => Manifest::memory(),
+    };
+
+    // renames can be capped across the whole run, so a pattern can be tried
+    // on a few files first; limits are validated by clap, so safe to unwrap
+    let mut ledger = Ledger {
+        guard,
+        manifest,
+        claims: Claims::default(),
+        limit: args
+            .value_of("limit")
+            .map(|limit| limit.parse::<u64>().unwrap()),
+        renamed: 0,
+    };
+
+    // files left in place across all buckets, reported once all are done
+    let mut failures = Failures::default();
+    let mut result = Ok(());
 
-        // skip
-        if dryrun {
-            continue;
+    // listings are read up front, as stdin can only be read once
+    let listed = match args.value_of("from_file") {
+        Some(path) => Some(sources::parse(
+            &listing::read(path)?,
+            ["flatten", "sanitize", "target", "transform"]
+                .iter()
+                .any(|name| args.is_present(name)),
+        )?),
+        None => None,
+    };
+
+    // checkpoints track a single walk, so can't span buckets
+    let buckets = cli::expand_buckets(&s3, args, true).await?;
+
+    if args.is_present("checkpoint") && buckets.len() > 1 {
+        return Err("A checkpoint can only be used to rename within a single bucket".into());
+    }
+
+    // buckets matched by a pattern which fail are reported once all are done
+    let is_pattern = filter::is_glob(&cli::get_bucket_pair(args).0);
+    let mut failed = Vec::new();
+
+    // rename within each bucket in turn
+    for (s3, bucket, prefix) in buckets {
+        // later buckets are left alone once the limit is reached
+        if ledger.remaining() == Some(0) {
+            info!("Reached the limit of {} renamed files", ledger.renamed);
+            break;
         }
 
-        // update the target with the prefix
-        let source = if key.starts_with(&bucket) {
-            key.to_string()
-        } else {
-            format!("{}/{}", bucket, key)
+        let listed = listed.as_deref();
+        let name = bucket.clone();
+
+        let err = match rename(&s3, args, &mut ledger, listed, bucket, prefix).await {
+            Ok(left) => {
+                failures.extend(left);
+                continue;
+            }
+            Err(err) => err,
         };
 
-        // create the copy request
-        let copy = CopyObjectRequest {
-            key: full_target.to_string(),
-            bucket: bucket.to_string(),
-            copy_source: source,
-            ..CopyObjectRequest::default()
+        if !is_pattern {
+            result = Err(err);
+            break;
+        }
+
+        error!("Unable to rename within bucket {}: {}", name, err);
+        failed.push(name);
+    }
+
+    // failed keys are written regardless, so they can be retried
+    if let Some(path) = args.value_of("failures_out") {
+        if let Err(err) = failures.write(path) {
+            // the error which failed the run is never hidden by this one
+            if result.is_ok() {
+                return Err(err);
+            }
+            error!("Unable to write failures to {}: {}", path, err);
+        }
+    }
+
+    // passthrough
+    result?;
+
+    // failed buckets fail the run, once every other bucket is done
+    if !failed.is_empty() {
+        return Err(format!(
+            "Unable to rename within {} buckets: {}",
+            failed.len(),
+            failed.join(", ")
+        )
+        .into());
+    }
+
+    // files left in place fail the run, once all buckets are attempted
+    if !failures.is_empty() {
+        failures.print();
+        return Err(format!("Unable to rename {} files", failures.len()).into());
+    }
+
+    // show planned deletions
+    ledger.guard.summarize();
+
+    Ok(())
+}
+
+/// Renames all matching files within a bucket/prefix pair.
+///
+/// Every target renamed into is claimed by its source within the ledger,
+/// so that a file is never renamed over the target of another file. The
+/// first file to map to a target is renamed, and any later file is skipped
+/// (or fails the run when strict). Files which fail without failing the run
+/// are returned.
+async fn rename(
+    s3: &S3Client,
+    args: &ArgMatches<'_>,
+    ledger: &mut Ledger,
+    listed: Option<&[Listed]>,
+    bucket: String,
+    prefix: Option<String>,
+) -> UtilResult<Failures> {
+    let Ledger {
+        guard,
+        manifest,
+        claims,
+        limit,
+        renamed,
+    } = ledger;
+
+    // parse all global arguments
+    let dryrun = cli::is_dry_run(args);
+
+    // compile the source pattern (matching everything when there isn't one);
+    // globs reference their wildcards differently, so targets are translated
+    let (source, translated) = if args.is_present("glob") {
+        let pattern = args.value_of("source").unwrap_or("**");
+        let source = glob::source(pattern)?;
+        let translated = match args.value_of("target") {
+            Some(target) => Some(glob::target(target, &source, pattern)?),
+            None => None,
         };
+        (source, translated)
+    } else {
+        (
+            Regex::new(args.value_of("source").unwrap_or_default())?,
+            None,
+        )
+    };
+
+    // bare directives are only formatted when asked, so a literal % is kept
+    let bare = if args.is_present("date_directives") {
+        Some(DateSource::Newest)
+    } else {
+        None
+    };
+
+    let dated = DatedTemplate::parse(
+        translated
+            .as_deref()
+            .or_else(|| args.value_of("target"))
+            .unwrap_or("$0"),
+        bare,
+    )?;
+    let target = CaseTemplate::parse(dated.template());
+    let exclude = RegexSet::new(args.values_of("exclude").into_iter().flatten())?;
+    let filter = Filter::from_args(args)?;
+    let limits = Limits::from_args(args, "files")?;
+
+    // conversion of entire targets, in place of spans in the template; a
+    // transform has no target, so the key is rendered as-is and converted
+    let case = match args.value_of("transform") {
+        Some("lowercase") => Case::Lower,
+        Some("uppercase") => Case::Upper,
+        _ if args.is_present("lowercase_target") => Case::Lower,
+        _ if args.is_present("uppercase_target") => Case::Upper,
+        _ => Case::Keep,
+    };
+
+    // unsafe characters are replaced once a target has been rendered
+    let sanitizer = if args.is_present("sanitize") {
+        Some(Sanitizer::from_args(args))
+    } else {
+        None
+    };
+
+    // flattening drops directories, so colliding targets can be suffixed
+    let flat_prefix = args.value_of("flatten");
+    let dedupe = args.is_present("dedupe_suffix");
+
+    // settings applied to every copy made
+    let template = copy_from_args(args)?;
+    let directive = template.metadata_directive.as_deref().unwrap_or_default();
+    let tagging = template.tagging_directive.as_deref().unwrap_or_default();
+
+    // copied tags are checked when asked, as not every store carries them over
+    let verify_tags = args.value_of("tagging_directive") == Some("copy");
+    let mut untagged = 0;
+
+    // files which can't be copied from their storage class are skipped
+    let mut skipped = 0;
+
+    // as are files without a date to format into their target
+    let mut undated = 0;
+
+    // every file processed can be written out as a record, for scripting
+    let output = Output::from_args(args);
+
+    // files failing to copy only fail the run once everything else is done
+    let keep_going = args.is_present("keep_going");
+    let mut failures = Failures::new(&bucket);
+
+    // files mapping to the target of another file are skipped
+    let strict = args.is_present("strict");
+
+    // existing targets are only replaced when explicit
+    let overwrite = args.is_present("overwrite");
+    let fail_on_existing = args.is_present("fail_on_existing");
+
+    // counts of everything matched, to summarize a dry run
+    let mut summary = Summary::default();
+
+    // copies keep their sources, so are logged differently
+    let no_delete = args.is_present("no_delete");
+    let action = if no_delete { "Copying" } else { "Renaming" };
+
+    // ACLs are reset by copies, so must be carried over explicitly
+    let preserve_acl = args.is_present("preserve_acl");
+    let mut unpreserved = 0;
+
+    if preserve_acl {
+        info!("Preserving the ACL of each renamed file");
+    }
+
+    // the target bucket defaults to the source bucket
+    let target_bucket = args
+        .value_of("target_bucket")
+        .map(|bucket| bucket.trim_start_matches("s3://").to_string())
+        .unwrap_or_else(|| bucket.clone());
+
+    // resolve clients for the region of each bucket
+    let (source_s3, source_region) = client::for_bucket(s3, &bucket).await;
+    let (target_s3, target_region) = if target_bucket == bucket {
+        (source_s3.clone(), source_region.clone())
+    } else {
+        client::for_bucket(s3, &target_bucket).await
+    };
 
-        // execute the copy of the object
-        s3.copy_object(copy).await?;
+    // requester pays buckets bill every request to the caller
+    let payer = request_payer(args);
 
-        // log out exactly what we're doing right now
-        info!("Removing {} sources...", key);
+    if payer.is_some() {
+        info!("Accepting the cost of all requests made as the requester");
+    }
 
-        // remove the old object after renaming
-        let delete = DeleteObjectRequest {
-            bucket: bucket.to_string(),
-            key: key.to_string(),
-            ..DeleteObjectRequest::default()
+    // keys are only counted up front when asked, as it doubles the listing
+    let mut progress = if args.is_present("progress") {
+        let total = match listed {
+            Some(listed) => Some(listed.len() as u64),
+            None if args.is_present("precount") => {
+                Some(count(&source_s3, &bucket, prefix.clone(), filter.clone(), payer).await?)
+            }
+            None => None,
         };
+        Some(Progress::new(total))
+    } else {
+        None
+    };
+
+    // checkpoints resume a walk after the last key processed, but are only
+    // ever written by real runs
+    let mut checkpoint = match args.value_of("checkpoint") {
+        Some(path) => Some(Checkpoint::load(
+            path,
+            &bucket,
+            args.value_of("source").unwrap_or_default(),
+            args.value_of("target").unwrap_or_default(),
+        )?),
+        None => None,
+    };
+
+    let start_after = checkpoint
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.after())
+        .map(str::to_string);
+
+    if let Some(ref key) = start_after {
+        info!("Resuming from checkpoint, after s3://{}/{}", bucket, key);
+    }
+
+    if dryrun {
+        checkpoint = None;
+    }
+
+    // listed keys are fetched directly, rather than walking the bucket
+    let mut sources = match listed {
+        Some(listed) => Sources::Listed {
+            s3: &source_s3,
+            bucket: bucket.clone(),
+            listed: listed.iter(),
+            missing: 0,
+            request_payer: payer,
+        },
+        None => {
+            let walker_bucket = bucket.clone();
+            let walker = ObjectWalker::new(&source_s3, walker_bucket, prefix.clone())
+                .filter(filter.clone())
+                .request_payer(payer.map(str::to_string))
+                .start_after(start_after.clone());
+            Sources::Walked(walker)
+        }
+    };
+
+    // numbered targets need a stable order, so every match is sorted first
+    let mut sequence = args.value_of("target").and_then(Sequence::parse);
+    let sort = match args.value_of("sort") {
+        Some("last_modified") => Some(Sort::LastModified),
+        Some(_) => Some(Sort::Key),
+        None if sequence.is_some() => Some(Sort::Key),
+        None => None,
+    };
+
+    // numbers would restart from one, so can't be resumed
+    if sequence.is_some() && args.is_present("checkpoint") {
+        return Err("Sequence numbers can't be resumed from a checkpoint".into());
+    }
 
-        // execute the delete of the object
-        s3.delete_object(delete).await?;
+    if let Some(sort) = sort {
+        sources = sources.sort(&source, sort).await?;
+    }
+
+    // removing from a versioned bucket keeps every previous version
+    let all_versions = args.is_present("versions");
+
+    if all_versions {
+        if !versions::is_versioned(&source_s3, &bucket).await? {
+            warn!(
+                "Bucket {} is not versioned, so only the latest version of each file will be renamed",
+                bucket
+            );
+        }
+    } else if !no_delete
+        && versions::is_versioned(&source_s3, &bucket)
+            .await
+            .unwrap_or(false)
+    {
+        warn!(
+            "Bucket {} is versioned, so previous versions of renamed files will remain (use --versions to rename them too)",
+            bucket
+        );
+    }
+
+    // moves name both buckets, so they can be checked in a dry run
+    if target_bucket != bucket {
+        info!(
+            "Moving renamed files from {} into {}",
+            client::describe(&bucket, &source_region),
+            client::describe(&target_bucket, &target_region)
+        );
+    }
+
+    // locked files can only be renamed into a bucket which supports locking
+    let preserve_lock = args.is_present("preserve_object_lock");
+    let lockable = preserve_lock && lock::is_enabled(&target_s3, &target_bucket).await?;
+
+    if preserve_lock && !lockable {
+        warn!(
+            "Bucket {} does not have Object Lock enabled, so locked files will fail to rename",
+            target_bucket
+        );
+    }
+
+    // sources with confirmed copies, removed in batches
+    let retry = Retry::from_args(args);
+    let mut removals = Vec::new();
+
+    // keys are checkpointed once processed, and nothing before is pending removal;
+    // failed keys are passed too, as they're retried via --failures-out instead
+    let mut current: Option<String> = None;
+    let mut processed: Option<String> = None;
+    let mut stopped = false;
+
+    // walk across all remote objects, keeping any failure until removals are done
+    let walked: UtilResult<()> = async {
+        while let Some((object, explicit)) = sources.next().await? {
+            if let Some(key) = current.take() {
+                if let Some(checkpoint) = checkpoint.as_mut().filter(|_| removals.is_empty()) {
+                    checkpoint.advance(&key)?;
+                }
+                processed = Some(key);
+            }
+
+            // stop once enough files have been renamed
+            if limit.filter(|limit| *renamed >= *limit).is_some() {
+                info!("Reached the limit of {} renamed files", renamed);
+                stopped = true;
+                break;
+            }
+
+            // unwrap the source key
+            let key = object.key.unwrap();
+            current = Some(key.clone());
+
+            // track progress
+            if let Some(progress) = progress.as_mut() {
+                progress.tick(&summary);
+            }
+
+            // skip non-matching files, unless listed with a target
+            if explicit.is_none() && !source.is_match(&key) {
+                continue;
+            }
+
+            summary.matched += 1;
+
+            // skip matching files which have been excluded
+            if exclude.is_match(&key) {
+                debug!("Skipping {} as it matches an exclusion", key);
+                summary.excluded += 1;
+                output.skipped(&key, None, "matches an exclusion");
+                continue;
+            }
+
+            // skip files outside of the size limits
+            let size = object.size.unwrap_or_default();
+            if !limits.is_within_size(size) {
+                debug!("Skipping {} ({} bytes) outside of size limits", key, size);
+                summary.outside_size += 1;
+                output.skipped(&key, None, "outside of size limits");
+                continue;
+            }
+
+            // skip files outside of the modification window
+            if !limits.is_within_window(parse_modified(object.last_modified.as_deref())) {
+                debug!(
+                    "Skipping {} (modified {}) outside of modification window",
+                    key,
+                    object.last_modified.as_deref().unwrap_or("never")
+                );
+                summary.outside_window += 1;
+                output.skipped(&key, None, "outside of modification window");
+                continue;
+            }
+
+            // date tokens are formatted from the modification date of each file
+            let mut range = DateRange::default();
+
+            if dated.is_dated() && explicit.is_none() {
+                let included = match object.last_modified.as_deref() {
+                    Some(date) => range.include(date),
+                    None => Err("No modification date was listed".into()),
+                };
+
+                if let Err(err) = included {
+                    error!("Unable to format target of {}, skipping: {}", key, err);
+                    undated += 1;
+                    output.skipped(&key, None, &err.to_string());
+                    continue;
+                }
+            }
+
+            // format the target path, including any case conversions
+            let is_explicit = explicit.is_some();
+            let is_sequenced = !is_explicit && flat_prefix.is_none() && sequence.is_some();
+            let mut full_target = match (explicit, flat_prefix) {
+                (Some(explicit), _) => explicit,
+                (None, Some(prefix)) => flatten::target(prefix, &key),
+                (None, None) => {
+                    let rendered = dated.resolve(&target.render(&source, &key, case), &range);
+                    match sequence.as_ref() {
+                        Some(sequence) => sequence.substitute(&rendered),
+                        None => rendered,
+                    }
+                }
+            };
+
+            // sanitize everything but explicit targets
+            if let (Some(sanitizer), false) = (&sanitizer, is_explicit) {
+                full_target = sanitizer.sanitize(&full_target);
+            }
+
+            // targets S3 would reject are caught before anything is copied
+            if let Some(reason) = keys::invalid(&full_target) {
+                let message = format!("Invalid target {:?} for {}: {}", full_target, key, reason);
+
+                if strict {
+                    return Err(message.into());
+                }
+
+                error!("{}, skipping", message);
+                summary.invalid += 1;
+                output.skipped(&key, Some(&full_target), &reason);
+                continue;
+            }
+
+            if let Some(reason) = keys::suspicious(&full_target) {
+                warn!(
+                    "Target {} of {} is likely unintended: {}",
+                    full_target, key, reason
+                );
+            }
+
+            // don't concat into self
+            if full_target == key {
+                summary.self_targets += 1;
+                output.skipped(&key, Some(&full_target), "target is the source");
+                continue;
+            }
+
+            // never rename over the target of another file
+            let mut located = format!("s3://{}/{}", target_bucket, full_target);
+            let source_location = format!("s3://{}/{}", bucket, key);
+
+            // unless suffixed, until the target is unique
+            if dedupe && claims.claimed(&located).is_some() {
+                let flattened = full_target.clone();
+                let mut suffix = 0;
+
+                while claims.claimed(&located).is_some() {
+                    suffix += 1;
+                    full_target = flatten::suffix(&flattened, suffix);
+                    located = format!("s3://{}/{}", target_bucket, full_target);
+                }
+
+                debug!("Suffixing target of {} as {}", source_location, located);
+            }
+
+            if let Some(existing) = claims.claimed(&located) {
+                let message = format!(
+                    "Both {} and {} would be renamed to {}",
+                    existing, source_location, located
+                );
+
+                if strict {
+                    return Err(message.into());
+                }
+
+                error!("{}, skipping {}", message, source_location);
+                summary.conflict(&located, existing, &source_location);
+                output.skipped(&key, Some(&full_target), &message);
+                continue;
+            }
+
+            // checked in dry runs too, so they can report existing targets
+            // (but never when overwriting, as any target is replaced anyway)
+            if !overwrite
+                && client::head(&target_s3, &target_bucket, &full_target, payer)
+                    .await?
+                    .is_some()
+            {
+                if fail_on_existing {
+                    return Err(format!("Target {} already exists", located).into());
+                }
+
+                warn!("Skipping {} as {} already exists", source_location, located);
+                summary.existing += 1;
+                output.skipped(&key, Some(&full_target), "target already exists");
+                continue;
+            }
+
+            claims.claim(located, source_location);
+
+            // the number is used up now the file is going to be renamed
+            if let (true, Some(sequence)) = (is_sequenced, sequence.as_mut()) {
+                sequence.advance();
+            }
+
+            // the storage class of the source is kept, unless overridden
+            let storage_class =
+                match storage_class(&source_s3, &bucket, &key, object.storage_class, payer).await {
+                    Ok(storage_class) => storage_class,
+                    Err(err) => {
+                        error!("Unable to rename {}: {}", key, err);
+                        skipped += 1;
+                        output.skipped(&key, Some(&full_target), &err.to_string());
+                        continue;
+                    }
+                };
+            let storage_class = template.storage_class.clone().or(storage_class);
+
+            // retention is never dropped quietly, so locked files fail without a lock
+            let lock = if preserve_lock {
+                match Lock::read(&source_s3, &bucket, &key, payer).await {
+                    Ok(lock) => lock,
+                    Err(err) => {
+                        error!("Unable to read Object Lock of {}, leaving it in place: {}", key, err);
+                        output.failed(&key, Some(&full_target), &err.to_string());
+                        failures.record(&key, "lock", err);
+                        continue;
+                    }
+                }
+            } else {
+                Lock::default()
+            };
+
+            if !lock.is_empty() && !lockable {
+                let message = format!(
+                    "Unable to preserve Object Lock ({}) in {}, as it does not have Object Lock enabled",
+                    lock.describe(),
+                    target_bucket
+                );
+                error!("{}, leaving {} in place", message, key);
+                output.failed(&key, Some(&full_target), &message);
+                failures.record(&key, "lock", message);
+                continue;
+            }
+
+            if !lock.is_empty() {
+                info!("Preserving Object Lock of {} ({})", key, lock.describe());
+            }
+
+            // log out exactly what we're renaming right now
+            info!(
+                "{} s3://{}/{} -> s3://{}/{} (metadata: {}, tags: {}, class: {})",
+                action,
+                bucket,
+                key,
+                target_bucket,
+                full_target,
+                directive,
+                tagging,
+                storage_class.as_deref().unwrap_or("STANDARD")
+            );
+
+            // every version of the source is renamed, oldest first
+            let history = if all_versions {
+                match versions::list(&source_s3, &bucket, &key, payer).await {
+                    Ok(history) => history,
+                    Err(err) => {
+                        error!("Unable to list versions of {}, skipping: {}", key, err);
+                        skipped += 1;
+                        output.skipped(&key, Some(&full_target), &err.to_string());
+                        failures.record(&key, "list versions of", err);
+                        continue;
+                    }
+                }
+            } else {
+                Vec::new()
+            };
+
+            if !history.is_empty() {
+                let markers = history.iter().filter(|version| version.is_marker).count();
+                info!(
+                    "Including {} versions and {} delete markers of s3://{}/{}",
+                    history.len() - markers,
+                    markers,
+                    bucket,
+                    key
+                );
+            }
+
+            summary.rename(size);
+
+            // skip, tallying the removals which would be made
+            if dryrun {
+                if !no_delete {
+                    guard.check(history.len().max(1) as u64)?;
+                }
+                *renamed += 1;
+                output.done(&key, &full_target);
+                continue;
+            }
+
+            // read the ACL up front, so nothing is copied without it
+            let acl = if preserve_acl {
+                match read_acl(&source_s3, &bucket, &key, payer).await {
+                    Ok(acl) => Some(acl),
+                    Err(err) => {
+                        error!("Unable to read ACL of {}, skipping: {}", key, err);
+                        unpreserved += 1;
+                        output.failed(&key, Some(&full_target), &err.to_string());
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // read the tags up front, to check they're copied
+            let expected_tags = if verify_tags {
+                match read_tags(&source_s3, &bucket, &key, payer).await {
+                    Ok(tags) => Some(tags),
+                    Err(err) => {
+                        error!("Unable to read tags of {}, skipping: {}", key, err);
+                        untagged += 1;
+                        output.failed(&key, Some(&full_target), &err.to_string());
+                        failures.record(&key, "verify tags of", err);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // previous versions are copied first, so the latest stays current
+            let mut previous = history
+                .iter()
+                .filter(|version| !version.is_marker)
+                .collect::<Vec<_>>();
+            let latest = previous.pop();
+
+            let mut copies = previous
+                .iter()
+                .map(|version| CopyObjectRequest {
+                    key: full_target.to_string(),
+                    bucket: target_bucket.to_string(),
+                    copy_source: versions::copy_source(&bucket, &key, &version.version_id),
+                    storage_class: template
+                        .storage_class
+                        .clone()
+                        .or_else(|| version.storage_class.clone()),
+                    ..template.clone()
+                })
+                .collect::<Vec<_>>();
+
+            // create the copy request (keys never include their bucket)
+            copies.push(lock.apply(CopyObjectRequest {
+                key: full_target.to_string(),
+                bucket: target_bucket.to_string(),
+                copy_source: match latest {
+                    Some(version) => versions::copy_source(&bucket, &key, &version.version_id),
+                    None => client::copy_source(&bucket, &key),
+                },
+                storage_class: storage_class.clone(),
+                ..template.clone()
+            }));
+
+            // execute each copy via the target region, stopping on failure
+            let mut copied = Ok(None);
+
+            for copy in copies {
+                copied = retry
+                    .run("copy", || target_s3.copy_object(copy.clone()))
+                    .await
+                    .map(|output| output.server_side_encryption);
+
+                if copied.is_err() {
+                    break;
+                }
+            }
+
+            let encryption = match copied {
+                Ok(encryption) => encryption,
+                Err(err) => {
+                    // same bucket errors need no further context
+                    let err = if target_bucket == bucket {
+                        UtilError::from(err)
+                    } else {
+                        // name both bucket locations to help with region mismatches
+                        UtilError::from(format!(
+                            "Unable to copy {} from {} into {}: {}",
+                            key,
+                            client::describe(&bucket, &source_region),
+                            client::describe(&target_bucket, &target_region),
+                            UtilError::from(err)
+                        ))
+                    };
+
+                    if !keep_going {
+                        return Err(err);
+                    }
+
+                    // the source is left in place, so the key can be retried
+                    error!("Unable to rename {}: {}", key, err);
+                    output.failed(&key, Some(&full_target), &err.to_string());
+                    failures.record(&key, "copy", err);
+                    continue;
+                }
+            };
+
+            // sources are only removed once their copy is known to be encrypted
+            if let Some(ref expected) = template.server_side_encryption {
+                if encryption.as_ref() != Some(expected) {
+                    let actual = encryption.unwrap_or_else(|| "no encryption".to_string());
+                    error!(
+                        "Copy of {} was written with {} rather than {}, leaving it in place",
+                        key, actual, expected
+                    );
+                    let message = format!("Copy was written with {}", actual);
+                    output.failed(&key, Some(&full_target), &message);
+                    failures.record(&key, "encrypt", message);
+                    continue;
+                }
+            }
+
+            // sources are only removed once their ACL has been applied
+            if let Some(acl) = acl {
+                let applied = write_acl(&target_s3, &target_bucket, &full_target, acl, payer);
+                if let Err(err) = applied.await {
+                    error!(
+                        "Unable to apply ACL to {}, leaving {} in place: {}",
+                        full_target, key, err
+                    );
+                    unpreserved += 1;
+                    output.failed(&key, Some(&full_target), &err.to_string());
+                    continue;
+                }
+            }
+
+            // sources are only removed once their tags are known to be copied
+            if let Some(expected) = expected_tags {
+                let copied = read_tags(&target_s3, &target_bucket, &full_target, payer);
+                let copied = match copied.await {
+                    Ok(copied) => copied,
+                    Err(err) => {
+                        warn!(
+                            "Unable to verify tags of {}, leaving {} in place: {}",
+                            full_target, key, err
+                        );
+                        untagged += 1;
+                        output.failed(&key, Some(&full_target), &err.to_string());
+                        failures.record(&key, "verify tags of", err);
+                        continue;
+                    }
+                };
+
+                if copied != expected {
+                    warn!(
+                        "Tags of {} were not copied onto {}, leaving it in place",
+                        key, full_target
+                    );
+                    untagged += 1;
+                    output.failed(&key, Some(&full_target), "tags were not copied");
+                    failures.record(&key, "verify tags of", "Tags were not copied");
+                    continue;
+                }
+            }
+
+            // confirmed renames are recorded, so they can be undone
+            let class = storage_class.as_deref().unwrap_or("STANDARD");
+            manifest.record((&bucket, &key), (&target_bucket, &full_target), class)?;
+            output.done(&key, &full_target);
+            *renamed += 1;
+
+            // copies are done once everything is applied
+            if no_delete {
+                continue;
+            }
+
+            // only confirmed copies are ever queued for removal
+            if history.is_empty() {
+                removals.push(ObjectIdentifier {
+                    key,
+                    ..ObjectIdentifier::default()
+                });
+            } else {
+                removals.extend(history.into_iter().map(|version| ObjectIdentifier {
+                    key: key.clone(),
+                    version_id: Some(version.version_id),
+                }));
+            }
+
+            if removals.len() >= client::MAX_REMOVAL_BATCH {
+                // a batch beyond the limit is left in place, as is everything after it
+                guard.check(removals.len() as u64)?;
+
+                let refused =
+                    client::remove_versions(&source_s3, &bucket, &removals, &retry, payer).await;
+                output.refused(&refused);
+                failures.refuse(refused);
+
+                // everything up to the batch has now been removed
+                if let Some(checkpoint) = checkpoint.as_mut() {
+                    checkpoint.advance(&removals[removals.len() - 1].key)?;
+                }
+
+                removals.clear();
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    // listed keys which don't exist are only reported
+    summary.missing = sources.missing();
+
+    // move past the status line, before anything else is logged
+    if let Some(progress) = progress.as_mut() {
+        progress.finish(&summary);
+    }
+
+    // remove any sources still queued, even when the walk failed; a batch
+    // already refused by the guard is refused again, so is left in place
+    let flushed = if removals.is_empty() {
+        Ok(())
+    } else {
+        guard.check(removals.len() as u64)
+    };
+
+    if flushed.is_ok() && !removals.is_empty() {
+        let refused = client::remove_versions(&source_s3, &bucket, &removals, &retry, payer).await;
+        output.refused(&refused);
+        failures.refuse(refused);
+    }
+
+    // nothing is pending removal, so the last key known to be processed is
+    // checkpointed (or the checkpoint removed, if there's nothing left); a
+    // batch refused by the guard keeps the checkpoint before it, so those
+    // keys are retried
+    if let Some(mut checkpoint) = checkpoint {
+        if walked.is_ok() {
+            processed = current.or(processed);
+        }
+
+        if flushed.is_err() {
+            checkpoint.save()?;
+        } else if walked.is_ok() && !stopped {
+            checkpoint.clear();
+        } else if let Some(ref key) = processed {
+            checkpoint.advance(key)?;
+            checkpoint.save()?;
+        }
+    }
+
+    // passthrough
+    walked?;
+    flushed?;
+
+    // failing to preserve an ACL doesn't fail the run, but shouldn't be missed
+    if unpreserved > 0 {
+        warn!(
+            "Unable to preserve the ACLs of {} files, so their sources were left in place",
+            unpreserved
+        );
+    }
+
+    // missing keys are usually a stale listing, so are worth a mention
+    if summary.missing > 0 {
+        warn!("Skipped {} listed files which don't exist", summary.missing);
+    }
+
+    // invalid targets usually mean a bad pattern, so should never be missed
+    if summary.invalid > 0 {
+        warn!(
+            "Skipped {} files with targets which can't be written (use --strict to fail instead)",
+            summary.invalid
+        );
+    }
+
+    // collisions lose data, so should never be missed (especially in dry runs)
+    if summary.collisions() > 0 {
+        warn!(
+            "Skipped {} files which would be renamed to the target of another file",
+            summary.collisions()
+        );
+    }
+
+    // as do files left alone to avoid replacing their targets
+    if summary.existing > 0 {
+        warn!(
+            "Skipped {} files as their targets already exist (use --overwrite to replace them)",
+            summary.existing
+        );
+    }
+
+    // as do files which couldn't be copied from their storage class
+    if skipped > 0 {
+        warn!(
+            "Skipped {} files which couldn't be copied (archived files must be restored first)",
+            skipped
+        );
+    }
+
+    // as do files which couldn't be dated
+    if undated > 0 {
+        warn!(
+            "Skipped {} files without a valid modification date to format into their target",
+            undated
+        );
+    }
+
+    // as do tags which didn't make it across
+    if untagged > 0 {
+        warn!(
+            "Unable to verify the tags of {} files, so their sources were left in place",
+            untagged
+        );
+    }
+
+    // dry runs finish with everything they found, as do all runs as records
+    if output.is_enabled() {
+        let requests = cost::snapshot(&Pricing::from_args(args)?);
+        output.summary(&bucket, &summary, failures.len(), &requests);
+    } else if dryrun {
+        summary.print(&bucket);
+    }
+
+    // renamed sources left behind are duplicates, so fail the run too
+    Ok(failures)
+}
+
+/// Undoes renames listed in a manifest, made from one bucket into another.
+///
+/// Renames are replayed newest first, copying each new key back to its old
+/// key before the new key is removed. Any new key which no longer exists is
+/// skipped, as it has either been undone already or removed since.
+async fn undo(s3: &S3Client, args: &ArgMatches<'_>, renames: &[Rename]) -> UtilResult<()> {
+    // parse all global arguments
+    let dryrun = cli::is_dry_run(args);
+
+    // renames are grouped by bucket, so the first names them all
+    let bucket = renames[0].source_bucket.clone();
+    let target_bucket = renames[0].target_bucket.clone();
+
+    // resolve clients for the region of each bucket
+    let (source_s3, _) = client::for_bucket(s3, &bucket).await;
+    let target_s3 = if target_bucket == bucket {
+        source_s3.clone()
+    } else {
+        client::for_bucket(s3, &target_bucket).await.0
+    };
+
+    // requester pays buckets bill every request to the caller
+    let payer = request_payer(args);
+
+    // restored keys, removed from their new location in batches
+    let retry = Retry::from_args(args);
+    let mut removals = Vec::new();
+    let mut unremoved = 0;
+    let mut missing = 0;
+
+    // replay every rename, keeping any failure until removals are done
+    let undone: UtilResult<()> = async {
+        for Rename { old, new, .. } in renames.iter().rev() {
+            // new keys which are gone can't be restored
+            let head = match client::head(&target_s3, &target_bucket, new, payer).await? {
+                Some(head) => head,
+                None => {
+                    warn!(
+                        "Skipping s3://{}/{} as it no longer exists",
+                        target_bucket, new
+                    );
+                    missing += 1;
+                    continue;
+                }
+            };
+
+            // log out exactly what we're restoring right now
+            info!(
+                "Restoring s3://{}/{} -> s3://{}/{}",
+                target_bucket, new, bucket, old
+            );
+
+            // skip
+            if dryrun {
+                continue;
+            }
+
+            // copy back into the old key, keeping the storage class
+            let copy = CopyObjectRequest {
+                key: old.to_string(),
+                bucket: bucket.to_string(),
+                copy_source: client::copy_source(&target_bucket, new),
+                storage_class: head.storage_class,
+                request_payer: payer.map(str::to_string),
+                ..CopyObjectRequest::default()
+            };
+
+            retry
+                .run("copy", || source_s3.copy_object(copy.clone()))
+                .await?;
+
+            // only restored keys are ever queued for removal
+            removals.push(new.to_string());
+
+            if removals.len() >= client::MAX_REMOVAL_BATCH {
+                let refused =
+                    client::remove_objects(&target_s3, &target_bucket, &removals, &retry, payer)
+                        .await;
+                unremoved += refused.len();
+                removals.clear();
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    // remove any new keys still queued, even when the undo failed
+    if !removals.is_empty() {
+        let refused =
+            client::remove_objects(&target_s3, &target_bucket, &removals, &retry, payer).await;
+        unremoved += refused.len();
+    }
+
+    // passthrough
+    undone?;
+
+    // skipped renames may need a closer look
+    if missing > 0 {
+        warn!(
+            "Skipped {} renames as their new keys no longer exist",
+            missing
+        );
+    }
+
+    // restored keys left behind are duplicates, so fail the run
+    if unremoved > 0 {
+        return Err(format!("Unable to remove {} restored keys", unremoved).into());
     }
 
     Ok(())
 }
+
+/// Counts all keys within a bucket/prefix pair which pass a filter.
+async fn count(
+    s3: &S3Client,
+    bucket: &str,
+    prefix: Option<String>,
+    filter: Filter,
+    request_payer: Option<&str>,
+) -> UtilResult<u64> {
+    let mut walker = ObjectWalker::new(s3, bucket.to_string(), prefix)
+        .filter(filter)
+        .request_payer(request_payer.map(str::to_string));
+    let mut total = 0;
+
+    while walker.next().await?.is_some() {
+        total += 1;
+    }
+
+    debug!("Counted {} keys to scan in {}", total, bucket);
+
+    Ok(total)
+}
+
+/// Constructs the request used as a template for each copy.
+///
+/// Metadata and tags are copied from each source unless any are provided,
+/// in which case they're replaced.
+fn copy_from_args(args: &ArgMatches<'_>) -> UtilResult<CopyObjectRequest> {
+    let content_type = args.value_of("content_type").map(str::to_string);
+
+    // pairs are validated by clap, so should be safe to unwrap
+    let metadata = args.values_of("metadata").map(|values| {
+        values
+            .map(|value| cli::parse_pair(value).unwrap())
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+    });
+
+    let provided = content_type.is_some() || metadata.is_some();
+    let metadata_directive = directive(args, "metadata", provided)?;
+
+    // tags are validated by clap, so should be safe to unwrap
+    let tagging = args.values_of("tag").map(|values| {
+        let pairs = values
+            .map(|value| tags::parse(value).unwrap())
+            .collect::<Vec<_>>();
+        tags::encode(&pairs)
+    });
+
+    let tagging_directive = directive(args, "tagging", tagging.is_some())?;
+
+    let copy = CopyObjectRequest {
+        content_type,
+        storage_class: args.value_of("storage_class").map(str::to_string),
+        metadata,
+        metadata_directive: Some(metadata_directive),
+        server_side_encryption: args.value_of("sse").map(str::to_string),
+        ssekms_key_id: args.value_of("sse_kms_key_id").map(str::to_string),
+        tagging,
+        tagging_directive: Some(tagging_directive),
+        request_payer: request_payer(args).map(str::to_string),
+        ..CopyObjectRequest::default()
+    };
+
+    // KMS keys can only be used with KMS encryption
+    if copy.ssekms_key_id.is_some() && copy.server_side_encryption.as_deref() != Some("aws:kms") {
+        return Err("A KMS key can only be provided when using aws:kms encryption".into());
+    }
+
+    if let Some(ref encryption) = copy.server_side_encryption {
+        match copy.ssekms_key_id {
+            Some(ref key_id) => info!("Renaming files with {} encryption ({})", encryption, key_id),
+            None => info!("Renaming files with {} encryption", encryption),
+        }
+    }
+
+    Ok(copy)
+}
+
+/// Determines a copy directive, as either `COPY` or `REPLACE`.
+///
+/// Directives replace when explicit, or when anything is provided to replace
+/// with. Asking to copy while also providing values is rejected, as S3 would
+/// silently ignore what was provided.
+fn directive(args: &ArgMatches<'_>, name: &str, provided: bool) -> UtilResult<String> {
+    let replace = match args.value_of(format!("{}_directive", name)) {
+        Some("copy") if provided => {
+            return Err(format!(
+                "Values for {} can only be provided with --{}-directive replace",
+                name, name
+            )
+            .into());
+        }
+        Some(directive) => directive == "replace",
+        None => provided,
+    };
+
+    if replace && !provided {
+        warn!(
+            "Replacing {} without providing any values, so it will all be dropped",
+            name
+        );
+    }
+
+    Ok(if replace { "REPLACE" } else { "COPY" }.to_string())
+}
+
+/// Determines the payer of each request, if accepting requester pays.
+fn request_payer(args: &ArgMatches<'_>) -> Option<&'static str> {
+    if args.is_present("requester_pays") {
+        Some("requester")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::case::{Case, CaseTemplate};
+    use super::versions;
+    use super::{cmd, copy_from_args, request_payer};
+    use crate::copy::{acl_request, tags_request};
+    use crate::dates::{DateRange, DateSource, DatedTemplate};
+    use regex::Regex;
+
+    #[test]
+    fn sending_the_request_payer() {
+        let args = cmd().get_matches_from(vec!["rename", "bucket", "a", "b", "--requester-pays"]);
+        let payer = request_payer(&args);
+        let requester = Some("requester".to_string());
+
+        assert_eq!(copy_from_args(&args).unwrap().request_payer, requester);
+        assert_eq!(tags_request("bucket", "a", payer).request_payer, requester);
+        assert_eq!(
+            acl_request("bucket", "b", Default::default(), payer).request_payer,
+            requester
+        );
+        assert_eq!(
+            versions::list_request("bucket", "a", payer).request_payer,
+            requester
+        );
+
+        let args = cmd().get_matches_from(vec!["rename", "bucket", "a", "b"]);
+
+        assert_eq!(copy_from_args(&args).unwrap().request_payer, None);
+        assert_eq!(
+            tags_request("bucket", "a", request_payer(&args)).request_payer,
+            None
+        );
+    }
+
+    #[test]
+    fn rendering_dated_targets() {
+        let dated =
+            DatedTemplate::parse(r"archive/%Y/%m/%d/\L$1", Some(DateSource::Newest)).unwrap();
+        let target = CaseTemplate::parse(dated.template());
+        let pattern = Regex::new(r"raw/(.*)").unwrap();
+
+        let mut range = DateRange::default();
+        range.include("2018-01-02T10:00:00.000Z").unwrap();
+
+        let rendered = target.render(&pattern, "raw/File.log", Case::Keep);
+
+        assert_eq!(
+            dated.resolve(&rendered, &range),
+            "archive/2018/01/02/file.log"
+        );
+
+        // bare directives are left alone unless asked for
+        let dated = DatedTemplate::parse(r"archive/100%/${maxdate:%Y}/$1", None).unwrap();
+        let target = CaseTemplate::parse(dated.template());
+        let rendered = target.render(&pattern, "raw/File.log", Case::Keep);
+
+        assert_eq!(
+            dated.resolve(&rendered, &range),
+            "archive/100%/2018/File.log"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Machine readable output of every file processed by a rename.
+//!
+//! With `--output jsonl`, a line of JSON is written to stdout for each file
+//! matched by a rename, naming the source, the target (once known), the
+//! status of the file and why it wasn't renamed (if it wasn't). A summary
+//! record with the totals of each bucket follows its files. All logging is
+//! moved to stderr in this mode, so stdout only ever contains records.
+use clap::ArgMatches;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::summary::Summary;
+use crate::cli;
+use crate::cost::Snapshot;
+
+/// Output structure writing a record of each file processed.
+pub struct Output {
+    action: &'static str,
+    dry_run: bool,
+    enabled: bool,
+}
+
+/// Main implementation.
+impl Output {
+    /// Constructs a new `Output` from the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> Output {
+        Output {
+            action: if args.is_present("no_delete") {
+                "copy"
+            } else {
+                "rename"
+            },
+            dry_run: cli::is_dry_run(args),
+            enabled: is_jsonl(args),
+        }
+    }
+
+    /// Determines whether records are being written.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Writes a file which was renamed (or would be, in a dry run).
+    pub fn done(&self, source: &str, target: &str) {
+        let status = if self.dry_run { "planned" } else { "done" };
+        self.write(record(self.action, source, Some(target), status, None));
+    }
+
+    /// Writes a file which was skipped, alongside the reason.
+    pub fn skipped(&self, source: &str, target: Option<&str>, reason: &str) {
+        self.write(record(self.action, source, target, "skipped", Some(reason)));
+    }
+
+    /// Writes a file which failed to rename, alongside the error.
+    pub fn failed(&self, source: &str, target: Option<&str>, error: &str) {
+        self.write(record(self.action, source, target, "failed", Some(error)));
+    }
+
+    /// Writes each renamed source which S3 refused to remove.
+    pub fn refused(&self, refused: &HashMap<String, String>) {
+        for (source, reason) in refused {
+            self.write(record("remove", source, None, "failed", Some(reason)));
+        }
+    }
+
+    /// Writes the totals of everything processed within a bucket.
+    ///
+    /// Requests are counted across the whole run, so the snapshot covers
+    /// every bucket processed so far, rather than just this one.
+    pub fn summary(&self, bucket: &str, summary: &Summary, failed: usize, requests: &Snapshot) {
+        self.write(json!({
+            "action": "summary",
+            "bucket": bucket,
+            "dry_run": self.dry_run,
+            "matched": summary.matched,
+            "renamed": summary.renamed(),
+            "bytes": summary.bytes(),
+            "skipped": summary.skipped(),
+            "failed": failed,
+            "requests": requests.to_json(),
+        }));
+    }
+
+    /// Writes a record as a line of JSON, if enabled.
+    ///
+    /// A reader going away shouldn't interrupt a rename part way through,
+    /// so any failure to write is ignored.
+    fn write(&self, record: Value) {
+        if self.enabled {
+            let _ = writeln!(io::stdout().lock(), "{}", record);
+        }
+    }
+}
+
+/// Determines whether JSON lines were requested as output.
+fn is_jsonl(args: &ArgMatches<'_>) -> bool {
+    args.value_of("output") == Some("jsonl")
+}
+
+/// Constructs the record of a single file.
+fn record(
+    action: &str,
+    source: &str,
+    target: Option<&str>,
+    status: &str,
+    error: Option<&str>,
+) -> Value {
+    json!({
+        "action": action,
+        "source": source,
+        "target": target,
+        "status": status,
+        "error": error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::record;
+
+    #[test]
+    fn recording_processed_files() {
+        assert_eq!(
+            record("rename", "logs/a.log", Some("archive/a.log"), "done", None).to_string(),
+            r#"{"action":"rename","error":null,"source":"logs/a.log","status":"done","target":"archive/a.log"}"#
+        );
+        assert_eq!(
+            record("copy", "logs/b.log", None, "skipped", Some("excluded")).to_string(),
+            r#"{"action":"copy","error":"excluded","source":"logs/b.log","status":"skipped","target":null}"#
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Module to track and display progress through long renames.
+//!
+//! Rather than a line per file, progress is shown as a single status line
+//! which is redrawn in place on stderr. The walk streams keys as they're
+//! listed, so the total is only known when it was counted up front (using
+//! `--precount`); without one, only the counts so far are shown.
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use super::summary::Summary;
+use crate::report::util;
+
+/// Interval to wait between each redraw of the status line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Progress structure to track the number of keys walked.
+pub struct Progress {
+    total: Option<u64>,
+    scanned: u64,
+    started: Instant,
+    drawn: Instant,
+}
+
+/// Progress impl.
+impl Progress {
+    /// Constructs a new `Progress` struct from an optional total.
+    pub fn new(total: Option<u64>) -> Progress {
+        let now = Instant::now();
+        Progress {
+            total,
+            scanned: 0,
+            started: now,
+            drawn: now,
+        }
+    }
+
+    /// Registers a walked key, redrawing the status line when one is due.
+    pub fn tick(&mut self, summary: &Summary) {
+        self.scanned += 1;
+
+        if self.drawn.elapsed() >= REDRAW_INTERVAL {
+            self.draw(summary);
+        }
+    }
+
+    /// Redraws the status line a final time, and moves past it.
+    pub fn finish(&mut self, summary: &Summary) {
+        self.draw(summary);
+        eprintln!();
+    }
+
+    /// Redraws the status line in place.
+    fn draw(&mut self, summary: &Summary) {
+        let line = describe(self.scanned, self.total, summary, self.started.elapsed());
+
+        // clear anything left over from a longer line
+        eprint!("\r\x1b[K{}", line);
+        io::stderr().flush().ok();
+
+        self.drawn = Instant::now();
+    }
+}
+
+/// Describes progress through a rename after the provided elapsed time.
+fn describe(scanned: u64, total: Option<u64>, summary: &Summary, elapsed: Duration) -> String {
+    // totals can be stale by the time we walk, so never go past them
+    let scanned_of = match total {
+        Some(total) if total > 0 && scanned <= total => format!(
+            "{} of {} keys ({:.1}%)",
+            scanned,
+            total,
+            scanned as f64 / total as f64 * 100.0
+        ),
+        _ => format!("{} keys", scanned),
+    };
+
+    // rates are calculated across the entire run
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        scanned as f64 / seconds
+    } else {
+        0.0
+    };
+
+    format!(
+        "Scanned {}, renamed {}, skipped {}, moved {} in {} ({:.1} keys/s)",
+        scanned_of,
+        summary.renamed(),
+        summary.skipped(),
+        util::convert_bytes(summary.bytes() as u64),
+        humantime::format_duration(Duration::from_secs(elapsed.as_secs())),
+        rate
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::summary::Summary;
+    use std::time::Duration;
+
+    #[test]
+    fn describing_rename_progress() {
+        let mut summary = Summary::default();
+
+        summary.matched = 3;
+        summary.rename(1024);
+        summary.rename(1024);
+
+        let elapsed = Duration::from_secs(10);
+        let counted = super::describe(50, Some(200), &summary, elapsed);
+        let uncounted = super::describe(50, None, &summary, elapsed);
+
+        assert_eq!(
+            counted,
+            "Scanned 50 of 200 keys (25.0%), renamed 2, skipped 1, moved 2.05kB in 10s (5.0 keys/s)"
+        );
+        assert_eq!(
+            uncounted,
+            "Scanned 50 keys, renamed 2, skipped 1, moved 2.05kB in 10s (5.0 keys/s)"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Sanitization of characters in keys which are unsafe for other tools.
+//!
+//! S3 accepts almost anything in a key, but documents a set of characters
+//! best avoided as they need special handling elsewhere. By default these
+//! are replaced with an underscore, along with any control characters;
+//! both the set of characters and their replacement can be changed.
+use clap::ArgMatches;
+
+/// Characters which S3 recommends avoiding within keys.
+pub const UNSAFE_CHARS: &str = "\\{}^%`[]\"<>~#|";
+
+/// Sanitizer structure to replace unsafe characters within keys.
+pub struct Sanitizer {
+    chars: Vec<char>,
+    replacement: String,
+}
+
+/// Main implementation.
+impl Sanitizer {
+    /// Constructs a new `Sanitizer` from the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> Sanitizer {
+        let chars = args.value_of("sanitize_chars").unwrap_or(UNSAFE_CHARS);
+        let replacement = args.value_of("sanitize_replacement").unwrap_or("_");
+
+        Sanitizer::new(chars, replacement)
+    }
+
+    /// Constructs a new `Sanitizer` from a set of characters and replacement.
+    pub fn new(chars: &str, replacement: &str) -> Sanitizer {
+        Sanitizer {
+            chars: chars.chars().collect(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    /// Replaces every unsafe (or control) character within a key.
+    pub fn sanitize(&self, key: &str) -> String {
+        let mut sanitized = String::with_capacity(key.len());
+
+        for c in key.chars() {
+            if c.is_control() || self.chars.contains(&c) {
+                sanitized.push_str(&self.replacement);
+            } else {
+                sanitized.push(c);
+            }
+        }
+
+        sanitized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sanitizer, UNSAFE_CHARS};
+
+    #[test]
+    fn sanitizing_unsafe_characters() {
+        let sanitizer = Sanitizer::new(UNSAFE_CHARS, "_");
+
+        assert_eq!(sanitizer.sanitize("logs/a.log"), "logs/a.log");
+        assert_eq!(
+            sanitizer.sanitize("logs\\{a}^b%20.log"),
+            "logs__a__b_20.log"
+        );
+        assert_eq!(sanitizer.sanitize("logs/a\tb\u{7f}.log"), "logs/a_b_.log");
+
+        let sanitizer = Sanitizer::new(" ", "-");
+
+        assert_eq!(sanitizer.sanitize("logs/my file%.log"), "logs/my-file%.log");
+    }
+}
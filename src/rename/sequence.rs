@@ -0,0 +1,92 @@
+//! Sequence numbers within rename targets, using a `{seq}` token.
+//!
+//! Each renamed file is assigned the next number in the sequence (starting
+//! at 1), and a width can be provided to pad the number with zeros (such as
+//! `{seq:06}`). The sequence only advances once a file is going to be renamed,
+//! so files which are skipped don't leave gaps in the numbering. Numbers are substituted once the
+//! target has been rendered, so they can't be mistaken for part of a capture
+//! reference (such as `$1{seq}` becoming `$11`).
+use regex::{Captures, Regex};
+
+/// Pattern matching each sequence token within a target.
+///
+/// Tokens can sit within a case span, so they're matched in any case.
+const TOKEN: &str = r"(?i)\{seq(?::(\d+))?\}";
+
+/// Sequence structure to number the targets of a rename.
+pub struct Sequence {
+    token: Regex,
+    next: u64,
+}
+
+/// Main implementation.
+impl Sequence {
+    /// Constructs a new `Sequence` if a target contains any tokens.
+    pub fn parse(target: &str) -> Option<Sequence> {
+        let token = Regex::new(TOKEN).unwrap();
+
+        if !token.is_match(target) {
+            return None;
+        }
+
+        Some(Sequence { token, next: 1 })
+    }
+
+    /// Substitutes the next number into every token within a target.
+    ///
+    /// The number isn't used up until `advance` is called, so a target can
+    /// be rendered for a file which is then skipped.
+    pub fn substitute(&self, target: &str) -> String {
+        let number = self.next;
+
+        self.token
+            .replace_all(target, |captures: &Captures<'_>| {
+                let width = captures
+                    .get(1)
+                    .and_then(|width| width.as_str().parse().ok())
+                    .unwrap_or(0);
+
+                format!("{:0width$}", number, width = width)
+            })
+            .into_owned()
+    }
+
+    /// Moves onto the next number, once a target has been used.
+    pub fn advance(&mut self) {
+        self.next += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sequence;
+
+    #[test]
+    fn numbering_sequenced_targets() {
+        let mut sequence = Sequence::parse("frames/frame-{seq:06}.jpg").unwrap();
+
+        assert_eq!(
+            sequence.substitute("frames/frame-{seq:06}.jpg"),
+            "frames/frame-000001.jpg"
+        );
+
+        // skipped files don't use up a number
+        assert_eq!(
+            sequence.substitute("frames/frame-{seq:06}.jpg"),
+            "frames/frame-000001.jpg"
+        );
+
+        sequence.advance();
+
+        assert_eq!(
+            sequence.substitute("frames/frame-{seq:06}.jpg"),
+            "frames/frame-000002.jpg"
+        );
+
+        sequence.advance();
+
+        assert_eq!(sequence.substitute("logs/{seq}-{SEQ:3}"), "logs/3-003");
+
+        assert!(Sequence::parse("frames/$1.jpg").is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Sources of the files to rename, either walked or explicitly listed.
+//!
+//! A listing names a single source key on each line, optionally followed by
+//! a tab and the target to rename it to. Listed keys are fetched one by one
+//! rather than walking the bucket, and keys without their own target are
+//! rendered using the target pattern as usual.
+//!
+//! Either kind of source can also be buffered and sorted before anything is
+//! renamed, so that files are renamed in a deterministic order.
+use chrono::{DateTime, SecondsFormat, Utc};
+use regex::Regex;
+use rusoto_s3::*;
+
+use std::vec::IntoIter;
+
+use crate::client;
+use crate::types::UtilResult;
+use crate::walker::ObjectWalker;
+
+/// Source keys listed alongside an optional explicit target.
+pub type Listed = (String, Option<String>);
+
+/// Orderings available to sort buffered sources by.
+#[derive(Clone, Copy)]
+pub enum Sort {
+    Key,
+    LastModified,
+}
+
+/// Sources of the files to rename within a bucket.
+pub enum Sources<'a> {
+    Walked(ObjectWalker<'a>),
+    Listed {
+        s3: &'a S3Client,
+        bucket: String,
+        listed: std::slice::Iter<'a, Listed>,
+        missing: u64,
+        request_payer: Option<&'a str>,
+    },
+    Buffered {
+        buffered: IntoIter<(Object, Option<String>)>,
+        missing: u64,
+    },
+}
+
+/// Main implementation.
+impl<'a> Sources<'a> {
+    /// Retrieves the next source, alongside any explicit target.
+    ///
+    /// Listed keys which don't exist are logged and skipped, rather than
+    /// failing the entire run.
+    pub async fn next(&mut self) -> UtilResult<Option<(Object, Option<String>)>> {
+        let (s3, bucket, listed, missing, request_payer) = match self {
+            Sources::Walked(walker) => {
+                return Ok(walker.next().await?.map(|object| (object, None)));
+            }
+            Sources::Buffered { buffered, .. } => return Ok(buffered.next()),
+            Sources::Listed {
+                s3,
+                bucket,
+                listed,
+                missing,
+                request_payer,
+            } => (s3, bucket, listed, missing, *request_payer),
+        };
+
+        for (key, target) in listed {
+            let head = match client::head(s3, bucket, key, request_payer).await? {
+                Some(head) => head,
+                None => {
+                    warn!("Skipping s3://{}/{} as it does not exist", bucket, key);
+                    *missing += 1;
+                    continue;
+                }
+            };
+
+            // listings carry RFC 3339 dates, so heads are converted to match
+            let last_modified = head
+                .last_modified
+                .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+                .map(|date| {
+                    date.with_timezone(&Utc)
+                        .to_rfc3339_opts(SecondsFormat::Millis, true)
+                });
+
+            let object = Object {
+                key: Some(key.to_string()),
+                size: head.content_length,
+                last_modified,
+                e_tag: head.e_tag,
+                storage_class: head.storage_class,
+                ..Object::default()
+            };
+
+            return Ok(Some((object, target.clone())));
+        }
+
+        Ok(None)
+    }
+
+    /// Buffers every remaining source matching a pattern, sorted by an order.
+    ///
+    /// Sources listed with an explicit target are always buffered, as they're
+    /// never matched against the pattern.
+    pub async fn sort(mut self, pattern: &Regex, sort: Sort) -> UtilResult<Sources<'a>> {
+        let mut buffered = Vec::new();
+
+        while let Some((object, explicit)) = self.next().await? {
+            let key = object.key.as_deref().unwrap_or_default();
+            if explicit.is_some() || pattern.is_match(key) {
+                buffered.push((object, explicit));
+            }
+        }
+
+        match sort {
+            Sort::Key => buffered.sort_by(|(left, _), (right, _)| left.key.cmp(&right.key)),
+            Sort::LastModified => buffered.sort_by(|(left, _), (right, _)| {
+                left.last_modified
+                    .cmp(&right.last_modified)
+                    .then_with(|| left.key.cmp(&right.key))
+            }),
+        }
+
+        Ok(Sources::Buffered {
+            buffered: buffered.into_iter(),
+            missing: self.missing(),
+        })
+    }
+
+    /// Retrieves the number of listed keys which don't exist.
+    pub fn missing(&self) -> u64 {
+        match self {
+            Sources::Walked(_) => 0,
+            Sources::Listed { missing, .. } => *missing,
+            Sources::Buffered { missing, .. } => *missing,
+        }
+    }
+}
+
+/// Parses a listing into source keys and any explicit targets.
+///
+/// Blank lines are ignored, but any line without a target fails the whole
+/// listing if there's no target pattern to render one with.
+pub fn parse(contents: &str, has_pattern: bool) -> UtilResult<Vec<Listed>> {
+    let mut listed = Vec::new();
+
+    for line in contents.lines() {
+        // allow listings written with Windows line endings
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // an explicit target follows the first tab
+        let (key, target) = match line.split_once('\t') {
+            Some((key, target)) if !target.trim().is_empty() => {
+                (key, Some(target.trim().to_string()))
+            }
+            Some((key, _)) => (key, None),
+            None => (line, None),
+        };
+
+        if target.is_none() && !has_pattern {
+            return Err(format!("No target provided for source {}", key).into());
+        }
+
+        listed.push((key.to_string(), target));
+    }
+
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parsing_listed_sources() {
+        let listed = parse("logs/a.log\r\n\nlogs/b.log\tarchive/b.log\n", true).unwrap();
+
+        assert_eq!(
+            listed,
+            vec![
+                ("logs/a.log".to_string(), None),
+                ("logs/b.log".to_string(), Some("archive/b.log".to_string())),
+            ]
+        );
+
+        assert!(parse("logs/a.log\n", false).is_err());
+        assert!(parse("logs/b.log\tarchive/b.log\n", false).is_ok());
+    }
+}
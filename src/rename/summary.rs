@@ -0,0 +1,122 @@
+//! Summaries of everything planned within a dry run.
+//!
+//! Before a large rename it's far more useful to know the totals (and any
+//! conflicts) than to read a line for every file. All counters are tracked
+//! while walking, and are printed once the walk is complete in the same
+//! `key=value` format as the `report` command, so they can be scripted.
+use crate::report::util;
+
+/// Counters tracked while renaming the files within a bucket.
+#[derive(Default)]
+pub struct Summary {
+    pub matched: u64,
+    pub missing: u64,
+    pub excluded: u64,
+    pub outside_size: u64,
+    pub outside_window: u64,
+    pub self_targets: u64,
+    pub invalid: u64,
+    pub existing: u64,
+    renamed: u64,
+    bytes: i64,
+    conflicts: Vec<(String, Vec<String>)>,
+}
+
+/// Main implementation.
+impl Summary {
+    /// Tracks a file to be renamed, alongside its size.
+    pub fn rename(&mut self, bytes: i64) {
+        self.renamed += 1;
+        self.bytes += bytes;
+    }
+
+    /// Retrieves the number of files to be renamed.
+    pub fn renamed(&self) -> u64 {
+        self.renamed
+    }
+
+    /// Retrieves the total bytes of the files to be renamed.
+    pub fn bytes(&self) -> i64 {
+        self.bytes
+    }
+
+    /// Retrieves the number of matching files which were skipped.
+    pub fn skipped(&self) -> u64 {
+        self.matched - self.renamed
+    }
+
+    /// Tracks a source colliding with the source already mapped to a target.
+    pub fn conflict(&mut self, target: &str, existing: &str, source: &str) {
+        if let Some((_, sources)) = self.conflicts.iter_mut().find(|(t, _)| t == target) {
+            sources.push(source.to_string());
+            return;
+        }
+
+        let sources = vec![existing.to_string(), source.to_string()];
+        self.conflicts.push((target.to_string(), sources));
+    }
+
+    /// Retrieves the number of sources skipped due to conflicts.
+    pub fn collisions(&self) -> usize {
+        self.conflicts
+            .iter()
+            .map(|(_, sources)| sources.len() - 1)
+            .sum()
+    }
+
+    /// Prints this summary under the `rename` header.
+    pub fn print(&self, bucket: &str) {
+        util::log_head("rename");
+        util::log_pair("bucket_name", bucket);
+        util::log_pair("matched_files", self.matched);
+        util::log_pair("renamed_files", self.renamed);
+        util::log_pair("renamed_bytes", self.bytes);
+        util::log_pair("skipped_missing", self.missing);
+        util::log_pair("skipped_excluded", self.excluded);
+        util::log_pair("skipped_outside_size", self.outside_size);
+        util::log_pair("skipped_outside_window", self.outside_window);
+        util::log_pair("skipped_self_targets", self.self_targets);
+        util::log_pair("skipped_invalid_targets", self.invalid);
+        util::log_pair("skipped_existing_targets", self.existing);
+        util::log_pair("skipped_collisions", self.collisions());
+        util::log_pair("conflicting_targets", self.conflicts.len());
+
+        for (target, sources) in &self.conflicts {
+            util::log_pair("conflicting_target", target);
+            util::log_pair("conflicting_sources", sources.join(","));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn tracking_conflicting_targets() {
+        let mut summary = Summary::default();
+
+        summary.conflict("s3://b/t1", "s3://b/a", "s3://b/b");
+        summary.conflict("s3://b/t2", "s3://b/c", "s3://b/d");
+        summary.conflict("s3://b/t1", "s3://b/a", "s3://b/e");
+
+        assert_eq!(summary.collisions(), 3);
+        assert_eq!(
+            summary.conflicts,
+            vec![
+                (
+                    "s3://b/t1".to_string(),
+                    vec![
+                        "s3://b/a".to_string(),
+                        "s3://b/b".to_string(),
+                        "s3://b/e".to_string()
+                    ]
+                ),
+                (
+                    "s3://b/t2".to_string(),
+                    vec!["s3://b/c".to_string(), "s3://b/d".to_string()]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Renaming every version of a file within a versioned bucket.
+//!
+//! Copies only ever carry a single version of an object, and removing an
+//! object from a versioned bucket only places a delete marker on top of its
+//! history. To rename the history too, each version is copied across in the
+//! order it was written, before every version (and marker) of the source is
+//! removed.
+use rusoto_s3::*;
+
+use crate::client;
+use crate::types::UtilResult;
+
+/// A single version (or delete marker) of an object.
+pub struct Version {
+    pub version_id: String,
+    pub last_modified: String,
+    pub storage_class: Option<String>,
+    pub is_marker: bool,
+}
+
+/// Determines whether a bucket keeps previous versions of its objects.
+///
+/// Buckets with versioning suspended are included, as any versions written
+/// before it was suspended are still kept.
+pub async fn is_versioned(s3: &S3Client, bucket: &str) -> UtilResult<bool> {
+    let request = GetBucketVersioningRequest {
+        bucket: bucket.to_string(),
+        ..GetBucketVersioningRequest::default()
+    };
+
+    let output = s3.get_bucket_versioning(request).await?;
+
+    Ok(output.status.is_some())
+}
+
+/// Lists every version and delete marker of an object, oldest first.
+pub async fn list(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> UtilResult<Vec<Version>> {
+    let mut versions = Vec::new();
+    let mut key_marker = None;
+    let mut version_id_marker = None;
+
+    loop {
+        // the key is a prefix of any longer key, so has to be matched exactly
+        let request = ListObjectVersionsRequest {
+            key_marker: key_marker.take(),
+            version_id_marker: version_id_marker.take(),
+            ..list_request(bucket, key, request_payer)
+        };
+
+        let output = s3.list_object_versions(request).await?;
+
+        for version in output.versions.unwrap_or_default() {
+            if version.key.as_deref() == Some(key) {
+                versions.push(Version {
+                    version_id: version.version_id.unwrap_or_default(),
+                    last_modified: version.last_modified.unwrap_or_default(),
+                    storage_class: version.storage_class,
+                    is_marker: false,
+                });
+            }
+        }
+
+        for marker in output.delete_markers.unwrap_or_default() {
+            if marker.key.as_deref() == Some(key) {
+                versions.push(Version {
+                    version_id: marker.version_id.unwrap_or_default(),
+                    last_modified: marker.last_modified.unwrap_or_default(),
+                    storage_class: None,
+                    is_marker: true,
+                });
+            }
+        }
+
+        // the key sorts before any longer key, so stop once listing moves past it
+        if output.is_truncated != Some(true) || output.next_key_marker.as_deref() != Some(key) {
+            break;
+        }
+
+        key_marker = output.next_key_marker;
+        version_id_marker = output.next_version_id_marker;
+    }
+
+    chronological(&mut versions);
+
+    Ok(versions)
+}
+
+/// Constructs the first request listing the versions of an object.
+pub fn list_request(
+    bucket: &str,
+    key: &str,
+    request_payer: Option<&str>,
+) -> ListObjectVersionsRequest {
+    ListObjectVersionsRequest {
+        bucket: bucket.to_string(),
+        prefix: Some(key.to_string()),
+        request_payer: request_payer.map(str::to_string),
+        ..ListObjectVersionsRequest::default()
+    }
+}
+
+/// Sorts versions into the order they were written.
+///
+/// Versions are listed newest first, so they're reversed before sorting to
+/// keep versions written within the same second in their listed order.
+pub fn chronological(versions: &mut [Version]) {
+    versions.reverse();
+    versions.sort_by(|left, right| left.last_modified.cmp(&right.last_modified));
+}
+
+/// Formats the source of a copy request, for a version of an object.
+pub fn copy_source(bucket: &str, key: &str, version_id: &str) -> String {
+    format!(
+        "{}?versionId={}",
+        client::copy_source(bucket, key),
+        client::encode(version_id, b"")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordering_versions_chronologically() {
+        let version = |version_id: &str, last_modified: &str| Version {
+            version_id: version_id.to_string(),
+            last_modified: last_modified.to_string(),
+            storage_class: None,
+            is_marker: false,
+        };
+
+        let mut versions = vec![
+            version("c", "2018-01-02T00:00:00.000Z"),
+            version("b", "2018-01-01T00:00:00.000Z"),
+            version("a", "2018-01-01T00:00:00.000Z"),
+        ];
+
+        chronological(&mut versions);
+
+        let ids = versions
+            .iter()
+            .map(|version| version.version_id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert_eq!(
+            copy_source("bucket", "logs/my file.log", "a+b"),
+            "bucket/logs/my%20file.log?versionId=a%2Bb"
+        );
+    }
+}
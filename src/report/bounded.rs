@@ -65,6 +65,19 @@ where
     inner_apply(upper, key, val, |left, right| left > right);
 }
 
+/// Utility function to merge lower/upper bounds which were tracked separately.
+pub fn merge<T>(
+    lower: &mut Bounded<T>,
+    upper: &mut Bounded<T>,
+    other_lower: Bounded<T>,
+    other_upper: Bounded<T>,
+) where
+    T: Clone + Eq + Ord + PartialEq + PartialOrd,
+{
+    inner_merge(lower, other_lower, |left, right| left < right);
+    inner_merge(upper, other_upper, |left, right| left > right);
+}
+
 /// Applies changes for a key/value based on a custom comparator.
 ///
 /// The comparator function is provided as an argument to embed easily into different
@@ -83,9 +96,30 @@ where
     }
 }
 
+/// Merges another bound into a bound based on a custom comparator.
+///
+/// When both bounds hold the same value the key of the existing bound is
+/// kept, and the counts of both bounds are summed to track all matches.
+#[inline]
+fn inner_merge<C, T>(bound: &mut Bounded<T>, other: Bounded<T>, cmp: C)
+where
+    T: Clone + Eq + Ord + PartialEq + PartialOrd,
+    C: FnOnce(&T, &T) -> bool,
+{
+    if other.is_unset() {
+        return;
+    }
+
+    if bound.is_unset() || cmp(other.value(), bound.value()) {
+        *bound = other;
+    } else if other.value() == bound.value() {
+        bound.count += other.count;
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Bounded;
+    use super::{apply, merge, Bounded};
 
     #[test]
     fn updating_bounded_values() {
@@ -110,4 +144,41 @@ mod tests {
         assert_eq!(bounded.value(), &75);
         assert_eq!(bounded.count(), 2);
     }
+
+    #[test]
+    fn merging_bounded_values() {
+        let values = [("a", 5), ("b", 3), ("c", 9), ("d", 3), ("e", 9), ("f", 3)];
+
+        let mut lower = Bounded::new(0);
+        let mut upper = Bounded::new(0);
+
+        for (key, val) in &values {
+            apply(&mut lower, &mut upper, key, val);
+        }
+
+        let mut left_lower = Bounded::new(0);
+        let mut left_upper = Bounded::new(0);
+        let mut right_lower = Bounded::new(0);
+        let mut right_upper = Bounded::new(0);
+
+        for (key, val) in &values[..3] {
+            apply(&mut left_lower, &mut left_upper, key, val);
+        }
+
+        for (key, val) in &values[3..] {
+            apply(&mut right_lower, &mut right_upper, key, val);
+        }
+
+        merge(&mut left_lower, &mut left_upper, right_lower, right_upper);
+
+        assert_eq!(left_lower.key(), lower.key());
+        assert_eq!(left_lower.value(), lower.value());
+        assert_eq!(left_lower.count(), lower.count());
+        assert_eq!(left_lower.count(), 3);
+
+        assert_eq!(left_upper.key(), upper.key());
+        assert_eq!(left_upper.value(), upper.value());
+        assert_eq!(left_upper.count(), upper.count());
+        assert_eq!(left_upper.count(), 2);
+    }
 }
@@ -0,0 +1,128 @@
+//! Modification time distribution metrics tracking for S3 objects.
+use chrono::DateTime;
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use super::{Metric, Section};
+
+/// Container struct for modification time distribution metrics.
+///
+/// Files are counted into a bin per period (such as a calendar month) of
+/// their modification time, with any unparseable time counted as unknown.
+pub struct AgeHistogram {
+    format: &'static str,
+    periods: BTreeMap<String, (u64, u64)>,
+}
+
+/// Main implementation.
+impl AgeHistogram {
+    /// Constructs a new `AgeHistogram` struct using a period granularity.
+    pub(super) fn new(granularity: &str) -> AgeHistogram {
+        AgeHistogram {
+            format: match granularity {
+                "year" => "%Y",
+                "day" => "%Y-%m-%d",
+                _ => "%Y-%m",
+            },
+            periods: BTreeMap::new(),
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for AgeHistogram {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        // format the period, falling back to unknown
+        let period = object
+            .last_modified
+            .as_deref()
+            .and_then(|modified| DateTime::parse_from_rfc3339(modified).ok())
+            .map(|modified| modified.format(self.format).to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        // increment the counters of the period
+        let (files, bytes) = self.periods.entry(period).or_insert((0, 0));
+
+        *files += 1;
+        *bytes += super::get_size(object);
+    }
+
+    /// Merges another `AgeHistogram` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<AgeHistogram>(other);
+
+        for (period, (files, bytes)) in other.periods {
+            let entry = self.periods.entry(period).or_insert((0, 0));
+
+            entry.0 += files;
+            entry.1 += bytes;
+        }
+    }
+
+    /// Retrieves all internal statistics under the `modification_histogram` header.
+    ///
+    /// Periods are in chronological order, followed by any unknown times.
+    fn sections(&self) -> Vec<Section> {
+        // next segment: modification_histogram
+        let mut histogram = Section::new("modification_histogram");
+
+        // add the count and bytes of each period
+        for (period, (files, bytes)) in &self.periods {
+            histogram.push(&format!("{}_files", period), files);
+            histogram.push(&format!("{}_bytes", period), bytes);
+        }
+
+        vec![histogram]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AgeHistogram;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(
+            || AgeHistogram::new("month"),
+            &metrics::tests::objects(),
+        );
+    }
+
+    #[test]
+    fn bucketing_modification_times() {
+        let mut objects = metrics::tests::objects();
+        let mut histogram = AgeHistogram::new("year");
+
+        objects[0].last_modified = Some("yesterday".to_string());
+        objects[1].last_modified = None;
+
+        for object in &objects {
+            histogram.register(object);
+        }
+
+        let periods = histogram
+            .periods
+            .iter()
+            .map(|(period, counts)| (period.as_str(), *counts))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            periods,
+            vec![
+                ("2018", (1, 50)),
+                ("2019", (1, 10)),
+                ("2020", (2, 60)),
+                ("unknown", (2, 40)),
+            ]
+        );
+    }
+}
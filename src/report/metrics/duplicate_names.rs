@@ -0,0 +1,178 @@
+//! Duplicate file name metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use super::{Metric, Section};
+
+/// Number of duplicate names listed under the `duplicate_names` header.
+const LISTED: usize = 10;
+
+/// Maximum number of unique names tracked, to bound memory usage.
+const MAX_NAMES: usize = 1_000_000;
+
+/// Container struct for duplicate file name metrics tracked by S3.
+///
+/// Files are counted by their name (the final segment of their key), so
+/// the same file written beneath several prefixes can be found. Once the
+/// number of unique names reaches a cap, new names are no longer tracked
+/// (but names already tracked are still counted).
+pub struct DuplicateNames {
+    names: HashMap<String, u64>,
+    limit: usize,
+    capped: bool,
+}
+
+/// Main implementation.
+impl DuplicateNames {
+    /// Constructs a new `DuplicateNames` struct.
+    pub(super) fn new() -> DuplicateNames {
+        DuplicateNames::with_limit(MAX_NAMES)
+    }
+
+    /// Constructs a new `DuplicateNames` struct, tracking up to `limit` names.
+    fn with_limit(limit: usize) -> DuplicateNames {
+        DuplicateNames {
+            names: HashMap::new(),
+            limit,
+            capped: false,
+        }
+    }
+
+    /// Adds occurrences of a name, unless the cap has been reached.
+    fn add(&mut self, name: &str, count: u64) {
+        if let Some(existing) = self.names.get_mut(name) {
+            *existing += count;
+            return;
+        }
+
+        if self.names.len() >= self.limit {
+            if !self.capped {
+                warn!(
+                    "Tracking the first {} file names only, duplicate names may be missed",
+                    self.limit
+                );
+                self.capped = true;
+            }
+            return;
+        }
+
+        self.names.insert(name.to_string(), count);
+    }
+}
+
+/// Metric implementation.
+impl Metric for DuplicateNames {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        let key = super::get_key(object);
+        let name = key.rsplit('/').next().unwrap_or(key);
+
+        // folder markers have no name to compare
+        if !name.is_empty() {
+            self.add(name, 1);
+        }
+    }
+
+    /// Merges another `DuplicateNames` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<DuplicateNames>(other);
+
+        self.capped |= other.capped;
+
+        for (name, count) in other.names {
+            self.add(&name, count);
+        }
+    }
+
+    /// Retrieves all internal statistics under the `duplicate_names` header.
+    ///
+    /// Names are ranked by their number of occurrences, then by name.
+    fn sections(&self) -> Vec<Section> {
+        // find all names occurring more than once
+        let mut names = self
+            .names
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .collect::<Vec<_>>();
+
+        // order the names by occurrences, then name
+        names.sort_by(|(lname, lcnt), (rname, rcnt)| rcnt.cmp(lcnt).then(lname.cmp(rname)));
+
+        // next segment: duplicate_names
+        let mut section = Section::new("duplicate_names");
+        section.push("duplicate_names", names.len());
+
+        // add the most frequent names by rank
+        for (idx, (name, count)) in names.iter().take(LISTED).enumerate() {
+            let label = format!("duplicate_name_{}", idx + 1);
+            section.push(&format!("{}_name", label), name);
+            section.push(&format!("{}_occurrences", label), count);
+        }
+
+        // note when the counts are incomplete
+        if self.capped {
+            section.push("tracked_names_capped", self.limit);
+        }
+
+        vec![section]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DuplicateNames;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(DuplicateNames::new, &metrics::tests::objects());
+    }
+
+    #[test]
+    fn detecting_duplicate_names() {
+        let mut objects = metrics::tests::objects();
+        let mut names = DuplicateNames::with_limit(4);
+
+        objects[2].key = Some("data/one.txt".to_string());
+        objects[5].key = Some("data/nested/one.txt".to_string());
+        objects[3].key = Some("data/two.gz".to_string());
+
+        for object in &objects {
+            names.register(object);
+        }
+
+        let pairs = names.sections().remove(0).pairs;
+        let labels = pairs
+            .iter()
+            .map(|(label, val)| format!("{}={}", label, val))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            labels,
+            vec![
+                "duplicate_names=2",
+                "duplicate_name_1_name=one.txt",
+                "duplicate_name_1_occurrences=3",
+                "duplicate_name_2_name=two.gz",
+                "duplicate_name_2_occurrences=2",
+            ]
+        );
+
+        let mut capped = DuplicateNames::with_limit(1);
+
+        for object in &objects {
+            capped.register(object);
+        }
+
+        assert!(capped.capped);
+        assert_eq!(capped.names.len(), 1);
+        assert_eq!(capped.names["one.txt"], 3);
+    }
+}
@@ -0,0 +1,169 @@
+//! Duplicate file metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use super::{Metric, Section};
+use crate::report::util;
+
+/// Number of duplicate groups listed under the `duplicates` header.
+const LISTED: usize = 10;
+
+/// Container struct for duplicate file metrics tracked by S3.
+///
+/// Files are grouped by their ETag and size, tracking the number of copies
+/// and the first key (by name) of each group. Every file is held in memory
+/// until the walk completes, so this metric has to be opted into.
+pub struct Duplicates {
+    groups: HashMap<(String, u64), (u64, String)>,
+}
+
+/// Main implementation.
+impl Duplicates {
+    /// Constructs a new `Duplicates` struct.
+    pub(super) fn new() -> Duplicates {
+        Duplicates {
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Adds copies of a file to the group of its ETag and size.
+    fn add(&mut self, e_tag: String, size: u64, copies: u64, key: &str) {
+        let (count, example) = self
+            .groups
+            .entry((e_tag, size))
+            .or_insert_with(|| (0, key.to_string()));
+
+        *count += copies;
+
+        if key < example.as_str() {
+            *example = key.to_string();
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for Duplicates {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        if let Some(ref e_tag) = object.e_tag {
+            let e_tag = e_tag.trim_matches('"').to_string();
+            self.add(e_tag, super::get_size(object), 1, super::get_key(object));
+        }
+    }
+
+    /// Merges another `Duplicates` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<Duplicates>(other);
+
+        for ((e_tag, size), (count, example)) in other.groups {
+            self.add(e_tag, size, count, &example);
+        }
+    }
+
+    /// Retrieves all internal statistics under the `duplicates` header.
+    ///
+    /// Groups are ranked by the bytes wasted on extra copies, then by ETag.
+    fn sections(&self) -> Vec<Section> {
+        // find all groups with more than a single copy
+        let mut groups = self
+            .groups
+            .iter()
+            .filter(|(_, (count, _))| *count > 1)
+            .map(|((e_tag, size), (count, example))| (e_tag, *count, size * (count - 1), example))
+            .collect::<Vec<_>>();
+
+        // order the groups by wasted bytes, then ETag
+        groups.sort_by(|(le_tag, _, lwasted, _), (re_tag, _, rwasted, _)| {
+            rwasted.cmp(lwasted).then(le_tag.cmp(re_tag))
+        });
+
+        let copies: u64 = groups.iter().map(|(_, count, _, _)| count - 1).sum();
+        let wasted: u64 = groups.iter().map(|(_, _, wasted, _)| wasted).sum();
+
+        // next segment: duplicates
+        let mut duplicates = Section::new("duplicates");
+        duplicates.push("duplicate_groups", groups.len());
+        duplicates.push("duplicate_files", copies);
+        duplicates.push("wasted_size", util::convert_bytes(wasted));
+        duplicates.push("wasted_bytes", wasted);
+
+        // add the largest groups by rank
+        for (idx, (e_tag, count, wasted, example)) in groups.iter().take(LISTED).enumerate() {
+            let label = format!("duplicate_{}", idx + 1);
+            duplicates.push(&format!("{}_etag", label), e_tag);
+            duplicates.push(&format!("{}_copies", label), count);
+            duplicates.push(&format!("{}_wasted_bytes", label), wasted);
+            duplicates.push(&format!("{}_example_name", label), example);
+        }
+
+        // multipart ETags depend on the part sizes of the upload
+        if groups.iter().any(|(e_tag, _, _, _)| e_tag.contains('-')) {
+            duplicates.push(
+                "multipart_note",
+                "multipart ETags only match for files uploaded with the same part sizes",
+            );
+        }
+
+        vec![duplicates]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Duplicates;
+    use crate::report::metrics::{self, Metric};
+    use rusoto_s3::Object;
+
+    /// Creates a set of objects with duplicated ETags.
+    fn objects() -> Vec<Object> {
+        let mut objects = metrics::tests::objects();
+        let e_tags = ["\"a\"", "\"b\"", "\"a\"", "\"c-2\"", "\"c-2\"", "\"a\""];
+
+        for (object, e_tag) in objects.iter_mut().zip(&e_tags) {
+            object.e_tag = Some(e_tag.to_string());
+        }
+
+        objects
+    }
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(Duplicates::new, &objects());
+    }
+
+    #[test]
+    fn detecting_duplicates() {
+        let mut duplicates = Duplicates::new();
+
+        for object in &objects() {
+            duplicates.register(object);
+        }
+
+        let pairs = duplicates.sections().remove(0).pairs;
+        let find = |label: &str| {
+            pairs
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, val)| val.as_str())
+        };
+
+        assert_eq!(find("duplicate_groups"), Some("2"));
+        assert_eq!(find("duplicate_files"), Some("3"));
+        assert_eq!(find("wasted_bytes"), Some("70"));
+        assert_eq!(find("duplicate_1_etag"), Some("c-2"));
+        assert_eq!(find("duplicate_1_example_name"), Some("four"));
+        assert_eq!(find("duplicate_2_copies"), Some("3"));
+        assert_eq!(
+            find("duplicate_2_example_name"),
+            Some("data/nested/six.csv")
+        );
+        assert!(find("multipart_note").is_some());
+    }
+}
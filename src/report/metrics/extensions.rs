@@ -1,22 +1,40 @@
 //! Extension metrics tracking for S3 objects.
 use rusoto_s3::Object;
+use std::any::Any;
 use std::collections::HashMap;
 use std::path::Path;
 
-use super::Metric;
+use super::{Metric, Section};
 use crate::report::util;
 
+/// Synthetic extension of keys without an extension.
+const NONE: &str = "<none>";
+
+/// Number of extensions listed by size under the `extensions` header.
+const LISTED: usize = 5;
+
 /// Container struct for extension metrics tracked by S3.
+///
+/// Each extension tracks the number of files and the total bytes of all
+/// files with it, and files without an extension are tracked as `<none>`.
+/// Extensions are lowercased unless case sensitive, to merge `.JPG` and
+/// `.jpg` together.
 pub struct Extensions {
-    extensions: HashMap<String, u64>,
+    extensions: HashMap<String, (u64, u64)>,
+    all: bool,
+    case_sensitive: bool,
 }
 
 /// Main implementation.
 impl Extensions {
     /// Constructs a new `Extensions` struct.
-    pub(super) fn new() -> Extensions {
+    ///
+    /// If `all` is set, every extension is printed rather than only listed.
+    pub(super) fn new(all: bool, case_sensitive: bool) -> Extensions {
         Extensions {
             extensions: HashMap::new(),
+            all,
+            case_sensitive,
         }
     }
 }
@@ -25,30 +43,164 @@ impl Extensions {
 impl Metric for Extensions {
     /// Registers an S3 `Object` with this metric struct.
     fn register(&mut self, object: &Object) {
-        // grab the file extensions and increment
-        if let Some(ext) = Path::new(super::get_key(object)).extension() {
-            *self
-                .extensions
-                .entry(ext.to_string_lossy().into_owned())
-                .or_insert(0) += 1;
+        // grab the file extension, if there is one
+        let ext = match Path::new(super::get_key(object)).extension() {
+            Some(ext) if self.case_sensitive => ext.to_string_lossy().into_owned(),
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => NONE.to_string(),
+        };
+
+        // increment the counters of the extension
+        let (count, bytes) = self.extensions.entry(ext).or_insert((0, 0));
+
+        *count += 1;
+        *bytes += super::get_size(object);
+    }
+
+    /// Merges another `Extensions` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<Extensions>(other);
+
+        for (ext, (count, bytes)) in other.extensions {
+            let entry = self.extensions.entry(ext).or_insert((0, 0));
+
+            entry.0 += count;
+            entry.1 += bytes;
         }
     }
 
-    /// Prints out all internal statistics under the `extensions` header.
-    fn print(&self) {
+    /// Retrieves all internal statistics under the `extensions` header.
+    ///
+    /// The extensions with the most bytes are listed by rank, and the count
+    /// and bytes of every extension are also listed under `extension_table`,
+    /// from the most frequent to the least frequent (then by name).
+    fn sections(&self) -> Vec<Section> {
         // next segment: extensions
-        util::log_head("extensions");
-        util::log_pair("unique_extensions", self.extensions.len());
+        let mut extensions = Section::new("extensions");
+        let unique = self.extensions.keys().filter(|ext| *ext != NONE).count();
+        extensions.push("unique_extensions", unique);
+
+        // order the extensions by frequency, then name
+        let mut counts = self.extensions.iter().collect::<Vec<_>>();
+        counts.sort_by(|(lext, (lcnt, _)), (rext, (rcnt, _))| rcnt.cmp(lcnt).then(lext.cmp(rext)));
+
+        // order the extensions by bytes, then name
+        let mut sizes = counts.clone();
+        sizes.sort_by(|(lext, (_, lbytes)), (rext, (_, rbytes))| {
+            rbytes.cmp(lbytes).then(lext.cmp(rext))
+        });
 
-        // find the most frequent extension
-        let prevalent_extension = self
-            .extensions
+        // add a potential most frequent and largest
+        if let (Some((popular, _)), Some((largest, _))) = (counts.first(), sizes.first()) {
+            extensions.push("most_popular_extension", popular);
+            extensions.push("largest_extension", largest);
+        }
+
+        // add the count and size of the largest extensions by rank
+        for (idx, (ext, (count, bytes))) in sizes.iter().take(LISTED).enumerate() {
+            let label = format!("extension_{}", idx + 1);
+            extensions.push(&format!("{}_name", label), ext);
+            extensions.push(&format!("{}_files", label), count);
+            extensions.push(&format!("{}_size", label), util::convert_bytes(*bytes));
+            extensions.push(&format!("{}_bytes", label), bytes);
+        }
+
+        // list out the count and bytes of every extension
+        let mut extension_table = if self.all {
+            Section::new("extension_table")
+        } else {
+            Section::listing("extension_table")
+        };
+
+        for (ext, (count, bytes)) in counts {
+            extension_table.push(&format!("{}_files", ext), count);
+            extension_table.push(&format!("{}_bytes", ext), bytes);
+        }
+
+        vec![extensions, extension_table]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(
+            || Extensions::new(false, false),
+            &metrics::tests::objects(),
+        );
+    }
+
+    #[test]
+    fn listing_extension_table() {
+        let mut extensions = Extensions::new(false, false);
+
+        for object in &metrics::tests::objects() {
+            extensions.register(object);
+        }
+
+        let sections = extensions.sections();
+        let listed = sections
+            .iter()
+            .find(|section| section.listing)
+            .expect("extension table should be listed");
+
+        let table = listed
+            .pairs
             .iter()
-            .max_by(|(_, left), (_, right)| left.cmp(right));
+            .filter(|(label, _)| label.ends_with("_files"))
+            .map(|(label, val)| format!("{}={}", label, val))
+            .collect::<Vec<_>>();
 
-        // log out a potential most frequent
-        if let Some((ext, _)) = prevalent_extension {
-            util::log_pair("most_popular_extension", ext);
+        assert_eq!(
+            table,
+            vec!["gz_files=2", "txt_files=2", "<none>_files=1", "csv_files=1"]
+        );
+
+        let find = |label: &str| {
+            sections[0]
+                .pairs
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, val)| val.as_str())
+        };
+
+        assert_eq!(find("unique_extensions"), Some("3"));
+        assert_eq!(find("most_popular_extension"), Some("gz"));
+        assert_eq!(find("largest_extension"), Some("gz"));
+        assert_eq!(find("extension_1_bytes"), Some("80"));
+        assert_eq!(find("extension_2_name"), Some("<none>"));
+        assert_eq!(find("extension_2_bytes"), Some("50"));
+
+        assert!(Extensions::new(true, false)
+            .sections()
+            .iter()
+            .all(|section| !section.listing));
+    }
+
+    #[test]
+    fn merging_extension_cases() {
+        let mut objects = metrics::tests::objects();
+        objects[0].key = Some("logs/2019/ONE.TXT".to_string());
+
+        let mut insensitive = Extensions::new(false, false);
+        let mut sensitive = Extensions::new(false, true);
+
+        for object in &objects {
+            insensitive.register(object);
+            sensitive.register(object);
         }
+
+        assert_eq!(insensitive.extensions["txt"], (2, 20));
+        assert_eq!(sensitive.extensions["txt"], (1, 10));
+        assert_eq!(sensitive.extensions["TXT"], (1, 10));
     }
 }
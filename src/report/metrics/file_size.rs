@@ -1,27 +1,58 @@
 //! File size metrics tracking for S3 objects.
 use rusoto_s3::Object;
 
-use super::Metric;
+use std::any::Any;
+use std::collections::BinaryHeap;
+
+use super::{Metric, Section};
 use crate::report::bounded::{self, Bounded};
 use crate::report::util;
 
 /// Container struct for file size metrics tracked by S3.
+///
+/// The smallest non-empty files are kept in a max-heap bounded to the
+/// number of files listed, so the largest listed file can be evicted as
+/// soon as a smaller one is seen. Empty files are only counted, as they're
+/// usually folder markers and would otherwise fill the listing.
 pub struct FileSize {
     total_keys: u64,
     total_space: u64,
+    empty_files: u64,
     largest_file: Bounded<u64>,
     smallest_file: Bounded<u64>,
+    bottom_files: BinaryHeap<(u64, String)>,
+    bottom_limit: usize,
 }
 
 /// Main implementation.
 impl FileSize {
-    /// Constructs a new `FileSize` struct.
-    pub(super) fn new() -> FileSize {
+    /// Constructs a new `FileSize` struct, listing up to `bottom` files.
+    pub(super) fn new(bottom: usize) -> FileSize {
         FileSize {
             total_keys: 0,
             total_space: 0,
+            empty_files: 0,
             largest_file: Bounded::new(0),
             smallest_file: Bounded::new(0),
+            bottom_files: BinaryHeap::new(),
+            bottom_limit: bottom,
+        }
+    }
+
+    /// Offers a file to the listing, evicting the largest if over the limit.
+    fn offer(&mut self, key: &str, size: u64) {
+        // skip files which can't make the listing without allocating
+        if self.bottom_files.len() >= self.bottom_limit {
+            match self.bottom_files.peek() {
+                Some((max_size, max_key)) if (size, key) < (*max_size, &**max_key) => (),
+                _ => return,
+            }
+        }
+
+        self.bottom_files.push((size, key.to_string()));
+
+        if self.bottom_files.len() > self.bottom_limit {
+            self.bottom_files.pop();
         }
     }
 }
@@ -37,6 +68,13 @@ impl Metric for FileSize {
         self.total_keys += 1;
         self.total_space += size;
 
+        // count empty files, or offer them to the listing
+        if size == 0 {
+            self.empty_files += 1;
+        } else {
+            self.offer(super::get_key(object), size);
+        }
+
         // apply bounded updates
         bounded::apply(
             &mut self.smallest_file,
@@ -46,8 +84,28 @@ impl Metric for FileSize {
         );
     }
 
-    /// Prints out all internal statistics under the `file_size` header.
-    fn print(&self) {
+    /// Merges another `FileSize` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<FileSize>(other);
+
+        self.total_keys += other.total_keys;
+        self.total_space += other.total_space;
+        self.empty_files += other.empty_files;
+
+        for (size, key) in other.bottom_files {
+            self.offer(&key, size);
+        }
+
+        bounded::merge(
+            &mut self.smallest_file,
+            &mut self.largest_file,
+            other.smallest_file,
+            other.largest_file,
+        );
+    }
+
+    /// Retrieves all internal statistics under the `file_size` header.
+    fn sections(&self) -> Vec<Section> {
         // get average file size, protect against /0
         let average_file = match self.total_keys {
             0 => 0,
@@ -55,22 +113,86 @@ impl Metric for FileSize {
         };
 
         // next segment: file_size
-        util::log_head("file_size");
+        let mut file_size = Section::new("file_size");
+
+        // add the average size as both readable and bytes
+        file_size.push("average_file_size", util::convert_bytes(average_file));
+        file_size.push("average_file_bytes", average_file);
 
-        // log the average size as both readable and bytes
-        util::log_pair("average_file_size", util::convert_bytes(average_file));
-        util::log_pair("average_file_bytes", average_file);
+        // add the bounds of the largest file
+        file_size.push_bound("largest_file", &self.largest_file, |section, size| {
+            section.push("largest_file_size", util::convert_bytes(size));
+            section.push("largest_file_bytes", size);
+        });
 
-        // log out the bounds of the largest file
-        util::log_bound("largest_file", &self.largest_file, |size| {
-            util::log_pair("largest_file_size", util::convert_bytes(size));
-            util::log_pair("largest_file_bytes", size);
+        // add the bounds of the smallest file
+        file_size.push_bound("smallest_file", &self.smallest_file, |section, size| {
+            section.push("smallest_file_size", util::convert_bytes(size));
+            section.push("smallest_file_bytes", size);
         });
 
-        // log out the bounds of the smallest file
-        util::log_bound("smallest_file", &self.smallest_file, |size| {
-            util::log_pair("smallest_file_size", util::convert_bytes(size));
-            util::log_pair("smallest_file_bytes", size);
+        // add the count of empty files, which are never listed
+        file_size.push("empty_files", self.empty_files);
+
+        // order the smallest files from smallest to largest
+        let bottom_files = self.bottom_files.clone().into_sorted_vec();
+
+        // add the size and name of each of the smallest files by rank
+        for (idx, (size, key)) in bottom_files.iter().enumerate() {
+            let label = format!("bottom_file_{}", idx + 1);
+            file_size.push(&format!("{}_size", label), util::convert_bytes(*size));
+            file_size.push(&format!("{}_bytes", label), size);
+            file_size.push(&format!("{}_name", label), key);
+        }
+
+        vec![file_size]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileSize;
+    use crate::report::metrics::{self, Metric};
+    use rusoto_s3::Object;
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(|| FileSize::new(3), &metrics::tests::objects());
+    }
+
+    #[test]
+    fn listing_smallest_files() {
+        let mut objects = metrics::tests::objects();
+        let mut file_size = FileSize::new(2);
+
+        objects.push(Object {
+            key: Some("logs/2019/".to_string()),
+            size: Some(0),
+            last_modified: Some("2019-01-01T00:00:00.000Z".to_string()),
+            ..Object::default()
         });
+
+        for object in &objects {
+            file_size.register(object);
+        }
+
+        let pairs = &file_size.sections()[0].pairs;
+        let find = |label: &str| {
+            pairs
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, val)| val.as_str())
+        };
+
+        assert_eq!(find("empty_files"), Some("1"));
+        assert_eq!(find("bottom_file_1_name"), Some("data/nested/six.csv"));
+        assert_eq!(find("bottom_file_2_name"), Some("data/three.txt"));
+        assert_eq!(find("bottom_file_2_bytes"), Some("10"));
+        assert_eq!(find("bottom_file_3_name"), None);
     }
 }
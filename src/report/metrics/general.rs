@@ -1,11 +1,12 @@
 //! General metrics tracking for S3 objects.
 use rusoto_s3::Object;
 
+use std::any::Any;
 use std::collections::HashSet;
 use std::path::Path;
 use std::time::{Duration, SystemTime};
 
-use super::Metric;
+use super::{Metric, Section};
 use crate::report::util;
 
 /// Container struct for general metrics tracked by S3.
@@ -23,10 +24,7 @@ impl General {
     pub(super) fn new(prefix: &Option<String>) -> General {
         General {
             folder_set: HashSet::new(),
-            nest_count: prefix
-                .as_ref()
-                .map(|s| s.matches('/').count().saturating_sub(1))
-                .unwrap_or(0),
+            nest_count: super::get_nesting(prefix).unwrap_or(0),
             start_time: SystemTime::now(),
             total_keys: 0,
             total_size: 0,
@@ -41,15 +39,11 @@ impl Metric for General {
         // grab the key of the object
         let key = super::get_key(object);
 
-        // count the number of prefix nests
-        let nest_count = key
-            .match_indices('/')
-            .nth(self.nest_count)
-            .map(|(idx, _)| idx + 1)
-            .unwrap_or(0);
-
         // walk the ancestors, skipping the file name
-        for dir in Path::new(&key[nest_count..]).ancestors().skip(1) {
+        for dir in Path::new(super::get_relative(key, self.nest_count))
+            .ancestors()
+            .skip(1)
+        {
             // convert to a string
             let path = dir.to_string_lossy();
 
@@ -67,8 +61,18 @@ impl Metric for General {
         self.total_size += super::get_size(object);
     }
 
-    /// Prints out all internal statistics under the `general` header.
-    fn print(&self) {
+    /// Merges another `General` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<General>(other);
+
+        self.folder_set.extend(other.folder_set);
+        self.start_time = self.start_time.min(other.start_time);
+        self.total_keys += other.total_keys;
+        self.total_size += other.total_size;
+    }
+
+    /// Retrieves all internal statistics under the `general` header.
+    fn sections(&self) -> Vec<Section> {
         // task done, so check execution time
         let task_duration = Duration::from_secs(
             SystemTime::now()
@@ -78,12 +82,30 @@ impl Metric for General {
         );
 
         // initial header!
-        println!("[general]");
+        let mut general = Section::new("general");
+
+        // add the total time, total space, and total file count
+        general.push("total_time", humantime::format_duration(task_duration));
+        general.push("total_files", self.total_keys);
+        general.push("total_folders", self.folder_set.len());
+        general.push("total_storage", util::convert_bytes(self.total_size));
+
+        vec![general]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::General;
+    use crate::report::metrics;
 
-        // log out the total time, total space, and total file count
-        util::log_pair("total_time", humantime::format_duration(task_duration));
-        util::log_pair("total_files", self.total_keys);
-        util::log_pair("total_folders", self.folder_set.len());
-        util::log_pair("total_storage", util::convert_bytes(self.total_size));
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(|| General::new(&None), &metrics::tests::objects());
     }
 }
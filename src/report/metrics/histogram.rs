@@ -0,0 +1,181 @@
+//! File size distribution metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+
+use super::{Metric, Section};
+use crate::cli;
+
+/// Default upper bounds of each size bin, as accepted by `--size-buckets`.
+pub const DEFAULT_BOUNDS: &str = "1KB,1MB,16MB,128MB,1GB,5GB";
+
+/// Container struct for file size distribution metrics tracked by S3.
+///
+/// Files are counted into a bin for empty files, a bin below each bound,
+/// and a final bin for anything at least as large as the last bound.
+pub struct Histogram {
+    bounds: Vec<(String, u64)>,
+    files: Vec<u64>,
+    bytes: Vec<u64>,
+}
+
+/// Main implementation.
+impl Histogram {
+    /// Constructs a new `Histogram` struct using a set of bin bounds.
+    pub(super) fn new(bounds: Vec<(String, u64)>) -> Histogram {
+        let bins = bounds.len() + 2;
+        Histogram {
+            bounds,
+            files: vec![0; bins],
+            bytes: vec![0; bins],
+        }
+    }
+
+    /// Retrieves the label of the bin at a given index.
+    fn label(&self, idx: usize) -> String {
+        match idx {
+            0 => "empty".to_string(),
+            idx if idx > self.bounds.len() => format!("at_least_{}", self.bounds[idx - 2].0),
+            idx => format!("under_{}", self.bounds[idx - 1].0),
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for Histogram {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        let size = super::get_size(object);
+
+        // find the first bin the size fits under
+        let idx = if size == 0 {
+            0
+        } else {
+            1 + self
+                .bounds
+                .iter()
+                .position(|(_, bound)| size < *bound)
+                .unwrap_or(self.bounds.len())
+        };
+
+        self.files[idx] += 1;
+        self.bytes[idx] += size;
+    }
+
+    /// Merges another `Histogram` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<Histogram>(other);
+
+        for (idx, (files, bytes)) in other.files.into_iter().zip(other.bytes).enumerate() {
+            self.files[idx] += files;
+            self.bytes[idx] += bytes;
+        }
+    }
+
+    /// Retrieves all internal statistics under the `size_histogram` header.
+    ///
+    /// Percentages are of the total number of files, to two decimal places.
+    fn sections(&self) -> Vec<Section> {
+        let total: u64 = self.files.iter().sum();
+
+        // next segment: size_histogram
+        let mut histogram = Section::new("size_histogram");
+
+        // add the count, bytes and share of each bin
+        for (idx, (files, bytes)) in self.files.iter().zip(&self.bytes).enumerate() {
+            let label = self.label(idx);
+            let percent = match total {
+                0 => 0.0,
+                v => *files as f64 * 100.0 / v as f64,
+            };
+
+            histogram.push(&format!("{}_files", label), files);
+            histogram.push(&format!("{}_bytes", label), bytes);
+            histogram.push(&format!("{}_percent", label), format!("{:.2}", percent));
+        }
+
+        vec![histogram]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Parses a comma separated list of bin bounds, in ascending order.
+///
+/// Each bound is labelled as it was written, so `1KB` becomes `under_1KB`.
+pub fn parse_bounds(value: &str) -> Result<Vec<(String, u64)>, String> {
+    let mut bounds: Vec<(String, u64)> = Vec::new();
+
+    for bound in value.split(',').map(str::trim) {
+        let size = cli::parse_size(bound)
+            .filter(|size| *size > 0)
+            .ok_or_else(|| format!("Invalid size bucket: {}", bound))?;
+
+        if let Some((previous, _)) = bounds.last().filter(|(_, last)| *last >= size) {
+            return Err(format!(
+                "Size buckets must be ascending, but {} follows {}",
+                bound, previous
+            ));
+        }
+
+        bounds.push((bound.to_string(), size));
+    }
+
+    Ok(bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bounds, Histogram, DEFAULT_BOUNDS};
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(
+            || Histogram::new(parse_bounds("20B,40B").unwrap()),
+            &metrics::tests::objects(),
+        );
+    }
+
+    #[test]
+    fn bucketing_file_sizes() {
+        let mut histogram = Histogram::new(parse_bounds("20B,40B").unwrap());
+
+        for object in &metrics::tests::objects() {
+            histogram.register(object);
+        }
+
+        assert_eq!(histogram.files, vec![0, 3, 1, 2]);
+        assert_eq!(histogram.bytes, vec![0, 30, 30, 100]);
+
+        let pairs = &histogram.sections()[0].pairs;
+
+        assert_eq!(pairs[0], ("empty_files".to_string(), "0".to_string()));
+        assert_eq!(
+            pairs[5],
+            ("under_20B_percent".to_string(), "50.00".to_string())
+        );
+        assert_eq!(
+            pairs[9],
+            ("at_least_40B_files".to_string(), "2".to_string())
+        );
+    }
+
+    #[test]
+    fn parsing_size_bounds() {
+        assert_eq!(parse_bounds(DEFAULT_BOUNDS).unwrap().len(), 6);
+        assert_eq!(
+            parse_bounds("1KiB, 1MB").unwrap(),
+            vec![("1KiB".to_string(), 1024), ("1MB".to_string(), 1_000_000)]
+        );
+
+        assert!(parse_bounds("").is_err());
+        assert!(parse_bounds("0B").is_err());
+        assert!(parse_bounds("1MB,1KB").is_err());
+        assert!(parse_bounds("1MB,1MB").is_err());
+        assert!(parse_bounds("1MB,lots").is_err());
+    }
+}
@@ -1,15 +1,35 @@
 //! Parent metric module exposing traits around metrics gathering.
+use clap::ArgMatches;
 use rusoto_s3::Object;
 
+use std::any::Any;
+use std::fmt::Display;
+
+pub mod age_histogram;
+pub mod duplicate_names;
+pub mod duplicates;
 pub mod extensions;
 pub mod file_size;
 pub mod general;
+pub mod histogram;
 pub mod modification;
+pub mod prefixes;
+pub mod storage_class;
+pub mod top_files;
 
+use self::age_histogram::AgeHistogram;
+use self::duplicate_names::DuplicateNames;
+use self::duplicates::Duplicates;
 use self::extensions::Extensions;
 use self::file_size::FileSize;
 use self::general::General;
+use self::histogram::Histogram;
 use self::modification::Modification;
+use self::prefixes::Prefixes;
+use self::storage_class::StorageClass;
+use self::top_files::TopFiles;
+use crate::report::bounded::Bounded;
+use crate::types::UtilResult;
 
 /// Metric trait to represent a metric tracker for S3.
 ///
@@ -20,18 +40,188 @@ pub trait Metric {
     /// Registers an S3 object for statistics.
     fn register(&mut self, object: &Object);
 
-    /// Prints the internal statistics.
-    fn print(&self);
+    /// Merges the statistics of another metric into this metric.
+    ///
+    /// The provided metric must be of the same concrete type as this one;
+    /// this allows metrics gathered independently to be combined.
+    fn merge(&mut self, other: Box<dyn Metric>);
+
+    /// Retrieves the internal statistics as named sections of values.
+    fn sections(&self) -> Vec<Section>;
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+/// A named section of statistics, as label/value pairs.
+///
+/// Sections are formatted by the report itself, so the same statistics can
+/// be printed as text or as rows of data.
+pub struct Section {
+    pub name: &'static str,
+    pub pairs: Vec<(String, String)>,
+    pub listing: bool,
+}
+
+/// Main implementation.
+impl Section {
+    /// Constructs a new `Section`, printed in every format.
+    pub fn new(name: &'static str) -> Section {
+        Section {
+            name,
+            pairs: Vec::new(),
+            listing: false,
+        }
+    }
+
+    /// Constructs a new `Section` of listed entries.
+    ///
+    /// Listings can grow with the number of objects, so they're only
+    /// printed in formats intended for further analysis.
+    pub fn listing(name: &'static str) -> Section {
+        Section {
+            listing: true,
+            ..Section::new(name)
+        }
+    }
+
+    /// Adds a label/value pair to this section.
+    pub fn push<T>(&mut self, label: &str, val: T)
+    where
+        T: Display,
+    {
+        self.pairs.push((label.to_string(), val.to_string()));
+    }
+
+    /// Adds a bounded value to this section, conditionally based on content.
+    pub fn push_bound<L, T>(&mut self, label: &str, bounded: &Bounded<T>, pusher: L)
+    where
+        L: FnOnce(&mut Section, T),
+        T: Clone,
+    {
+        let key = match bounded.key() {
+            Some(key) => key.clone(),
+            None => return,
+        };
+
+        pusher(self, bounded.value().clone());
+        self.push(&format!("{}_name", label), key);
+
+        if bounded.count() > 1 {
+            self.push(&format!("{}_others", label), bounded.count());
+        }
+    }
+}
+
+/// Options controlling the metrics gathered by a chain.
+pub struct Options {
+    pub top: usize,
+    pub bottom: usize,
+    pub bounds: Vec<(String, u64)>,
+    pub granularity: String,
+    pub prefix_top: Option<usize>,
+    pub extensions_all: bool,
+    pub extensions_case_sensitive: bool,
+    pub duplicates: bool,
+    pub duplicate_names: bool,
+}
+
+/// Main implementation.
+impl Options {
+    /// Constructs a new `Options` from the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> UtilResult<Options> {
+        let number = |name: &str| {
+            args.value_of(name)
+                .map(|value: &str| value.parse().unwrap())
+        };
+        let bounds = args
+            .value_of("size_buckets")
+            .unwrap_or(histogram::DEFAULT_BOUNDS);
+
+        Ok(Options {
+            top: number("top").unwrap_or(0),
+            bottom: number("bottom").unwrap_or(0),
+            bounds: histogram::parse_bounds(bounds)?,
+            granularity: args.value_of("granularity").unwrap_or("month").to_string(),
+            prefix_top: number("prefix_top"),
+            extensions_all: args.is_present("extensions_all"),
+            extensions_case_sensitive: args.is_present("case_sensitive_extensions"),
+            duplicates: args.is_present("duplicates"),
+            duplicate_names: args.is_present("duplicate_names"),
+        })
+    }
 }
 
 /// Returns a chain of `Metric` objects in deterministic order.
-pub fn chain(prefix: &Option<String>) -> Vec<Box<dyn Metric>> {
-    vec![
+///
+/// The largest and smallest files are only listed if a non-zero number
+/// is requested, the largest prefixes are only capped if requested, and
+/// duplicates (of content or names) are only detected if requested, as
+/// they use more memory.
+pub fn chain(prefix: &Option<String>, options: &Options) -> Vec<Box<dyn Metric>> {
+    let mut chain: Vec<Box<dyn Metric>> = vec![
         Box::new(General::new(prefix)),
-        Box::new(FileSize::new()),
-        Box::new(Extensions::new()),
+        Box::new(Prefixes::new(prefix, options.prefix_top)),
+        Box::new(FileSize::new(options.bottom)),
+        Box::new(Histogram::new(options.bounds.clone())),
+        Box::new(StorageClass::new()),
+        Box::new(Extensions::new(
+            options.extensions_all,
+            options.extensions_case_sensitive,
+        )),
         Box::new(Modification::new()),
-    ]
+        Box::new(AgeHistogram::new(&options.granularity)),
+    ];
+
+    if options.top > 0 {
+        chain.push(Box::new(TopFiles::new(options.top)));
+    }
+
+    if options.duplicates {
+        chain.push(Box::new(Duplicates::new()));
+    }
+
+    if options.duplicate_names {
+        chain.push(Box::new(DuplicateNames::new()));
+    }
+
+    chain
+}
+
+/// Merges a chain of `Metric` objects into another chain of the same shape.
+pub fn merge(chain: &mut [Box<dyn Metric>], other: Vec<Box<dyn Metric>>) {
+    for (metric, other) in chain.iter_mut().zip(other) {
+        metric.merge(other);
+    }
+}
+
+/// Downcasts a boxed `Metric` into a concrete metric type.
+pub fn downcast<M: Metric + 'static>(metric: Box<dyn Metric>) -> M {
+    *metric
+        .into_any()
+        .downcast::<M>()
+        .expect("metrics should only merge with the same type")
+}
+
+/// Retrieves the nesting of a prefix, if any, for use with `get_relative`.
+pub fn get_nesting(prefix: &Option<String>) -> Option<usize> {
+    prefix
+        .as_ref()
+        .map(|s| s.matches('/').count().saturating_sub(1))
+}
+
+/// Retrieves a key relative to the nesting of a prefix.
+///
+/// Every segment of the key up to (and including) the segment at the index
+/// of the nesting is skipped, so keys under `logs/` are relative to `logs/`.
+pub fn get_relative(key: &str, nesting: usize) -> &str {
+    let offset = key
+        .match_indices('/')
+        .nth(nesting)
+        .map(|(idx, _)| idx + 1)
+        .unwrap_or(0);
+
+    &key[offset..]
 }
 
 /// Retrieves the key of an `Object` as a `&String`.
@@ -53,3 +243,71 @@ pub fn get_size(object: &Object) -> u64 {
 fn unwrap_opt<'a, V>(opt: &'a Option<V>, expect: &str) -> &'a V {
     opt.as_ref().expect(expect)
 }
+
+#[cfg(test)]
+pub mod tests {
+    use rusoto_s3::Object;
+
+    use super::{Metric, Section};
+
+    /// Asserts that merging a metric gathered in halves matches gathering all.
+    ///
+    /// Metrics are compared by their sections, so anything reported by the
+    /// metric has to survive being merged.
+    pub fn assert_merge_consistent<M, F>(create: F, objects: &[Object])
+    where
+        M: Metric + 'static,
+        F: Fn() -> M,
+    {
+        let (head, tail) = objects.split_at(objects.len() / 2);
+
+        let mut single = create();
+        let mut merged = create();
+        let mut other = create();
+
+        for object in objects {
+            single.register(object);
+        }
+
+        for object in head {
+            merged.register(object);
+        }
+
+        for object in tail {
+            other.register(object);
+        }
+
+        merged.merge(Box::new(other));
+
+        let flatten = |sections: Vec<Section>| {
+            sections
+                .into_iter()
+                .map(|section| (section.name, section.pairs, section.listing))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(flatten(merged.sections()), flatten(single.sections()));
+    }
+
+    /// Creates a set of objects to exercise metrics with.
+    pub fn objects() -> Vec<Object> {
+        let objects = [
+            ("logs/2019/one.txt", 10, "2019-01-01T00:00:00.000Z"),
+            ("logs/two.gz", 30, "2018-06-01T00:00:00.000Z"),
+            ("data/three.txt", 10, "2020-03-01T00:00:00.000Z"),
+            ("four", 50, "2018-06-01T00:00:00.000Z"),
+            ("logs/2019/five.gz", 50, "2020-03-01T00:00:00.000Z"),
+            ("data/nested/six.csv", 10, "2019-07-01T00:00:00.000Z"),
+        ];
+
+        objects
+            .iter()
+            .map(|(key, size, modified)| Object {
+                key: Some(key.to_string()),
+                size: Some(*size),
+                last_modified: Some(modified.to_string()),
+                ..Object::default()
+            })
+            .collect()
+    }
+}
@@ -1,9 +1,10 @@
 //! Modification metrics tracking for S3 objects.
 use rusoto_s3::Object;
 
-use super::Metric;
+use std::any::Any;
+
+use super::{Metric, Section};
 use crate::report::bounded::{self, Bounded};
-use crate::report::util;
 
 /// Container struct for modificaton metrics tracked by S3.
 pub struct Modification {
@@ -34,19 +35,49 @@ impl Metric for Modification {
         );
     }
 
-    /// Prints out all internal statistics under the `modification` header.
-    fn print(&self) {
+    /// Merges another `Modification` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<Modification>(other);
+
+        bounded::merge(
+            &mut self.earliest_file,
+            &mut self.latest_file,
+            other.earliest_file,
+            other.latest_file,
+        );
+    }
+
+    /// Retrieves all internal statistics under the `modification` header.
+    fn sections(&self) -> Vec<Section> {
         // next segment: modification
-        util::log_head("modification");
+        let mut modification = Section::new("modification");
 
-        // log out the bounds of the earliest file
-        util::log_bound("earliest_file", &self.earliest_file, |date| {
-            util::log_pair("earliest_file_date", date);
+        // add the bounds of the earliest file
+        modification.push_bound("earliest_file", &self.earliest_file, |section, date| {
+            section.push("earliest_file_date", date);
         });
 
-        // log out the bounds of the latest file
-        util::log_bound("latest_file", &self.latest_file, |date| {
-            util::log_pair("latest_file_date", date);
+        // add the bounds of the latest file
+        modification.push_bound("latest_file", &self.latest_file, |section, date| {
+            section.push("latest_file_date", date);
         });
+
+        vec![modification]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Modification;
+    use crate::report::metrics;
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(Modification::new, &metrics::tests::objects());
     }
 }
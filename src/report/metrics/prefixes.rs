@@ -0,0 +1,166 @@
+//! Prefix metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use super::{Metric, Section};
+use crate::report::util;
+
+/// Synthetic prefix of keys without any further nesting.
+const ROOT: &str = "<root>";
+
+/// Container struct for prefix metrics tracked by S3.
+///
+/// Files are tallied against the first level prefix beneath any prefix
+/// being reported on, so `logs/2019/one.txt` is counted under `logs`.
+pub struct Prefixes {
+    nesting: Option<usize>,
+    prefixes: HashMap<String, (u64, u64)>,
+    limit: Option<usize>,
+}
+
+/// Main implementation.
+impl Prefixes {
+    /// Constructs a new `Prefixes` struct, listing up to `limit` prefixes.
+    pub(super) fn new(prefix: &Option<String>, limit: Option<usize>) -> Prefixes {
+        Prefixes {
+            nesting: super::get_nesting(prefix),
+            prefixes: HashMap::new(),
+            limit,
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for Prefixes {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        // keys are already relative without a prefix
+        let key = super::get_key(object);
+        let relative = match self.nesting {
+            Some(nesting) => super::get_relative(key, nesting),
+            None => key,
+        };
+
+        // take the first segment, unless it's the file name
+        let prefix = match relative.find('/') {
+            Some(idx) => &relative[..idx],
+            None => ROOT,
+        };
+
+        // increment the counters of the prefix
+        let (files, bytes) = self.prefixes.entry(prefix.to_string()).or_insert((0, 0));
+
+        *files += 1;
+        *bytes += super::get_size(object);
+    }
+
+    /// Merges another `Prefixes` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<Prefixes>(other);
+
+        for (prefix, (files, bytes)) in other.prefixes {
+            let entry = self.prefixes.entry(prefix).or_insert((0, 0));
+
+            entry.0 += files;
+            entry.1 += bytes;
+        }
+    }
+
+    /// Retrieves all internal statistics under the `prefixes` header.
+    ///
+    /// Prefixes are ranked by the bytes stored beneath them, then by name,
+    /// and any beyond the limit are only counted.
+    fn sections(&self) -> Vec<Section> {
+        // order the prefixes by bytes, then name
+        let mut prefixes = self.prefixes.iter().collect::<Vec<_>>();
+        prefixes.sort_by(|(lprefix, (_, lbytes)), (rprefix, (_, rbytes))| {
+            rbytes.cmp(lbytes).then(lprefix.cmp(rprefix))
+        });
+
+        // next segment: prefixes
+        let mut section = Section::new("prefixes");
+        section.push("unique_prefixes", prefixes.len());
+
+        // cap the prefixes to any limit
+        let limit = self.limit.unwrap_or(prefixes.len()).min(prefixes.len());
+
+        // add the name, count and size of each prefix by rank
+        for (idx, (prefix, (files, bytes))) in prefixes.drain(..limit).enumerate() {
+            let label = format!("prefix_{}", idx + 1);
+            section.push(&format!("{}_name", label), prefix);
+            section.push(&format!("{}_files", label), files);
+            section.push(&format!("{}_size", label), util::convert_bytes(*bytes));
+            section.push(&format!("{}_bytes", label), bytes);
+        }
+
+        // note any prefixes beyond the limit
+        if !prefixes.is_empty() {
+            section.push("other_prefixes", prefixes.len());
+        }
+
+        vec![section]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Prefixes;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(
+            || Prefixes::new(&None, None),
+            &metrics::tests::objects(),
+        );
+    }
+
+    #[test]
+    fn ranking_prefixes() {
+        let mut prefixes = Prefixes::new(&None, Some(2));
+
+        for object in &metrics::tests::objects() {
+            prefixes.register(object);
+        }
+
+        let pairs = prefixes.sections().remove(0).pairs;
+        let labels = pairs
+            .iter()
+            .filter(|(label, _)| !label.ends_with("_size"))
+            .map(|(label, val)| format!("{}={}", label, val))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            labels,
+            vec![
+                "unique_prefixes=3",
+                "prefix_1_name=logs",
+                "prefix_1_files=3",
+                "prefix_1_bytes=90",
+                "prefix_2_name=<root>",
+                "prefix_2_files=1",
+                "prefix_2_bytes=50",
+                "other_prefixes=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn nesting_beneath_prefixes() {
+        let mut prefixes = Prefixes::new(&Some("logs/".to_string()), None);
+
+        for object in &metrics::tests::objects()[..2] {
+            prefixes.register(object);
+        }
+
+        assert_eq!(prefixes.prefixes["2019"], (1, 10));
+        assert_eq!(prefixes.prefixes["<root>"], (1, 30));
+    }
+}
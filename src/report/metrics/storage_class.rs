@@ -0,0 +1,136 @@
+//! Storage class metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use super::{Metric, Section};
+
+/// Storage class of objects listed without one, as the S3 default.
+const DEFAULT_CLASS: &str = "STANDARD";
+
+/// Container struct for storage class metrics tracked by S3.
+pub struct StorageClass {
+    classes: HashMap<String, (u64, u64)>,
+    implied: u64,
+}
+
+/// Main implementation.
+impl StorageClass {
+    /// Constructs a new `StorageClass` struct.
+    pub(super) fn new() -> StorageClass {
+        StorageClass {
+            classes: HashMap::new(),
+            implied: 0,
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for StorageClass {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        // objects without a class are stored as the default
+        let class = match object.storage_class {
+            Some(ref class) => class.as_str(),
+            None => {
+                self.implied += 1;
+                DEFAULT_CLASS
+            }
+        };
+
+        // increment the counters of the class
+        let (files, bytes) = self.classes.entry(class.to_string()).or_insert((0, 0));
+
+        *files += 1;
+        *bytes += super::get_size(object);
+    }
+
+    /// Merges another `StorageClass` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<StorageClass>(other);
+
+        for (class, (files, bytes)) in other.classes {
+            let entry = self.classes.entry(class).or_insert((0, 0));
+
+            entry.0 += files;
+            entry.1 += bytes;
+        }
+
+        self.implied += other.implied;
+    }
+
+    /// Retrieves all internal statistics under the `storage_class` header.
+    ///
+    /// Classes are ordered by the bytes stored within them, then by name.
+    fn sections(&self) -> Vec<Section> {
+        // order the classes by bytes, then name
+        let mut classes = self.classes.iter().collect::<Vec<_>>();
+        classes.sort_by(|(lclass, (_, lbytes)), (rclass, (_, rbytes))| {
+            rbytes.cmp(lbytes).then(lclass.cmp(rclass))
+        });
+
+        // next segment: storage_class
+        let mut storage_class = Section::new("storage_class");
+
+        // add the count and bytes of each class
+        for (class, (files, bytes)) in classes {
+            storage_class.push(&format!("{}_files", class), files);
+            storage_class.push(&format!("{}_bytes", class), bytes);
+        }
+
+        // note any files which were assumed to be the default
+        if self.implied > 0 {
+            storage_class.push("implied_standard_files", self.implied);
+        }
+
+        vec![storage_class]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageClass;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(StorageClass::new, &metrics::tests::objects());
+    }
+
+    #[test]
+    fn tallying_storage_classes() {
+        let mut objects = metrics::tests::objects();
+        let mut storage_class = StorageClass::new();
+
+        objects[0].storage_class = Some("STANDARD".to_string());
+        objects[1].storage_class = Some("GLACIER".to_string());
+        objects[3].storage_class = Some("GLACIER".to_string());
+
+        for object in &objects {
+            storage_class.register(object);
+        }
+
+        let pairs = storage_class.sections().remove(0).pairs;
+        let labels = pairs
+            .iter()
+            .map(|(label, val)| format!("{}={}", label, val))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            labels,
+            vec![
+                "GLACIER_files=2",
+                "GLACIER_bytes=80",
+                "STANDARD_files=4",
+                "STANDARD_bytes=80",
+                "implied_standard_files=3",
+            ]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Largest file metrics tracking for S3 objects.
+use rusoto_s3::Object;
+
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::{Metric, Section};
+use crate::report::util;
+
+/// Container struct for the largest files tracked by S3.
+///
+/// Files are kept in a min-heap bounded to the number of files listed, so
+/// the smallest listed file can be evicted as soon as a larger one is seen.
+pub struct TopFiles {
+    files: BinaryHeap<Reverse<(u64, String)>>,
+    limit: usize,
+}
+
+/// Main implementation.
+impl TopFiles {
+    /// Constructs a new `TopFiles` struct, listing up to `limit` files.
+    pub(super) fn new(limit: usize) -> TopFiles {
+        TopFiles {
+            files: BinaryHeap::new(),
+            limit,
+        }
+    }
+
+    /// Offers a file to the listing, evicting the smallest if over the limit.
+    fn offer(&mut self, key: &str, size: u64) {
+        // skip files which can't make the listing without allocating
+        if self.files.len() >= self.limit {
+            match self.files.peek() {
+                Some(Reverse((min_size, min_key))) if (size, key) > (*min_size, &**min_key) => (),
+                _ => return,
+            }
+        }
+
+        self.files.push(Reverse((size, key.to_string())));
+
+        if self.files.len() > self.limit {
+            self.files.pop();
+        }
+    }
+}
+
+/// Metric implementation.
+impl Metric for TopFiles {
+    /// Registers an S3 `Object` with this metric struct.
+    fn register(&mut self, object: &Object) {
+        self.offer(super::get_key(object), super::get_size(object));
+    }
+
+    /// Merges another `TopFiles` metric struct into this one.
+    fn merge(&mut self, other: Box<dyn Metric>) {
+        let other = super::downcast::<TopFiles>(other);
+
+        for Reverse((size, key)) in other.files {
+            self.offer(&key, size);
+        }
+    }
+
+    /// Retrieves all internal statistics under the `top_files` header.
+    ///
+    /// Files are ranked from the largest, with ties ordered by key.
+    fn sections(&self) -> Vec<Section> {
+        // order the files from largest to smallest
+        let mut files = self
+            .files
+            .iter()
+            .map(|Reverse(file)| file)
+            .collect::<Vec<_>>();
+        files.sort_by(|(lsize, lkey), (rsize, rkey)| rsize.cmp(lsize).then(lkey.cmp(rkey)));
+
+        // next segment: top_files
+        let mut top_files = Section::new("top_files");
+
+        // add the size and name of each file by rank
+        for (idx, (size, key)) in files.into_iter().enumerate() {
+            let label = format!("top_file_{}", idx + 1);
+            top_files.push(&format!("{}_size", label), util::convert_bytes(*size));
+            top_files.push(&format!("{}_bytes", label), size);
+            top_files.push(&format!("{}_name", label), key);
+        }
+
+        vec![top_files]
+    }
+
+    /// Converts this metric into an `Any`, to enable downcasting.
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopFiles;
+    use crate::report::metrics::{self, Metric};
+
+    #[test]
+    fn merging_matches_single_registration() {
+        metrics::tests::assert_merge_consistent(|| TopFiles::new(3), &metrics::tests::objects());
+    }
+
+    #[test]
+    fn listing_largest_files() {
+        let mut top_files = TopFiles::new(3);
+
+        for object in &metrics::tests::objects() {
+            top_files.register(object);
+        }
+
+        assert_eq!(top_files.files.len(), 3);
+
+        let pairs = &top_files.sections()[0].pairs;
+        let names = pairs
+            .iter()
+            .filter(|(label, _)| label.ends_with("_name"))
+            .map(|(_, val)| val.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["four", "logs/2019/five.gz", "logs/two.gz"]);
+        assert_eq!(pairs[1], ("top_file_1_bytes".to_string(), "50".to_string()));
+    }
+}
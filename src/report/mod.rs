@@ -2,15 +2,28 @@
 //!
 //! This utility can be used to generate a report about the provided
 //! S3 bucket, including things like file sizes, modification dates, etc.
-use clap::{App, ArgMatches, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use rusoto_s3::*;
 
+use serde_json::json;
+
+use std::io::{self, ErrorKind, Write};
+
 use crate::cli;
+use crate::client;
+use crate::cost::{self, Pricing};
+use crate::filter::{self, Filter};
 use crate::types::UtilResult;
 use crate::walker::ObjectWalker;
 
+use self::metrics::{histogram, Metric, Options, Section};
+use self::output::Output;
+use self::progress::Progress;
+
 pub mod bounded;
 pub mod metrics;
+pub mod output;
+pub mod progress;
 pub mod util;
 
 /// Generates an appropriate `SubCommand` for this module.
@@ -18,30 +31,356 @@ pub fn cmd<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("report")
         .about("Gather metadata about your S3 buckets")
         .args(&cli::global_args())
+        .args(&cli::filter_args())
+        .args(&[
+            Arg::with_name("approx_total")
+                .help("An approximate object count, used to estimate progress")
+                .long("approx-total")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("bottom")
+                .help("List the smallest N non-empty files, alongside their sizes")
+                .long("bottom")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("case_sensitive_extensions")
+                .help("Treat extensions differing only in case as different")
+                .long("case-sensitive-extensions"),
+            Arg::with_name("cloudwatch_estimate")
+                .help("Estimate progress using CloudWatch bucket metrics")
+                .long("cloudwatch-estimate")
+                .conflicts_with("approx_total"),
+            Arg::with_name("duplicate_names")
+                .help("Detect file names repeated beneath multiple prefixes")
+                .long("duplicate-names"),
+            Arg::with_name("duplicates")
+                .help("Detect duplicate files by ETag, using more memory")
+                .long("duplicates"),
+            Arg::with_name("extensions_all")
+                .help("List every extension, alongside its count and bytes")
+                .long("extensions-all"),
+            Arg::with_name("format")
+                .help("Print metrics as text (the default) or as CSV rows")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "csv"])
+                .conflicts_with_all(&["raw", "no_metrics"]),
+            Arg::with_name("granularity")
+                .help("Period of the modification histogram bins (defaults to month)")
+                .long("granularity")
+                .takes_value(true)
+                .possible_values(&["year", "month", "day"]),
+            Arg::with_name("no_metrics")
+                .help("Skip gathering and printing metrics")
+                .long("no-metrics"),
+            Arg::with_name("output")
+                .help("Write the report to a file, logging to stderr")
+                .long("output")
+                .takes_value(true)
+                .conflicts_with("no_metrics"),
+            Arg::with_name("prefix_top")
+                .help("Only list the largest N prefixes")
+                .long("prefix-top")
+                .takes_value(true)
+                .validator(cli::validate_number),
+            Arg::with_name("raw")
+                .help("Stream each object as a JSON line while walking")
+                .long("raw"),
+            Arg::with_name("size_buckets")
+                .help("Upper bounds of the file size histogram bins, comma separated")
+                .long("size-buckets")
+                .takes_value(true)
+                .validator(|value| histogram::parse_bounds(&value).map(|_| ())),
+            Arg::with_name("top")
+                .help("List the largest N files, alongside their sizes")
+                .long("top")
+                .takes_value(true)
+                .validator(cli::validate_number),
+        ])
 }
 
 /// Executes this subcommand and returns a `UtilResult` to indicate success.
+///
+/// Bucket patterns will generate a report section per bucket, followed by
+/// an aggregate of all buckets. Buckets which can't be walked are skipped.
 pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
-    // parse all global arguments
-    let (bucket, prefix) = cli::get_bucket_pair(args);
+    // expand any bucket patterns
+    let buckets = cli::expand_buckets(&s3, args, false).await?;
+    let (pattern, _) = cli::get_bucket_pair(args);
+    let csv = args.value_of("format") == Some("csv");
+
+    // open the destination before walking anything; raw records own stdout,
+    // so a report without a file is written to stderr alongside the logs
+    let mut out = match args.value_of("output") {
+        None if args.is_present("raw") => Output::stderr(),
+        path => Output::new(path)?,
+    };
+
+    // CSV starts with a row of column names
+    if csv {
+        util::write_row(&mut out, "section", "key", "value")?;
+    }
+
+    // plain buckets report as they always have
+    if !filter::is_glob(&pattern) {
+        let (s3, bucket, prefix) = buckets.into_iter().next().unwrap();
+        if let Some(chain) = report(&s3, args, bucket, prefix).await? {
+            print(&mut out, &chain, csv, None)?;
+            if !args.is_present("no_metrics") {
+                print_requests(&mut out, args, csv)?;
+            }
+            out.finish()?;
+        }
+        return Ok(());
+    }
+
+    // aggregate metrics and skipped buckets
+    let mut aggregate: Option<Vec<Box<dyn Metric>>> = None;
+    let mut skipped = Vec::new();
+    let count = buckets.len();
+
+    for (s3, bucket, prefix) in buckets {
+        // walk the bucket, skipping any failures
+        let chain = match report(&s3, args, bucket.clone(), prefix).await {
+            Ok(Some(chain)) => chain,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                error!("Skipping bucket {}: {}", bucket, err);
+                skipped.push(bucket);
+                continue;
+            }
+        };
+
+        // skip statistics if disabled
+        if args.is_present("no_metrics") {
+            continue;
+        }
+
+        // print the bucket section, separated from the previous bucket
+        if csv {
+            print(&mut out, &chain, csv, Some(&bucket))?;
+        } else {
+            let mut section = Section::new("bucket");
+            section.push("bucket_name", &bucket);
+            print_text(&mut out, &section, aggregate.is_none())?;
+            writeln!(out)?;
+            print(&mut out, &chain, csv, None)?;
+        }
+
+        // merge into the aggregate
+        match aggregate {
+            Some(ref mut aggregate) => metrics::merge(aggregate, chain),
+            None => aggregate = Some(chain),
+        }
+    }
+
+    // skip statistics if disabled
+    if args.is_present("no_metrics") {
+        return Ok(());
+    }
+
+    // print the aggregate section
+    let mut section = Section::new("aggregate");
+    section.push("total_buckets", count);
+    section.push("skipped_buckets", skipped.len());
+
+    if !skipped.is_empty() {
+        section.push("skipped_bucket_names", skipped.join(","));
+    }
+
+    if csv {
+        print_csv(&mut out, &section, None)?;
+    } else {
+        print_text(&mut out, &section, false)?;
+    }
+
+    if let Some(aggregate) = aggregate {
+        if !csv {
+            writeln!(out)?;
+        }
+        print(&mut out, &aggregate, csv, None)?;
+    }
+
+    print_requests(&mut out, args, csv)?;
+    out.finish()
+}
+
+/// Walks a bucket/prefix pair, gathering metrics about all objects.
+///
+/// If metrics are disabled the returned chain is empty, and if the reader
+/// of raw records goes away the walk ends early and `None` is returned.
+async fn report(
+    s3: &S3Client,
+    args: &ArgMatches<'_>,
+    bucket: String,
+    prefix: Option<String>,
+) -> UtilResult<Option<Vec<Box<dyn Metric>>>> {
+    // fetch an estimated total, used to track progress
+    let total = estimate(s3, &bucket, args).await?;
+
+    // parse the output switches
+    let raw = args.is_present("raw");
+    let chain_enabled = !args.is_present("no_metrics");
+    let filter = Filter::from_args(args)?;
+    let options = Options::from_args(args)?;
 
     // create our set of metric meters
-    let mut chain = metrics::chain(&prefix);
-    let mut walker = ObjectWalker::new(&s3, bucket, prefix);
+    let mut chain = metrics::chain(&prefix, &options);
+    let mut progress = Progress::new(total);
+    let mut walker = ObjectWalker::new(s3, bucket, prefix).filter(filter);
 
     // walk and check all metrics
     while let Some(object) = walker.next().await? {
+        // track progress
+        progress.tick();
+
+        // stream raw records, stopping if the reader went away
+        if raw {
+            if let Err(err) = write_raw(&mut io::stdout(), &object) {
+                if err.kind() == ErrorKind::BrokenPipe {
+                    return Ok(None);
+                }
+                return Err(err.into());
+            }
+        }
+
+        // skip metrics if disabled
+        if !chain_enabled {
+            continue;
+        }
+
         // iterate all metrics meters
         for metric in &mut chain {
             metric.register(&object);
         }
     }
 
-    // print all statistics
-    for metric in &chain {
-        metric.print();
+    // no statistics if disabled
+    if !chain_enabled {
+        chain.clear();
+    }
+
+    Ok(Some(chain))
+}
+
+/// Prints all statistics in a chain of metrics.
+///
+/// Sections of CSV rows are named after the bucket they belong to (such as
+/// `my.bucket/general`) if scoped, as rows have no headers to group them.
+fn print(
+    out: &mut Output,
+    chain: &[Box<dyn Metric>],
+    csv: bool,
+    scope: Option<&str>,
+) -> io::Result<()> {
+    let sections = chain.iter().flat_map(|metric| metric.sections());
+
+    for (idx, section) in sections.enumerate() {
+        if csv {
+            print_csv(out, &section, scope)?;
+        } else if !section.listing {
+            print_text(out, &section, idx == 0)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a section as text, separated from any sections before it.
+fn print_text(out: &mut Output, section: &Section, first: bool) -> io::Result<()> {
+    if !first {
+        writeln!(out)?;
+    }
+
+    writeln!(out, "[{}]", section.name)?;
+
+    for (label, val) in &section.pairs {
+        writeln!(out, "{}={}", label, val)?;
+    }
+
+    Ok(())
+}
+
+/// Prints a section as CSV rows, with the name of any bucket in scope.
+fn print_csv(out: &mut Output, section: &Section, scope: Option<&str>) -> io::Result<()> {
+    let name = match scope {
+        Some(scope) => format!("{}/{}", scope, section.name),
+        None => section.name.to_string(),
+    };
+
+    for (label, val) in &section.pairs {
+        util::write_row(out, &name, label, val)?;
     }
 
-    // done
     Ok(())
 }
+
+/// Prints the requests made so far, and their estimated cost, as a section.
+///
+/// This keeps the cost of gathering a report alongside it, even when the
+/// report is written to a file and the summary on stderr is lost.
+fn print_requests(out: &mut Output, args: &ArgMatches<'_>, csv: bool) -> UtilResult<()> {
+    let snapshot = cost::snapshot(&Pricing::from_args(args)?);
+    let mut section = Section::new("requests");
+
+    for (label, count) in snapshot.counts() {
+        section.push(&label, count);
+    }
+
+    section.push(
+        "estimated_cost",
+        format!("${:.6}", snapshot.estimated_cost()),
+    );
+
+    if csv {
+        print_csv(out, &section, None)?;
+    } else {
+        print_text(out, &section, false)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an `Object` to the provided writer as a line of JSON.
+///
+/// Each line is flushed immediately to make sure that pipelines are able
+/// to consume records as they arrive, rather than at the end of the walk.
+fn write_raw<W: Write>(writer: &mut W, object: &Object) -> io::Result<()> {
+    let record = json!({
+        "key": object.key,
+        "size": object.size,
+        "last_modified": object.last_modified,
+        "etag": object.e_tag.as_ref().map(|e_tag| e_tag.trim_matches('"')),
+        "storage_class": object.storage_class,
+    });
+
+    writeln!(writer, "{}", record)?;
+    writer.flush()
+}
+
+/// Fetches an estimated object count based on the provided arguments.
+///
+/// A provided approximate total is always used as-is; otherwise CloudWatch
+/// will be checked if requested, as it requires additional permissions.
+async fn estimate(s3: &S3Client, bucket: &str, args: &ArgMatches<'_>) -> UtilResult<Option<u64>> {
+    // explicit totals take priority
+    if let Some(total) = args.value_of("approx_total") {
+        return Ok(Some(total.parse().unwrap()));
+    }
+
+    // skip CloudWatch unless requested
+    if !args.is_present("cloudwatch_estimate") {
+        return Ok(None);
+    }
+
+    // metrics live in the bucket region
+    let region = client::locate(s3, bucket).await?;
+    let total = progress::estimate(region, bucket).await?;
+
+    // let the user know when nothing was found
+    if total.is_none() {
+        info!("Unable to locate CloudWatch metrics for {}", bucket);
+    }
+
+    Ok(total)
+}
@@ -0,0 +1,99 @@
+//! Destination of a formatted report, either stdout (or stderr) or a file.
+//!
+//! A report written to a file is written to a temporary file alongside it,
+//! and only moved into place once complete. This means that readers never
+//! see a partial report, and a failed report never replaces a previous one.
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use crate::types::UtilResult;
+
+/// Output structure writing a report to its destination.
+pub struct Output {
+    paths: Option<(String, String)>,
+    writer: Box<dyn Write>,
+}
+
+/// Main implementation.
+impl Output {
+    /// Constructs a new `Output`, writing to a file if a path is provided.
+    pub fn new(path: Option<&str>) -> UtilResult<Output> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                return Ok(Output {
+                    paths: None,
+                    writer: Box::new(io::stdout()),
+                })
+            }
+        };
+
+        let temp = format!("{}.{}.tmp", path, process::id());
+        let file = File::create(&temp)
+            .map_err(|err| format!("Unable to create report file {}: {}", temp, err))?;
+
+        Ok(Output {
+            paths: Some((temp, path.to_string())),
+            writer: Box::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Constructs a new `Output` writing to stderr, for when stdout is taken.
+    pub fn stderr() -> Output {
+        Output {
+            paths: None,
+            writer: Box::new(io::stderr()),
+        }
+    }
+
+    /// Completes the report, moving any file into place.
+    pub fn finish(mut self) -> UtilResult<()> {
+        self.flush()?;
+
+        if let Some((temp, path)) = self.paths.take() {
+            fs::rename(&temp, &path).map_err(|err| {
+                let _ = fs::remove_file(&temp);
+                format!("Unable to move report into {}: {}", path, err)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Describes a failure to write with the path of the report file.
+    fn describe<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        match (result, &self.paths) {
+            (Err(err), Some((_, path))) => Err(io::Error::new(
+                err.kind(),
+                format!("Unable to write report to {}: {}", path, err),
+            )),
+            (result, _) => result,
+        }
+    }
+}
+
+/// Write implementation.
+impl Write for Output {
+    /// Writes a buffer to the destination, naming any file on failure.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf);
+        self.describe(written)
+    }
+
+    /// Flushes the destination, naming any file on failure.
+    fn flush(&mut self) -> io::Result<()> {
+        let flushed = self.writer.flush();
+        self.describe(flushed)
+    }
+}
+
+/// Drop implementation.
+impl Drop for Output {
+    /// Removes the temporary file of a report which was never finished.
+    fn drop(&mut self) {
+        if let Some((ref temp, _)) = self.paths {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Module to track and emit progress heartbeats during report walks.
+use rusoto_cloudwatch::{CloudWatch, Dimension, GetMetricStatisticsInput};
+use rusoto_core::region::Region;
+
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::client;
+use crate::types::UtilResult;
+
+/// Interval to wait between each emitted heartbeat.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Weight given to the most recent window when rolling the listing rate.
+const RATE_SMOOTHING: f64 = 0.3;
+
+/// Progress structure to track the number of objects walked.
+///
+/// Heartbeats are only emitted when an estimate of the total number of
+/// objects is available, as neither the completion percentage nor the
+/// remaining time can be calculated without one.
+pub struct Progress {
+    total: Option<u64>,
+    scanned: u64,
+    rate: Option<f64>,
+    window_start: Instant,
+    window_scanned: u64,
+}
+
+/// Progress impl.
+impl Progress {
+    /// Constructs a new `Progress` struct from an optional estimate.
+    pub fn new(total: Option<u64>) -> Progress {
+        Progress {
+            total,
+            scanned: 0,
+            rate: None,
+            window_start: Instant::now(),
+            window_scanned: 0,
+        }
+    }
+
+    /// Registers a walked object, emitting a heartbeat when one is due.
+    pub fn tick(&mut self) {
+        self.scanned += 1;
+
+        // no estimate, no heartbeat
+        let total = match self.total {
+            Some(total) => total,
+            None => return,
+        };
+
+        // only emit once per interval
+        let elapsed = self.window_start.elapsed();
+        if elapsed < HEARTBEAT_INTERVAL {
+            return;
+        }
+
+        // calculate the rate of the current window
+        let walked = (self.scanned - self.window_scanned) as f64;
+        let current = walked / elapsed.as_secs_f64();
+
+        // roll the current window into the listing rate
+        let rate = match self.rate {
+            Some(rate) => rate + RATE_SMOOTHING * (current - rate),
+            None => current,
+        };
+
+        // reset the window
+        self.rate = Some(rate);
+        self.window_start = Instant::now();
+        self.window_scanned = self.scanned;
+
+        info!("{}", describe(self.scanned, total, rate));
+    }
+}
+
+/// Describes progress against an estimated total at the provided rate.
+fn describe(scanned: u64, total: u64, rate: f64) -> String {
+    // estimates can be too low, so we can't predict past them
+    if scanned >= total || total == 0 {
+        return format!(
+            "Scanned {} objects, exceeding the estimate of ~{}",
+            scanned, total
+        );
+    }
+
+    // calculate the completion and remaining time
+    let percent = scanned as f64 / total as f64 * 100.0;
+    let remaining = if rate > 0.0 {
+        let seconds = ((total - scanned) as f64 / rate).ceil() as u64;
+        humantime::format_duration(Duration::from_secs(seconds)).to_string()
+    } else {
+        "unknown".to_string()
+    };
+
+    format!(
+        "Scanned {} of ~{} objects ({:.1}%), ETA {}",
+        scanned, total, percent, remaining
+    )
+}
+
+/// Estimates the number of objects in a bucket via CloudWatch metrics.
+///
+/// This uses the daily `NumberOfObjects` storage metric, which covers the
+/// entire bucket and may lag behind by a day or two. When the walk is only
+/// working within a prefix, the estimate is an upper bound.
+pub async fn estimate(region: Region, bucket: &str) -> UtilResult<Option<u64>> {
+    // storage metrics live in the region of the bucket
+    let cloudwatch = client::cloudwatch(region)?;
+
+    // metrics are daily, so look back a few days to find one
+    let end_time = SystemTime::now();
+    let start_time = end_time - Duration::from_secs(3 * 86_400);
+
+    // create the request to fetch the object count metric
+    let request = GetMetricStatisticsInput {
+        namespace: "AWS/S3".to_string(),
+        metric_name: "NumberOfObjects".to_string(),
+        dimensions: Some(vec![
+            Dimension {
+                name: "BucketName".to_string(),
+                value: bucket.to_string(),
+            },
+            Dimension {
+                name: "StorageType".to_string(),
+                value: "AllStorageTypes".to_string(),
+            },
+        ]),
+        start_time: humantime::format_rfc3339_seconds(start_time).to_string(),
+        end_time: humantime::format_rfc3339_seconds(end_time).to_string(),
+        period: 86_400,
+        statistics: Some(vec!["Average".to_string()]),
+        ..GetMetricStatisticsInput::default()
+    };
+
+    // execute the request and await the response
+    let response = cloudwatch.get_metric_statistics(request).await?;
+
+    // use the most recent datapoint, if there is one
+    let latest = response
+        .datapoints
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|point| point.average.is_some())
+        .max_by(|left, right| left.timestamp.cmp(&right.timestamp));
+
+    Ok(latest.and_then(|point| point.average).map(|avg| avg as u64))
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn describing_progress_with_estimates() {
+        let halfway = super::describe(500, 1000, 50.0);
+        let stalled = super::describe(500, 1000, 0.0);
+        let exceeded = super::describe(1500, 1000, 50.0);
+
+        assert_eq!(halfway, "Scanned 500 of ~1000 objects (50.0%), ETA 10s");
+        assert_eq!(stalled, "Scanned 500 of ~1000 objects (50.0%), ETA unknown");
+        assert_eq!(
+            exceeded,
+            "Scanned 1500 objects, exceeding the estimate of ~1000"
+        );
+    }
+}
@@ -1,40 +1,15 @@
 //! General utility module housing formatting functions.
 use pretty_bytes::converter::convert;
 
+use std::borrow::Cow;
 use std::fmt::Display;
-
-use super::bounded::Bounded;
+use std::io::{self, Write};
 
 /// Converts a byte count to a `String` representation.
 pub fn convert_bytes(bytes: u64) -> String {
     convert(bytes as f64).replacen(' ', "", 1)
 }
 
-/// Logs out a bounded value, conditionally based on content.
-pub fn log_bound<L, T>(label: &str, bounded: &Bounded<T>, logger: L)
-where
-    L: FnOnce(T),
-    T: Clone,
-{
-    let bounded_key = bounded.key().clone();
-
-    if bounded_key.is_none() {
-        return;
-    }
-
-    let bounded_val = bounded.value().clone();
-    let bounded_cnt = bounded.count();
-
-    let key = bounded_key.unwrap();
-
-    logger(bounded_val);
-    log_pair(&format!("{}_name", label), key);
-
-    if bounded_cnt > 1 {
-        log_pair(&format!("{}_others", label), bounded_cnt);
-    }
-}
-
 /// Logs a header using a common format.
 pub fn log_head(label: &str) {
     println!("\n[{}]", label);
@@ -48,9 +23,33 @@ where
     println!("{}={}", label, val);
 }
 
+/// Writes a section/label/value row as CSV.
+pub fn write_row<W>(out: &mut W, section: &str, label: &str, val: &str) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(out, "{},{},{}", escape(section), escape(label), escape(val))
+}
+
+/// Escapes a CSV field, quoting it if it contains any special characters.
+pub fn escape(field: &str) -> Cow<'_, str> {
+    if !field.contains(&[',', '"', '\r', '\n'][..]) {
+        return Cow::Borrowed(field);
+    }
+    Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+}
+
 #[cfg(test)]
 mod tests {
 
+    #[test]
+    fn escaping_csv_fields() {
+        assert_eq!(super::escape("logs/a.log"), "logs/a.log");
+        assert_eq!(super::escape("logs/a,b.log"), "\"logs/a,b.log\"");
+        assert_eq!(super::escape("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+        assert_eq!(super::escape("two\nlines"), "\"two\nlines\"");
+    }
+
     #[test]
     fn converting_bytes_to_string() {
         let bval = 512_u64;
@@ -0,0 +1,199 @@
+//! Retries of requests which fail for transient reasons.
+//!
+//! Busy buckets will regularly throttle requests (and occasionally fail them
+//! outright), so requests failing due to throttling, server errors or timeouts
+//! are retried with exponential backoff. Each delay has jitter added, so that
+//! concurrent requests don't all retry at the same moment. Any other failure
+//! (such as access being denied) is returned immediately.
+//!
+//! When requests are rate limited, every attempt (including each retry) has
+//! to wait for the limiter before it can be sent.
+use clap::ArgMatches;
+use rusoto_core::RusotoError;
+
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::limit::Limiter;
+
+/// Largest exponent used when backing off, to avoid overflowing delays.
+const MAX_EXPONENT: u32 = 16;
+
+/// Longest delay before any retry, however large the base delay.
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Policy used to retry requests which fail for transient reasons.
+#[derive(Clone, Default)]
+pub struct Retry {
+    retries: u32,
+    base: Duration,
+    limiter: Option<Limiter>,
+}
+
+/// Main implementation.
+impl Retry {
+    /// Constructs a new `Retry` from a number of retries and base delay.
+    pub fn new(retries: u32, base: Duration) -> Retry {
+        Retry {
+            retries,
+            base,
+            limiter: None,
+        }
+    }
+
+    /// Sets the limiter every attempt must wait on before being sent.
+    pub fn limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Constructs a `Retry` from the provided arguments.
+    pub fn from_args(args: &ArgMatches<'_>) -> Retry {
+        // both are validated as numbers by clap, but may not fit a u32
+        let retries = args
+            .value_of("retries")
+            .unwrap()
+            .parse()
+            .unwrap_or(u32::MAX);
+        let base = args.value_of("retry_base_delay").unwrap().parse().unwrap();
+
+        let retry = Retry::new(retries, Duration::from_millis(base));
+
+        // rate limits are also validated as numbers by clap
+        match args.value_of("rate_limit") {
+            Some(rate) => {
+                let rate = rate.parse().unwrap_or(u32::MAX);
+                retry.limiter(Limiter::new(rate))
+            }
+            None => retry,
+        }
+    }
+
+    /// Runs a request, retrying it for as long as it fails transiently.
+    ///
+    /// The request is constructed again for each attempt, as sending a
+    /// request will consume it.
+    pub async fn run<T, E, F, R>(&self, name: &str, mut request: F) -> Result<T, RusotoError<E>>
+    where
+        E: Error + 'static,
+        F: FnMut() -> R,
+        R: Future<Output = Result<T, RusotoError<E>>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            if let Some(ref limiter) = self.limiter {
+                limiter.acquire().await;
+            }
+
+            let err = match request().await {
+                Err(err) if attempt < self.retries && is_retryable(&err) => err,
+                result => return result,
+            };
+
+            let delay = backoff(self.base, attempt, jitter());
+            attempt += 1;
+
+            warn!(
+                "Retrying {} in {}ms (attempt {} of {}): {}",
+                name,
+                delay.as_millis(),
+                attempt,
+                self.retries,
+                err
+            );
+
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Determines whether a failed request can be retried.
+///
+/// Errors raised when dispatching a request cover timeouts and dropped
+/// connections. S3 doesn't model throttling or server errors, so these
+/// surface as unknown errors and are checked by their status and body.
+fn is_retryable<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => is_transient(response.status.as_u16(), &response.body),
+        _ => false,
+    }
+}
+
+/// Determines whether a response status and body signal a transient error.
+fn is_transient(status: u16, body: &[u8]) -> bool {
+    if status == 429 || status >= 500 {
+        return true;
+    }
+
+    let body = String::from_utf8_lossy(body);
+
+    ["SlowDown", "Throttling", "RequestTimeout"]
+        .iter()
+        .any(|code| body.contains(&format!("<Code>{}</Code>", code)))
+}
+
+/// Calculates the delay before a retry, from a jitter between 0 and 1.
+///
+/// The delay doubles on each attempt (up to a maximum), and is then reduced
+/// by up to half using the jitter, so retries are spread out without ever
+/// being rushed.
+fn backoff(base: Duration, attempt: u32, jitter: f64) -> Duration {
+    let delay = base
+        .checked_mul(2u32.pow(attempt.min(MAX_EXPONENT)))
+        .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+
+    delay / 2 + (delay / 2).mul_f64(jitter.clamp(0.0, 1.0))
+}
+
+/// Generates a jitter between 0 and 1 from the system clock.
+fn jitter() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default() as f64
+        / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, is_transient, MAX_DELAY};
+    use std::time::Duration;
+
+    #[test]
+    fn backing_off_exponentially() {
+        let base = Duration::from_millis(100);
+
+        assert_eq!(backoff(base, 0, 1.0), Duration::from_millis(100));
+        assert_eq!(backoff(base, 1, 1.0), Duration::from_millis(200));
+        assert_eq!(backoff(base, 3, 1.0), Duration::from_millis(800));
+        assert_eq!(backoff(base, 3, 0.0), Duration::from_millis(400));
+        assert_eq!(backoff(base, 3, 0.5), Duration::from_millis(600));
+        assert_eq!(backoff(base, 100, 1.0), backoff(base, 16, 1.0));
+        assert_eq!(backoff(base, 16, 1.0), MAX_DELAY);
+
+        let huge = Duration::from_millis(u64::MAX);
+
+        assert_eq!(backoff(huge, 0, 1.0), MAX_DELAY);
+        assert_eq!(backoff(huge, 16, 0.0), MAX_DELAY / 2);
+    }
+
+    #[test]
+    fn detecting_transient_errors() {
+        assert!(is_transient(500, b""));
+        assert!(is_transient(503, b"<Error><Code>SlowDown</Code></Error>"));
+        assert!(is_transient(429, b""));
+        assert!(is_transient(
+            400,
+            b"<Error><Code>RequestTimeout</Code></Error>"
+        ));
+
+        assert!(!is_transient(
+            403,
+            b"<Error><Code>AccessDenied</Code></Error>"
+        ));
+        assert!(!is_transient(404, b"<Error><Code>NoSuchKey</Code></Error>"));
+    }
+}
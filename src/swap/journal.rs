@@ -0,0 +1,154 @@
+//! Journaling of swap progress, to allow resuming an interrupted swap.
+//!
+//! The journal is a small append-only file of tab separated records; the
+//! first names the prefixes being swapped, and every other record marks a
+//! phase as completed or reverted. Without a path, the journal is kept in
+//! memory only (and a swap cannot be resumed).
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::types::UtilResult;
+
+use super::PHASES;
+
+/// Journal structure tracking the progress of a swap.
+#[derive(Default)]
+pub struct Journal {
+    path: Option<PathBuf>,
+    swap: Option<(String, String, String)>,
+    complete: [bool; PHASES],
+}
+
+/// Main implementation.
+impl Journal {
+    /// Constructs a new `Journal` held only in memory.
+    pub fn memory() -> Journal {
+        Journal::default()
+    }
+
+    /// Loads a `Journal` from a file, which may not exist yet.
+    pub fn load(path: &str) -> UtilResult<Journal> {
+        let mut journal = Journal {
+            path: Some(PathBuf::from(path)),
+            ..Journal::default()
+        };
+
+        // open the journal, which may not exist yet
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(journal),
+            Err(err) => return Err(err.into()),
+        };
+
+        // replay each record in order
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let fields = line.split('\t').collect::<Vec<_>>();
+
+            let phase = fields.get(1).and_then(|phase| phase.parse::<usize>().ok());
+
+            match (fields[0], fields.len(), phase) {
+                ("swap", 4, _) => {
+                    journal.swap = Some((
+                        fields[1].to_string(),
+                        fields[2].to_string(),
+                        fields[3].to_string(),
+                    ))
+                }
+                ("complete", 2, Some(phase)) if phase < PHASES => journal.complete[phase] = true,
+                ("revert", 2, Some(phase)) if phase < PHASES => journal.complete[phase] = false,
+                _ => return Err(format!("Invalid swap record in {}: {}", path, line).into()),
+            }
+        }
+
+        Ok(journal)
+    }
+
+    /// Retrieves the first, second and temporary prefixes of a journaled swap.
+    pub fn swap(&self) -> Option<(&str, &str, &str)> {
+        self.swap
+            .as_ref()
+            .map(|(first, second, temp)| (first.as_str(), second.as_str(), temp.as_str()))
+    }
+
+    /// Records the start of a swap, unless it has already been recorded.
+    pub fn start(&mut self, first: &str, second: &str, temp: &str) -> UtilResult<()> {
+        if self.swap.is_some() {
+            return Ok(());
+        }
+
+        self.append(&format!("swap\t{}\t{}\t{}", first, second, temp))?;
+        self.swap = Some((first.to_string(), second.to_string(), temp.to_string()));
+
+        Ok(())
+    }
+
+    /// Determines whether a phase has been completed.
+    pub fn is_complete(&self, phase: usize) -> bool {
+        self.complete[phase]
+    }
+
+    /// Records a phase as completed.
+    pub fn complete(&mut self, phase: usize) -> UtilResult<()> {
+        self.append(&format!("complete\t{}", phase))?;
+        self.complete[phase] = true;
+        Ok(())
+    }
+
+    /// Records a phase as reverted.
+    pub fn revert(&mut self, phase: usize) -> UtilResult<()> {
+        self.append(&format!("revert\t{}", phase))?;
+        self.complete[phase] = false;
+        Ok(())
+    }
+
+    /// Removes the journal, as there is nothing left to resume.
+    pub fn clear(&mut self) {
+        if let Some(ref path) = self.path {
+            let _ = fs::remove_file(path);
+        }
+        self.swap = None;
+        self.complete = [false; PHASES];
+    }
+
+    /// Appends a record to the journal file, if there is one.
+    fn append(&self, record: &str) -> UtilResult<()> {
+        if let Some(ref path) = self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+
+    #[test]
+    fn resuming_from_a_journal() {
+        let path = std::env::temp_dir().join("s3-utils-swap-test");
+        let path = path.to_string_lossy().into_owned();
+
+        let mut journal = Journal::load(&path).unwrap();
+
+        assert!(journal.swap().is_none());
+
+        journal.start("a/", "b/", "a.swap-1/").unwrap();
+        journal.complete(0).unwrap();
+        journal.complete(1).unwrap();
+        journal.revert(1).unwrap();
+
+        let mut journal = Journal::load(&path).unwrap();
+
+        assert_eq!(journal.swap(), Some(("a/", "b/", "a.swap-1/")));
+        assert!(journal.is_complete(0));
+        assert!(!journal.is_complete(1));
+        assert!(!journal.is_complete(2));
+
+        journal.clear();
+
+        assert!(Journal::load(&path).unwrap().swap().is_none());
+    }
+}
@@ -0,0 +1,611 @@
+//! Swapping of two prefixes within a bucket, via a temporary prefix.
+//!
+//! S3 has no way to rename a prefix atomically, so a swap is carried out in
+//! phases which never remove anything until the following copies are in
+//! place: the first prefix is copied into a temporary prefix, the second is
+//! copied over the first (and anything left over in the first removed), and
+//! the temporary prefix is copied over the second (and anything left over in
+//! the second removed) before the temporary prefix is removed. A failing
+//! phase rolls back everything before it, using the copies still in place;
+//! once every copy is in place, a failure is left to be resumed instead.
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rusoto_s3::*;
+
+use std::collections::HashSet;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cli;
+use crate::client;
+use crate::copy::{self, read_acl, storage_class, write_acl};
+use crate::filter;
+use crate::guard::DeleteGuard;
+use crate::lock::{self, Lock};
+use crate::retry::Retry;
+use crate::types::UtilResult;
+use crate::walker::ObjectWalker;
+
+use self::journal::Journal;
+
+pub mod journal;
+
+/// Number of phases needed to complete a swap.
+pub const PHASES: usize = 6;
+
+/// Last phase which copies anything, so can still be rolled back.
+const LAST_COPY: usize = 3;
+
+/// Last phase which leaves a prefix with a mix of old and new contents.
+const LAST_LEFTOVER: usize = 4;
+
+/// A single phase of a swap.
+#[derive(Clone, Copy)]
+enum Phase<'a> {
+    /// Copies every object in a prefix over another prefix.
+    Copy(&'a str, &'a str),
+    /// Removes every object in a prefix which isn't in another prefix (or
+    /// every object at all, without one).
+    Prune(&'a str, Option<&'a str>),
+}
+
+/// Main implementation.
+impl Phase<'_> {
+    /// Describes this phase, for logging.
+    fn describe(&self) -> String {
+        match self {
+            Phase::Copy(from, to) => format!("copying {} -> {}", from, to),
+            Phase::Prune(prefix, Some(like)) => {
+                format!("removing keys from {} which aren't in {}", prefix, like)
+            }
+            Phase::Prune(prefix, None) => format!("removing {}", prefix),
+        }
+    }
+}
+
+/// Everything needed to move objects between prefixes within a bucket.
+struct Mover<'a> {
+    s3: &'a S3Client,
+    bucket: &'a str,
+    retry: Retry,
+    preserve_acl: bool,
+    preserve_lock: bool,
+}
+
+/// Generates an appropriate `SubCommand` for this module.
+pub fn cmd<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("swap")
+        .about("Swap the contents of two prefixes in S3")
+        .args(&cli::global_args())
+        .args(&[
+            Arg::with_name("preserve_acl")
+                .help("Copies the ACL of each file onto its swapped file")
+                .long("preserve-acl"),
+            Arg::with_name("preserve_object_lock")
+                .help("Carries the Object Lock retention and legal hold of each file across")
+                .long("preserve-object-lock"),
+            Arg::with_name("retries")
+                .help("The number of times to retry requests failing transiently")
+                .long("retries")
+                .takes_value(true)
+                .default_value("3")
+                .validator(cli::validate_number),
+            Arg::with_name("retry_base_delay")
+                .help("The base delay between retries, in milliseconds")
+                .long("retry-base-delay")
+                .takes_value(true)
+                .default_value("100")
+                .validator(cli::validate_number),
+            Arg::with_name("state_file")
+                .help("A file to journal progress to, allowing a swap to resume")
+                .long("state-file")
+                .takes_value(true),
+            Arg::with_name("first")
+                .help("The first prefix to swap")
+                .index(2)
+                .required(true),
+            Arg::with_name("second")
+                .help("The second prefix to swap")
+                .index(3)
+                .required(true),
+        ])
+}
+
+/// Executes this subcommand and returns a `UtilResult` to indicate success.
+pub async fn exec(s3: S3Client, args: &ArgMatches<'_>) -> UtilResult<()> {
+    // parse all global arguments
+    let dryrun = cli::is_dry_run(args);
+    let (bucket, prefix) = cli::get_bucket_pair(args);
+
+    // swaps are journaled per bucket, so patterns can't be supported
+    if filter::is_glob(&bucket) {
+        return Err("Unable to swap prefixes across a bucket pattern".into());
+    }
+
+    // resolve both prefixes (unwrap should be safe)
+    let first = resolve_prefix(&prefix, args.value_of("first").unwrap())?;
+    let second = resolve_prefix(&prefix, args.value_of("second").unwrap())?;
+
+    // nested prefixes would move each other around
+    if first.starts_with(&second) || second.starts_with(&first) {
+        return Err(format!("Unable to swap overlapping prefixes: {} {}", first, second).into());
+    }
+
+    // load any journal left behind by a previous run
+    let mut journal = match args.value_of("state_file") {
+        Some(path) => Journal::load(path)?,
+        None => Journal::memory(),
+    };
+
+    // reuse the temporary prefix when resuming
+    let temp = match journal.swap() {
+        None => temp_prefix(&first)?,
+        Some((j_first, j_second, j_temp)) => {
+            if j_first != first || j_second != second {
+                return Err(format!(
+                    "State file belongs to a swap of {} and {}",
+                    j_first, j_second
+                )
+                .into());
+            }
+            j_temp.to_string()
+        }
+    };
+
+    // the phases required to complete a swap
+    let phases = [
+        Phase::Copy(&first, &temp),
+        Phase::Copy(&second, &first),
+        Phase::Prune(&first, Some(&second)),
+        Phase::Copy(&temp, &second),
+        Phase::Prune(&second, Some(&temp)),
+        Phase::Prune(&temp, None),
+    ];
+
+    // every phase checks its removals against the guard before making them
+    let mut guard = DeleteGuard::from_args(args)?;
+
+    // locked files can only be copied within a bucket which supports locking
+    let preserve_lock = args.is_present("preserve_object_lock");
+    if preserve_lock && !lock::is_enabled(&s3, &bucket).await? {
+        return Err(format!("Bucket {} does not have Object Lock enabled", bucket).into());
+    }
+
+    let mover = Mover {
+        s3: &s3,
+        bucket: &bucket,
+        retry: Retry::from_args(args),
+        preserve_acl: args.is_present("preserve_acl"),
+        preserve_lock,
+    };
+
+    // log out the plan (tallying what each phase would remove), and skip
+    if dryrun {
+        for (idx, phase) in phases.iter().enumerate() {
+            info!("Phase {}/{}: {}", idx + 1, PHASES, phase.describe());
+        }
+
+        // the original contents of each prefix, wherever they are right now
+        let original_first = if journal.is_complete(0) {
+            &temp
+        } else {
+            &first
+        };
+        let original_second = if journal.is_complete(2) {
+            &first
+        } else {
+            &second
+        };
+
+        let first_keys = list_keys(&s3, &bucket, original_first).await?;
+        let second_keys = list_keys(&s3, &bucket, original_second).await?;
+
+        if !journal.is_complete(2) {
+            let leftovers =
+                leftover_keys(original_first, original_second, &first_keys, &second_keys);
+            guard.check(leftovers.len() as u64)?;
+        }
+
+        if !journal.is_complete(4) {
+            let leftovers =
+                leftover_keys(original_second, original_first, &second_keys, &first_keys);
+            guard.check(leftovers.len() as u64)?;
+        }
+
+        if !journal.is_complete(5) {
+            guard.check(first_keys.len() as u64)?;
+        }
+
+        guard.summarize();
+        return Ok(());
+    }
+
+    // journal the swap before touching anything
+    journal.start(&first, &second, &temp)?;
+    info!("Swapping {} <-> {} via {}...", first, second, temp);
+
+    // readers can see a partial state from the first overwrite until the
+    // leftovers of the second prefix are removed
+    let mut window = None;
+
+    for (idx, phase) in phases.iter().enumerate() {
+        // resumed runs skip anything already done
+        if journal.is_complete(idx) {
+            info!("Skipping completed phase {}/{}", idx + 1, PHASES);
+            continue;
+        }
+
+        if idx > 0 && idx <= LAST_LEFTOVER && window.is_none() {
+            window = Some(Instant::now());
+        }
+
+        // log out exactly what we're doing right now
+        info!("Phase {}/{}: {}", idx + 1, PHASES, phase.describe());
+
+        // carry out the phase, rolling back everything on failure
+        let err = match mover.run(&mut guard, *phase).await {
+            Ok(count) => {
+                info!("Phase {}/{} complete ({} objects)", idx + 1, PHASES, count);
+                journal.complete(idx)?;
+
+                if let Some(window) = window.filter(|_| idx == LAST_LEFTOVER) {
+                    info!(
+                        "Prefixes were inconsistent for {}",
+                        humantime::format_duration(window.elapsed())
+                    );
+                }
+                continue;
+            }
+            Err(err) => err,
+        };
+
+        error!("Phase {}/{} failed: {}", idx + 1, PHASES, err);
+
+        // every copy is in place, so only leftovers remain to be removed
+        if idx > LAST_COPY {
+            return Err(format!(
+                "Prefixes are swapped, but leftovers remain in {} (resume with --state-file to remove them): {}",
+                phase_prefix(*phase),
+                err
+            )
+            .into());
+        }
+
+        // move everything back, using the copies still in place
+        let prefixes = (first.as_str(), second.as_str(), temp.as_str());
+        let rollback = roll_back(&mover, &mut guard, &mut journal, prefixes, idx);
+
+        if let Err(rollback) = rollback.await {
+            return Err(format!(
+                "Unable to roll back phase {}/{}: {} (original failure: {})",
+                idx + 1,
+                PHASES,
+                rollback,
+                err
+            )
+            .into());
+        }
+
+        // rolled back fully, so nothing to resume
+        journal.clear();
+
+        return Err(err);
+    }
+
+    // all phases complete, so nothing to resume
+    journal.clear();
+    info!("Swap complete");
+
+    Ok(())
+}
+
+/// Rolls back a swap which failed during a phase.
+///
+/// Each prefix is restored from the copy of its original contents which is
+/// still in place. The journal is reverted before each prefix is restored,
+/// so an interrupted rollback leaves a journal which resumes the swap (as
+/// every phase converges from a partial state).
+async fn roll_back(
+    mover: &Mover<'_>,
+    guard: &mut DeleteGuard,
+    journal: &mut Journal,
+    prefixes: (&str, &str, &str),
+    failed: usize,
+) -> UtilResult<()> {
+    let (first, second, temp) = prefixes;
+    let mut steps = Vec::new();
+
+    // the second prefix was being overwritten, and the first holds its contents
+    if failed >= LAST_COPY {
+        steps.push((None, Phase::Copy(first, second)));
+        steps.push((None, Phase::Prune(second, Some(first))));
+    }
+
+    // the first prefix was being overwritten, and the temporary holds its contents
+    if failed >= 1 {
+        steps.push((Some(1), Phase::Copy(temp, first)));
+        steps.push((None, Phase::Prune(first, Some(temp))));
+    }
+
+    steps.push((Some(0), Phase::Prune(temp, None)));
+
+    for (reverted, phase) in steps {
+        // revert every completed phase from this one onwards
+        if let Some(reverted) = reverted {
+            for idx in (reverted..=failed).rev() {
+                if journal.is_complete(idx) {
+                    journal.revert(idx)?;
+                }
+            }
+        }
+
+        info!("Rolling back: {}", phase.describe());
+        mover.run(guard, phase).await?;
+    }
+
+    Ok(())
+}
+
+/// Determines the prefix written to by a phase.
+fn phase_prefix<'a>(phase: Phase<'a>) -> &'a str {
+    match phase {
+        Phase::Copy(_, to) => to,
+        Phase::Prune(prefix, _) => prefix,
+    }
+}
+
+/// Main implementation.
+impl Mover<'_> {
+    /// Carries out a single phase, returning the number of objects touched.
+    async fn run(&self, guard: &mut DeleteGuard, phase: Phase<'_>) -> UtilResult<usize> {
+        match phase {
+            Phase::Copy(from, to) => self.copy_prefix(from, to).await,
+            Phase::Prune(prefix, like) => self.prune_prefix(guard, prefix, like).await,
+        }
+    }
+
+    /// Copies all objects beneath a prefix over another prefix.
+    ///
+    /// The copy of every source is verified to exist in the target prefix, and
+    /// nothing is removed from either. Copying is idempotent, so a partially
+    /// copied prefix can simply be copied again.
+    async fn copy_prefix(&self, from: &str, to: &str) -> UtilResult<usize> {
+        // gather all objects up front, as the target may be listed alongside
+        let objects = list_objects(self.s3, self.bucket, from).await?;
+        let keys = objects
+            .iter()
+            .filter_map(|object| object.key.clone())
+            .collect::<Vec<_>>();
+
+        // copy every object into the target prefix
+        for object in objects {
+            let key = object.key.unwrap_or_default();
+            self.copy_object(
+                &key,
+                &target_key(from, to, &key),
+                object.size,
+                object.storage_class,
+            )
+            .await?;
+        }
+
+        // verify that everything arrived
+        let copied = list_keys(self.s3, self.bucket, to).await?;
+        let missing = missing_keys(from, to, &keys, &copied);
+
+        if !missing.is_empty() {
+            return Err(format!(
+                "Expected {} objects in {} after copying, but {} were missing (such as {})",
+                keys.len(),
+                to,
+                missing.len(),
+                missing[0]
+            )
+            .into());
+        }
+
+        Ok(keys.len())
+    }
+
+    /// Copies a single object to another key, keeping its storage class (and
+    /// its ACL and Object Lock, when asked to).
+    async fn copy_object(
+        &self,
+        key: &str,
+        target: &str,
+        size: Option<i64>,
+        listed: Option<String>,
+    ) -> UtilResult<()> {
+        let (s3, bucket) = (self.s3, self.bucket);
+
+        // archived objects fail here, before anything is copied
+        let storage_class = storage_class(s3, bucket, key, listed, None).await?;
+
+        let lock = if self.preserve_lock {
+            Lock::read(s3, bucket, key, None).await?
+        } else {
+            Lock::default()
+        };
+
+        let acl = if self.preserve_acl {
+            Some(read_acl(s3, bucket, key, None).await?)
+        } else {
+            None
+        };
+
+        let request = lock.apply(CopyObjectRequest {
+            key: target.to_string(),
+            bucket: bucket.to_string(),
+            copy_source: client::copy_source(bucket, key),
+            storage_class,
+            ..CopyObjectRequest::default()
+        });
+
+        let size = size.unwrap_or_default();
+        copy::copy(s3, &request, (bucket, key), size, &self.retry).await?;
+
+        if let Some(acl) = acl {
+            write_acl(s3, bucket, target, acl, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes all objects beneath a prefix which aren't beneath another prefix.
+    ///
+    /// Without another prefix, every object is removed. Removals are checked
+    /// against the guard first, so a refused phase removes nothing.
+    async fn prune_prefix(
+        &self,
+        guard: &mut DeleteGuard,
+        prefix: &str,
+        like: Option<&str>,
+    ) -> UtilResult<usize> {
+        let mut keys = list_keys(self.s3, self.bucket, prefix).await?;
+
+        // keep anything which also exists in the other prefix
+        if let Some(like) = like {
+            let kept = list_keys(self.s3, self.bucket, like).await?;
+            keys = leftover_keys(prefix, like, &keys, &kept);
+        }
+
+        // remove all of the leftovers, within the limit
+        guard.check(keys.len() as u64)?;
+
+        let refused = client::remove_objects(self.s3, self.bucket, &keys, &self.retry, None).await;
+
+        if !refused.is_empty() {
+            return Err(
+                format!("Unable to remove {} objects from {}", refused.len(), prefix).into(),
+            );
+        }
+
+        Ok(keys.len())
+    }
+}
+
+/// Lists all objects beneath a prefix.
+async fn list_objects(s3: &S3Client, bucket: &str, prefix: &str) -> UtilResult<Vec<Object>> {
+    let mut walker = ObjectWalker::new(s3, bucket.to_string(), Some(prefix.to_string()));
+    let mut objects = Vec::new();
+
+    while let Some(object) = walker.next().await? {
+        objects.push(object);
+    }
+
+    Ok(objects)
+}
+
+/// Lists all keys beneath a prefix.
+async fn list_keys(s3: &S3Client, bucket: &str, prefix: &str) -> UtilResult<Vec<String>> {
+    let objects = list_objects(s3, bucket, prefix).await?;
+    Ok(objects
+        .into_iter()
+        .filter_map(|object| object.key)
+        .collect())
+}
+
+/// Determines the key a source is moved into, beneath the target prefix.
+fn target_key(from: &str, to: &str, key: &str) -> String {
+    format!("{}{}", to, &key[from.len()..])
+}
+
+/// Determines which keys beneath a prefix have no counterpart in a listing
+/// of another prefix.
+fn leftover_keys(from: &str, to: &str, keys: &[String], listed: &[String]) -> Vec<String> {
+    let listed = listed.iter().map(String::as_str).collect::<HashSet<_>>();
+
+    keys.iter()
+        .filter(|key| !listed.contains(target_key(from, to, key).as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Determines which copies of a set of sources are missing from a listing.
+///
+/// The listing may contain other keys (such as those left by a partially
+/// completed attempt), which are ignored.
+fn missing_keys(from: &str, to: &str, keys: &[String], copied: &[String]) -> Vec<String> {
+    leftover_keys(from, to, keys, copied)
+        .iter()
+        .map(|key| target_key(from, to, key))
+        .collect()
+}
+
+/// Resolves a swap prefix against the bucket prefix, as a directory.
+fn resolve_prefix(base: &Option<String>, prefix: &str) -> UtilResult<String> {
+    let prefix = prefix.trim_matches('/');
+
+    if prefix.is_empty() {
+        return Err("Unable to swap the root of a bucket".into());
+    }
+
+    Ok(match base {
+        Some(base) => format!("{}/{}/", base, prefix),
+        None => format!("{}/", prefix),
+    })
+}
+
+/// Generates a temporary prefix as a sibling of the provided prefix.
+fn temp_prefix(prefix: &str) -> UtilResult<String> {
+    let epoch = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let prefix = prefix.trim_end_matches('/');
+
+    Ok(format!("{}.swap-{}/", prefix, epoch.as_secs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leftover_keys, missing_keys, resolve_prefix, temp_prefix};
+
+    #[test]
+    fn resolving_swap_prefixes() {
+        let base = Some("sites".to_string());
+
+        assert_eq!(resolve_prefix(&None, "live").unwrap(), "live/");
+        assert_eq!(resolve_prefix(&None, "/live/").unwrap(), "live/");
+        assert_eq!(resolve_prefix(&base, "live").unwrap(), "sites/live/");
+
+        assert!(resolve_prefix(&None, "/").is_err());
+    }
+
+    #[test]
+    fn finding_missing_copies() {
+        let keys = vec!["live/a".to_string(), "live/b".to_string()];
+
+        // keys from an earlier attempt don't count against the copies
+        let copied = vec![
+            "temp/a".to_string(),
+            "temp/b".to_string(),
+            "temp/c".to_string(),
+        ];
+
+        assert!(missing_keys("live/", "temp/", &keys, &copied).is_empty());
+
+        let copied = vec!["temp/a".to_string(), "temp/c".to_string()];
+
+        assert_eq!(
+            missing_keys("live/", "temp/", &keys, &copied),
+            vec!["temp/b".to_string()]
+        );
+    }
+
+    #[test]
+    fn finding_leftover_keys() {
+        let keys = vec!["live/a".to_string(), "live/b".to_string()];
+        let kept = vec!["next/b".to_string(), "next/c".to_string()];
+
+        assert_eq!(
+            leftover_keys("live/", "next/", &keys, &kept),
+            vec!["live/a".to_string()]
+        );
+        assert!(leftover_keys("live/", "next/", &[], &kept).is_empty());
+    }
+
+    #[test]
+    fn generating_sibling_temp_prefixes() {
+        let temp = temp_prefix("site/live/").unwrap();
+
+        assert!(temp.starts_with("site/live.swap-"));
+        assert!(temp.ends_with('/'));
+        assert!(!temp.starts_with("site/live/"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Tags applied to every target created, in the form `key=value`.
+//!
+//! S3 limits objects to 10 tags, with keys of up to 128 characters and
+//! values of up to 256 characters, so tags are validated as arguments are
+//! parsed rather than failing the first upload created. Tags are sent when
+//! creating each upload, as a URL encoded query string.
+use crate::client;
+
+/// Maximum number of tags which can be applied to an object.
+pub const MAX_TAGS: u64 = 10;
+
+/// Maximum length of a tag key, in characters.
+const MAX_KEY_LENGTH: usize = 128;
+
+/// Maximum length of a tag value, in characters.
+const MAX_VALUE_LENGTH: usize = 256;
+
+/// Parses a tag from a `key=value` pair.
+///
+/// Only the first `=` separates the key from the value, so values can
+/// contain any further `=` characters.
+pub fn parse(value: &str) -> Result<(&str, &str), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Tag must be in the form key=value: {}", value))?;
+
+    if key.is_empty() || key.chars().count() > MAX_KEY_LENGTH {
+        return Err(format!(
+            "Tag key must be between 1 and {} characters: {}",
+            MAX_KEY_LENGTH, key
+        ));
+    }
+
+    if value.chars().count() > MAX_VALUE_LENGTH {
+        return Err(format!(
+            "Tag value must be at most {} characters: {}",
+            MAX_VALUE_LENGTH, value
+        ));
+    }
+
+    Ok((key, value))
+}
+
+/// Validates that an argument value is a tag, as accepted by `parse`.
+pub fn validate(value: String) -> Result<(), String> {
+    parse(&value).map(|_| ())
+}
+
+/// Encodes a list of tags into a URL encoded query string.
+pub fn encode(tags: &[(&str, &str)]) -> String {
+    tags.iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                client::encode(key, b""),
+                client::encode(value, b"")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, parse};
+
+    #[test]
+    fn parsing_tags() {
+        assert_eq!(parse("team=data"), Ok(("team", "data")));
+        assert_eq!(parse("query=a=b"), Ok(("query", "a=b")));
+        assert_eq!(parse("empty="), Ok(("empty", "")));
+
+        assert!(parse("team").is_err());
+        assert!(parse("=data").is_err());
+        assert!(parse(&format!("{}=data", "k".repeat(129))).is_err());
+        assert!(parse(&format!("team={}", "v".repeat(257))).is_err());
+        assert!(parse(&format!("team={}", "v".repeat(256))).is_ok());
+    }
+
+    #[test]
+    fn encoding_tags() {
+        let tags = [
+            ("team", "data eng"),
+            ("pipeline", "a/b&c=d"),
+            ("owner", "\u{e9}"),
+        ];
+
+        assert_eq!(
+            encode(&tags),
+            "team=data%20eng&pipeline=a%2Fb%26c%3Dd&owner=%C3%A9"
+        );
+    }
+}
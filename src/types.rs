@@ -5,6 +5,7 @@
 use logger::SetLoggerError;
 use quick_xml::events::Event;
 use quick_xml::Reader;
+use rusoto_core::region::ParseRegionError;
 use rusoto_core::request;
 
 use std::fmt::{self, Debug, Display, Formatter};
@@ -50,9 +51,11 @@ macro_rules! derive_from {
 // Easy derivations of derive_from.
 derive_from!(&'a str);
 derive_from!(io::Error);
+derive_from!(chrono::ParseError);
 derive_from!(clap::Error);
 derive_from!(SetLoggerError);
 derive_from!(regex::Error);
+derive_from!(ParseRegionError);
 derive_from!(request::TlsError);
 derive_from!(time::SystemTimeError);
 derive_from!(String);
@@ -63,6 +66,16 @@ macro_rules! derive_from_rusoto {
         impl From<rusoto_core::RusotoError<$type>> for UtilError {
             /// Converts a Rusoto error to a `UtilError`.
             fn from(err: rusoto_core::RusotoError<$type>) -> UtilError {
+                // region mismatches only surface via a response header
+                if let rusoto_core::RusotoError::Unknown(ref response) = err {
+                    if let Some(region) = response.headers.get("x-amz-bucket-region") {
+                        return UtilError(format!(
+                            "Request was sent to the wrong region; bucket is located in {}",
+                            region
+                        ));
+                    }
+                }
+
                 // grab the raw conversion
                 let msg = err.to_string();
 
@@ -103,13 +116,28 @@ macro_rules! derive_from_rusoto {
 }
 
 // derive error display for all used rusoto_s3 types
+derive_from_rusoto!(rusoto_cloudwatch::GetMetricStatisticsError);
 derive_from_rusoto!(rusoto_s3::AbortMultipartUploadError);
 derive_from_rusoto!(rusoto_s3::CompleteMultipartUploadError);
 derive_from_rusoto!(rusoto_s3::CopyObjectError);
 derive_from_rusoto!(rusoto_s3::CreateMultipartUploadError);
 derive_from_rusoto!(rusoto_s3::DeleteObjectError);
+derive_from_rusoto!(rusoto_s3::DeleteObjectsError);
+derive_from_rusoto!(rusoto_s3::GetBucketLocationError);
+derive_from_rusoto!(rusoto_s3::GetBucketVersioningError);
+derive_from_rusoto!(rusoto_s3::GetObjectError);
+derive_from_rusoto!(rusoto_s3::GetObjectLockConfigurationError);
+derive_from_rusoto!(rusoto_s3::GetObjectAclError);
+derive_from_rusoto!(rusoto_s3::GetObjectTaggingError);
+derive_from_rusoto!(rusoto_s3::HeadObjectError);
+derive_from_rusoto!(rusoto_s3::ListBucketsError);
+derive_from_rusoto!(rusoto_s3::ListMultipartUploadsError);
+derive_from_rusoto!(rusoto_s3::ListObjectVersionsError);
 derive_from_rusoto!(rusoto_s3::ListObjectsV2Error);
 derive_from_rusoto!(rusoto_s3::ListPartsError);
+derive_from_rusoto!(rusoto_s3::PutObjectError);
+derive_from_rusoto!(rusoto_s3::PutObjectAclError);
+derive_from_rusoto!(rusoto_s3::UploadPartError);
 derive_from_rusoto!(rusoto_s3::UploadPartCopyError);
 
 #[cfg(test)]
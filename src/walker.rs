@@ -4,6 +4,7 @@
 //! to walk over objects in S3 in a more idiomatic manner. At some point
 //! (hopefully soon) this will change to use an asynchronous `Stream`,
 //! when Rusoto migrates to Futures 0.3 and beyond.
+use crate::filter::Filter;
 use crate::types::UtilResult;
 use rusoto_s3::*;
 use std::future::Future;
@@ -30,7 +31,10 @@ pub struct ObjectWalker<'a> {
     bucket: String,
     prefix: Option<String>,
     buffer: Vec<Object>,
+    filter: Filter,
     finished: bool,
+    request_payer: Option<String>,
+    start_after: Option<String>,
 }
 
 impl<'a> ObjectWalker<'a> {
@@ -42,10 +46,31 @@ impl<'a> ObjectWalker<'a> {
             prefix,
             token: None,
             buffer: Vec::new(),
+            filter: Filter::default(),
             finished: false,
+            request_payer: None,
+            start_after: None,
         }
     }
 
+    /// Attaches a `Filter` to this walker, to skip non-matching objects.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets the payer of each listing, to walk requester pays buckets.
+    pub fn request_payer(mut self, request_payer: Option<String>) -> Self {
+        self.request_payer = request_payer;
+        self
+    }
+
+    /// Starts this walker after a key, skipping everything up to it.
+    pub fn start_after(mut self, start_after: Option<String>) -> Self {
+        self.start_after = start_after;
+        self
+    }
+
     /// Attempts to fetch the next `Object` in the S3 archives.
     ///
     /// Calls can fail, which is why a `Result` is returned. Even if a call
@@ -56,43 +81,53 @@ impl<'a> ObjectWalker<'a> {
     /// there may already be buffered data to be returned immediately.
     pub fn next(&mut self) -> Pin<Box<dyn Future<Output = UtilResult<Option<Object>>> + '_>> {
         Box::pin(async move {
-            // always check the buffer first
-            if !self.buffer.is_empty() {
-                return Ok(Some(self.buffer.remove(0)));
-            }
+            loop {
+                // always check the buffer first
+                while !self.buffer.is_empty() {
+                    let object = self.buffer.remove(0);
 
-            // if done, no fetch
-            if self.finished {
-                return Ok(None);
-            }
+                    // skip anything which doesn't pass the filter
+                    if let Some(ref key) = object.key {
+                        if !self.filter.is_match(key) {
+                            continue;
+                        }
+                    }
 
-            // create a request to list objects
-            let request = ListObjectsV2Request {
-                bucket: self.bucket.clone(),
-                prefix: self.prefix.clone(),
-                continuation_token: self.token.clone(),
-                ..ListObjectsV2Request::default()
-            };
+                    return Ok(Some(object));
+                }
 
-            // execute the request and await the response (blocking)
-            let response = self.s3.list_objects_v2(request).await?;
+                // if done, no fetch
+                if self.finished {
+                    return Ok(None);
+                }
 
-            // check contents (although should always be there)
-            if response.contents.is_none() {
-                return Ok(None);
-            }
+                // create a request to list objects
+                let request = ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: self.prefix.clone(),
+                    continuation_token: self.token.clone(),
+                    request_payer: self.request_payer.clone(),
+                    start_after: self.start_after.clone(),
+                    ..ListObjectsV2Request::default()
+                };
 
-            // store the page and next identifier
-            self.buffer = response.contents.unwrap();
-            self.token = response.next_continuation_token;
+                // execute the request and await the response (blocking)
+                let response = self.s3.list_objects_v2(request).await?;
 
-            // check for last page
-            if self.token == None {
-                self.finished = true;
-            }
+                // check contents (although should always be there)
+                if response.contents.is_none() {
+                    return Ok(None);
+                }
+
+                // store the page and next identifier
+                self.buffer = response.contents.unwrap();
+                self.token = response.next_continuation_token;
 
-            // pass back
-            self.next().await
+                // check for last page
+                if self.token == None {
+                    self.finished = true;
+                }
+            }
         })
     }
 }